@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec4 color;
+layout(location = 1) in float offset;
+
+layout(location = 0) out vec4 color_out;
+
+// Must agree with `boundaries.vert`
+const float HALF_WIDTH = 1.0;
+
+void main() {
+    // Fraction of the pixel covered by the line, approximated from its distance to the center
+    float coverage = clamp(HALF_WIDTH + 0.5 - abs(offset), 0, 1);
+    color_out = vec4(color.rgb, color.a * coverage);
+}
@@ -0,0 +1,61 @@
+#version 450
+
+#include "common.h"
+
+// Endpoints of a segment of a geodesic, in the coordinates of the node being outlined
+layout(location = 0) in vec4 a;
+layout(location = 1) in vec4 b;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec4 color_out;
+// Distance from the center of the line, in pixels
+layout(location = 1) out float offset;
+
+layout(push_constant) uniform PushConstants {
+    // Maps the outlined node's coordinates to local node space
+    mat4 transform;
+    // Viewport size in pixels
+    vec2 viewport;
+};
+
+// Must agree with `boundaries.frag`
+const float HALF_WIDTH = 1.0;
+
+// Each set of 6 vertices makes a quad spanning the segment, with which end and which side of the
+// line each vertex lies on
+const uint ends[6] = {0, 1, 1, 1, 0, 0};
+const float sides[6] = {-1, -1, 1, 1, 1, -1};
+
+void main() {
+    vec4 local_a = transform * a;
+    vec4 local_b = transform * b;
+    vec4 clip_a = view_projection * local_a;
+    vec4 clip_b = view_projection * local_b;
+    if (clip_a.w <= 0 || clip_b.w <= 0) {
+        // Behind the viewer, where screen-space direction is meaningless. Segments are short, so
+        // dropping them entirely leaves no noticeable gap.
+        gl_Position = vec4(0, 0, 0, 1);
+        offset = 0;
+        color_out = vec4(0);
+        return;
+    }
+    vec2 screen_a = clip_a.xy / clip_a.w * viewport * 0.5;
+    vec2 screen_b = clip_b.xy / clip_b.w * viewport * 0.5;
+    vec2 dir = screen_b - screen_a;
+    dir = length(dir) > 0 ? normalize(dir) : vec2(1, 0);
+    vec2 normal = vec2(-dir.y, dir.x);
+
+    uint end = ends[gl_VertexIndex];
+    float side = sides[gl_VertexIndex];
+    vec4 clip = end == 0 ? clip_a : clip_b;
+    // Widen by an extra pixel on each side to leave room for the antialiased falloff
+    float extent = HALF_WIDTH + 1.0;
+    clip.xy += normal * side * extent * 2.0 / viewport * clip.w;
+    gl_Position = clip;
+    offset = side * extent;
+
+    // Fade out with distance, like everything else
+    vec4 local = end == 0 ? local_a : local_b;
+    float dist = acosh(max(-mip(local, view_position) / sqrt(mip(local, local) * mip(view_position, view_position)), 1));
+    color_out = vec4(color.rgb, color.a * exp(-pow(dist * fog_density, 5)));
+}
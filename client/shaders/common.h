@@ -9,8 +9,22 @@ layout(set = 0, binding = 0) uniform Common {
     mat4 view_projection;
     // Maps clip space to view space
     mat4 inverse_projection;
+    // Sky colors directly above and below the viewer; alpha is unused
+    vec4 horizon_top;
+    vec4 horizon_bottom;
+    // Position of the viewer in local node space
+    vec4 view_position;
+    // Ideal point of the sun in local node space
+    vec4 sun;
     float fog_density;
     float time;
+    // Brightness of direct sunlight, from 0 at night to 1 at midday
+    float daylight;
 };
 
+// Minkowski inner product
+float mip(vec4 a, vec4 b) {
+    return dot(a.xyz, b.xyz) - a.w * b.w;
+}
+
 #endif
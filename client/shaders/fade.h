@@ -0,0 +1,20 @@
+#ifndef FADE_H
+#define FADE_H
+
+// 4x4 ordered dither thresholds
+const uint bayer[16] = {
+    0, 8, 2, 10,
+    12, 4, 14, 6,
+    3, 11, 1, 9,
+    15, 7, 13, 5
+};
+
+// Whether the current fragment of a surface with opacity `fade` should be discarded. Fading
+// surfaces use screen-door transparency rather than blending so that they're still drawn in the
+// opaque pass, writing the depth that the fog pass relies on.
+bool faded(float fade) {
+    uvec2 cell = uvec2(gl_FragCoord.xy) % 4;
+    return fade < (float(bayer[cell.y * 4 + cell.x]) + 0.5) / 16.0;
+}
+
+#endif
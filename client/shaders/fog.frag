@@ -16,6 +16,11 @@ void main() {
     float view_length = length(view_pos);
     // Convert to true hyperbolic distance, taking care to respect atanh's domain
     float dist = view_length >= 1.0 ? INFINITY : atanh(view_length);
+    // Klein model geodesics through the origin are straight lines, so the view ray's direction is
+    // also the direction of the ideal point where it meets the conformal boundary. Shade that
+    // point by its angle above or below the horizontal, and fade geometry into it.
+    float t = asin(clamp(normalize(view_pos).y, -1.0, 1.0)) / PI + 0.5;
+    vec3 horizon = mix(horizon_bottom.rgb, horizon_top.rgb, t);
     // Exponential^k fog
-    fog = vec4(0.5, 0.65, 0.9, exp(-pow(dist * fog_density, 5)));
+    fog = vec4(horizon, exp(-pow(dist * fog_density, 5)));
 }
@@ -0,0 +1,25 @@
+#version 450
+
+#include "fade.h"
+
+layout(location = 0) in vec3 lattice;
+layout(location = 1) in vec3 normal;
+layout(location = 2) flat in float fade;
+layout(location = 3) in float lighting;
+layout(location = 4) in vec3 tint;
+layout(location = 5) flat in uint layer;
+layout(location = 0) out vec4 color;
+
+layout(set = 1, binding = 1) uniform sampler2DArray textures;
+
+void main() {
+    if (faded(fade)) {
+        discard;
+    }
+    // Project the texture along whichever axis the surface faces most directly, repeating it once
+    // per voxel
+    vec3 a = abs(normal);
+    uint axis = a.x >= a.y && a.x >= a.z ? 0 : (a.y >= a.z ? 1 : 2);
+    vec2 uv = fract(vec2(lattice[(axis + 1) % 3], lattice[(axis + 2) % 3]));
+    color = texture(textures, vec3(uv, layer)) * vec4(tint, 1) * lighting;
+}
@@ -0,0 +1,49 @@
+#version 450
+
+#include "common.h"
+#include "shading.h"
+#include "surface-extraction/surface.h"
+
+// Maps from cube space ([0..1]^3) to local node space
+layout(location = 0) in mat4 transform;
+// Opacity of the chunk as it fades in
+layout(location = 4) in float fade;
+// Tints at the corners of the chunk's cube of incident nodes, per `ChunkTints`
+layout(location = 5) in uvec4 tints_low;
+layout(location = 6) in uvec4 tints_high;
+// Position and outward normal in cube space, per `meshed::Vertex`
+layout(location = 7) in vec3 position;
+layout(location = 8) in vec3 normal;
+layout(location = 9) in uint material;
+// Light level at the vertex as a fraction of the brightest, per `meshed::Vertex`
+layout(location = 10) in float light;
+
+// Position in units of voxels, from which textures are projected
+layout(location = 0) out vec3 lattice;
+layout(location = 1) out vec3 normal_out;
+layout(location = 2) flat out float fade_out;
+layout(location = 3) out float lighting;
+layout(location = 4) out vec3 tint;
+layout(location = 5) flat out uint layer;
+
+invariant gl_Position;
+
+layout(push_constant) uniform PushConstants {
+    uint dimension;
+};
+
+void main() {
+    lattice = position * dimension;
+    normal_out = normal;
+    fade_out = fade;
+    layer = material - 1;
+    tint = material_tintable(material) ? chunk_tint(tints_low, tints_high, position) : vec3(1);
+    // Transformed exactly as in `voxels.vert`, so that vertices shared with a neighboring chunk
+    // drawn from GPU-extracted faces land in the same place
+    precise vec4 local = transform[0] * position.x + transform[1] * position.y;
+    local = local + transform[2] * position.z;
+    local = local + transform[3];
+    gl_Position = view_projection * local;
+    // Light from emissive voxels is seen regardless of the sun
+    lighting = max(surface_lighting(local, surface_normal(transform, normal, position)), light);
+}
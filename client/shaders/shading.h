@@ -0,0 +1,52 @@
+#ifndef SHADING_H
+#define SHADING_H
+
+#include "common.h"
+
+// Tint at a point in chunk coordinates, given the tints at the corners of the chunk's cube of
+// incident nodes, per `ChunkTints`. Must agree with `ChunkTints::at`.
+vec3 chunk_tint(uvec4 tints_low, uvec4 tints_high, vec3 coords) {
+    // A chunk spans the half of the cube of incident nodes nearest its own node
+    vec3 t = coords * 0.5;
+    uint corners[8] = {
+        tints_low.x, tints_low.y, tints_low.z, tints_low.w,
+        tints_high.x, tints_high.y, tints_high.z, tints_high.w
+    };
+    // Corners are indexed by x * 4 + y * 2 + z
+    vec3 v[8];
+    for (int i = 0; i < 8; ++i) {
+        v[i] = unpackUnorm4x8(corners[i]).rgb;
+    }
+    return mix(
+        mix(mix(v[0], v[4], t.x), mix(v[2], v[6], t.x), t.y),
+        mix(mix(v[1], v[5], t.x), mix(v[3], v[7], t.x), t.y),
+        t.z);
+}
+
+// Unit normal in local node space of the plane through `point` perpendicular to `normal`, both in
+// the chunk coordinates `transform` maps from. Planes are geodesic, so the raised covector of the
+// plane is its normal everywhere. Zero if `normal` is. Must agree with `face_normal` in
+// `voxels/tests.rs`.
+vec4 surface_normal(mat4 transform, vec3 normal, vec3 point) {
+    vec4 plane = vec4(normal, -dot(normal, point));
+    plane = plane * inverse(transform);
+    vec4 n = vec4(plane.xyz, -plane.w);
+    return n / max(sqrt(mip(n, n)), 1e-6);
+}
+
+// Brightness of a surface at `local`, with normal `n`, both in local node space
+float surface_lighting(vec4 local, vec4 n) {
+    // Light from the viewer: the direction towards it is the tangent at this vertex of the geodesic
+    // leading there
+    vec4 p = local / sqrt(-mip(local, local));
+    vec4 to_view = view_position + mip(view_position, p) * p;
+    float facing = mip(n, to_view);
+    float view_light = abs(facing) / max(sqrt(mip(to_view, to_view)), 1e-6);
+    // Light from the sun, on whichever side of the face is visible. Unlike the viewer, the sun
+    // lies at infinity, so this is the tangent of the geodesic towards an ideal point.
+    vec4 to_sun = sun + mip(sun, p) * p;
+    float sun_light = max(sign(facing) * mip(n, to_sun), 0) / max(sqrt(mip(to_sun, to_sun)), 1e-6);
+    return mix(0.5, 1.0, view_light) * mix(0.4, 1.0, daylight * sun_light);
+}
+
+#endif
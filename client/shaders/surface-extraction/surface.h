@@ -5,10 +5,30 @@
 struct Surface {
     // (x y, z, axis)
     uint pos_axis;
-    // (occlusion, padding, mat, mat)
+    // (occlusion, squash, light, mat)
     uint occlusion_mat;
 };
 
+// Level of the brightest light. Must agree with `light::MAX_LIGHT`.
+const float MAX_LIGHT = 15.0;
+
+const uint SHAPE_FULL = 0;
+const uint SHAPE_SLAB = 1;
+
+// Whether a material's color varies with climate. Must agree with `Material::is_tintable`.
+bool material_tintable(uint mat) {
+    return mat == 5 || mat == 8 || mat == 13 || mat == 17;
+}
+
+// How the quad of a face is fitted to a partially filled voxel
+//
+// Full-size quad
+const uint SQUASH_NONE = 0;
+// Vertices at the top of the voxel are lowered halfway, for the sides of a slab
+const uint SQUASH_SIDE = 1;
+// The whole quad is lowered halfway, for the top of a slab
+const uint SQUASH_TOP = 2;
+
 // [0,2^8)^3
 uvec3 get_pos(Surface s) {
     return uvec3(s.pos_axis & 0xFF, (s.pos_axis >> 8) & 0xFF, (s.pos_axis >> 16) & 0xFF);
@@ -27,20 +47,29 @@ uint get_axis(Surface s) {
 }
 
 uint get_mat(Surface s) {
-    return s.occlusion_mat & 0xFFFF;
+    return s.occlusion_mat & 0xFF;
+}
+
+// Light level of the open side of the face, per `LightField`
+uint get_light(Surface s) {
+    return (s.occlusion_mat >> 8) & 0xFF;
+}
+
+uint get_squash(Surface s) {
+    return (s.occlusion_mat >> 16) & 0xFF;
 }
 
 float get_occlusion(Surface s, uvec2 texcoords) {
     return float((s.occlusion_mat >> (24 + 2 * (texcoords.x | texcoords.y << 1))) & 0x03) / 3.0;
 }
 
-Surface surface(uvec3 pos, uint axis, bool reverse, uint mat, uvec4 occlusion) {
+Surface surface(uvec3 pos, uint axis, bool reverse, uint mat, uint light, uint squash, uvec4 occlusion) {
     Surface result;
     // Flip the quad if necessary to prevent the triangle dividing line from being parallel to the
     // gradient of ambient occlusion, ensuring isotropy.
     axis += 3 * uint(reverse) + 6 * uint(occlusion.y + occlusion.z > occlusion.x + occlusion.w);
     result.pos_axis = pos.x | pos.y << 8 | pos.z << 16 | axis << 24;
-    result.occlusion_mat = mat | occlusion.x << 24 | occlusion.y << 26 | occlusion.z << 28 | occlusion.w << 30;
+    result.occlusion_mat = mat | light << 8 | squash << 16 | occlusion.x << 24 | occlusion.y << 26 | occlusion.z << 28 | occlusion.w << 30;
     return result;
 }
 
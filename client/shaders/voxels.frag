@@ -1,11 +1,19 @@
 #version 450
 
+#include "fade.h"
+
 layout(location = 0) in vec3 texcoords;
 layout(location = 1) in float occlusion;
+layout(location = 2) flat in float fade;
+layout(location = 3) in float lighting;
+layout(location = 4) in vec3 tint;
 layout(location = 0) out vec4 color;
 
 layout(set = 1, binding = 1) uniform sampler2DArray textures;
 
 void main() {
-    color = texture(textures, texcoords) * occlusion;
+    if (faded(fade)) {
+        discard;
+    }
+    color = texture(textures, texcoords) * vec4(tint, 1) * occlusion * lighting;
 }
@@ -1,13 +1,24 @@
 #version 460
 
 #include "common.h"
+#include "shading.h"
 #include "surface-extraction/surface.h"
 
 // Maps from cube space ([0..1]^3) to local node space
 layout(location = 0) in mat4 transform;
+// Opacity of the chunk as it fades in
+layout(location = 4) in float fade;
+// Tints at the corners of the chunk's cube of incident nodes, per `ChunkTints`
+layout(location = 5) in uvec4 tints_low;
+layout(location = 6) in uvec4 tints_high;
 
 layout(location = 0) out vec3 texcoords_out;
 layout(location = 1) out float occlusion;
+layout(location = 2) flat out float fade_out;
+layout(location = 3) out float lighting;
+layout(location = 4) out vec3 tint;
+
+invariant gl_Position;
 
 layout(set = 1, binding = 0) readonly restrict buffer Surfaces {
     Surface surfaces[];
@@ -15,48 +26,74 @@ layout(set = 1, binding = 0) readonly restrict buffer Surfaces {
 
 layout(push_constant) uniform PushConstants {
     uint dimension;
+    // Number of pieces along each edge into which every face of the chunk is split
+    uint subdivisions;
+    // Which of the subdivisions^2 pieces of each face this draw produces
+    uint piece;
 };
 
-// Each set of 6 vertices makes a ring around the quad, with the middle and start/end vertices
-// duplicated. Note that the sign only indicates the winding of the face; all faces contain the
+// Each set of 6 vertices makes a ring around the quad, in texture coordinates, with the middle and
+// start/end vertices duplicated. Rows [0, 2) are used for faces with axis [0, 6), whose winding
+// differs by sign. Note that the sign only indicates the winding of the face; all faces contain the
 // origin regardless.
-const uvec3 vertices[12][6] = {
-    {{0, 0, 0}, {0, 0, 1}, {0, 1, 1}, {0, 1, 1}, {0, 1, 0}, {0, 0, 0}}, // -X
-    {{0, 0, 0}, {1, 0, 0}, {1, 0, 1}, {1, 0, 1}, {0, 0, 1}, {0, 0, 0}}, // -Y
-    {{0, 0, 0}, {0, 1, 0}, {1, 1, 0}, {1, 1, 0}, {1, 0, 0}, {0, 0, 0}}, // -Z
-
-    {{0, 0, 0}, {0, 1, 0}, {0, 1, 1}, {0, 1, 1}, {0, 0, 1}, {0, 0, 0}}, // +X
-    {{0, 0, 0}, {0, 0, 1}, {1, 0, 1}, {1, 0, 1}, {1, 0, 0}, {0, 0, 0}}, // +Y
-    {{0, 0, 0}, {1, 0, 0}, {1, 1, 0}, {1, 1, 0}, {0, 1, 0}, {0, 0, 0}}, // +Z
-
-    // Versions of the above rotated 90 degrees so the diagonal goes the other way, used to improve
-    // the consistency of barycentric interpolation of ambient occlusion
-    {{0, 0, 1}, {0, 1, 1}, {0, 1, 0}, {0, 1, 0}, {0, 0, 0}, {0, 0, 1}}, // -X
-    {{1, 0, 0}, {1, 0, 1}, {0, 0, 1}, {0, 0, 1}, {0, 0, 0}, {1, 0, 0}}, // -Y
-    {{0, 1, 0}, {1, 1, 0}, {1, 0, 0}, {1, 0, 0}, {0, 0, 0}, {0, 1, 0}}, // -Z
-
-    {{0, 0, 1}, {0, 0, 0}, {0, 1, 0}, {0, 1, 0}, {0, 1, 1}, {0, 0, 1}}, // +X
-    {{1, 0, 0}, {0, 0, 0}, {0, 0, 1}, {0, 0, 1}, {1, 0, 1}, {1, 0, 0}}, // +Y
-    {{0, 1, 0}, {0, 0, 0}, {1, 0, 0}, {1, 0, 0}, {1, 1, 0}, {0, 1, 0}}  // +Z
-};
-
 const uvec2 texcoords[4][6] = {
     {{0, 0}, {0, 1}, {1, 1}, {1, 1}, {1, 0}, {0, 0}},
     {{0, 0}, {1, 0}, {1, 1}, {1, 1}, {0, 1}, {0, 0}},
-    // Rotated versions
+    // Versions of the above rotated 90 degrees so the diagonal goes the other way, used to improve
+    // the consistency of barycentric interpolation of ambient occlusion
     {{0, 1}, {1, 1}, {1, 0}, {1, 0}, {0, 0}, {0, 1}},
     {{0, 1}, {0, 0}, {1, 0}, {1, 0}, {1, 1}, {0, 1}},
 };
 
+// Ambient occlusion at an arbitrary point on a face, interpolated from its corners
+float get_occlusion(Surface s, vec2 texcoords) {
+    return mix(
+        mix(get_occlusion(s, uvec2(0, 0)), get_occlusion(s, uvec2(1, 0)), texcoords.x),
+        mix(get_occlusion(s, uvec2(0, 1)), get_occlusion(s, uvec2(1, 1)), texcoords.x),
+        texcoords.y);
+}
+
 void main()  {
     uint index = gl_VertexIndex / 6;
     uint vertex = gl_VertexIndex % 6;
     Surface s = surfaces[index];
     uvec3 pos = get_pos(s);
     uint axis = get_axis(s);
-    uvec2 uv = texcoords[axis / 3][vertex];
+    uvec2 corner_uv = texcoords[axis / 3][vertex];
+    // Position of this vertex within the whole face. Exact when the face isn't subdivided.
+    vec2 uv = (uvec2(piece % subdivisions, piece / subdivisions) + corner_uv) / float(subdivisions);
     texcoords_out = vec3(uv, get_mat(s) - 1);
-    occlusion = get_occlusion(s, uv);
-    vec3 relative_coords = vertices[axis][vertex] + pos;
-    gl_Position = view_projection * transform * vec4(relative_coords / dimension, 1);
+    occlusion = subdivisions == 1 ? get_occlusion(s, corner_uv) : get_occlusion(s, uv);
+    fade_out = fade;
+    // Texture coordinates run along the two axes following the face's normal
+    uint normal = axis % 3;
+    vec3 face_coords = vec3(0);
+    face_coords[(normal + 1) % 3] = uv.x;
+    face_coords[(normal + 2) % 3] = uv.y;
+    uint squash = get_squash(s);
+    if (squash == SQUASH_SIDE) {
+        face_coords.y *= 0.5;
+    }
+    vec3 relative_coords = face_coords + pos;
+    if (squash == SQUASH_TOP) {
+        relative_coords.y -= 0.5;
+    }
+    relative_coords /= dimension;
+    tint = material_tintable(get_mat(s)) ? chunk_tint(tints_low, tints_high, relative_coords) : vec3(1);
+    // Apply the transform one axis at a time, forbidding fused or reordered arithmetic. The
+    // column for a given side of the node is the same in every chunk incident to that side, zero
+    // terms vanish exactly, and a sum of two terms commutes, so a vertex on a face shared by two
+    // chunks of the same node gets exactly the same position from both, leaving no cracks.
+    precise vec4 local = transform[0] * relative_coords.x + transform[1] * relative_coords.y;
+    local = local + transform[2] * relative_coords.z;
+    local = local + transform[3];
+    gl_Position = view_projection * local;
+
+    // Faces are planes of constant chunk coordinate along their normal axis
+    vec3 face_normal = vec3(0);
+    face_normal[normal] = 1;
+    // Light from emissive voxels is seen regardless of the sun
+    lighting = max(
+        surface_lighting(local, surface_normal(transform, face_normal, relative_coords)),
+        float(get_light(s)) / MAX_LIGHT);
 }
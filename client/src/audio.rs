@@ -0,0 +1,93 @@
+//! How sounds should be heard given where they come from
+//!
+//! Produces the parameters an audio backend needs to place a sound, without playing anything
+//! itself. Sound spreads over spheres whose area grows exponentially with hyperbolic distance, so
+//! amplitude falls off with `sinh` of the distance rather than the distance itself, and distant
+//! sounds fade far faster than they would in flat space.
+
+use common::math;
+
+/// Placement of a sound relative to a listener
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Spatialization {
+    /// Factor to scale the sound's amplitude by, in [0, 1]
+    pub gain: f32,
+    /// Stereo position, from -1 for fully left through 0 for centered to 1 for fully right
+    pub pan: f32,
+}
+
+/// Placement of a sound emitted at `source` as heard by `listener`, both in the same coordinates
+///
+/// Sounds within `reference_distance` of the listener, in absolute units, are heard at full
+/// volume. The listener faces -Z with +X to its right, as the camera does.
+pub fn spatialize(
+    listener: &math::Isometry<f32>,
+    source: &math::Isometry<f32>,
+    reference_distance: f32,
+) -> Spatialization {
+    // Where the source lies in the listener's frame
+    let relative = listener.inverse() * (*source * math::origin());
+    // Isometries keep the source's position normalized, so its distance from the listener at the
+    // origin follows from `w` alone. The Minkowski norm that `math::distance` divides by cancels
+    // catastrophically for distant sources. Rounding error in degenerate poses, including NaN, is
+    // best heard as the source being right here.
+    let distance = relative.w.max(1.0).acosh();
+    let gain = if distance <= reference_distance {
+        1.0
+    } else {
+        reference_distance.sinh() / distance.sinh()
+    };
+    // Geodesics through the origin are straight lines in these coordinates, so the spatial part
+    // of the position is the direction towards the source
+    let pan = na::Unit::try_new(relative.xyz(), 1e-6).map_or(0.0, |dir| dir.x);
+    Spatialization { gain, pan }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    const REFERENCE: f32 = 0.1;
+
+    fn at(direction: na::Unit<na::Vector3<f32>>, distance: f32) -> math::Isometry<f32> {
+        math::Isometry::from_matrix_unchecked(math::translate_along(&direction, distance))
+    }
+
+    #[test]
+    fn attenuation_monotonic() {
+        let listener = math::Isometry::identity();
+        let gain =
+            |distance| spatialize(&listener, &at(-na::Vector3::z_axis(), distance), REFERENCE).gain;
+        assert_eq!(gain(0.0), 1.0);
+        assert_eq!(gain(REFERENCE * 0.5), 1.0);
+        let mut previous = 1.0;
+        for i in 1..40 {
+            let current = gain(REFERENCE + i as f32 * 0.25);
+            assert!(current < previous, "gain rose to {} at step {}", current, i);
+            assert!(current > 0.0);
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn pan_follows_direction() {
+        let listener = math::Isometry::identity();
+        let pan = |direction| spatialize(&listener, &at(direction, 2.0), REFERENCE).pan;
+        assert_abs_diff_eq!(pan(-na::Vector3::z_axis()), 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(pan(na::Vector3::x_axis()), 1.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(pan(-na::Vector3::x_axis()), -1.0, epsilon = 1e-6);
+        // Coincident sounds have no direction
+        assert_eq!(spatialize(&listener, &listener, REFERENCE).pan, 0.0);
+
+        // Only the source's position relative to the listener matters
+        let listener = at(na::Vector3::y_axis(), 1.5)
+            * math::Isometry::from_matrix_unchecked(
+                na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), 0.7).to_homogeneous(),
+            );
+        let ahead = listener * at(-na::Vector3::z_axis(), 3.0);
+        let heard = spatialize(&listener, &ahead, REFERENCE);
+        assert_abs_diff_eq!(heard.pan, 0.0, epsilon = 1e-4);
+        assert_abs_diff_eq!(heard.gain, REFERENCE.sinh() / 3.0f32.sinh(), epsilon = 1e-4);
+    }
+}
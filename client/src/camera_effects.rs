@@ -0,0 +1,136 @@
+//! Cosmetic camera motion layered over the simulated view
+//!
+//! Head bob raises and lowers the eye in time with footsteps, and FOV kick briefly widens the field
+//! of view while speeding up. Both only affect what's rendered; the simulated pose, and everything
+//! derived from it such as chunk loading, is untouched.
+
+use common::{math, proto::Position};
+
+/// Vertical travel of the eye to either side of rest at full bob, in meters
+const BOB_AMPLITUDE: f32 = 0.04;
+/// Distance covered by one full up-and-down cycle of the eye, in meters
+const STRIDE: f32 = 1.6;
+/// Speed at and above which bob reaches full amplitude, in meters per second
+const FULL_BOB_SPEED: f32 = 6.0;
+/// Greatest fractional increase in field of view
+const MAX_KICK: f32 = 0.1;
+/// Acceleration, in meters per second per second, producing the greatest kick
+const FULL_KICK_ACCELERATION: f32 = 48.0;
+/// Time constant, in seconds, with which the kick tracks changes in speed
+const KICK_TIME: f32 = 0.15;
+
+pub struct CameraEffects {
+    head_bob: bool,
+    fov_kick: bool,
+    /// Position within the current stride, in radians
+    phase: f32,
+    /// Meters per second
+    speed: f32,
+    /// Smoothed speed, lagging `speed` so that the difference reflects acceleration
+    smoothed_speed: f32,
+}
+
+impl CameraEffects {
+    pub fn new(head_bob: bool, fov_kick: bool) -> Self {
+        Self {
+            head_bob,
+            fov_kick,
+            phase: 0.0,
+            speed: 0.0,
+            smoothed_speed: 0.0,
+        }
+    }
+
+    /// Advance by `dt` seconds while moving at `speed` meters per second
+    pub fn step(&mut self, dt: f32, speed: f32) {
+        self.speed = speed.max(0.0);
+        self.phase = (self.phase + dt * self.speed / STRIDE * 2.0 * std::f32::consts::PI)
+            % (2.0 * std::f32::consts::PI);
+        // Exponential smoothing is stable for any dt
+        self.smoothed_speed += (self.speed - self.smoothed_speed) * (1.0 - (-dt / KICK_TIME).exp());
+    }
+
+    /// The view to render in place of `view`
+    pub fn apply(&self, view: &Position, meters_to_absolute: f32) -> Position {
+        let mut result = *view;
+        if self.head_bob {
+            let offset = bob(self.phase, self.speed) * meters_to_absolute;
+            result.local *= math::translate_along(&na::Vector3::y_axis(), offset);
+        }
+        result
+    }
+
+    /// Factor by which to scale the field of view
+    pub fn fov_scale(&self) -> f32 {
+        if !self.fov_kick {
+            return 1.0;
+        }
+        // The lag of an exponential smoother behind a steadily rising input is the rate of rise
+        // times the time constant
+        let acceleration = (self.speed - self.smoothed_speed) / KICK_TIME;
+        1.0 + MAX_KICK * (acceleration / FULL_KICK_ACCELERATION).max(0.0).min(1.0)
+    }
+}
+
+/// Height of the eye above rest, in meters, at `phase` radians through a stride at `speed` meters
+/// per second
+pub fn bob(phase: f32, speed: f32) -> f32 {
+    BOB_AMPLITUDE * (speed / FULL_BOB_SPEED).max(0.0).min(1.0) * phase.sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    #[test]
+    fn bob_shape() {
+        for i in 0..16 {
+            let phase = i as f32 * 0.4;
+            assert_eq!(bob(phase, 0.0), 0.0);
+            assert_abs_diff_eq!(
+                bob(phase, 3.0),
+                bob(phase + 2.0 * std::f32::consts::PI, 3.0),
+                epsilon = 1e-6
+            );
+            // Clamped at high speed
+            assert_eq!(bob(phase, 100.0), bob(phase, FULL_BOB_SPEED));
+            assert!(bob(phase, 100.0).abs() <= BOB_AMPLITUDE);
+        }
+    }
+
+    #[test]
+    fn view_only() {
+        let view = Position {
+            node: common::graph::NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::x_axis(), 0.3),
+        };
+        let original = view;
+        let mut effects = CameraEffects::new(true, true);
+        // Start running
+        for _ in 0..5 {
+            effects.step(0.02, 10.0);
+        }
+        assert!(effects.fov_scale() > 1.0);
+        assert!(effects.fov_scale() <= 1.0 + MAX_KICK);
+        let rendered = effects.apply(&view, 1.0);
+        assert_eq!(view.local, original.local);
+        assert!(rendered.local != view.local);
+        // The eye moves only vertically, relative to the view
+        let eye = view.local.try_inverse().unwrap() * rendered.local * math::origin();
+        assert_abs_diff_eq!(eye.x, 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(eye.z, 0.0, epsilon = 1e-6);
+
+        // Steady speed settles the kick
+        for _ in 0..100 {
+            effects.step(0.02, 10.0);
+        }
+        assert_abs_diff_eq!(effects.fov_scale(), 1.0, epsilon = 1e-3);
+
+        // Disabled effects do nothing
+        let mut effects = CameraEffects::new(false, false);
+        effects.step(0.1, 10.0);
+        assert_eq!(effects.apply(&view, 1.0).local, view.local);
+        assert_eq!(effects.fov_scale(), 1.0);
+    }
+}
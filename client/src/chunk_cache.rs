@@ -0,0 +1,126 @@
+//! Accounting for the memory occupied by voxel data
+//!
+//! Chunks are generated on demand as they come into view, and otherwise kept indefinitely, so a
+//! player who explores widely would accumulate voxel data without bound. `ChunkCache` tracks the
+//! size of each populated chunk and when it was last rendered, so that the least recently rendered
+//! can be discarded once a cap is exceeded, regardless of how near they are. Discarded chunks
+//! revert to `Chunk::Fresh`, and are regenerated if they're needed again.
+
+use std::collections::BTreeMap;
+
+use fxhash::FxHashMap;
+
+use common::node::{ChunkId, VoxelData};
+
+pub struct ChunkCache {
+    /// Bytes of voxel data beyond which chunks are evicted
+    cap: usize,
+    /// Bytes of voxel data currently populated
+    bytes: usize,
+    /// Incremented on every use, ordering uses
+    clock: u64,
+    /// When each populated chunk was last used, and its size
+    chunks: FxHashMap<ChunkId, (u64, usize)>,
+    /// Populated chunks by time of last use
+    recency: BTreeMap<u64, ChunkId>,
+}
+
+impl ChunkCache {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            bytes: 0,
+            clock: 0,
+            chunks: FxHashMap::default(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    /// Bytes of voxel data currently populated
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Account for `chunk` having been populated with `voxels`
+    pub fn insert(&mut self, chunk: ChunkId, voxels: &VoxelData) {
+        self.remove(chunk);
+        let size = voxels.heap_size();
+        self.bytes += size;
+        self.clock += 1;
+        self.chunks.insert(chunk, (self.clock, size));
+        self.recency.insert(self.clock, chunk);
+    }
+
+    /// Record that `chunk` was just used, deferring its eviction
+    pub fn touch(&mut self, chunk: ChunkId) {
+        let clock = self.clock + 1;
+        if let Some(&mut (ref mut used, _)) = self.chunks.get_mut(&chunk) {
+            self.recency.remove(&*used);
+            *used = clock;
+            self.recency.insert(clock, chunk);
+            self.clock = clock;
+        }
+    }
+
+    /// Stop accounting for `chunk`, e.g. because it was evicted
+    pub fn remove(&mut self, chunk: ChunkId) {
+        if let Some((used, size)) = self.chunks.remove(&chunk) {
+            self.recency.remove(&used);
+            self.bytes -= size;
+        }
+    }
+
+    /// The least recently used chunk, if the cap is exceeded
+    pub fn excess(&self) -> Option<ChunkId> {
+        if self.bytes <= self.cap {
+            return None;
+        }
+        self.recency.values().next().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{dodeca::Vertex, graph::NodeId, world::Material};
+
+    const DIMENSION: u8 = 12;
+
+    fn dense() -> VoxelData {
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(DIMENSION);
+        voxels
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let chunk_size = dense().heap_size();
+        let mut cache = ChunkCache::new(3 * chunk_size);
+        let chunks = Vertex::iter()
+            .take(5)
+            .map(|v| ChunkId::new(NodeId::ROOT, v))
+            .collect::<Vec<_>>();
+        // Solid chunks take no space beyond the graph itself
+        cache.insert(chunks[0], &VoxelData::Solid(Material::Stone));
+        assert_eq!(cache.bytes(), 0);
+        cache.insert(chunks[0], &dense());
+        for &chunk in &chunks[1..3] {
+            cache.insert(chunk, &dense());
+        }
+        assert_eq!(cache.excess(), None);
+        assert_eq!(cache.bytes(), 3 * chunk_size);
+
+        // Keep the oldest chunk in use while loading beyond the cap
+        cache.touch(chunks[0]);
+        for &chunk in &chunks[3..] {
+            cache.insert(chunk, &dense());
+        }
+        let mut evicted = Vec::new();
+        while let Some(chunk) = cache.excess() {
+            cache.remove(chunk);
+            evicted.push(chunk);
+        }
+        assert_eq!(evicted, [chunks[1], chunks[2]]);
+        assert_eq!(cache.bytes(), 3 * chunk_size);
+    }
+}
@@ -0,0 +1,327 @@
+//! Extraction of chunk surfaces on the CPU
+//!
+//! Most surfaces are extracted on the GPU, directly into the buffers they're drawn from, but only
+//! as the faces of whole voxels. Smooth terrain, and exports, need surfaces in a form that can be
+//! reshaped and read back, so these are extracted here into a `ChunkMesh` in the chunk's own
+//! lattice, independent of where the chunk lies.
+
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use common::world::Material;
+
+/// How terrain surfaces are shaped
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    /// The faces of solid voxels, exactly
+    Blocky,
+    /// An isosurface of the terrain's density, found by surface nets, where solid voxels count as
+    /// dense and void ones as empty
+    Smooth,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style::Blocky
+    }
+}
+
+/// Boundaries between solid and void voxels in a single chunk, in the chunk's own lattice
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkMesh {
+    /// Positions with each coordinate in `[0, dimension]`, where voxel corners lie on integers
+    pub vertices: Vec<[f32; 3]>,
+    /// Outward unit normal at each vertex, averaged over the faces that share it
+    pub normals: Vec<[f32; 3]>,
+    /// Material of the solid voxel whose face first reached each vertex
+    pub materials: Vec<Material>,
+    /// Indices into `vertices`, counterclockwise when viewed from outside the surface
+    pub faces: Vec<[u32; 4]>,
+}
+
+impl ChunkMesh {
+    /// Extract the surface, shaped according to `style`, of the chunk whose voxels, including
+    /// margins, are `voxels`
+    pub fn extract_styled(style: Style, dimension: u8, voxels: &[Material]) -> Self {
+        let mut mesh = Self::default();
+        mesh.fill_styled(style, dimension, voxels);
+        mesh
+    }
+
+    /// Like `extract_styled`, but reusing the storage of `self`
+    pub fn fill_styled(&mut self, style: Style, dimension: u8, voxels: &[Material]) {
+        match style {
+            Style::Blocky => self.fill(dimension, voxels),
+            Style::Smooth => {
+                let densities = voxels
+                    .iter()
+                    .map(|x| if x.is_void() { -1.0 } else { 1.0 })
+                    .collect::<Vec<_>>();
+                self.fill_smooth(dimension, voxels, &densities);
+            }
+        }
+    }
+
+    /// Replace the contents of `self` with the surface of the chunk whose voxels, including
+    /// margins, are `voxels`
+    pub fn fill(&mut self, dimension: u8, voxels: &[Material]) {
+        let dim = isize::from(dimension);
+        self.build(
+            dim,
+            |p| voxels[margin_index(dim, p)],
+            |corner| [corner.x as f32, corner.y as f32, corner.z as f32],
+        );
+    }
+
+    /// Replace the contents of `self` with the surface where `densities`, sampled at the center of
+    /// each voxel of a chunk including margins, cross zero
+    ///
+    /// Positive densities are inside the surface, and `voxels` supplies the materials of those
+    /// voxels. Faces are the same as those `fill` would find if every voxel with positive density
+    /// were solid, but each corner is moved to the mean of the points where the density, linearly
+    /// interpolated, crosses zero along the edges between the eight voxel centers around it.
+    pub fn fill_smooth(&mut self, dimension: u8, voxels: &[Material], densities: &[f32]) {
+        let dim = isize::from(dimension);
+        let density = |p: na::Vector3<isize>| densities[margin_index(dim, p)];
+        self.build(
+            dim,
+            |p| {
+                if density(p) > 0.0 {
+                    // Dense voxels that happen to be void still need some material to be drawn in
+                    match voxels[margin_index(dim, p)] {
+                        Material::Void => Material::Stone,
+                        x => x,
+                    }
+                } else {
+                    Material::Void
+                }
+            },
+            |corner| smooth_vertex(dim, density, corner),
+        );
+    }
+
+    /// Replace the contents of `self` with the faces of the solid voxels `material` reports, with
+    /// corners placed by `position`
+    fn build(
+        &mut self,
+        dim: isize,
+        material: impl Fn(na::Vector3<isize>) -> Material,
+        position: impl Fn(na::Vector3<isize>) -> [f32; 3],
+    ) {
+        self.vertices.clear();
+        self.normals.clear();
+        self.materials.clear();
+        self.faces.clear();
+        // Vertices are shared between faces within a chunk
+        let mut indices = FxHashMap::<na::Vector3<isize>, u32>::default();
+        for_each_face(
+            dim,
+            |p| !material(p).is_void(),
+            |voxel, corners| {
+                let mut face = [0; 4];
+                for (out, &corner) in face.iter_mut().zip(&corners) {
+                    let ChunkMesh {
+                        ref mut vertices,
+                        ref mut normals,
+                        ref mut materials,
+                        ..
+                    } = *self;
+                    *out = *indices.entry(corner).or_insert_with(|| {
+                        vertices.push(position(corner));
+                        normals.push([0.0; 3]);
+                        materials.push(material(voxel));
+                        (vertices.len() - 1) as u32
+                    });
+                }
+                self.faces.push(face);
+            },
+        );
+
+        // Weight each face's contribution to its corners' normals by its area
+        for face in &self.faces {
+            let corner = |i: usize| na::Vector3::from(self.vertices[face[i] as usize]);
+            let normal = (corner(2) - corner(0)).cross(&(corner(3) - corner(1))) / 2.0;
+            for &index in face {
+                let sum = &mut self.normals[index as usize];
+                *sum = (na::Vector3::from(*sum) + normal).into();
+            }
+        }
+        for normal in &mut self.normals {
+            // Corners squeezed together by smoothing may have no well-defined normal, left zero
+            let n = na::Vector3::from(*normal);
+            if let Some(n) = na::Unit::try_new(n, 1e-6) {
+                *normal = n.into_inner().into();
+            }
+        }
+    }
+}
+
+/// Call `face` with the coordinates of each solid voxel of the chunk that has a void neighbor,
+/// and the corners of the face between them, counterclockwise when viewed from the void side
+///
+/// Coordinates exclude margins, so `solid` is also asked about voxels at -1 and `dim`.
+fn for_each_face(
+    dim: isize,
+    solid: impl Fn(na::Vector3<isize>) -> bool,
+    mut face: impl FnMut(na::Vector3<isize>, [na::Vector3<isize>; 4]),
+) {
+    for z in 0..dim {
+        for y in 0..dim {
+            for x in 0..dim {
+                let voxel = na::Vector3::new(x, y, z);
+                if !solid(voxel) {
+                    continue;
+                }
+                for axis in 0..3 {
+                    for &positive in &[false, true] {
+                        let mut step = na::Vector3::zeros();
+                        step[axis] = if positive { 1 } else { -1 };
+                        if solid(voxel + step) {
+                            continue;
+                        }
+                        let (mut u, mut v) = (na::Vector3::zeros(), na::Vector3::zeros());
+                        u[(axis + 1) % 3] = 1;
+                        v[(axis + 2) % 3] = 1;
+                        let base = if positive { voxel + step } else { voxel };
+                        let mut corners = [base, base + u, base + u + v, base + v];
+                        if !positive {
+                            corners.reverse();
+                        }
+                        face(voxel, corners);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Position of the surface nets vertex for the lattice point `corner`
+fn smooth_vertex(
+    dim: isize,
+    density: impl Fn(na::Vector3<isize>) -> f32,
+    corner: na::Vector3<isize>,
+) -> [f32; 3] {
+    let mut sum = na::Vector3::<f32>::zeros();
+    let mut count = 0;
+    for axis in 0..3 {
+        let (mut u, mut v) = (na::Vector3::zeros(), na::Vector3::zeros());
+        u[(axis + 1) % 3] = 1;
+        v[(axis + 2) % 3] = 1;
+        for &offset in &[na::zero(), u, v, u + v] {
+            let a = corner - na::Vector3::repeat(1) + offset;
+            let mut b = a;
+            b[axis] += 1;
+            let (da, db) = (density(a), density(b));
+            if (da > 0.0) == (db > 0.0) {
+                continue;
+            }
+            // Voxel centers lie halfway between lattice points
+            let mut crossing = a.map(|x| x as f32 + 0.5);
+            crossing[axis] += da / (da - db);
+            sum += crossing;
+            count += 1;
+        }
+    }
+    // The edge crossed by the face this corner belongs to is among those considered, so `count`
+    // is never zero
+    let p = sum / count as f32;
+    // Vertices beyond the chunk's boundary would be carried elsewhere by this chunk's transform
+    // than by the neighbor's, opening cracks, whereas both agree on the boundary itself
+    let p = p.map(|x| x.max(0.0).min(dim as f32));
+    [p.x, p.y, p.z]
+}
+
+/// Index of the voxel at `p` in a chunk's data, including margins
+fn margin_index(dim: isize, p: na::Vector3<isize>) -> usize {
+    // Length (of cube sides) with margins
+    let lwm = dim + 2;
+    ((p.x + 1) + (p.y + 1) * lwm + (p.z + 1) * lwm.pow(2)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_sphere() {
+        const DIMENSION: u8 = 16;
+        const RADIUS: f32 = 5.0;
+        let dim = isize::from(DIMENSION);
+        let center = na::Vector3::repeat(f32::from(DIMENSION) / 2.0);
+        let lwm = usize::from(DIMENSION) + 2;
+        let mut densities = vec![0.0; lwm.pow(3)];
+        for z in -1..=dim {
+            for y in -1..=dim {
+                for x in -1..=dim {
+                    let p = na::Vector3::new(x, y, z);
+                    let sample = p.map(|x| x as f32 + 0.5);
+                    densities[margin_index(dim, p)] = RADIUS - (sample - center).norm();
+                }
+            }
+        }
+        let voxels = vec![Material::Dirt; lwm.pow(3)];
+        let mut mesh = ChunkMesh::default();
+        mesh.fill_smooth(DIMENSION, &voxels, &densities);
+        assert!(!mesh.faces.is_empty());
+        for (vertex, normal) in mesh.vertices.iter().zip(&mesh.normals) {
+            let offset = na::Vector3::from(*vertex) - center;
+            let distance = offset.norm();
+            assert!(
+                (distance - RADIUS).abs() < 0.1,
+                "{:?} is {} from the center",
+                vertex,
+                distance
+            );
+            // Normals point roughly away from the center
+            assert!(na::Vector3::from(*normal).dot(&(offset / distance)) > 0.8);
+        }
+        assert!(mesh.materials.iter().all(|&x| x == Material::Dirt));
+        // Closed, with every face outward-facing
+        let mut edges = FxHashMap::default();
+        for face in &mesh.faces {
+            let corners = face
+                .iter()
+                .map(|&i| na::Vector3::from(mesh.vertices[i as usize]))
+                .collect::<Vec<_>>();
+            let normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+            let middle = corners.iter().sum::<na::Vector3<f32>>() / 4.0;
+            assert!(normal.dot(&(middle - center)) > 0.0);
+            for i in 0..4 {
+                *edges.entry((face[i], face[(i + 1) % 4])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn smooth_within_chunk() {
+        const DIMENSION: u8 = 4;
+        let lwm = usize::from(DIMENSION) + 2;
+        // Solid below a slope, extending into the margins
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        for z in 0..lwm {
+            for y in 0..lwm {
+                for x in 0..lwm {
+                    if y < x {
+                        voxels[x + y * lwm + z * lwm.pow(2)] = Material::Dirt;
+                    }
+                }
+            }
+        }
+        let blocky = ChunkMesh::extract_styled(Style::Blocky, DIMENSION, &voxels);
+        let smooth = ChunkMesh::extract_styled(Style::Smooth, DIMENSION, &voxels);
+        // The same faces, with corners moved
+        assert_eq!(smooth.faces, blocky.faces);
+        assert_ne!(smooth.vertices, blocky.vertices);
+        // Vertices never leave the chunk, where a neighbor's transform would disagree
+        for vertex in &smooth.vertices {
+            assert!(vertex
+                .iter()
+                .all(|&x| x >= 0.0 && x <= f32::from(DIMENSION)));
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Round-trip time and server clock offset estimation
+//!
+//! Each `proto::Pong` yields one sample of both, computed as in NTP: the server's time between
+//! receiving the ping and replying is excluded from the round trip, and the offset assumes the
+//! trip each way took equally long. Samples are smoothed as in TCP's retransmission timer (RFC
+//! 6298), which also tracks the mean deviation of round-trip time as a measure of jitter.
+
+use std::time::Duration;
+
+use common::proto::Pong;
+
+/// Weight of each new sample in the smoothed estimates
+const ALPHA: f64 = 1.0 / 8.0;
+/// Weight of each new sample in the smoothed round-trip time deviation
+const BETA: f64 = 1.0 / 4.0;
+
+pub struct ClockSync {
+    /// Smoothed round-trip time, in microseconds
+    rtt: f64,
+    /// Smoothed mean deviation of round-trip time, in microseconds
+    rtt_deviation: f64,
+    /// Smoothed server clock minus client clock, in microseconds
+    offset: f64,
+    samples: u32,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self {
+            rtt: 0.0,
+            rtt_deviation: 0.0,
+            offset: 0.0,
+            samples: 0,
+        }
+    }
+
+    /// Incorporate `pong`, received at client time `now`
+    pub fn update(&mut self, pong: &Pong, now: u64) {
+        let (t0, t1, t2, t3) = (
+            pong.client_time as f64,
+            pong.received as f64,
+            pong.sent as f64,
+            now as f64,
+        );
+        let rtt = ((t3 - t0) - (t2 - t1)).max(0.0);
+        let offset = ((t1 - t0) + (t2 - t3)) / 2.0;
+        if self.samples == 0 {
+            self.rtt = rtt;
+            self.rtt_deviation = rtt / 2.0;
+            self.offset = offset;
+        } else {
+            self.rtt_deviation += BETA * ((self.rtt - rtt).abs() - self.rtt_deviation);
+            self.rtt += ALPHA * (rtt - self.rtt);
+            self.offset += ALPHA * (offset - self.offset);
+        }
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// Smoothed round-trip time, if any samples have been taken
+    pub fn rtt(&self) -> Option<Duration> {
+        self.estimate(self.rtt)
+            .map(|x| Duration::from_micros(x as u64))
+    }
+
+    /// Smoothed mean deviation of round-trip time, or zero if no samples have been taken
+    pub fn jitter(&self) -> Duration {
+        Duration::from_micros(self.rtt_deviation as u64)
+    }
+
+    /// Smoothed estimate of the server's clock minus ours, in seconds
+    pub fn offset(&self) -> Option<f64> {
+        self.estimate(self.offset).map(|x| x * 1e-6)
+    }
+
+    fn estimate(&self, x: f64) -> Option<f64> {
+        if self.samples == 0 {
+            None
+        } else {
+            Some(x)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges() {
+        // Each way takes 40ms, and the server's clock is 5s ahead
+        const DELAY: u64 = 40_000;
+        const OFFSET: u64 = 5_000_000;
+        let mut clock = ClockSync::new();
+        assert!(clock.rtt().is_none());
+        assert!(clock.offset().is_none());
+        assert_eq!(clock.jitter(), Duration::from_secs(0));
+
+        let mut now = 0;
+        for i in 0..200 {
+            // The server takes a varying amount of time to reply, which mustn't count
+            let hold = (i % 7) * 10_000;
+            // A single packet is badly delayed on the way back
+            let extra = if i == 100 { 500_000 } else { 0 };
+            let received = now + DELAY + OFFSET;
+            let pong = Pong {
+                client_time: now,
+                received,
+                sent: received + hold,
+            };
+            now += 2 * DELAY + hold + extra;
+            clock.update(&pong, now);
+            now += 1_000_000;
+        }
+
+        let rtt = clock.rtt().unwrap().as_micros() as i64;
+        assert!((rtt - 2 * DELAY as i64).abs() < 100, "RTT {}us", rtt);
+        let offset = clock.offset().unwrap();
+        assert!((offset - 5.0).abs() < 1e-4, "offset {}s", offset);
+        assert!(clock.jitter() < Duration::from_millis(1));
+    }
+}
@@ -3,18 +3,61 @@ use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use serde::Deserialize;
 use tracing::{debug, error, info};
 
-use common::{SimConfig, SimConfigRaw};
+use crate::chunk_mesh::Style;
+use crate::input::{Bindings, RawBindings};
+use common::{SimConfig, SimConfigRaw, WorldId};
 
 pub struct Config {
     pub name: Arc<str>,
+    /// Secret for the server's account named `name`, if it has one
+    ///
+    /// Sent to whichever server the client connects to, since server certificates aren't verified.
+    pub token: Option<String>,
     pub data_dir: PathBuf,
     pub chunk_load_parallelism: u32,
+    /// Threads that generate terrain and load assets, or `None` for one per CPU core
+    ///
+    /// A single thread generates chunks one at a time, in the order they're requested, which is
+    /// easier to debug. The terrain generated is the same regardless.
+    pub worldgen_threads: Option<usize>,
+    /// Threads that extract surfaces on the CPU when exporting terrain
+    pub meshing_threads: usize,
+    /// Time over which newly loaded chunks fade in
+    pub chunk_fade: Duration,
+    /// Bytes of voxel data to keep loaded before discarding the least recently rendered chunks
+    pub voxel_memory_cap: usize,
+    /// Linear RGB color of the sky directly above the viewer
+    pub horizon_top: [f32; 3],
+    /// Linear RGB color of the sky directly below the viewer
+    pub horizon_bottom: [f32; 3],
     pub server: Option<SocketAddr>,
+    /// World to join on the server
+    pub world: WorldId,
+    /// Frame of reference in which movement input is interpreted
+    pub movement_frame: MovementFrame,
+    /// Whether to continuously level the camera against the terrain, cancelling the roll that
+    /// moving around curved paths otherwise introduces
+    pub stabilize_roll: bool,
+    /// Estimated relative error of the view transform beyond which to warn that precision is
+    /// degraded
+    pub precision_warning: f32,
+    /// Whether to bob the camera up and down in time with movement
+    pub head_bob: bool,
+    /// Whether to briefly widen the field of view when accelerating
+    pub fov_kick: bool,
+    /// Shape of terrain surfaces, as drawn and as exported
+    pub terrain_style: Style,
+    /// Distance in meters beyond which chunks are drawn at half resolution, halving again at twice
+    /// that distance, or 0 to always draw full resolution
+    pub lod_distance: f32,
+    /// Which key triggers each action
+    pub bindings: Bindings,
     pub local_simulation: SimConfig,
 }
 
@@ -25,10 +68,26 @@ impl Config {
         // Read and parse config file
         let RawConfig {
             name,
+            token,
             data_dir,
             local_simulation,
             chunk_load_parallelism,
+            worldgen_threads,
+            meshing_threads,
+            chunk_fade_ms,
+            voxel_memory_cap_mb,
+            horizon_top,
+            horizon_bottom,
             server,
+            world,
+            movement_frame,
+            stabilize_roll,
+            precision_warning,
+            head_bob,
+            fov_kick,
+            terrain_style,
+            lod_distance,
+            bindings,
         } = match fs::read(&path) {
             Ok(data) => {
                 info!("found config at {}", path.display());
@@ -52,10 +111,34 @@ impl Config {
         // Massage into final form
         Config {
             name: name.unwrap_or_else(|| whoami::user().into()),
+            token,
             data_dir: data_dir.unwrap_or_else(|| dirs.data_dir().into()),
             chunk_load_parallelism: chunk_load_parallelism.unwrap_or(256),
+            worldgen_threads: worldgen_threads.filter(|&n| n > 0),
+            meshing_threads: meshing_threads.unwrap_or(4),
+            chunk_fade: Duration::from_millis(chunk_fade_ms.unwrap_or(300).into()),
+            voxel_memory_cap: voxel_memory_cap_mb.unwrap_or(1024) as usize * 1024 * 1024,
+            horizon_top: horizon_top.unwrap_or([0.5, 0.65, 0.9]),
+            horizon_bottom: horizon_bottom.unwrap_or([0.15, 0.2, 0.3]),
             server,
-            local_simulation: SimConfig::from_raw(&local_simulation),
+            world: world.unwrap_or(WorldId::DEFAULT),
+            movement_frame: movement_frame.unwrap_or(MovementFrame::View),
+            stabilize_roll: stabilize_roll.unwrap_or(false),
+            precision_warning: precision_warning.unwrap_or(1e-4),
+            head_bob: head_bob.unwrap_or(false),
+            fov_kick: fov_kick.unwrap_or(false),
+            terrain_style: terrain_style.unwrap_or_default(),
+            lod_distance: lod_distance.unwrap_or(60.0),
+            bindings: Bindings::new(&bindings.overrides()).unwrap_or_else(|e| {
+                error!("ignoring key bindings: {}", e);
+                Bindings::default()
+            }),
+            local_simulation: SimConfig::from_raw(&local_simulation.migrate().unwrap_or_else(
+                |e| {
+                    error!("ignoring local simulation config: {}", e);
+                    SimConfigRaw::default()
+                },
+            )),
         }
     }
 
@@ -81,14 +164,41 @@ impl Config {
     }
 }
 
+/// How movement input is oriented
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementFrame {
+    /// "Forward" is wherever the player is looking
+    View,
+    /// "Forward" is fixed relative to the character's body, regardless of where the player looks
+    World,
+}
+
 /// Data as parsed directly out of the config file
 #[derive(Deserialize, Default)]
 #[serde(deny_unknown_fields)]
 struct RawConfig {
     name: Option<Arc<str>>,
+    token: Option<String>,
     data_dir: Option<PathBuf>,
     chunk_load_parallelism: Option<u32>,
+    worldgen_threads: Option<usize>,
+    meshing_threads: Option<usize>,
+    chunk_fade_ms: Option<u32>,
+    voxel_memory_cap_mb: Option<u32>,
+    horizon_top: Option<[f32; 3]>,
+    horizon_bottom: Option<[f32; 3]>,
     server: Option<SocketAddr>,
+    world: Option<WorldId>,
+    movement_frame: Option<MovementFrame>,
+    stabilize_roll: Option<bool>,
+    precision_warning: Option<f32>,
+    head_bob: Option<bool>,
+    fov_kick: Option<bool>,
+    terrain_style: Option<Style>,
+    lod_distance: Option<f32>,
+    #[serde(default)]
+    bindings: RawBindings,
     #[serde(default)]
     local_simulation: SimConfigRaw,
 }
@@ -0,0 +1,153 @@
+//! Export of loaded terrain to standard mesh formats
+//!
+//! Hyperbolic space can't be represented faithfully by a Euclidean mesh, so exported geometry uses
+//! the Klein (projective) model relative to the node the export was centered on: straight lines
+//! remain straight, but distances and angles are increasingly distorted away from the center, and
+//! the entire world fits within the unit ball.
+
+use std::io::{self, Write};
+
+use crate::chunk_mesh::{ChunkMesh, Style};
+use common::{
+    dodeca::Vertex,
+    math,
+    node::{Chunk, DualGraph, VoxelData},
+    parallel,
+    proto::Position,
+};
+
+/// Quad mesh in Klein coordinates
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<na::Point3<f32>>,
+    /// Indices into `vertices`, counterclockwise when viewed from outside the surface
+    pub faces: Vec<[u32; 4]>,
+}
+
+impl Mesh {
+    /// Extract the surfaces of all populated chunks in nodes within `distance` of `start`
+    ///
+    /// Surfaces are shaped according to `style`. Extraction is spread across up to `threads`
+    /// threads, without affecting the result.
+    pub fn build(
+        graph: &DualGraph,
+        dimension: u8,
+        style: Style,
+        start: &Position,
+        distance: f64,
+        threads: usize,
+    ) -> Self {
+        let mut chunks = Vec::new();
+        let mut transforms = Vec::new();
+        for (node, node_transform) in graph.nearby_nodes(start, distance) {
+            let node = match *graph.get(node) {
+                Some(ref x) => x,
+                None => continue,
+            };
+            for vertex in Vertex::iter() {
+                if let Chunk::Populated {
+                    voxels: VoxelData::Dense(ref data),
+                    ..
+                } = node.chunks[vertex]
+                {
+                    chunks.push(data.clone());
+                    transforms.push(node_transform * vertex.chunk_to_node().map(|x| x as f32));
+                }
+            }
+        }
+        let chunks = parallel::map(chunks, threads, move |data| {
+            ChunkMesh::extract_styled(style, dimension, &data)
+        });
+        let mut mesh = Mesh::default();
+        for (chunk, transform) in chunks.into_iter().zip(&transforms) {
+            mesh.add_chunk(dimension, &chunk, transform);
+        }
+        mesh
+    }
+
+    /// Append `chunk`, carried into the exported space by `transform`
+    ///
+    /// `transform` maps from chunk coordinates, scaled to [0..1]^3, to the exported space.
+    fn add_chunk(&mut self, dimension: u8, chunk: &ChunkMesh, transform: &na::Matrix4<f32>) {
+        // Transforms which reverse winding must have their faces reversed to remain outward-facing
+        let flip = math::parity(transform);
+        let offset = self.vertices.len() as u32;
+        for vertex in &chunk.vertices {
+            let p = na::Vector3::from(*vertex) / f32::from(dimension);
+            let p = transform * p.push(1.0);
+            self.vertices.push(na::Point3::from(p.xyz() / p.w));
+        }
+        for face in &chunk.faces {
+            let mut face = [
+                face[0] + offset,
+                face[1] + offset,
+                face[2] + offset,
+                face[3] + offset,
+            ];
+            if flip {
+                face.reverse();
+            }
+            self.faces.push(face);
+        }
+    }
+
+    /// Write in Wavefront OBJ format
+    pub fn write_obj<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "# hypermine export, Klein model coordinates")?;
+        for v in &self.vertices {
+            writeln!(out, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for f in &self.faces {
+            // OBJ indices are 1-based
+            writeln!(out, "f {} {} {} {}", f[0] + 1, f[1] + 1, f[2] + 1, f[3] + 1)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{graph::NodeId, node::Node, world::Material, worldgen::NodeState, Chunks};
+
+    #[test]
+    fn single_voxel() {
+        const DIMENSION: u8 = 4;
+        let mut graph = DualGraph::new();
+        let mut voxels = vec![Material::Void; (usize::from(DIMENSION) + 2).pow(3)];
+        // Voxel (1, 1, 1), accounting for margins
+        let lwm = usize::from(DIMENSION) + 2;
+        voxels[2 + 2 * lwm + 2 * lwm.pow(2)] = Material::Stone;
+        let mut chunks = Chunks::default();
+        chunks[Vertex::A] = Chunk::populated(DIMENSION, VoxelData::Dense(voxels.into()));
+        *graph.get_mut(NodeId::ROOT) = Some(Node {
+            state: NodeState::root(),
+            chunks,
+        });
+
+        let mesh = Mesh::build(
+            &graph,
+            DIMENSION,
+            Style::Blocky,
+            &Position::origin(),
+            1.0,
+            1,
+        );
+        let mut obj = Vec::new();
+        mesh.write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let vertices = obj.lines().filter(|l| l.starts_with("v ")).count();
+        let faces = obj.lines().filter(|l| l.starts_with("f ")).count();
+        assert_eq!(vertices, 8);
+        assert_eq!(faces, 6);
+        for line in obj.lines().filter(|l| l.starts_with("v ")) {
+            let coords = line[2..]
+                .split(' ')
+                .map(|x| x.parse::<f32>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(coords.len(), 3);
+            // Klein model coordinates lie within the unit ball
+            assert!(na::Vector3::new(coords[0], coords[1], coords[2]).norm() < 1.0);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Placement of sprites that always face the camera, such as nameplates
+//!
+//! In hyperbolic space the way from a sprite to the camera isn't the difference of their
+//! coordinates, and apparent size doesn't fall off in inverse proportion to distance. Billboards
+//! are therefore turned towards the camera along the geodesic joining them, in the sprite's own
+//! frame, and sized in terms of hyperbolic lengths.
+
+use common::math;
+
+/// How large a billboard should be
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BillboardSize {
+    /// Full width in absolute units, so that the sprite shrinks with distance like any object
+    Absolute(f32),
+    /// Full width in radians as seen from the camera, so that e.g. text remains legible
+    Angular(f32),
+}
+
+/// Transform placing a unit quad, centered on the origin in the xy plane and facing +z, at
+/// `position` facing `camera`
+///
+/// The quad's +y edge leans towards `up`, a direction in the frame of `translate(&origin(),
+/// position)` as used by `math::parallel_transport`.
+pub fn billboard(
+    position: &na::Vector4<f32>,
+    camera: &na::Vector4<f32>,
+    up: &na::Vector3<f32>,
+    size: BillboardSize,
+) -> na::Matrix4<f32> {
+    // The camera, as seen from the sprite
+    let camera = math::translate(position, &math::origin()) * camera;
+    let normal = na::Unit::try_new(camera.xyz(), 1e-6).unwrap_or_else(na::Vector3::z_axis);
+    let right = [*up, na::Vector3::y(), na::Vector3::x()]
+        .iter()
+        .find_map(|x| na::Unit::try_new(x.cross(&*normal), 1e-3))
+        .unwrap();
+    let top = normal.cross(&*right);
+
+    // Half-width in the Klein model centered on the sprite, where the quad's corners lie
+    let half_width = match size {
+        BillboardSize::Absolute(width) => (width / 2.0).tanh(),
+        BillboardSize::Angular(angle) => {
+            // Right triangle between sprite, camera, and the middle of the quad's edge
+            let distance = math::distance(&math::origin(), &camera);
+            ((angle / 2.0).tan() * distance.sinh()).min(MAX_HALF_WIDTH)
+        }
+    };
+    let scale = 2.0 * half_width;
+    math::translate(&math::origin(), position)
+        * na::Matrix4::from_columns(&[
+            (right.into_inner() * scale).push(0.0),
+            (top * scale).push(0.0),
+            normal.into_inner().push(0.0),
+            na::Vector4::w(),
+        ])
+}
+
+/// Bound on the Klein half-width of a billboard, short of the ideal boundary at 1, reached by
+/// distant billboards of fixed angular size
+const MAX_HALF_WIDTH: f32 = 0.999;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(direction: na::Vector3<f32>, distance: f32) -> na::Vector4<f32> {
+        math::translate_along(&na::Unit::new_normalize(direction), distance) * math::origin()
+    }
+
+    /// `v` scaled to unit length under the Minkowski inner product
+    fn unit(v: na::Vector4<f32>) -> na::Vector4<f32> {
+        v / math::mip(&v, &v).sqrt()
+    }
+
+    #[test]
+    fn faces_along_geodesic() {
+        let sprite = point(na::Vector3::x(), 1.0);
+        let camera = point(na::Vector3::new(0.3, 1.0, -0.5), 0.8);
+        let transform = billboard(
+            &sprite,
+            &camera,
+            &na::Vector3::y(),
+            BillboardSize::Absolute(0.2),
+        );
+        assert!((transform * math::origin() - sprite).amax() < 1e-5);
+
+        // The normal is tangent to the geodesic from sprite to camera
+        let normal = unit(transform * na::Vector4::z());
+        let geodesic = unit(camera + sprite * math::mip(&sprite, &camera));
+        assert!((normal - geodesic).amax() < 1e-4);
+    }
+
+    #[test]
+    fn sized_hyperbolically() {
+        let sprite = point(na::Vector3::new(1.0, 0.2, 0.0), 3.0);
+        let camera = math::origin();
+        let edges = |size| {
+            let transform = billboard(&sprite, &camera, &na::Vector3::y(), size);
+            (
+                transform * na::Vector4::new(-0.5, 0.0, 0.0, 1.0),
+                transform * na::Vector4::new(0.5, 0.0, 0.0, 1.0),
+            )
+        };
+
+        let (left, right) = edges(BillboardSize::Absolute(0.4));
+        assert!((math::distance(&sprite, &left) - 0.2).abs() < 1e-4);
+        assert!((math::distance(&left, &right) - 0.4).abs() < 1e-4);
+
+        let (left, right) = edges(BillboardSize::Angular(0.1));
+        let angle = left.xyz().normalize().dot(&right.xyz().normalize()).acos();
+        assert!((angle - 0.1).abs() < 1e-4, "{}", angle);
+    }
+}
@@ -0,0 +1,363 @@
+//! Debug overlay outlining the dodecahedral cells of the tiling and the chunks within them
+//!
+//! Every edge is drawn as a chain of short segments along its geodesic, each expanded into a
+//! screen-aligned quad with antialiased edges. The same geometry, in node coordinates, is drawn
+//! once for each nearby node.
+
+use std::mem;
+
+use ash::{version::DeviceV1_0, vk, Device};
+use lahar::DedicatedMapping;
+use memoffset::offset_of;
+use vk_shader_macros::include_glsl;
+
+use super::Base;
+use common::{defer, dodeca::Vertex, math};
+
+const VERT: &[u32] = include_glsl!("shaders/boundaries.vert");
+const FRAG: &[u32] = include_glsl!("shaders/boundaries.frag");
+
+/// Number of segments each edge is divided into, so that depth and fading follow the geodesic
+const PIECES_PER_EDGE: usize = 8;
+const CELL_COLOR: [f32; 4] = [1.0, 0.8, 0.2, 0.9];
+const CHUNK_COLOR: [f32; 4] = [0.3, 0.7, 1.0, 0.5];
+
+pub struct Boundaries {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    segments: DedicatedMapping<[Segment]>,
+}
+
+impl Boundaries {
+    #[allow(clippy::unneeded_field_pattern)] // Silence offset_of warnings nonsense
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Define the outward-facing interface of the shaders, incl. uniforms, samplers, etc.
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&[gfx.common_layout])
+                        .push_constant_ranges(&[vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        }]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(
+                            &vk::PipelineVertexInputStateCreateInfo::builder()
+                                .vertex_binding_descriptions(&[vk::VertexInputBindingDescription {
+                                    binding: 0,
+                                    stride: mem::size_of::<Segment>() as u32,
+                                    input_rate: vk::VertexInputRate::INSTANCE,
+                                }])
+                                .vertex_attribute_descriptions(&[
+                                    vk::VertexInputAttributeDescription {
+                                        location: 0,
+                                        binding: 0,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: offset_of!(Segment, a) as u32,
+                                    },
+                                    vk::VertexInputAttributeDescription {
+                                        location: 1,
+                                        binding: 0,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: offset_of!(Segment, b) as u32,
+                                    },
+                                    vk::VertexInputAttributeDescription {
+                                        location: 2,
+                                        binding: 0,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: offset_of!(Segment, color) as u32,
+                                    },
+                                ]),
+                        )
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        // Drawn over everything, after fog, so boundaries can be seen through
+                        // terrain
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(false)
+                                .depth_write_enable(false),
+                        )
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::TRUE,
+                                    src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                    color_blend_op: vk::BlendOp::ADD,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(1)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("boundaries"));
+
+            // Clean up the shaders explicitly, so the defer guards don't hold onto references we're
+            // moving into `Self` to be returned
+            v_guard.invoke();
+            f_guard.invoke();
+
+            let geometry = node_segments();
+            let mut segments = DedicatedMapping::zeroed_array(
+                device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                geometry.len(),
+            );
+            segments.copy_from_slice(&geometry);
+            gfx.set_name(segments.buffer(), cstr!("boundary segments"));
+
+            Self {
+                pipeline_layout,
+                pipeline,
+                segments,
+            }
+        }
+    }
+
+    /// Record commands to outline the node whose coordinates `transform` maps to the view's node
+    pub unsafe fn draw(
+        &mut self,
+        device: &Device,
+        common_ds: vk::DescriptorSet,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        transform: &na::Matrix4<f32>,
+    ) {
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[common_ds],
+            &[],
+        );
+        let constants = PushConstants {
+            transform: *transform,
+            viewport: na::Vector2::new(extent.width as f32, extent.height as f32),
+        };
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            super::as_bytes(&constants),
+        );
+        device.cmd_bind_vertex_buffers(cmd, 0, &[self.segments.buffer()], &[0]);
+        device.cmd_draw(cmd, 6, self.segments.len() as u32, 0, 0);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.segments.destroy(device);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    transform: na::Matrix4<f32>,
+    /// Dimensions of the viewport in pixels
+    viewport: na::Vector2<f32>,
+}
+
+/// A piece of a boundary, between two points on the hyperboloid in node coordinates
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Segment {
+    a: na::Vector4<f32>,
+    b: na::Vector4<f32>,
+    color: na::Vector4<f32>,
+}
+
+/// Segments outlining a node's chunks, and so also the node's dodecahedron
+///
+/// Edges shared by several chunks are only produced once.
+fn node_segments() -> Vec<Segment> {
+    let mut edges = Vec::<[na::Vector4<f64>; 2]>::new();
+    let mut segments = Vec::new();
+    for vertex in Vertex::iter() {
+        let chunk_to_node = vertex.chunk_to_node();
+        for axis in 0..3 {
+            for corner in 0..4 {
+                // The other two coordinates, which are constant along the edge
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let mut start = na::Vector4::new(0.0, 0.0, 0.0, 1.0);
+                start[u] = f64::from(corner & 1);
+                start[v] = f64::from(corner >> 1);
+                let mut end = start;
+                end[axis] = 1.0;
+                let endpoints = [
+                    math::lorentz_normalize(&(chunk_to_node * start)),
+                    math::lorentz_normalize(&(chunk_to_node * end)),
+                ];
+                let duplicate = edges.iter().any(|x| {
+                    (x[0] - endpoints[0]).norm() + (x[1] - endpoints[1]).norm() < 1e-6
+                        || (x[0] - endpoints[1]).norm() + (x[1] - endpoints[0]).norm() < 1e-6
+                });
+                if duplicate {
+                    continue;
+                }
+                edges.push(endpoints);
+                // Chunk edges where two coordinates are 1 lie where two of the dodecahedron's
+                // sides meet
+                let color = if corner == 3 { CELL_COLOR } else { CHUNK_COLOR };
+                let points = geodesic(&endpoints[0], &endpoints[1], PIECES_PER_EDGE);
+                segments.extend(points.windows(2).map(|pair| Segment {
+                    a: na::convert(pair[0]),
+                    b: na::convert(pair[1]),
+                    color: color.into(),
+                }));
+            }
+        }
+    }
+    segments
+}
+
+/// `pieces + 1` points evenly spaced along the geodesic from `a` to `b`, inclusive
+fn geodesic(a: &na::Vector4<f64>, b: &na::Vector4<f64>, pieces: usize) -> Vec<na::Vector4<f64>> {
+    let distance = math::distance(a, b);
+    // Unit tangent at `a` towards `b`
+    let tangent = b + a * math::mip(a, b);
+    let tangent = tangent / math::mip(&tangent, &tangent).sqrt();
+    (0..=pieces)
+        .map(|i| {
+            let t = distance * i as f64 / pieces as f64;
+            a * t.cosh() + tangent * t.sinh()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_edge_follows_geodesic() {
+        let segments = node_segments();
+        let cell_color = na::Vector4::from(CELL_COLOR);
+        // A dodecahedron has 30 edges, each divided between the chunks at either end
+        assert_eq!(
+            segments.iter().filter(|x| x.color == cell_color).count(),
+            60 * PIECES_PER_EDGE
+        );
+
+        let start = segments.iter().position(|x| x.color == cell_color).unwrap();
+        let edge = &segments[start..start + PIECES_PER_EDGE];
+        let a = na::convert::<_, na::Vector4<f64>>(edge[0].a);
+        let b = na::convert::<_, na::Vector4<f64>>(edge[PIECES_PER_EDGE - 1].b);
+        // Geodesics are the intersections of the hyperboloid with planes through the origin, so
+        // every point on this one is a combination of `a` and `b`
+        let gram = na::Matrix2::new(
+            math::mip(&a, &a),
+            math::mip(&a, &b),
+            math::mip(&b, &a),
+            math::mip(&b, &b),
+        )
+        .try_inverse()
+        .unwrap();
+        for (i, segment) in edge.iter().enumerate() {
+            // Consecutive segments join up
+            if i > 0 {
+                assert!((segment.a - edge[i - 1].b).norm() < 1e-6);
+            }
+            for p in &[segment.a, segment.b] {
+                let p = na::convert::<_, na::Vector4<f64>>(*p);
+                assert!(
+                    (math::mip(&p, &p) + 1.0).abs() < 1e-5,
+                    "off the hyperboloid"
+                );
+                let weights = gram * na::Vector2::new(math::mip(&a, &p), math::mip(&b, &p));
+                let residual = p - a * weights.x - b * weights.y;
+                assert!(residual.norm() < 1e-5, "{} off geodesic by {}", p, residual);
+                // ...and lies between them
+                assert!(weights.x > -1e-5 && weights.y > -1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn geodesic_evenly_spaced() {
+        let a = math::origin();
+        let b = math::translate_along(&na::Vector3::y_axis(), 1.5) * math::origin();
+        let points = geodesic(&a, &b, 3);
+        assert!((points[3] - b).norm() < 1e-9);
+        for pair in points.windows(2) {
+            assert!((math::distance(&pair[0], &pair[1]) - 0.5).abs() < 1e-9);
+        }
+    }
+}
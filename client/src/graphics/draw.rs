@@ -5,9 +5,9 @@ use ash::{version::DeviceV1_0, vk};
 use lahar::Staged;
 use metrics::timing;
 
-use super::{fog, voxels, Base, Fog, Frustum, GltfScene, Meshes, Voxels};
-use crate::{sim, Asset, Config, Loader, Sim};
-use common::proto::{Character, Position};
+use super::{fog, voxels, Base, Boundaries, Fog, Frustum, GltfScene, Meshes, Voxels};
+use crate::{camera_effects::CameraEffects, occlusion::Occlusion, sim, Asset, Config, Loader, Sim};
+use common::{math, proto::Position};
 
 /// Manages rendering, independent of what is being rendered to
 pub struct Draw {
@@ -40,6 +40,9 @@ pub struct Draw {
     voxels: Option<Voxels>,
     meshes: Meshes,
     fog: Fog,
+    boundaries: Boundaries,
+    /// Whether to outline nearby nodes and chunks
+    show_boundaries: bool,
 
     /// Reusable storage for barriers that prevent races between image upload and read
     image_barriers: Vec<vk::ImageMemoryBarrier>,
@@ -48,10 +51,16 @@ pub struct Draw {
 
     /// Miscellany
     character_model: Asset<GltfScene>,
+    /// Chunks hidden from the viewer, so that entities within them can be skipped
+    occlusion: Occlusion,
 }
 
 /// Maximum number of simultaneous frames in flight
 const PIPELINE_DEPTH: u32 = 2;
+/// Most voxels of open space around the viewer searched for entity culling
+const OCCLUSION_LIMIT: usize = 1 << 15;
+/// Distance in meters around an entity's origin at which it counts as visible if any point is
+const ENTITY_CULLING_GRACE: f32 = 1.0;
 const TIMESTAMPS_PER_FRAME: u32 = 3;
 
 impl Draw {
@@ -178,6 +187,8 @@ impl Draw {
 
             let fog = Fog::new(&gfx);
 
+            let boundaries = Boundaries::new(&gfx);
+
             gfx.save_pipeline_cache();
 
             let character_model = loader.load(
@@ -203,11 +214,14 @@ impl Draw {
                 voxels: None,
                 meshes,
                 fog,
+                boundaries,
+                show_boundaries: false,
 
                 buffer_barriers: Vec::new(),
                 image_barriers: Vec::new(),
 
                 character_model,
+                occlusion: Occlusion::new(OCCLUSION_LIMIT),
             }
         }
     }
@@ -227,6 +241,11 @@ impl Draw {
         self.voxels = Some(voxels);
     }
 
+    /// Show or hide the outlines of nearby nodes and chunks
+    pub fn toggle_boundaries(&mut self) {
+        self.show_boundaries = !self.show_boundaries;
+    }
+
     /// Waits for a frame's worth of resources to become available for use in rendering a new frame
     ///
     /// Call before signaling the image_acquired semaphore or invoking `draw`.
@@ -260,9 +279,14 @@ impl Draw {
         extent: vk::Extent2D,
         present: vk::Semaphore,
         frustum: &Frustum,
+        effects: &CameraEffects,
     ) {
         let draw_started = Instant::now();
-        let view = sim.view();
+        let view = match sim.params() {
+            Some(params) => effects.apply(&sim.view(), params.meters_to_absolute),
+            None => sim.view(),
+        };
+        let (sun, daylight) = sim.sun();
         let projection = frustum.projection(0.01);
         let view_projection = projection.matrix() * view.local.try_inverse().unwrap();
         self.loader.drive();
@@ -430,7 +454,11 @@ impl Draw {
             );
         }
 
+        if let Some(chunk_size) = sim.params().map(|x| x.chunk_size) {
+            self.occlusion.update(&mut sim.graph, chunk_size, &view);
+        }
         if let Some(params) = sim.params() {
+            let grace = ENTITY_CULLING_GRACE * params.meters_to_absolute;
             for (node, transform) in sim
                 .graph
                 .nearby_nodes(&view, f64::from(self.cfg.local_simulation.view_distance))
@@ -440,16 +468,22 @@ impl Draw {
                         // Don't draw ourself
                         continue;
                     }
-                    let pos = sim
-                        .world
-                        .get::<Position>(entity)
+                    let local = sim
+                        .displayed_transform(entity)
                         .expect("positionless entity in graph");
+                    let position = Position { node, local };
+                    if !self
+                        .occlusion
+                        .visible(&sim.graph, params.chunk_size, &position, grace)
+                    {
+                        continue;
+                    }
                     if let Some(character_model) = self.loader.get(self.character_model) {
-                        if let Ok(ch) = sim.world.get::<Character>(entity) {
+                        if let Ok(look) = sim.world.get::<sim::Look>(entity) {
                             let transform = transform
-                                * pos.local
+                                * local
                                 * na::Matrix4::new_scaling(params.meters_to_absolute)
-                                * ch.orientation.to_homogeneous();
+                                * look.current().to_homogeneous();
                             for mesh in &character_model.0 {
                                 self.meshes
                                     .draw(device, state.common_ds, cmd, mesh, &transform);
@@ -464,6 +498,16 @@ impl Draw {
 
         self.fog.draw(device, state.common_ds, cmd);
 
+        if self.show_boundaries {
+            for (_, transform) in sim
+                .graph
+                .nearby_nodes(&view, f64::from(self.cfg.local_simulation.view_distance))
+            {
+                self.boundaries
+                    .draw(device, state.common_ds, cmd, extent, &transform);
+            }
+        }
+
         // Finish up
         device.cmd_end_render_pass(cmd);
         device.cmd_write_timestamp(
@@ -489,8 +533,13 @@ impl Draw {
             Uniforms {
                 view_projection,
                 inverse_projection: *projection.inverse().matrix(),
+                horizon_top: horizon_uniform(self.cfg.horizon_top, daylight),
+                horizon_bottom: horizon_uniform(self.cfg.horizon_bottom, daylight),
+                view_position: view.local * math::origin(),
+                sun,
                 fog_density: fog::density(self.cfg.local_simulation.view_distance, 1e-3, 5.0),
                 time: self.epoch.elapsed().as_secs_f32().fract(),
+                daylight,
             },
         );
 
@@ -552,6 +601,7 @@ impl Drop for Draw {
             device.destroy_pipeline_layout(self.common_pipeline_layout, None);
             self.fog.destroy(device);
             self.meshes.destroy(device);
+            self.boundaries.destroy(device);
             if let Some(mut voxels) = self.voxels.take() {
                 voxels.destroy(device);
             }
@@ -595,7 +645,28 @@ struct Uniforms {
     /// Camera projection matrix
     view_projection: na::Matrix4<f32>,
     inverse_projection: na::Matrix4<f32>,
+    /// Sky color directly above the viewer
+    horizon_top: na::Vector4<f32>,
+    /// Sky color directly below the viewer
+    horizon_bottom: na::Vector4<f32>,
+    /// Position of the viewer in local node space
+    view_position: na::Vector4<f32>,
+    /// Ideal point of the sun in local node space
+    sun: na::Vector4<f32>,
     fog_density: f32,
     /// Cycles through [0,1) once per second for simple animation effects
     time: f32,
+    /// Brightness of direct sunlight, from 0 at night to 1 at midday
+    daylight: f32,
+}
+
+/// Sky color dimmed at night, but never to pitch black so the horizon remains visible
+fn horizon_uniform(color: [f32; 3], daylight: f32) -> na::Vector4<f32> {
+    let brightness = 0.2 + 0.8 * daylight;
+    na::Vector4::new(
+        color[0] * brightness,
+        color[1] * brightness,
+        color[2] * brightness,
+        1.0,
+    )
 }
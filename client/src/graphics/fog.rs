@@ -153,3 +153,38 @@ impl Fog {
 pub fn density(distance: f32, transmission: f32, exponent: f32) -> f32 {
     transmission.recip().ln().powf(exponent.recip()) / distance
 }
+
+/// Color of the sky at a certain angle above the horizontal, in radians
+///
+/// Mirrors the computation in `fog.frag`: the color is interpolated linearly between `bottom`,
+/// directly below the viewer, and `top`, directly above.
+pub fn horizon_color(top: [f32; 3], bottom: [f32; 3], vertical_angle: f32) -> [f32; 3] {
+    let t = (vertical_angle / std::f32::consts::PI + 0.5).max(0.0).min(1.0);
+    let mix = |i: usize| bottom[i] + (top[i] - bottom[i]) * t;
+    [mix(0), mix(1), mix(2)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    #[test]
+    fn horizon_interpolation() {
+        let top = [1.0, 0.5, 0.0];
+        let bottom = [0.0, 0.5, 1.0];
+        assert_abs_diff_eq!(&horizon_color(top, bottom, FRAC_PI_2)[..], &top[..]);
+        assert_abs_diff_eq!(&horizon_color(top, bottom, -FRAC_PI_2)[..], &bottom[..]);
+        assert_abs_diff_eq!(
+            &horizon_color(top, bottom, 0.0)[..],
+            &[0.5, 0.5, 0.5][..],
+            epsilon = 1e-6
+        );
+        assert_abs_diff_eq!(
+            &horizon_color(top, bottom, FRAC_PI_4)[..],
+            &[0.75, 0.5, 0.25][..],
+            epsilon = 1e-6
+        );
+    }
+}
@@ -13,7 +13,7 @@ use futures_util::future::{try_join_all, BoxFuture, FutureExt};
 use lahar::{BufferRegionAlloc, DedicatedImage};
 use tracing::{error, trace};
 
-use super::{meshes::Vertex, Base, Mesh};
+use super::{meshes::Vertex, vertex_cache, Base, Mesh};
 use crate::loader::{Cleanup, LoadCtx, LoadFuture, Loadable};
 
 pub struct GlbFile {
@@ -280,17 +280,19 @@ async fn load_geom(
         }
     }
 
-    let indices = prim
+    let mut indices = prim
         .read_indices()
         .ok_or_else(|| anyhow!("indices missing"))?
-        .into_u32();
+        .into_u32()
+        .collect::<Vec<_>>();
+    vertex_cache::optimize(&mut indices);
     let index_count = indices.len();
     let mut i_staging = ctx
         .staging
         .alloc(index_count * 4)
         .await
         .ok_or_else(|| anyhow!("too large"))?;
-    for (idx, storage) in indices.zip(i_staging.chunks_exact_mut(4)) {
+    for (idx, storage) in indices.into_iter().zip(i_staging.chunks_exact_mut(4)) {
         storage.copy_from_slice(&idx.to_ne_bytes());
     }
 
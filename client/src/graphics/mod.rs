@@ -1,6 +1,8 @@
 #![allow(clippy::missing_safety_doc)] // Vulkan wrangling is categorically unsafe
 
 mod base;
+mod billboard;
+mod boundaries;
 mod core;
 mod draw;
 mod fog;
@@ -8,6 +10,7 @@ mod frustum;
 mod gltf_mesh;
 mod meshes;
 mod png_array;
+mod vertex_cache;
 pub mod voxels;
 mod window;
 
@@ -16,9 +19,11 @@ mod tests;
 
 pub use self::{
     base::Base,
+    billboard::{billboard, BillboardSize},
+    boundaries::Boundaries,
     core::Core,
     draw::Draw,
-    fog::Fog,
+    fog::{horizon_color, Fog},
     frustum::Frustum,
     gltf_mesh::{GlbFile, GltfScene},
     meshes::{Mesh, Meshes},
@@ -0,0 +1,201 @@
+//! Triangle reordering for GPU post-transform vertex cache efficiency
+//!
+//! GPUs cache the results of recent vertex shader invocations, so an indexed mesh whose triangles
+//! reuse recently referenced vertices shades fewer vertices. This implements Tom Forsyth's "Linear-
+//! Speed Vertex Cache Optimisation", which greedily emits the triangle whose vertices are most
+//! recently used, favoring vertices with few remaining triangles so that none are left stranded.
+//!
+//! Voxel surfaces extracted on the GPU are drawn without an index buffer, each face expanding to its
+//! own six vertices, and those extracted on the CPU are drawn in extraction order, so only indexed
+//! meshes such as those loaded from glTF benefit.
+
+/// Size of the simulated LRU cache. Real hardware varies, but the ordering is insensitive to the
+/// exact value.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+/// Score of the vertices of the most recently emitted triangle, deliberately low to avoid
+/// producing long strips that strand their neighbors
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Reorder the triangles of the triangle list `indices` to improve vertex cache hit rate
+///
+/// The set of triangles, and the winding of each, is preserved exactly.
+pub fn optimize(indices: &mut [u32]) {
+    let triangle_count = indices.len() / 3;
+    let vertex_count = indices.iter().max().map_or(0, |&x| x as usize + 1);
+
+    // Triangles referencing each vertex, stored contiguously by vertex, with each vertex's
+    // unemitted triangles first
+    let mut vertices = vec![VertexState::default(); vertex_count];
+    for &index in &indices[..triangle_count * 3] {
+        vertices[index as usize].live += 1;
+    }
+    let mut offset = 0;
+    for vertex in &mut vertices {
+        vertex.offset = offset;
+        offset += vertex.live as usize;
+        vertex.live = 0;
+    }
+    let mut adjacency = vec![0; offset];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            let vertex = &mut vertices[index as usize];
+            adjacency[vertex.offset + vertex.live as usize] = triangle as u32;
+            vertex.live += 1;
+        }
+    }
+    for vertex in &mut vertices {
+        vertex.score = score(None, vertex.live);
+    }
+
+    let mut scores = indices
+        .chunks_exact(3)
+        .map(|corners| corners.iter().map(|&i| vertices[i as usize].score).sum())
+        .collect::<Vec<f32>>();
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    // Most recently used vertices first, with room for a triangle's worth of overflow
+    let mut cache = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best = None;
+
+    for _ in 0..triangle_count {
+        // When no cached vertex has a triangle remaining, fall back to a full search
+        let triangle = best.unwrap_or_else(|| {
+            (0..triangle_count)
+                .filter(|&t| !emitted[t])
+                .max_by(|&a, &b| scores[a].partial_cmp(&scores[b]).unwrap())
+                .unwrap()
+        });
+        emitted[triangle] = true;
+        let corners = [
+            indices[triangle * 3],
+            indices[triangle * 3 + 1],
+            indices[triangle * 3 + 2],
+        ];
+        output.extend_from_slice(&corners);
+
+        for &index in &corners {
+            let vertex = &mut vertices[index as usize];
+            let live = &mut adjacency[vertex.offset..vertex.offset + vertex.live as usize];
+            let position = live.iter().position(|&t| t as usize == triangle).unwrap();
+            live.swap(position, live.len() - 1);
+            vertex.live -= 1;
+            cache.retain(|&x| x != index);
+        }
+        for &index in corners.iter().rev() {
+            cache.insert(0, index);
+        }
+
+        // Rescore every vertex whose cache position changed, and the triangles they're part of
+        best = None;
+        let mut best_score = -1.0;
+        for (position, &index) in cache.iter().enumerate() {
+            let vertex = &mut vertices[index as usize];
+            vertex.score = if position < CACHE_SIZE {
+                score(Some(position), vertex.live)
+            } else {
+                score(None, vertex.live)
+            };
+        }
+        for &index in &cache {
+            let vertex = &vertices[index as usize];
+            for &t in &adjacency[vertex.offset..vertex.offset + vertex.live as usize] {
+                let t = t as usize;
+                scores[t] = indices[t * 3..t * 3 + 3]
+                    .iter()
+                    .map(|&i| vertices[i as usize].score)
+                    .sum();
+                if scores[t] > best_score {
+                    best = Some(t);
+                    best_score = scores[t];
+                }
+            }
+        }
+        cache.truncate(CACHE_SIZE);
+    }
+
+    indices[..output.len()].copy_from_slice(&output);
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct VertexState {
+    /// Start of this vertex's triangles in the adjacency list
+    offset: usize,
+    /// Number of this vertex's triangles not yet emitted
+    live: u32,
+    score: f32,
+}
+
+/// Desirability of emitting a triangle using a vertex
+fn score(cache_position: Option<usize>, live: u32) -> f32 {
+    if live == 0 {
+        // Not used by any remaining triangle
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(x) if x < 3 => LAST_TRIANGLE_SCORE,
+        Some(x) => (1.0 - (x - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER),
+    };
+    // Prioritize vertices with few triangles left, so they can be finished off
+    cache_score + VALENCE_BOOST_SCALE * (live as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average cache miss ratio: vertex shader invocations per triangle with a FIFO cache
+    fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+        let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+        let mut misses = 0;
+        for &index in indices {
+            if cache.contains(&index) {
+                continue;
+            }
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_back();
+            }
+            cache.push_front(index);
+        }
+        misses as f32 / (indices.len() / 3) as f32
+    }
+
+    fn triangles(indices: &[u32]) -> Vec<&[u32]> {
+        let mut result = indices.chunks_exact(3).collect::<Vec<_>>();
+        result.sort_unstable();
+        result
+    }
+
+    #[test]
+    fn grid() {
+        // A square grid of quads, with triangles in a scattered order
+        const SIZE: u32 = 32;
+        let mut grid = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let corner = |dx, dy| (y + dy) * (SIZE + 1) + x + dx;
+                grid.extend_from_slice(&[corner(0, 0), corner(1, 0), corner(1, 1)]);
+                grid.extend_from_slice(&[corner(0, 0), corner(1, 1), corner(0, 1)]);
+            }
+        }
+        let count = grid.len() / 3;
+        let mut indices = Vec::with_capacity(grid.len());
+        for i in 0..count {
+            // 601 is coprime to the triangle count, so this visits every triangle once
+            let t = i * 601 % count;
+            indices.extend_from_slice(&grid[t * 3..t * 3 + 3]);
+        }
+        let before = acmr(&indices, 16);
+
+        optimize(&mut indices);
+        assert_eq!(triangles(&indices), triangles(&grid));
+        let after = acmr(&indices, 16);
+        assert!(after < before, "ACMR {} -> {}", before, after);
+        // Each vertex is shared by ~6 triangles, so the ideal approaches 0.5
+        assert!(after < 0.8, "ACMR {}", after);
+    }
+}
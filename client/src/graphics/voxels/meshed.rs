@@ -0,0 +1,377 @@
+//! Storage for chunk surfaces extracted on the CPU
+//!
+//! Smooth terrain can't be described by the axis-aligned faces that GPU extraction produces, so
+//! its surfaces are extracted by worker threads into `ChunkMesh`es instead. Each is copied into
+//! host-visible buffers shared by every such chunk, from which it's drawn as an indexed mesh using
+//! the same per-chunk transforms, fades, and tints as GPU-extracted surfaces.
+
+use std::ops::Range;
+
+use ash::{vk, Device};
+use lahar::DedicatedMapping;
+
+use crate::{chunk_mesh::ChunkMesh, graphics::Base};
+use common::{
+    chunk::{majority, VoxelView},
+    light::MAX_LIGHT,
+    world::Material,
+};
+
+/// A vertex of a CPU-extracted surface, as read by `meshed.vert`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Vertex {
+    /// Position in the chunk's cube, [0..1]^3
+    pub position: [f32; 3],
+    /// Outward normal in the same space, or zero if undefined
+    pub normal: [f32; 3],
+    /// `Material` discriminant
+    pub material: u32,
+    /// Light level as a fraction of `MAX_LIGHT`
+    pub light: f32,
+}
+
+pub struct MeshStorage {
+    vertices: DedicatedMapping<[Vertex]>,
+    indices: DedicatedMapping<[u32]>,
+    vertex_space: Arena,
+    index_space: Arena,
+}
+
+/// Where a surface lies in `MeshStorage`
+#[derive(Debug, Copy, Clone)]
+pub struct MeshAlloc {
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+impl MeshStorage {
+    /// Allocate room for `vertices` vertices and `indices` indices, shared among all surfaces
+    pub fn new(gfx: &Base, vertices: u32, indices: u32) -> Self {
+        unsafe {
+            let vertex_buffer = DedicatedMapping::zeroed_array(
+                &gfx.device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vertices as usize,
+            );
+            gfx.set_name(vertex_buffer.buffer(), cstr!("meshed vertices"));
+            let index_buffer = DedicatedMapping::zeroed_array(
+                &gfx.device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                indices as usize,
+            );
+            gfx.set_name(index_buffer.buffer(), cstr!("meshed indices"));
+            Self {
+                vertices: vertex_buffer,
+                indices: index_buffer,
+                vertex_space: Arena::new(vertices),
+                index_space: Arena::new(indices),
+            }
+        }
+    }
+
+    /// Copy `mesh`, extracted from cells `cell_size` voxels across of a chunk `dimension` voxels
+    /// across and lit by `light`, per `padded_light`, into storage
+    ///
+    /// Each vertex takes the brightest light among the voxels around it, so that faces are lit by
+    /// whichever side of them is open. Triangles are wound the other way if `reverse_winding` is set, for chunks whose transform
+    /// reverses orientation. Returns `None` if there isn't room.
+    pub fn insert(
+        &mut self,
+        mesh: &ChunkMesh,
+        light: &[u8],
+        cell_size: u32,
+        dimension: u8,
+        reverse_winding: bool,
+    ) -> Option<MeshAlloc> {
+        let vertex_count = mesh.vertices.len() as u32;
+        let index_count = mesh.faces.len() as u32 * 6;
+        let first_vertex = self.vertex_space.alloc(vertex_count)?;
+        let first_index = match self.index_space.alloc(index_count) {
+            Some(x) => x,
+            None => {
+                self.vertex_space
+                    .free(first_vertex..first_vertex + vertex_count);
+                return None;
+            }
+        };
+
+        let dim = f32::from(dimension);
+        // Cells at the far edge of a chunk whose dimension isn't a multiple of their size are cut
+        // short by the chunk's boundary
+        let position = |x: f32| (x * cell_size as f32).min(dim) / dim;
+        // Nearest voxel corner, in full-detail voxels, for looking up light
+        let corner = |x: f32| (x * cell_size as f32).round().min(dim) as i32;
+        let vertices = &mut self.vertices[first_vertex as usize..][..vertex_count as usize];
+        for (i, out) in vertices.iter_mut().enumerate() {
+            let p = mesh.vertices[i];
+            *out = Vertex {
+                position: [position(p[0]), position(p[1]), position(p[2])],
+                normal: mesh.normals[i],
+                material: mesh.materials[i] as u32,
+                light: f32::from(corner_light(
+                    light,
+                    dimension,
+                    [corner(p[0]), corner(p[1]), corner(p[2])],
+                )) / f32::from(MAX_LIGHT),
+            };
+        }
+        let indices = &mut self.indices[first_index as usize..][..index_count as usize];
+        for (face, out) in mesh.faces.iter().zip(indices.chunks_exact_mut(6)) {
+            // Split each quad into two counterclockwise triangles
+            let [a, b, c, d] = *face;
+            if reverse_winding {
+                out.copy_from_slice(&[a, c, b, a, d, c]);
+            } else {
+                out.copy_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+
+        Some(MeshAlloc {
+            first_vertex,
+            vertex_count,
+            first_index,
+            index_count,
+        })
+    }
+
+    /// Make the space occupied by `alloc` available to future surfaces
+    ///
+    /// `alloc` must not be in use by a frame in flight.
+    pub fn remove(&mut self, alloc: &MeshAlloc) {
+        self.vertex_space
+            .free(alloc.first_vertex..alloc.first_vertex + alloc.vertex_count);
+        self.index_space
+            .free(alloc.first_index..alloc.first_index + alloc.index_count);
+    }
+
+    /// Make surfaces inserted since the last flush visible to the GPU
+    pub unsafe fn flush(&self, device: &Device) {
+        self.vertices.flush(device);
+        self.indices.flush(device);
+    }
+
+    pub fn vertex_buffer(&self) -> vk::Buffer {
+        self.vertices.buffer()
+    }
+
+    pub fn index_buffer(&self) -> vk::Buffer {
+        self.indices.buffer()
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.vertices.destroy(device);
+        self.indices.destroy(device);
+    }
+}
+
+/// Brightest light among the voxels of a chunk `dimension` voxels across that meet at `corner`,
+/// given the light of each, including margins, per `padded_light`
+///
+/// Margins hold the light of neighboring chunks, so corners on a boundary are lit from both sides.
+fn corner_light(light: &[u8], dimension: u8, corner: [i32; 3]) -> u8 {
+    // Length (of cube sides) with margins
+    let lwm = i32::from(dimension) + 2;
+    let mut result = 0;
+    for i in 0..8 {
+        // Offset by the margin, so that the voxel below the corner is at `corner` itself
+        let voxel = |axis: usize| corner[axis] + (i >> axis & 1);
+        let index = voxel(0) + voxel(1) * lwm + voxel(2) * lwm.pow(2);
+        result = result.max(light[index as usize]);
+    }
+    result
+}
+
+/// Voxels, including margins, of a chunk at the level of detail `view` shows, given the chunk's
+/// full-detail `voxels`, including margins, of side `dimension`
+///
+/// Cells at `level` cover `2^level` voxels along each edge. Neighboring chunks' levels of detail
+/// aren't at hand, so each margin cell takes the most common material among the full-detail margin
+/// voxels it covers.
+pub fn coarse_voxels(
+    view: VoxelView<'_>,
+    level: u32,
+    dimension: u8,
+    voxels: &[Material],
+) -> Vec<Material> {
+    let side = isize::from(view.dimension());
+    let dim = isize::from(dimension);
+    let cell = 1 << level;
+    // Full-detail coordinates covered by a cell along one axis, including margins
+    let covered = |c: isize| {
+        if c < 0 {
+            -1..0
+        } else if c >= side {
+            dim..dim + 1
+        } else {
+            c * cell..((c + 1) * cell).min(dim)
+        }
+    };
+    // Length (of cube sides) with margins
+    let lwm = dim + 2;
+    let mut result = Vec::with_capacity((side as usize + 2).pow(3));
+    let mut block = Vec::new();
+    for z in -1..=side {
+        for y in -1..=side {
+            for x in -1..=side {
+                if [x, y, z].iter().all(|&c| c >= 0 && c < side) {
+                    result.push(view.get([x as u8, y as u8, z as u8]));
+                    continue;
+                }
+                block.clear();
+                for fz in covered(z) {
+                    for fy in covered(y) {
+                        for fx in covered(x) {
+                            block.push(
+                                voxels
+                                    [((fx + 1) + (fy + 1) * lwm + (fz + 1) * lwm.pow(2)) as usize],
+                            );
+                        }
+                    }
+                }
+                result.push(majority(&block));
+            }
+        }
+    }
+    result
+}
+
+/// Assigns ranges of a fixed-size buffer, first fit
+struct Arena {
+    /// Unassigned ranges, in order, none of them adjacent or empty
+    free: Vec<Range<u32>>,
+}
+
+impl Arena {
+    fn new(size: u32) -> Self {
+        Self {
+            free: if size == 0 { Vec::new() } else { vec![0..size] },
+        }
+    }
+
+    /// Start of a newly assigned range of `size`, if there's a free one large enough
+    fn alloc(&mut self, size: u32) -> Option<u32> {
+        if size == 0 {
+            return Some(0);
+        }
+        let i = self.free.iter().position(|x| x.end - x.start >= size)?;
+        let start = self.free[i].start;
+        self.free[i].start += size;
+        if self.free[i].start == self.free[i].end {
+            self.free.remove(i);
+        }
+        Some(start)
+    }
+
+    /// Return `range`, previously assigned by `alloc`, merging it with any free neighbors
+    fn free(&mut self, range: Range<u32>) {
+        if range.start == range.end {
+            return;
+        }
+        let i = match self.free.binary_search_by_key(&range.start, |x| x.start) {
+            Ok(_) => panic!("double free of {:?}", range),
+            Err(i) => i,
+        };
+        let joins_previous = i > 0 && self.free[i - 1].end == range.start;
+        let joins_next = i < self.free.len() && self.free[i].start == range.end;
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            }
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        light::LightField,
+        node::{voxel_index, Chunk, VoxelData},
+    };
+
+    #[test]
+    fn coarse_margins() {
+        const DIMENSION: u8 = 4;
+        let lwm = usize::from(DIMENSION) + 2;
+        let index = |x: usize, y: usize, z: usize| x + y * lwm + z * lwm.pow(2);
+        // Solid in the lower half, and in the -X margin above it where a neighbor's wall rises
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        for z in 0..lwm {
+            for y in 0..lwm {
+                for x in 0..lwm {
+                    if y <= 2 || (x == 0 && y <= 4) {
+                        voxels[index(x, y, z)] = Material::Dirt;
+                    }
+                }
+            }
+        }
+        let chunk = Chunk::populated(DIMENSION, VoxelData::Dense(voxels.clone().into()));
+        let coarse = coarse_voxels(chunk.lod(1).unwrap(), 1, DIMENSION, &voxels);
+        let coarse_index = |x: usize, y: usize, z: usize| x + y * 4 + z * 16;
+        assert_eq!(coarse.len(), 4usize.pow(3));
+        for z in 0..4 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    let expected = if y <= 1 || (x == 0 && y <= 2) {
+                        Material::Dirt
+                    } else {
+                        Material::Void
+                    };
+                    assert_eq!(coarse[coarse_index(x, y, z)], expected, "{:?}", [x, y, z]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corners_take_brightest_light() {
+        const DIMENSION: u8 = 4;
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, [0, 0, 0])] = Material::Lava;
+        let mut light = LightField::compute(DIMENSION, &voxels).padded();
+        // Every corner of the glowing voxel is fully lit, including those on the chunk's boundary
+        assert_eq!(corner_light(&light, DIMENSION, [0, 0, 0]), MAX_LIGHT);
+        assert_eq!(corner_light(&light, DIMENSION, [1, 1, 1]), MAX_LIGHT);
+        // Beyond it, light falls off
+        assert_eq!(corner_light(&light, DIMENSION, [2, 1, 1]), MAX_LIGHT - 1);
+        assert_eq!(corner_light(&light, DIMENSION, [4, 4, 4]), MAX_LIGHT - 9);
+        assert_eq!(corner_light(&light, DIMENSION, [0, 4, 4]), MAX_LIGHT - 6);
+
+        // Light in the margins, from a neighboring chunk, reaches corners on the boundary
+        light[voxel_index(DIMENSION, [0, 3, 3]) - 1] = MAX_LIGHT;
+        assert_eq!(corner_light(&light, DIMENSION, [0, 4, 4]), MAX_LIGHT);
+        assert_eq!(corner_light(&light, DIMENSION, [1, 4, 4]), MAX_LIGHT - 6);
+    }
+
+    #[test]
+    fn arena_reuses_freed_space() {
+        let mut arena = Arena::new(10);
+        let a = arena.alloc(4).unwrap();
+        let b = arena.alloc(4).unwrap();
+        assert_eq!((a, b), (0, 4));
+        assert_eq!(arena.alloc(3), None, "only 2 remain");
+        assert_eq!(arena.alloc(0), Some(0), "empty ranges are always available");
+
+        // Freed space is reused, first fit
+        arena.free(a..a + 4);
+        assert_eq!(arena.alloc(1), Some(0));
+        assert_eq!(arena.alloc(3), Some(1));
+        assert_eq!(arena.alloc(3), None);
+
+        // Neighboring free ranges merge into one large enough for anything
+        arena.free(0..1);
+        arena.free(b..b + 4);
+        arena.free(1..4);
+        assert_eq!(arena.free, [0..10]);
+        assert_eq!(arena.alloc(10), Some(0));
+    }
+}
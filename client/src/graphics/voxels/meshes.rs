@@ -0,0 +1,133 @@
+//! Sharing of extracted surfaces between chunks with identical voxels
+//!
+//! Flat terrain produces many chunks whose voxels, margins included, are exactly the same, and so
+//! whose surfaces are too. Rather than extract each of them, later chunks draw the faces extracted
+//! for the first as further instances, each with its own transform.
+//!
+//! This saves extraction work and voxel uploads, not memory: face storage is still reserved for
+//! every slot in `DrawBuffer`, whether its chunk draws its own faces or another's.
+
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use fxhash::{FxHashMap, FxHasher64};
+
+use common::{lru_slab::SlotId, world::Material};
+
+/// Identifies the surface extracted from a chunk's voxels
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MeshKey {
+    hash: u64,
+    /// Chunks of opposite parity produce the same faces wound the other way
+    reverse_winding: bool,
+}
+
+impl MeshKey {
+    pub fn new(voxels: &[Material], reverse_winding: bool) -> Self {
+        let mut hasher = FxHasher64::default();
+        voxels.hash(&mut hasher);
+        Self {
+            hash: hasher.finish(),
+            reverse_winding,
+        }
+    }
+}
+
+/// Extracted surfaces available for sharing, by the voxels they were extracted from
+#[derive(Default)]
+pub struct MeshTable {
+    entries: FxHashMap<MeshKey, Entry>,
+}
+
+struct Entry {
+    /// Confirms a match, so that a hash collision can't draw the wrong surface
+    voxels: Arc<[Material]>,
+    owner: SlotId,
+}
+
+impl MeshTable {
+    /// The slot holding the surface extracted from `voxels`, if any
+    pub fn get(&self, key: MeshKey, voxels: &Arc<[Material]>) -> Option<SlotId> {
+        let entry = self.entries.get(&key)?;
+        if Arc::ptr_eq(&entry.voxels, voxels) || entry.voxels[..] == voxels[..] {
+            Some(entry.owner)
+        } else {
+            None
+        }
+    }
+
+    /// Record that `owner` holds the surface extracted from `voxels`, superseding any other
+    /// surface with the same key
+    pub fn insert(&mut self, key: MeshKey, voxels: Arc<[Material]>, owner: SlotId) {
+        self.entries.insert(key, Entry { voxels, owner });
+    }
+
+    /// Forget the surface held by `owner`, which is about to be reused
+    pub fn remove(&mut self, key: MeshKey, owner: SlotId) {
+        if self.entries.get(&key).map_or(false, |x| x.owner == owner) {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Voxels of a chunk half filled with stone, including margins
+    fn flat(dimension: usize) -> Arc<[Material]> {
+        let side = dimension + 2;
+        (0..side.pow(3))
+            .map(|i| {
+                if i / side.pow(2) < side / 2 {
+                    Material::Stone
+                } else {
+                    Material::Void
+                }
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[test]
+    fn identical_chunks_share() {
+        let mut table = MeshTable::default();
+        let mut next_slot = 0;
+        // Each chunk gets a slot of its own, holding its transform, and draws faces from `owner`
+        let mut place = |table: &mut MeshTable, voxels: Arc<[Material]>, reverse_winding| {
+            let slot = SlotId(next_slot);
+            next_slot += 1;
+            let key = MeshKey::new(&voxels, reverse_winding);
+            let owner = table.get(key, &voxels).unwrap_or_else(|| {
+                table.insert(key, voxels, slot);
+                slot
+            });
+            (slot, owner)
+        };
+
+        // Separately generated, so not the same allocation
+        let chunks = (0..4)
+            .map(|_| place(&mut table, flat(12), false))
+            .collect::<Vec<_>>();
+        for (i, &(slot, owner)) in chunks.iter().enumerate() {
+            assert_eq!(slot, SlotId(i as u32), "every chunk has its own transform");
+            assert_eq!(owner, SlotId(0), "every chunk draws the same faces");
+        }
+
+        let (slot, owner) = place(&mut table, flat(12), true);
+        assert_eq!(owner, slot, "opposite winding can't be shared");
+        let mut other = flat(12).to_vec();
+        other[0] = Material::Dirt;
+        let (slot, owner) = place(&mut table, other.into(), false);
+        assert_eq!(owner, slot, "different voxels can't be shared");
+
+        // Only the owner's removal forgets the surface
+        let key = MeshKey::new(&flat(12), false);
+        table.remove(key, SlotId(1));
+        assert_eq!(table.get(key, &flat(12)), Some(SlotId(0)));
+        table.remove(key, SlotId(0));
+        assert_eq!(table.get(key, &flat(12)), None);
+    }
+}
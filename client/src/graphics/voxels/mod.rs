@@ -1,32 +1,49 @@
+mod meshed;
+mod meshes;
 mod surface;
 pub mod surface_extraction;
 
 #[cfg(test)]
 mod tests;
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use ash::{vk, Device};
-use metrics::timing;
+use fxhash::FxHashSet;
+use metrics::{gauge, timing};
 use tracing::warn;
 
 use crate::{
+    chunk_cache::ChunkCache,
+    chunk_mesh::{ChunkMesh, Style},
     graphics::{Base, Frustum},
     loader::{Cleanup, LoadCtx, LoadFuture, Loadable, WorkQueue},
+    mesh_cache::MeshCache,
+    mesh_pool::MeshPool,
     Config, Loader, Sim,
 };
 use common::{
     dodeca,
     dodeca::Vertex,
-    graph::NodeId,
+    graph::{GraphEvent, NodeId, Subscription},
+    light::padded_light,
     lru_slab::SlotId,
     math,
-    node::{Chunk, VoxelData},
+    node::{voxel_coords, Chunk, ChunkId, DualGraph, VoxelData},
+    world::{self, Material},
+    worldgen::ChunkTints,
     LruSlab,
 };
 
+use meshed::{MeshAlloc, MeshStorage};
+use meshes::{MeshKey, MeshTable};
 use surface::Surface;
-use surface_extraction::{DrawBuffer, ExtractTask, ScratchBuffer, SurfaceExtraction};
+use surface_extraction::{
+    pack_voxel, DrawBuffer, ExtractTask, ScratchBuffer, ShareTask, SurfaceExtraction,
+};
 
 pub struct Voxels {
     config: Arc<Config>,
@@ -34,9 +51,24 @@ pub struct Voxels {
     extraction_scratch: ScratchBuffer,
     surfaces: DrawBuffer,
     states: LruSlab<SurfaceState>,
+    /// Surfaces that chunks with identical voxels can draw instead of extracting their own
+    meshes: MeshTable,
     draw: Surface,
     max_chunks: u32,
     worldgen: WorkQueue<ChunkDesc>,
+    /// Surfaces extracted on the CPU, for terrain styles the GPU can't extract
+    meshed: MeshStorage,
+    meshing: WorkQueue<MeshDesc>,
+    /// Surfaces extracted in previous runs, or for identical chunks
+    mesh_cache: MeshCache,
+    /// Storage for meshing jobs to extract surfaces into, returned once uploaded
+    mesh_pool: MeshPool,
+    /// Chunks whose surfaces are being extracted by `meshing`
+    meshing_pending: FxHashSet<ChunkId>,
+    /// Memory occupied by voxel data in the graph
+    cache: ChunkCache,
+    /// Generation and edits of chunks, whose light may reach their neighbors' surfaces
+    subscription: Option<Subscription>,
 }
 
 impl Voxels {
@@ -69,11 +101,19 @@ impl Voxels {
         );
         Self {
             worldgen: loader.make_queue(config.chunk_load_parallelism as usize),
+            meshed: MeshStorage::new(gfx, MESHED_VERTICES, MESHED_INDICES),
+            meshing: loader.make_queue(config.chunk_load_parallelism as usize),
+            mesh_cache: MeshCache::new(config.data_dir.join("mesh-cache")),
+            mesh_pool: MeshPool::default(),
+            meshing_pending: FxHashSet::default(),
+            cache: ChunkCache::new(config.voxel_memory_cap),
+            subscription: None,
             config,
             surface_extraction,
             extraction_scratch,
             surfaces,
             states: LruSlab::with_capacity(max_chunks),
+            meshes: MeshTable::default(),
             draw,
             max_chunks,
         }
@@ -91,20 +131,41 @@ impl Voxels {
         cmd: vk::CommandBuffer,
         frustum: &Frustum,
     ) {
+        let now = Instant::now();
         // Clean up after previous frame
         for i in frame.extracted.drain(..) {
             self.extraction_scratch.free(i);
         }
-        for chunk in frame.drawn.drain(..) {
+        for (chunk, _) in frame.drawn.drain(..) {
+            self.states.peek_mut(chunk).refcount -= 1;
+            if let Mesh::Shared(owner) = self.states.peek(chunk).mesh {
+                self.states.peek_mut(owner).refcount -= 1;
+            }
+        }
+        for chunk in frame.meshed.drain(..) {
             self.states.peek_mut(chunk).refcount -= 1;
         }
-        while let Some(chunk) = self.worldgen.poll() {
+        while let Some(mut chunk) = self.worldgen.poll() {
+            let id = ChunkId::new(chunk.node, chunk.chunk);
+            sim.restore_edits(id, &mut chunk.voxels);
+            self.cache.insert(id, &chunk.voxels);
             sim.graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.chunk] =
-                Chunk::Populated {
-                    surface: None,
-                    voxels: chunk.voxels,
-                };
+                Chunk::populated(self.surfaces.dimension() as u8, chunk.voxels);
+            sim.graph
+                .notify(GraphEvent::ChunkGenerated(chunk.node, chunk.chunk));
+        }
+        let mut meshes_uploaded = false;
+        while let Some(mesh) = self.meshing.poll() {
+            self.meshing_pending
+                .remove(&ChunkId::new(mesh.node, mesh.chunk));
+            meshes_uploaded |= self.upload_mesh(&mut sim.graph, now, &mesh);
+            self.mesh_pool.put(mesh.mesh);
         }
+        if meshes_uploaded {
+            self.meshed.flush(device);
+        }
+        self.relight_neighbors(&mut sim.graph);
+        gauge!("voxels.meshes_allocated", self.mesh_pool.allocated() as i64);
 
         // Determine what to load/render
         let view = sim.view();
@@ -121,18 +182,26 @@ impl Voxels {
             "frame.cpu.voxels.graph_traversal",
             graph_traversal_started.elapsed()
         );
-        // Sort nodes by distance to the view to prioritize loading closer data and improve early Z
-        // performance
-        let view_pos = view.local * math::origin();
+        // Sort nodes by `priority` to load what's close and in view first, and to draw roughly
+        // front-to-back, improving early Z performance
+        let local_to_view = view.local.try_inverse().unwrap();
+        let priority_of = |xf: &na::Matrix4<f32>| priority(&(local_to_view * xf * math::origin()));
         nodes.sort_unstable_by(|&(_, ref xf_a), &(_, ref xf_b)| {
-            math::distance(&view_pos, &(xf_a * math::origin()))
-                .partial_cmp(&math::distance(&view_pos, &(xf_b * math::origin())))
+            priority_of(xf_b)
+                .partial_cmp(&priority_of(xf_a))
                 .unwrap_or(std::cmp::Ordering::Less)
         });
+        let preset = sim
+            .params()
+            .map_or_else(Default::default, |x| x.worldgen_preset);
         let node_scan_started = Instant::now();
         let frustum_planes = frustum.planes();
-        let local_to_view = view.local.try_inverse().unwrap();
+        let lod_distance =
+            self.config.lod_distance * self.config.local_simulation.meters_to_absolute;
+        // Chunks too small to have the coarsest level use the coarsest they have
+        let max_level = common::chunk::count_levels(self.surfaces.dimension() as u8) - 1;
         let mut extractions = Vec::new();
+        let mut shares = Vec::new();
         for &(node, ref node_transform) in &nodes {
             let node_to_view = local_to_view * node_transform;
             let origin = node_to_view * math::origin();
@@ -144,8 +213,19 @@ impl Voxels {
 
             use Chunk::*;
             for chunk in Vertex::iter() {
+                // Defer eviction of anything in view
+                self.cache.touch(ChunkId::new(node, chunk));
+                let transform = node_transform * chunk.chunk_to_node().map(|x| x as f32);
+                let level = lod_level(&(local_to_view * transform), lod_distance).min(max_level);
+                // Level of detail of the CPU-extracted surface to draw, or `None` to extract on the
+                // GPU, which can only produce blocky surfaces at full detail
+                let wanted = if self.config.terrain_style == Style::Blocky && level == 0 {
+                    None
+                } else {
+                    Some(level)
+                };
                 // Fetch existing chunk, or extract surface of new chunk
-                match sim
+                let data = match sim
                     .graph
                     .get_mut(node)
                     .as_mut()
@@ -157,6 +237,7 @@ impl Voxels {
                         // Generate voxel data
                         if let Some(params) = common::worldgen::ChunkParams::new(
                             self.surfaces.dimension() as u8,
+                            preset,
                             &sim.graph,
                             node,
                             chunk,
@@ -169,66 +250,195 @@ impl Voxels {
                         continue;
                     }
                     Populated {
-                        ref mut surface,
+                        ref surface,
                         ref voxels,
-                    } => match (surface, voxels) {
-                        (&mut Some(slot), _) => {
+                        ..
+                    } => {
+                        if let Some(slot) = *surface {
                             // Render an already-extracted surface
-                            self.states.get_mut(slot).refcount += 1;
-                            frame.drawn.push(slot);
-                            // Transfer transform
-                            frame.surface.transforms_mut()[slot.0 as usize] =
-                                node_transform * chunk.chunk_to_node().map(|x| x as f32);
-                        }
-                        (&mut ref mut surface @ None, &VoxelData::Dense(ref data)) => {
-                            // Extract a surface so it can be drawn in future frames
-                            if frame.extracted.len() == self.config.chunk_load_parallelism as usize
-                            {
-                                continue;
+                            if let Mesh::Shared(owner) = self.states.get_mut(slot).mesh {
+                                // Keep the faces drawn here from being reused while in flight
+                                self.states.get_mut(owner).refcount += 1;
                             }
-                            let removed = if self.states.len() == self.max_chunks {
-                                let slot = self.states.lru().expect("full LRU table is nonempty");
-                                if self.states.peek(slot).refcount != 0 {
-                                    warn!("MAX_CHUNKS is too small");
-                                    break;
-                                }
-                                Some(self.states.remove(slot))
+                            let state = self.states.get_mut(slot);
+                            state.refcount += 1;
+                            if let Mesh::Meshed { .. } = state.mesh {
+                                frame.meshed.push(slot);
                             } else {
-                                None
-                            };
-                            let scratch_slot = self.extraction_scratch.alloc().expect("there are at least chunks_loaded_per_frame scratch slots per frame");
-                            frame.extracted.push(scratch_slot);
-                            let slot = self.states.insert(SurfaceState {
-                                node,
-                                chunk,
-                                refcount: 0,
-                            });
-                            *surface = Some(slot);
-                            let storage = self.extraction_scratch.storage(scratch_slot);
-                            storage.copy_from_slice(&data[..]);
-                            if let Some(lru) = removed {
-                                if let Populated {
-                                    ref mut surface, ..
-                                } =
-                                    sim.graph.get_mut(lru.node).as_mut().unwrap().chunks[lru.chunk]
-                                {
-                                    *surface = None;
-                                }
+                                frame
+                                    .drawn
+                                    .push((slot, subdivisions(&(local_to_view * transform))));
+                            }
+                            // Transfer transform
+                            frame.surface.transforms_mut()[slot.0 as usize] = transform;
+                            frame.surface.fades_mut()[slot.0 as usize] =
+                                fade(now - state.loaded, self.config.chunk_fade);
+                            frame.surface.tints_mut()[slot.0 as usize] = state.tints.packed();
+                            if state.mesh.level() == wanted && !state.stale {
+                                continue;
                             }
-                            let node_is_odd = sim.graph.length(node) & 1 != 0;
-                            extractions.push(ExtractTask {
-                                index: scratch_slot,
-                                indirect_offset: self.surfaces.indirect_offset(slot.0),
-                                face_offset: self.surfaces.face_offset(slot.0),
-                                draw_id: slot.0,
-                                reverse_winding: chunk.parity() ^ node_is_odd,
-                            });
+                            // Keep drawing this surface until its replacement at the level of
+                            // detail now wanted, or with up to date light, is ready
                         }
-                        (None, &VoxelData::Solid(_)) => continue,
+                        match *voxels {
+                            VoxelData::Dense(ref data) => data.clone(),
+                            VoxelData::Solid(_) => continue,
+                        }
+                    }
+                };
+                let previous = match sim.graph.get(node).as_ref().unwrap().chunks[chunk] {
+                    Populated { surface, .. } => surface,
+                    _ => unreachable!(),
+                };
+
+                if let Some(level) = wanted {
+                    // Extract the surface on a worker thread, to be set up once it's ready
+                    let id = ChunkId::new(node, chunk);
+                    if self.meshing_pending.contains(&id) {
+                        continue;
+                    }
+                    let view = sim.graph.get(node).as_ref().unwrap().chunks[chunk]
+                        .lod(level)
+                        .expect("populated chunks have every level up to count_levels");
+                    let voxels = if level == 0 {
+                        data.clone()
+                    } else {
+                        meshed::coarse_voxels(view, level, self.surfaces.dimension() as u8, &data)
+                            .into()
+                    };
+                    let desc = MeshDesc {
+                        node,
+                        chunk,
+                        level,
+                        style: self.config.terrain_style,
+                        dimension: view.dimension(),
+                        source: data,
+                        voxels,
+                        cache: self.mesh_cache.clone(),
+                        pool: self.mesh_pool.clone(),
+                    };
+                    if self.meshing.load(desc).is_ok() {
+                        self.meshing_pending.insert(id);
+                    }
+                    continue;
+                }
+
+                // Set up a surface so the chunk can be drawn in future frames, extracting it unless
+                // an identical chunk's can be shared
+                let node_is_odd = sim.graph.length(node) & 1 != 0;
+                let reverse_winding = chunk.parity() ^ node_is_odd;
+                let key = MeshKey::new(&data, reverse_winding);
+                let extraction_full =
+                    frame.extracted.len() == self.config.chunk_load_parallelism as usize;
+                if extraction_full && self.meshes.get(key, &data).is_none() {
+                    continue;
+                }
+                let dimension = self.surfaces.dimension() as u8;
+                let light = padded_light(&sim.graph, dimension, ChunkId::new(node, chunk))
+                    .expect("surfaces are only extracted from populated chunks");
+                // Light from neighbors doesn't follow from a chunk's own voxels, so surfaces
+                // showing it can't be shared
+                let lit_margins = margins_lit(dimension, &light);
+                if extraction_full && lit_margins {
+                    continue;
+                }
+                if !self.make_room(&mut sim.graph) {
+                    warn!("MAX_CHUNKS is too small");
+                    break;
+                }
+                // Looked up again in case that eviction claimed the surface to be shared
+                let owner = if lit_margins {
+                    None
+                } else {
+                    self.meshes.get(key, &data)
+                };
+                if extraction_full && owner.is_none() {
+                    continue;
+                }
+                let slot = self.states.insert(SurfaceState {
+                    node,
+                    chunk,
+                    refcount: 0,
+                    // Replacements for surfaces at other levels of detail don't fade in again
+                    loaded: previous.map_or(now, |x| self.states.peek(x).loaded),
+                    tints: ChunkTints::new(&sim.graph, node, chunk).unwrap_or_default(),
+                    lit_margins,
+                    stale: false,
+                    mesh: match owner {
+                        Some(owner) => Mesh::Shared(owner),
+                        None => Mesh::Owned {
+                            key,
+                            sharers: Vec::new(),
+                        },
                     },
+                });
+                if let Populated {
+                    ref mut surface, ..
+                } = sim.graph.get_mut(node).as_mut().unwrap().chunks[chunk]
+                {
+                    *surface = Some(slot);
+                }
+                match owner {
+                    Some(owner) => {
+                        if let Mesh::Owned {
+                            ref mut sharers, ..
+                        } = self.states.peek_mut(owner).mesh
+                        {
+                            sharers.push(slot);
+                        }
+                        shares.push(ShareTask {
+                            source_indirect_offset: self.surfaces.indirect_offset(owner.0),
+                            indirect_offset: self.surfaces.indirect_offset(slot.0),
+                            draw_id: slot.0,
+                        });
+                    }
+                    None => {
+                        let scratch_slot = self.extraction_scratch.alloc().expect(
+                            "there are at least chunks_loaded_per_frame scratch slots per frame",
+                        );
+                        frame.extracted.push(scratch_slot);
+                        let storage = self.extraction_scratch.storage(scratch_slot);
+                        for ((out, &material), &level) in
+                            storage.iter_mut().zip(&data[..]).zip(&light)
+                        {
+                            *out = pack_voxel(material, level);
+                        }
+                        if !lit_margins {
+                            self.meshes.insert(key, data, slot);
+                        }
+                        extractions.push(ExtractTask {
+                            index: scratch_slot,
+                            indirect_offset: self.surfaces.indirect_offset(slot.0),
+                            face_offset: self.surfaces.face_offset(slot.0),
+                            draw_id: slot.0,
+                            reverse_winding,
+                        });
+                    }
                 }
             }
         }
+
+        // Discard the least recently rendered voxel data to stay within the memory cap
+        while let Some(id) = self.cache.excess() {
+            if let Chunk::Populated {
+                surface: Some(slot),
+                ..
+            } = sim.graph.get(id.node).as_ref().unwrap().chunks[id.vertex]
+            {
+                if self.states.peek(slot).refcount != 0 {
+                    // Everything remaining is in use by a frame in flight
+                    warn!("voxel memory cap is too small");
+                    break;
+                }
+                self.evict(&mut sim.graph, slot);
+            }
+            sim.graph.get_mut(id.node).as_mut().unwrap().chunks[id.vertex] = Chunk::Fresh;
+            self.cache.remove(id);
+            sim.graph
+                .notify(GraphEvent::ChunkEvicted(id.node, id.vertex));
+        }
+        gauge!("voxels.cache_bytes", self.cache.bytes() as i64);
+
         self.extraction_scratch.extract(
             device,
             &self.surface_extraction,
@@ -237,9 +447,181 @@ impl Voxels {
             cmd,
             &extractions,
         );
+        self.surface_extraction
+            .share(device, self.surfaces.indirect_buffer(), cmd, &shares);
         timing!("frame.cpu.voxels.node_scan", node_scan_started.elapsed());
     }
 
+    /// Set up a surface for the chunk `mesh` was extracted from, returning whether it was written
+    /// to `self.meshed`
+    fn upload_mesh(&mut self, graph: &mut DualGraph, now: Instant, mesh: &LoadedMesh) -> bool {
+        let previous = match graph.get(mesh.node).as_ref().unwrap().chunks[mesh.chunk] {
+            Chunk::Populated {
+                voxels: VoxelData::Dense(ref data),
+                surface,
+                ..
+            } if Arc::ptr_eq(data, &mesh.source) => surface,
+            // Evicted or edited since extraction began
+            _ => return false,
+        };
+        let dimension = self.surfaces.dimension() as u8;
+        // Looked up now, rather than with the voxels, to take in neighbors generated meanwhile
+        let light = padded_light(graph, dimension, ChunkId::new(mesh.node, mesh.chunk))
+            .expect("checked to be populated above");
+        let lit_margins = margins_lit(dimension, &light);
+        // Replacements for surfaces at other levels of detail don't fade in again
+        let loaded = previous.map_or(now, |x| self.states.peek(x).loaded);
+        if !self.make_room(graph) {
+            warn!("MAX_CHUNKS is too small");
+            return false;
+        }
+        let node_is_odd = graph.length(mesh.node) & 1 != 0;
+        let reverse_winding = mesh.chunk.parity() ^ node_is_odd;
+        let alloc = loop {
+            if let Some(alloc) = self.meshed.insert(
+                &mesh.mesh,
+                &light,
+                1 << mesh.level,
+                dimension,
+                reverse_winding,
+            ) {
+                break alloc;
+            }
+            // Make space by discarding the least recently drawn surfaces, which will be extracted
+            // again if they come back into view
+            match self.states.lru() {
+                Some(slot) if self.states.peek(slot).refcount == 0 => self.evict(graph, slot),
+                _ => {
+                    warn!("no space for CPU-extracted surfaces");
+                    return false;
+                }
+            }
+        };
+        let slot = self.states.insert(SurfaceState {
+            node: mesh.node,
+            chunk: mesh.chunk,
+            refcount: 0,
+            loaded,
+            tints: ChunkTints::new(graph, mesh.node, mesh.chunk).unwrap_or_default(),
+            lit_margins,
+            stale: false,
+            mesh: Mesh::Meshed {
+                level: mesh.level,
+                alloc,
+            },
+        });
+        if let Chunk::Populated {
+            ref mut surface, ..
+        } = graph.get_mut(mesh.node).as_mut().unwrap().chunks[mesh.chunk]
+        {
+            *surface = Some(slot);
+        }
+        true
+    }
+
+    /// Mark for replacement the surfaces whose light from neighboring chunks may have changed with
+    /// the generation or editing of those chunks since the last call
+    fn relight_neighbors(&mut self, graph: &mut DualGraph) {
+        let changed = match self.subscription {
+            Some(ref subscription) => graph
+                .events(subscription)
+                .iter()
+                .filter_map(|event| match *event {
+                    GraphEvent::ChunkGenerated(node, vertex) => {
+                        Some((ChunkId::new(node, vertex), false))
+                    }
+                    GraphEvent::ChunkEdited(node, vertex) => {
+                        Some((ChunkId::new(node, vertex), true))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            None => {
+                self.subscription = Some(graph.subscribe());
+                return;
+            }
+        };
+        let dimension = self.surfaces.dimension() as u8;
+        for (chunk, edited) in changed {
+            let lit = graph
+                .get(chunk.node)
+                .as_ref()
+                .and_then(|x| x.chunks[chunk.vertex].light())
+                .map_or(false, |x| x.lights_boundary());
+            // A newly generated chunk can only have made its neighbors brighter, but an edited one
+            // may also have darkened those it lit before
+            if !lit && !edited {
+                continue;
+            }
+            for neighbor in world::neighboring_chunks(graph, dimension, chunk) {
+                if let Some(&Chunk::Populated {
+                    surface: Some(slot),
+                    ..
+                }) = graph
+                    .get(neighbor.node)
+                    .as_ref()
+                    .map(|x| &x.chunks[neighbor.vertex])
+                {
+                    let state = self.states.peek_mut(slot);
+                    state.stale |= lit || state.lit_margins;
+                }
+            }
+        }
+    }
+
+    /// Ensure a slot is free for a new surface, evicting the least recently drawn if necessary
+    ///
+    /// Returns `false` if every slot is in use by a frame in flight.
+    fn make_room(&mut self, graph: &mut DualGraph) -> bool {
+        if self.states.len() < self.max_chunks {
+            return true;
+        }
+        let slot = self.states.lru().expect("full LRU table is nonempty");
+        if self.states.peek(slot).refcount != 0 {
+            return false;
+        }
+        self.evict(graph, slot);
+        true
+    }
+
+    /// Free `slot` for reuse, along with the slots of any chunks drawing its faces
+    ///
+    /// `slot` must not be in use by a frame in flight, which also rules out those that share it.
+    fn evict(&mut self, graph: &mut DualGraph, slot: SlotId) {
+        match self.release(graph, slot) {
+            Mesh::Owned { key, sharers } => {
+                self.meshes.remove(key, slot);
+                for sharer in sharers {
+                    self.release(graph, sharer);
+                }
+            }
+            Mesh::Shared(owner) => {
+                if let Mesh::Owned {
+                    ref mut sharers, ..
+                } = self.states.peek_mut(owner).mesh
+                {
+                    sharers.retain(|&x| x != slot);
+                }
+            }
+            Mesh::Meshed { alloc, .. } => self.meshed.remove(&alloc),
+        }
+    }
+
+    /// Remove `slot` from the table and from the chunk it was drawn for
+    fn release(&mut self, graph: &mut DualGraph, slot: SlotId) -> Mesh {
+        let state = self.states.remove(slot);
+        if let Chunk::Populated {
+            ref mut surface, ..
+        } = graph.get_mut(state.node).as_mut().unwrap().chunks[state.chunk]
+        {
+            // An edited chunk may already have moved on to a new surface
+            if *surface == Some(slot) {
+                *surface = None;
+            }
+        }
+        state.mesh
+    }
+
     pub unsafe fn draw(
         &mut self,
         device: &Device,
@@ -259,8 +641,17 @@ impl Voxels {
         ) {
             return;
         }
-        for chunk in &frame.drawn {
-            self.draw.draw(device, cmd, &self.surfaces, chunk.0);
+        for &(chunk, subdivisions) in &frame.drawn {
+            self.draw
+                .draw(device, cmd, &self.surfaces, chunk.0, subdivisions);
+        }
+        if !frame.meshed.is_empty() {
+            self.draw.bind_meshed(device, cmd, &self.meshed);
+            for &chunk in &frame.meshed {
+                if let Mesh::Meshed { ref alloc, .. } = self.states.peek(chunk).mesh {
+                    self.draw.draw_meshed(device, cmd, alloc, chunk.0);
+                }
+            }
         }
         timing!("frame.cpu.voxels.draw", started.elapsed());
     }
@@ -269,6 +660,7 @@ impl Voxels {
         self.surface_extraction.destroy(device);
         self.extraction_scratch.destroy(device);
         self.surfaces.destroy(device);
+        self.meshed.destroy(device);
         self.draw.destroy(device);
     }
 }
@@ -277,7 +669,10 @@ pub struct Frame {
     surface: surface::Frame,
     /// Scratch slots completed in this frame
     extracted: Vec<u32>,
-    drawn: Vec<SlotId>,
+    /// Chunks to draw, and the number of pieces along each edge to split their faces into
+    drawn: Vec<(SlotId, u32)>,
+    /// Chunks to draw from surfaces extracted on the CPU
+    meshed: Vec<SlotId>,
 }
 
 impl Frame {
@@ -292,6 +687,7 @@ impl Frame {
             surface: surface::Frame::new(gfx, ctx.states.capacity()),
             extracted: Vec::new(),
             drawn: Vec::new(),
+            meshed: Vec::new(),
         }
     }
 }
@@ -299,10 +695,125 @@ impl Frame {
 /// Maximum number of concurrently drawn voxel chunks
 const MAX_CHUNKS: u32 = 8192;
 
+/// Coarsest level of detail chunks are drawn at, each level halving resolution
+const MAX_LOD: u32 = 2;
+
+/// Number of vertices of CPU-extracted surfaces that can be stored at once
+const MESHED_VERTICES: u32 = 1 << 20;
+
+/// Number of indices of CPU-extracted surfaces that can be stored at once, enough for two
+/// triangles per vertex
+const MESHED_INDICES: u32 = 6 * MESHED_VERTICES;
+
+/// Opacity of a chunk `elapsed` after its surface was extracted, ramping linearly up to 1 over
+/// `duration`
+fn fade(elapsed: Duration, duration: Duration) -> f32 {
+    if elapsed >= duration {
+        return 1.0;
+    }
+    elapsed.as_secs_f32() / duration.as_secs_f32()
+}
+
+/// Number of pieces along each edge into which the faces of a chunk should be split
+///
+/// The projective model compresses distant geometry, so a chunk far from the viewer covers far
+/// more hyperbolic extent than its projected size suggests, and a single flat quad per face reads
+/// as faceted. Compares the hyperbolic length of the chunk's diagonal to its length in view space,
+/// adding a subdivision each time that ratio doubles past `TESSELLATION_THRESHOLD`.
+fn subdivisions(chunk_to_view: &na::Matrix4<f32>) -> u32 {
+    let a = chunk_to_view * math::origin();
+    let b = chunk_to_view * na::Vector4::new(1.0, 1.0, 1.0, 1.0);
+    let projected = (a.xyz() / a.w - b.xyz() / b.w).norm();
+    // The Minkowski norms `distance` divides by cancel catastrophically in f32 for distant chunks
+    let distortion =
+        math::distance(&na::convert::<_, na::Vector4<f64>>(a), &na::convert(b)) as f32 / projected;
+    if distortion <= TESSELLATION_THRESHOLD {
+        return 1;
+    }
+    let extra = (distortion / TESSELLATION_THRESHOLD).log2().ceil() as u32;
+    1 + extra.min(MAX_SUBDIVISIONS - 1)
+}
+
+/// Level of detail to draw a chunk at, where `lod_distance` is the distance from the viewer at
+/// which to start halving resolution
+///
+/// Resolution halves again at each doubling of that distance, down to `MAX_LOD`, and never if
+/// `lod_distance` isn't positive.
+fn lod_level(chunk_to_view: &na::Matrix4<f32>, lod_distance: f32) -> u32 {
+    if lod_distance <= 0.0 {
+        return 0;
+    }
+    let center = chunk_to_view * na::Vector4::new(0.5, 0.5, 0.5, 1.0);
+    // As in `subdivisions`, f32 is too imprecise for distant chunks
+    let distance = math::distance(&math::origin(), &na::convert::<_, na::Vector4<f64>>(center));
+    let mut level = 0;
+    while level < MAX_LOD && distance > f64::from(lod_distance) * f64::from(1 << level) {
+        level += 1;
+    }
+    level
+}
+
+/// How urgently to load and draw chunks near `p`, a point in view space
+///
+/// Nearer is more urgent, but so is nearer the center of view: points straight ahead count as if
+/// `VIEW_PRIORITY` closer than they are, and points directly behind as if that much farther.
+fn priority(p: &na::Vector4<f32>) -> f32 {
+    let distance = math::distance(&math::origin(), p).max(0.0);
+    // The viewer's own surroundings are centered on it, so there's no direction to consider
+    let alignment = na::Unit::try_new(p.xyz(), 1e-6).map_or(1.0, |dir| -dir.z);
+    VIEW_PRIORITY * alignment - distance
+}
+
+/// Distance, in absolute units, that looking directly at something is worth in `priority`
+const VIEW_PRIORITY: f32 = 1.0;
+
+/// Ratio of hyperbolic to projected length beyond which faces are subdivided
+const TESSELLATION_THRESHOLD: f32 = 8.0;
+
+/// Upper bound on `subdivisions`, limiting the number of draws issued per chunk to its square
+const MAX_SUBDIVISIONS: u32 = 3;
+
 struct SurfaceState {
     node: NodeId,
     chunk: common::dodeca::Vertex,
     refcount: u32,
+    /// When the surface was extracted, for fading in
+    loaded: Instant,
+    tints: ChunkTints,
+    /// Whether any light from neighboring chunks reaches the surface
+    lit_margins: bool,
+    /// Whether light from neighboring chunks may have changed since extraction, so that the surface
+    /// should be replaced
+    stale: bool,
+    mesh: Mesh,
+}
+
+/// Where the faces drawn for a surface come from
+enum Mesh {
+    /// Extracted into the surface's own slot, and drawn by those of `sharers` too
+    Owned { key: MeshKey, sharers: Vec<SlotId> },
+    /// Extracted into the slot of another surface
+    Shared(SlotId),
+    /// Extracted on the CPU, at a level of detail per `Chunk::lod`
+    Meshed { level: u32, alloc: MeshAlloc },
+}
+
+impl Mesh {
+    /// Level of detail of a CPU-extracted surface, or `None` for one extracted on the GPU
+    fn level(&self) -> Option<u32> {
+        match *self {
+            Mesh::Meshed { level, .. } => Some(level),
+            _ => None,
+        }
+    }
+}
+
+/// Whether any margin voxel is lit in `light`, per `padded_light`
+fn margins_lit(dimension: u8, light: &[u8]) -> bool {
+    light
+        .iter()
+        .enumerate()
+        .any(|(i, &level)| level > 0 && voxel_coords(dimension, i).is_none())
 }
 
 struct ChunkDesc {
@@ -332,3 +843,49 @@ impl Loadable for ChunkDesc {
         })
     }
 }
+
+struct MeshDesc {
+    node: NodeId,
+    chunk: Vertex,
+    level: u32,
+    style: Style,
+    /// Side length of `voxels`, excluding margins
+    dimension: u8,
+    /// The chunk's voxels, to detect edits made before the surface is ready
+    source: Arc<[Material]>,
+    /// The chunk's voxels at `level` of detail, including margins
+    voxels: Arc<[Material]>,
+    cache: MeshCache,
+    pool: MeshPool,
+}
+
+struct LoadedMesh {
+    node: NodeId,
+    chunk: Vertex,
+    level: u32,
+    /// The voxels the surface was extracted from, to detect edits made in the meantime
+    source: Arc<[Material]>,
+    mesh: ChunkMesh,
+}
+
+impl Cleanup for LoadedMesh {
+    unsafe fn cleanup(self, _gfx: &Base) {}
+}
+
+impl Loadable for MeshDesc {
+    type Output = LoadedMesh;
+    fn load(self, _ctx: &LoadCtx) -> LoadFuture<'_, Self::Output> {
+        Box::pin(async move {
+            let mesh =
+                self.cache
+                    .get_or_extract(self.style, self.dimension, &self.voxels, &self.pool);
+            Ok(LoadedMesh {
+                node: self.node,
+                chunk: self.chunk,
+                level: self.level,
+                source: self.source,
+                mesh,
+            })
+        })
+    }
+}
@@ -1,20 +1,27 @@
-use std::ptr;
+use std::{mem, ptr};
 
 use ash::{version::DeviceV1_0, vk, Device};
 use lahar::{DedicatedImage, DedicatedMapping};
 use vk_shader_macros::include_glsl;
 
-use super::surface_extraction::DrawBuffer;
+use super::{
+    meshed::{self, MeshAlloc, MeshStorage},
+    surface_extraction::DrawBuffer,
+};
 use crate::{graphics::Base, Asset, Loader};
 use common::{defer, world::Material};
 
 const VERT: &[u32] = include_glsl!("shaders/voxels.vert");
 const FRAG: &[u32] = include_glsl!("shaders/voxels.frag");
+const MESHED_VERT: &[u32] = include_glsl!("shaders/meshed.vert");
+const MESHED_FRAG: &[u32] = include_glsl!("shaders/meshed.frag");
 
 pub struct Surface {
     static_ds_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    /// Draws surfaces extracted on the CPU, from `MeshStorage`
+    meshed_pipeline: vk::Pipeline,
     descriptor_pool: vk::DescriptorPool,
     ds: vk::DescriptorSet,
     colors: Asset<DedicatedImage>,
@@ -37,6 +44,22 @@ impl Surface {
                 .unwrap();
             let f_guard = defer(|| device.destroy_shader_module(frag, None));
 
+            let meshed_vert = device
+                .create_shader_module(
+                    &vk::ShaderModuleCreateInfo::builder().code(&MESHED_VERT),
+                    None,
+                )
+                .unwrap();
+            let mv_guard = defer(|| device.destroy_shader_module(meshed_vert, None));
+
+            let meshed_frag = device
+                .create_shader_module(
+                    &vk::ShaderModuleCreateInfo::builder().code(&MESHED_FRAG),
+                    None,
+                )
+                .unwrap();
+            let mf_guard = defer(|| device.destroy_shader_module(meshed_frag, None));
+
             let static_ds_layout = device
                 .create_descriptor_set_layout(
                     &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
@@ -105,115 +128,195 @@ impl Surface {
                         .push_constant_ranges(&[vk::PushConstantRange {
                             stage_flags: vk::ShaderStageFlags::VERTEX,
                             offset: 0,
-                            size: 4,
+                            size: 12,
                         }]),
                     None,
                 )
                 .unwrap();
 
             let entry_point = cstr!("main").as_ptr();
-            let mut pipelines = device
-                .create_graphics_pipelines(
-                    gfx.pipeline_cache,
-                    &[vk::GraphicsPipelineCreateInfo::builder()
-                        .stages(&[
-                            vk::PipelineShaderStageCreateInfo {
-                                stage: vk::ShaderStageFlags::VERTEX,
-                                module: vert,
-                                p_name: entry_point,
-                                ..Default::default()
-                            },
-                            vk::PipelineShaderStageCreateInfo {
-                                stage: vk::ShaderStageFlags::FRAGMENT,
-                                module: frag,
-                                p_name: entry_point,
-                                ..Default::default()
-                            },
-                        ])
-                        .vertex_input_state(
-                            &vk::PipelineVertexInputStateCreateInfo::builder()
-                                .vertex_binding_descriptions(&[vk::VertexInputBindingDescription {
-                                    binding: 0,
-                                    stride: TRANSFORM_SIZE as u32,
-                                    input_rate: vk::VertexInputRate::INSTANCE,
-                                }])
-                                .vertex_attribute_descriptions(&[
-                                    vk::VertexInputAttributeDescription {
-                                        location: 0,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 0,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 1,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 16,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 2,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 32,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 3,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 48,
-                                    },
-                                ]),
-                        )
-                        .input_assembly_state(
-                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
-                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
-                        )
-                        .viewport_state(
-                            &vk::PipelineViewportStateCreateInfo::builder()
-                                .scissor_count(1)
-                                .viewport_count(1),
-                        )
-                        .rasterization_state(
-                            &vk::PipelineRasterizationStateCreateInfo::builder()
-                                .cull_mode(vk::CullModeFlags::BACK)
-                                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                                .polygon_mode(vk::PolygonMode::FILL)
-                                .line_width(1.0),
-                        )
-                        .multisample_state(
-                            &vk::PipelineMultisampleStateCreateInfo::builder()
-                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
-                        )
-                        .depth_stencil_state(
-                            &vk::PipelineDepthStencilStateCreateInfo::builder()
-                                .depth_test_enable(true)
-                                .depth_write_enable(true)
-                                .depth_compare_op(vk::CompareOp::GREATER),
-                        )
-                        .color_blend_state(
-                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
-                                vk::PipelineColorBlendAttachmentState {
-                                    blend_enable: vk::TRUE,
-                                    src_color_blend_factor: vk::BlendFactor::ONE,
-                                    dst_color_blend_factor: vk::BlendFactor::ZERO,
-                                    color_blend_op: vk::BlendOp::ADD,
-                                    color_write_mask: vk::ColorComponentFlags::R
-                                        | vk::ColorComponentFlags::G
-                                        | vk::ColorComponentFlags::B,
-                                    ..Default::default()
-                                },
-                            ]),
-                        )
-                        .dynamic_state(
-                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
-                                vk::DynamicState::VIEWPORT,
-                                vk::DynamicState::SCISSOR,
-                            ]),
-                        )
+            // Both pipelines read the per-chunk instance data; CPU-extracted surfaces also read
+            // their own vertices
+            let bindings = [
+                vk::VertexInputBindingDescription {
+                    binding: 0,
+                    stride: TRANSFORM_SIZE as u32,
+                    input_rate: vk::VertexInputRate::INSTANCE,
+                },
+                vk::VertexInputBindingDescription {
+                    binding: 1,
+                    stride: FADE_SIZE as u32,
+                    input_rate: vk::VertexInputRate::INSTANCE,
+                },
+                vk::VertexInputBindingDescription {
+                    binding: 2,
+                    stride: TINTS_SIZE as u32,
+                    input_rate: vk::VertexInputRate::INSTANCE,
+                },
+                vk::VertexInputBindingDescription {
+                    binding: 3,
+                    stride: mem::size_of::<meshed::Vertex>() as u32,
+                    input_rate: vk::VertexInputRate::VERTEX,
+                },
+            ];
+            let attributes = [
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 16,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 2,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 3,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 48,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 4,
+                    binding: 1,
+                    format: vk::Format::R32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 5,
+                    binding: 2,
+                    format: vk::Format::R32G32B32A32_UINT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 6,
+                    binding: 2,
+                    format: vk::Format::R32G32B32A32_UINT,
+                    offset: 16,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 7,
+                    binding: 3,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 8,
+                    binding: 3,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 12,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 9,
+                    binding: 3,
+                    format: vk::Format::R32_UINT,
+                    offset: 24,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 10,
+                    binding: 3,
+                    format: vk::Format::R32_SFLOAT,
+                    offset: 28,
+                },
+            ];
+            let extracted_input = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&bindings[..3])
+                .vertex_attribute_descriptions(&attributes[..7]);
+            let meshed_input = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&bindings)
+                .vertex_attribute_descriptions(&attributes);
+            let extracted_stages = [
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::VERTEX,
+                    module: vert,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::FRAGMENT,
+                    module: frag,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+            ];
+            let meshed_stages = [
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::VERTEX,
+                    module: meshed_vert,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::FRAGMENT,
+                    module: meshed_frag,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+            ];
+            let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+            let viewport = vk::PipelineViewportStateCreateInfo::builder()
+                .scissor_count(1)
+                .viewport_count(1);
+            let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+                .cull_mode(vk::CullModeFlags::BACK)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .polygon_mode(vk::PolygonMode::FILL)
+                .line_width(1.0);
+            let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(true)
+                .depth_write_enable(true)
+                .depth_compare_op(vk::CompareOp::GREATER);
+            let color_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ZERO,
+                color_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B,
+                ..Default::default()
+            }];
+            let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&color_blend_attachments);
+            let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+            let dynamic =
+                vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+            let pipeline_info =
+                |stages: &[vk::PipelineShaderStageCreateInfo],
+                 vertex_input: &vk::PipelineVertexInputStateCreateInfo| {
+                    vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(stages)
+                        .vertex_input_state(vertex_input)
+                        .input_assembly_state(&input_assembly)
+                        .viewport_state(&viewport)
+                        .rasterization_state(&rasterization)
+                        .multisample_state(&multisample)
+                        .depth_stencil_state(&depth_stencil)
+                        .color_blend_state(&color_blend)
+                        .dynamic_state(&dynamic)
                         .layout(pipeline_layout)
                         .render_pass(gfx.render_pass)
                         .subpass(0)
-                        .build()],
+                        .build()
+                };
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[
+                        pipeline_info(&extracted_stages, &extracted_input),
+                        pipeline_info(&meshed_stages, &meshed_input),
+                    ],
                     None,
                 )
                 .unwrap()
@@ -221,11 +324,15 @@ impl Surface {
 
             let pipeline = pipelines.next().unwrap();
             gfx.set_name(pipeline, cstr!("voxels"));
+            let meshed_pipeline = pipelines.next().unwrap();
+            gfx.set_name(meshed_pipeline, cstr!("meshed voxels"));
 
             // Clean up the shaders explicitly, so the defer guards don't hold onto references we're
             // moving into `Self` to be returned
             v_guard.invoke();
             f_guard.invoke();
+            mv_guard.invoke();
+            mf_guard.invoke();
 
             let colors = loader.load(
                 "voxel materials",
@@ -239,6 +346,7 @@ impl Surface {
                 static_ds_layout,
                 pipeline_layout,
                 pipeline,
+                meshed_pipeline,
                 descriptor_pool,
                 ds,
                 colors,
@@ -301,7 +409,16 @@ impl Surface {
             &[common_ds, self.ds],
             &[],
         );
-        device.cmd_bind_vertex_buffers(cmd, 0, &[frame.transforms.buffer()], &[0]);
+        device.cmd_bind_vertex_buffers(
+            cmd,
+            0,
+            &[
+                frame.transforms.buffer(),
+                frame.fades.buffer(),
+                frame.tints.buffer(),
+            ],
+            &[0, 0, 0],
+        );
 
         device.cmd_push_constants(
             cmd,
@@ -320,18 +437,63 @@ impl Surface {
         cmd: vk::CommandBuffer,
         buffer: &DrawBuffer,
         chunk: u32,
+        subdivisions: u32,
+    ) {
+        // Each draw produces the same piece of every face in the chunk
+        for piece in 0..subdivisions.pow(2) {
+            let mut constants = [0; 8];
+            constants[0..4].copy_from_slice(&subdivisions.to_ne_bytes());
+            constants[4..8].copy_from_slice(&piece.to_ne_bytes());
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                4,
+                &constants,
+            );
+            device.cmd_draw_indirect(
+                cmd,
+                buffer.indirect_buffer(),
+                buffer.indirect_offset(chunk),
+                1,
+                16,
+            );
+        }
+    }
+
+    /// Switch to drawing surfaces from `storage`, after `bind`
+    pub unsafe fn bind_meshed(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        storage: &MeshStorage,
+    ) {
+        // The pipelines share a layout, so descriptor sets and push constants remain bound
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.meshed_pipeline);
+        device.cmd_bind_vertex_buffers(cmd, 3, &[storage.vertex_buffer()], &[0]);
+        device.cmd_bind_index_buffer(cmd, storage.index_buffer(), 0, vk::IndexType::UINT32);
+    }
+
+    pub unsafe fn draw_meshed(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        mesh: &MeshAlloc,
+        chunk: u32,
     ) {
-        device.cmd_draw_indirect(
+        device.cmd_draw_indexed(
             cmd,
-            buffer.indirect_buffer(),
-            buffer.indirect_offset(chunk),
+            mesh.index_count,
             1,
-            16,
+            mesh.first_index,
+            mesh.first_vertex as i32,
+            chunk,
         );
     }
 
     pub unsafe fn destroy(&mut self, device: &Device) {
         device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline(self.meshed_pipeline, None);
         device.destroy_pipeline_layout(self.pipeline_layout, None);
         device.destroy_descriptor_set_layout(self.static_ds_layout, None);
         device.destroy_descriptor_pool(self.descriptor_pool, None);
@@ -343,6 +505,10 @@ impl Surface {
 
 pub struct Frame {
     transforms: DedicatedMapping<[na::Matrix4<f32>]>,
+    /// Opacity of each chunk, for fading in newly loaded chunks
+    fades: DedicatedMapping<[f32]>,
+    /// Climate-dependent colors of each chunk, per `ChunkTints::packed`
+    tints: DedicatedMapping<[[u32; 8]]>,
 }
 
 impl Frame {
@@ -355,20 +521,52 @@ impl Frame {
                 count as usize * TRANSFORM_SIZE as usize,
             );
             gfx.set_name(transforms.buffer(), cstr!("voxel transforms"));
-            Self { transforms }
+            let fades = DedicatedMapping::zeroed_array(
+                &gfx.device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                count as usize,
+            );
+            gfx.set_name(fades.buffer(), cstr!("voxel fades"));
+            let tints = DedicatedMapping::zeroed_array(
+                &gfx.device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                count as usize,
+            );
+            gfx.set_name(tints.buffer(), cstr!("voxel tints"));
+            Self {
+                transforms,
+                fades,
+                tints,
+            }
         }
     }
 
     pub fn transforms_mut(&mut self) -> &mut [na::Matrix4<f32>] {
         &mut self.transforms
     }
+
+    pub fn fades_mut(&mut self) -> &mut [f32] {
+        &mut self.fades
+    }
+
+    pub fn tints_mut(&mut self) -> &mut [[u32; 8]] {
+        &mut self.tints
+    }
 }
 
 impl Frame {
     pub unsafe fn destroy(&mut self, device: &Device) {
         self.transforms.destroy(device);
+        self.fades.destroy(device);
+        self.tints.destroy(device);
     }
 }
 
 // 4x4 f32 matrix
 pub const TRANSFORM_SIZE: vk::DeviceSize = 64;
+// f32
+pub const FADE_SIZE: vk::DeviceSize = 4;
+// 8 packed RGBA8 colors
+pub const TINTS_SIZE: vk::DeviceSize = 32;
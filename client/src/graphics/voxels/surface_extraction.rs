@@ -5,7 +5,10 @@ use lahar::{DedicatedBuffer, DedicatedMapping};
 use vk_shader_macros::include_glsl;
 
 use crate::graphics::{as_bytes, Base, VkDrawIndirectCommand};
-use common::{defer, world::Material};
+use common::{
+    defer,
+    world::{Material, VoxelShape},
+};
 
 const EXTRACT: &[u32] = include_glsl!("shaders/surface-extraction/extract.comp", target: vulkan1_1);
 
@@ -144,6 +147,66 @@ impl SurfaceExtraction {
         }
     }
 
+    /// Point the draws of `tasks` at faces already extracted for other chunks
+    ///
+    /// Must be recorded after any `ScratchBuffer::extract` that writes those faces.
+    pub unsafe fn share(
+        &self,
+        device: &Device,
+        indirect_buffer: vk::Buffer,
+        cmd: vk::CommandBuffer,
+        tasks: &[ShareTask],
+    ) {
+        if tasks.is_empty() {
+            return;
+        }
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            Default::default(),
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                ..Default::default()
+            }],
+            &[],
+            &[],
+        );
+        for task in tasks {
+            // Everything but the instance, which selects the chunk's own transform
+            device.cmd_copy_buffer(
+                cmd,
+                indirect_buffer,
+                indirect_buffer,
+                &[vk::BufferCopy {
+                    src_offset: task.source_indirect_offset,
+                    dst_offset: task.indirect_offset,
+                    size: INDIRECT_SIZE - 4,
+                }],
+            );
+            device.cmd_update_buffer(
+                cmd,
+                indirect_buffer,
+                task.indirect_offset + INDIRECT_SIZE - 4,
+                &task.draw_id.to_ne_bytes(),
+            );
+        }
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            Default::default(),
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::INDIRECT_COMMAND_READ,
+                ..Default::default()
+            }],
+            &[],
+            &[],
+        );
+    }
+
     pub unsafe fn destroy(&mut self, device: &Device) {
         device.destroy_descriptor_set_layout(self.params_layout, None);
         device.destroy_descriptor_set_layout(self.ds_layout, None);
@@ -160,7 +223,8 @@ pub struct ScratchBuffer {
     voxel_buffer_unit: vk::DeviceSize,
     /// Size of a single entry in the state buffer
     state_buffer_unit: vk::DeviceSize,
-    voxels_staging: DedicatedMapping<[Material]>,
+    /// Voxels to extract surfaces from, per `pack_voxel`
+    voxels_staging: DedicatedMapping<[u16]>,
     voxels: DedicatedBuffer,
     state: DedicatedBuffer,
     descriptor_pool: vk::DescriptorPool,
@@ -175,7 +239,7 @@ impl ScratchBuffer {
         let device = &*gfx.device;
         // Padded by 2 on each dimension so each voxel of interest has a full neighborhood
         let voxel_buffer_unit = round_up(
-            mem::size_of::<Material>() as vk::DeviceSize * (dimension as vk::DeviceSize + 2).pow(3),
+            mem::size_of::<u16>() as vk::DeviceSize * (dimension as vk::DeviceSize + 2).pow(3),
             // Pad at least to multiples of 4 so the shaders can safely read in 32 bit units
             gfx.limits.min_storage_buffer_offset_alignment.max(4),
         );
@@ -200,7 +264,7 @@ impl ScratchBuffer {
                 device,
                 &gfx.memory_properties,
                 vk::BufferUsageFlags::TRANSFER_SRC,
-                (voxels_size / mem::size_of::<Material>() as vk::DeviceSize) as usize,
+                (voxels_size / mem::size_of::<u16>() as vk::DeviceSize) as usize,
             );
             gfx.set_name(voxels_staging.buffer(), cstr!("voxels staging"));
 
@@ -302,9 +366,9 @@ impl ScratchBuffer {
         self.free_slots.push(index);
     }
 
-    /// Includes a one-voxel margin around the entire volume
-    pub fn storage(&mut self, index: u32) -> &mut [Material] {
-        let start = index as usize * (self.voxel_buffer_unit as usize / mem::size_of::<Material>());
+    /// Includes a one-voxel margin around the entire volume, with each voxel per `pack_voxel`
+    pub fn storage(&mut self, index: u32) -> &mut [u16] {
+        let start = index as usize * (self.voxel_buffer_unit as usize / mem::size_of::<u16>());
         let length = (self.dimension + 2).pow(3) as usize;
         &mut self.voxels_staging[start..start + length]
     }
@@ -325,14 +389,14 @@ impl ScratchBuffer {
             0,
             as_bytes(&Params {
                 dimension: self.dimension,
+                slab_materials: materials_shaped(VoxelShape::Slab),
             }),
         );
         device.cmd_fill_buffer(cmd, self.state.handle, 0, vk::WHOLE_SIZE, 0);
 
         let voxel_count = (self.dimension + 2).pow(3) as usize;
-        let voxels_range =
-            voxel_count as vk::DeviceSize * mem::size_of::<Material>() as vk::DeviceSize;
-        let max_faces = 3 * (self.dimension.pow(3) + self.dimension.pow(2));
+        let voxels_range = voxel_count as vk::DeviceSize * mem::size_of::<u16>() as vk::DeviceSize;
+        let max_faces = max_faces(self.dimension);
         let dispatch = dispatch_sizes(self.dimension);
         self.voxels_staging.flush(device);
         device.cmd_bind_descriptor_sets(
@@ -510,6 +574,15 @@ pub struct ExtractTask {
     pub reverse_winding: bool,
 }
 
+/// Specifies a chunk to be drawn from faces already extracted for another
+#[derive(Debug, Copy, Clone)]
+pub struct ShareTask {
+    /// Location of the indirect draw of the chunk whose faces are shared
+    pub source_indirect_offset: vk::DeviceSize,
+    pub indirect_offset: vk::DeviceSize,
+    pub draw_id: u32,
+}
+
 fn dispatch_sizes(dimension: u32) -> na::Vector3<u32> {
     fn divide_rounding_up(x: u32, y: u32) -> u32 {
         debug_assert!(x > 0 && y > 0);
@@ -529,6 +602,27 @@ fn dispatch_sizes(dimension: u32) -> na::Vector3<u32> {
 #[derive(Copy, Clone)]
 struct Params {
     dimension: u32,
+    /// Per `materials_shaped`
+    slab_materials: u32,
+}
+
+/// A voxel as surface extraction reads it, with its `Material` discriminant in the low 8 bits and its
+/// light level, per `padded_light`, in the high 8
+pub fn pack_voxel(material: Material, light: u8) -> u16 {
+    material as u16 | u16::from(light) << 8
+}
+
+/// Bitmask of the materials having `shape`, indexed by `Material` discriminant
+fn materials_shaped(shape: VoxelShape) -> u32 {
+    // Also ensures discriminants fit in the 8 bits `pack_voxel` allows
+    assert!(
+        Material::COUNT <= 32,
+        "too many materials for a 32-bit mask"
+    );
+    Material::ALL
+        .iter()
+        .filter(|x| x.shape() == shape)
+        .fold(0, |mask, &x| mask | 1 << x as u32)
 }
 
 /// Manages storage for ready-to-render voxels
@@ -546,7 +640,7 @@ impl DrawBuffer {
     pub fn new(gfx: &Base, count: u32, dimension: u32) -> Self {
         let device = &*gfx.device;
 
-        let max_faces = 3 * (dimension.pow(3) + dimension.pow(2));
+        let max_faces = max_faces(dimension);
         let face_buffer_unit = round_up(
             max_faces as vk::DeviceSize * FACE_SIZE,
             gfx.limits.min_storage_buffer_offset_alignment,
@@ -562,6 +656,7 @@ impl DrawBuffer {
                     .usage(
                         vk::BufferUsageFlags::STORAGE_BUFFER
                             | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_SRC
                             | vk::BufferUsageFlags::TRANSFER_DST,
                     )
                     .sharing_mode(vk::SharingMode::EXCLUSIVE),
@@ -628,6 +723,14 @@ const INDIRECT_SIZE: vk::DeviceSize = 16;
 
 const FACE_SIZE: vk::DeviceSize = 8;
 
+/// Upper bound on the number of faces extracted from a chunk
+///
+/// Each voxel and those one past the end on each axis contributes at most one face per axis,
+/// except that a partially filled voxel can leave two faces visible along y.
+fn max_faces(dimension: u32) -> u32 {
+    4 * (dimension.pow(3) + dimension.pow(2))
+}
+
 const WORKGROUP_SIZE: [u32; 3] = [4, 4, 4];
 
 fn round_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
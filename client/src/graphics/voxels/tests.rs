@@ -1,12 +1,19 @@
-use std::{mem, sync::Arc};
+use std::{mem, sync::Arc, time::Duration};
 
 use ash::{version::DeviceV1_0, vk};
 use lahar::DedicatedMapping;
 use renderdoc::{RenderDoc, V110};
 
-use super::{surface_extraction, SurfaceExtraction};
+use super::{
+    fade, lod_level, priority, subdivisions, surface_extraction, SurfaceExtraction, MAX_LOD,
+    VIEW_PRIORITY,
+};
 use crate::graphics::{Base, VkDrawIndirectCommand};
-use common::world::Material;
+use common::{
+    dodeca::{Side, Vertex},
+    math,
+    world::Material,
+};
 
 struct SurfaceExtractionTest {
     gfx: Arc<Base>,
@@ -143,8 +150,9 @@ struct Surface {
     y: u8,
     z: u8,
     axis: u8,
-    mat: Material,
-    _padding: u8,
+    mat: u8,
+    light: u8,
+    squash: u8,
     occlusion: u8,
 }
 
@@ -157,7 +165,7 @@ fn surface_extraction() {
     let mut test = SurfaceExtractionTest::new();
 
     for x in test.scratch.storage(0) {
-        *x = Material::Void;
+        *x = Material::Void as u16;
     }
 
     test.run();
@@ -168,7 +176,7 @@ fn surface_extraction() {
     );
 
     for x in test.scratch.storage(0) {
-        *x = Material::Stone;
+        *x = Material::Stone as u16;
     }
 
     test.run();
@@ -180,12 +188,13 @@ fn surface_extraction() {
 
     let storage = test.scratch.storage(0);
     for x in &mut storage[..] {
-        *x = Material::Void;
+        *x = Material::Void as u16;
     }
     for z in 0..((DIMENSION + 2) / 2) {
         for y in 0..(DIMENSION + 2) {
             for x in 0..(DIMENSION + 2) {
-                storage[x + y * (DIMENSION + 2) + z * (DIMENSION + 2).pow(2)] = Material::Stone;
+                storage[x + y * (DIMENSION + 2) + z * (DIMENSION + 2).pow(2)] =
+                    Material::Stone as u16;
             }
         }
     }
@@ -204,8 +213,9 @@ fn surface_extraction() {
             y: 0,
             z: 1,
             axis: 5,
-            mat: Material::Stone,
-            _padding: 0,
+            mat: Material::Stone as u8,
+            light: 0,
+            squash: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -213,8 +223,9 @@ fn surface_extraction() {
             y: 0,
             z: 1,
             axis: 5,
-            mat: Material::Stone,
-            _padding: 0,
+            mat: Material::Stone as u8,
+            light: 0,
+            squash: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -222,8 +233,9 @@ fn surface_extraction() {
             y: 1,
             z: 1,
             axis: 5,
-            mat: Material::Stone,
-            _padding: 0,
+            mat: Material::Stone as u8,
+            light: 0,
+            squash: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -231,11 +243,190 @@ fn surface_extraction() {
             y: 1,
             z: 1,
             axis: 5,
-            mat: Material::Stone,
-            _padding: 0,
+            mat: Material::Stone as u8,
+            light: 0,
+            squash: 0,
             occlusion: 0xFF,
         },
     ] {
         assert!(surfaces.contains(expected));
     }
+
+    // A lone slab
+    let storage = test.scratch.storage(0);
+    for x in &mut storage[..] {
+        *x = Material::Void as u16;
+    }
+    storage[1 + (DIMENSION + 2) + (DIMENSION + 2).pow(2)] = Material::GreyBrickSlab as u16;
+
+    test.run();
+
+    assert_eq!(test.indirect.vertex_count, 6 * 6, "slabs have six faces");
+    let surfaces = &test.surfaces[..6];
+    let squashed = |squash| surfaces.iter().filter(|s| s.squash == squash).count();
+    assert_eq!(squashed(1), 4, "sides of slabs are half height");
+    assert_eq!(squashed(2), 1, "tops of slabs are lowered");
+    assert_eq!(squashed(0), 1, "bottoms of slabs are unchanged");
+}
+
+#[test]
+fn fade_ramp() {
+    let duration = Duration::from_millis(300);
+    assert_eq!(fade(Duration::from_millis(0), duration), 0.0);
+    assert!((fade(Duration::from_millis(150), duration) - 0.5).abs() < 1e-6);
+    assert_eq!(fade(duration, duration), 1.0);
+    assert_eq!(fade(Duration::from_secs(10), duration), 1.0);
+    // Fading can be disabled entirely
+    assert_eq!(fade(Duration::from_millis(0), Duration::from_millis(0)), 1.0);
+}
+
+#[test]
+fn looked_at_chunks_first() {
+    let at = |direction: na::Unit<na::Vector3<f32>>, distance: f32| {
+        priority(&(math::translate_along(&direction, distance) * math::origin()))
+    };
+    // The view looks along -Z
+    let ahead = at(-na::Vector3::z_axis(), 2.0);
+    let side = at(na::Vector3::x_axis(), 2.0);
+    let above = at(na::Vector3::y_axis(), 2.0);
+    let behind = at(na::Vector3::z_axis(), 2.0);
+    assert!(ahead > side);
+    assert!((side - above).abs() < 1e-5);
+    assert!(side > behind);
+    // Distance still matters: something close behind beats something far ahead
+    assert!(at(na::Vector3::z_axis(), 0.5) > at(-na::Vector3::z_axis(), 2.5 + VIEW_PRIORITY));
+    // The viewer's own position comes first of all
+    assert!(priority(&math::origin()) > ahead);
+}
+
+#[test]
+fn distant_chunks_subdivided() {
+    let chunk_to_node = Vertex::A.chunk_to_node().map(|x| x as f32);
+    assert_eq!(
+        subdivisions(&chunk_to_node),
+        1,
+        "nearby faces are drawn whole"
+    );
+    let far = math::translate_along(&na::Vector3::x_axis(), 4.0f32) * chunk_to_node;
+    assert!(subdivisions(&far) > 1, "distant faces are subdivided");
+    let farther = math::translate_along(&na::Vector3::x_axis(), 8.0f32) * chunk_to_node;
+    assert!(subdivisions(&farther) >= subdivisions(&far));
+}
+
+#[test]
+fn distant_chunks_coarsened() {
+    let chunk_to_node = Vertex::A.chunk_to_node().map(|x| x as f32);
+    let at =
+        |distance: f32| math::translate_along(&na::Vector3::x_axis(), distance) * chunk_to_node;
+    assert_eq!(
+        lod_level(&at(0.0), 2.0),
+        0,
+        "nearby chunks are drawn at full detail"
+    );
+    assert_eq!(lod_level(&at(2.0), 2.0), 1);
+    assert_eq!(lod_level(&at(4.0), 2.0), 2);
+    assert_eq!(
+        lod_level(&at(8.0), 2.0),
+        MAX_LOD,
+        "detail is only reduced so far"
+    );
+    assert_eq!(lod_level(&at(8.0), 0.0), 0, "reduction can be disabled");
+}
+
+/// Unit normal, in local node space, of the face lying in the plane where cube coordinate `axis` of
+/// a chunk equals `offset`
+///
+/// Chunk transforms are projective maps of the Klein model, so every face remains a geodesic plane
+/// however warped it looks. Carrying the plane's covector through `chunk_to_local` and raising its
+/// index yields a Minkowski vector orthogonal to every point on the plane, which is the normal at
+/// all of them at once. Must agree with `surface_normal` in `shading.h`.
+fn face_normal(chunk_to_local: &na::Matrix4<f32>, axis: usize, offset: f32) -> na::Vector4<f32> {
+    let mut plane = na::RowVector4::zeros();
+    plane[axis] = 1.0;
+    plane.w = -offset;
+    let plane = plane * chunk_to_local.try_inverse().unwrap();
+    let normal = na::Vector4::new(plane.x, plane.y, plane.z, -plane.w);
+    normal / math::mip(&normal, &normal).sqrt()
+}
+
+#[test]
+fn face_normal_matches_warped_surface() {
+    let direction = na::Unit::new_normalize(na::Vector3::new(1.0, 2.0, 3.0));
+    let transform = math::translate_along(&direction, 1.7) * Vertex::A.chunk_to_node();
+    // Point on the hyperboloid corresponding to cube coordinates `x`
+    let warp = |x: na::Vector3<f64>| math::lorentz_normalize(&(transform * x.push(1.0)));
+    let h = 1e-4;
+    for axis in 0..3 {
+        for &offset in &[0.25, 0.5, 1.0] {
+            let mut center = na::Vector3::repeat(0.5);
+            center[axis] = offset;
+            let p = warp(center);
+            // Finite-difference tangent along `i`, scaled to unit Minkowski length
+            let tangent = |i: usize| {
+                let mut step = na::Vector3::zeros();
+                step[i] = h;
+                let t = (warp(center + step) - warp(center - step)) / (2.0 * h);
+                t / math::mip(&t, &t).sqrt()
+            };
+            let (u, v) = (tangent((axis + 1) % 3), tangent((axis + 2) % 3));
+            // Component of the tangent across the face orthogonal to the face itself
+            let mut expected = tangent(axis);
+            let v = v - u * math::mip(&v, &u);
+            let v = v / math::mip(&v, &v).sqrt();
+            for t in &[u, v] {
+                expected -= t * math::mip(&expected, t);
+            }
+            expected /= math::mip(&expected, &expected).sqrt();
+
+            let actual = face_normal(&transform.map(|x| x as f32), axis, offset as f32);
+            let actual = actual.map(f64::from);
+            assert!(math::mip(&actual, &p).abs() < 1e-3, "normal is tangent");
+            assert!(
+                (actual - expected).amax() < 1e-2,
+                "axis {} offset {}: {} != {}",
+                axis,
+                offset,
+                actual,
+                expected
+            );
+        }
+    }
+}
+
+/// `voxels.vert` leaves no cracks between chunks only if chunks sharing a face have exactly the
+/// same transform columns for the sides they share, and the same origin
+#[test]
+fn chunk_transforms_agree() {
+    let direction = na::Unit::new_normalize(na::Vector3::new(1.0, 2.0, 3.0));
+    let node_transform = math::translate_along(&direction, 1.7f32);
+    for a in Vertex::iter() {
+        for b in Vertex::iter().filter(|&b| b != a) {
+            let a_sides = a.canonical_sides();
+            let b_sides = b.canonical_sides();
+            let shared = a_sides
+                .iter()
+                .filter(|&&s| b_sides.contains(&s))
+                .cloned()
+                .collect::<Vec<_>>();
+            if shared.len() != 2 {
+                // Not face-adjacent
+                continue;
+            }
+            // As computed for drawing
+            let a_xf = node_transform * a.chunk_to_node().map(|x| x as f32);
+            let b_xf = node_transform * b.chunk_to_node().map(|x| x as f32);
+            let axis = |sides: &[Side; 3], side| sides.iter().position(|&s| s == side).unwrap();
+            for &side in &shared {
+                assert_eq!(
+                    a_xf.column(axis(&a_sides, side)),
+                    b_xf.column(axis(&b_sides, side)),
+                    "{:?} and {:?} disagree on {:?}",
+                    a,
+                    b,
+                    side
+                );
+            }
+            assert_eq!(a_xf.column(3), b_xf.column(3));
+        }
+    }
 }
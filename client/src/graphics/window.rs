@@ -1,22 +1,27 @@
 use std::f32;
 use std::ffi::CStr;
+use std::fs;
+use std::io;
 use std::sync::Arc;
 use std::time::Instant;
 
 use ash::{extensions::khr, version::DeviceV1_0, vk};
 use lahar::DedicatedImage;
-use tracing::info;
+use tracing::{error, info};
 use winit::{
     dpi::PhysicalSize,
-    event::{
-        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
-    },
+    event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{Window as WinitWindow, WindowBuilder},
 };
 
 use super::{Base, Core, Draw, Frustum};
-use crate::{Config, Sim};
+use crate::{
+    camera_effects::CameraEffects,
+    export,
+    input::{Action, Held},
+    Config, Sim,
+};
 
 /// OS window
 pub struct EarlyWindow {
@@ -53,6 +58,7 @@ pub struct Window {
     swapchain_needs_update: bool,
     draw: Option<Draw>,
     sim: Sim,
+    effects: CameraEffects,
 }
 
 impl Window {
@@ -72,6 +78,7 @@ impl Window {
         Self {
             _core: core,
             window: early.window,
+            effects: CameraEffects::new(config.head_bob, config.fov_kick),
             config,
             metrics,
             event_loop: Some(early.event_loop),
@@ -103,14 +110,7 @@ impl Window {
         ));
         // Construct the core rendering object
         self.draw = Some(Draw::new(gfx, self.config.clone()));
-        let mut forward = false;
-        let mut back = false;
-        let mut left = false;
-        let mut right = false;
-        let mut up = false;
-        let mut down = false;
-        let mut clockwise = false;
-        let mut anticlockwise = false;
+        let mut held = Held::default();
         let mut last_frame = Instant::now();
         let mut focused = true;
         self.event_loop
@@ -118,20 +118,19 @@ impl Window {
             .unwrap()
             .run(move |event, _, control_flow| match event {
                 Event::MainEventsCleared => {
-                    let velocity = na::Vector3::x() * (right as u8 as f32 - left as u8 as f32)
-                        + na::Vector3::y() * (up as u8 as f32 - down as u8 as f32)
-                        + na::Vector3::z() * (back as u8 as f32 - forward as u8 as f32);
-                    self.sim.velocity(velocity);
+                    self.sim.velocity(held.velocity());
 
                     self.sim.rotate(&na::UnitQuaternion::from_axis_angle(
                         &-na::Vector3::z_axis(),
-                        (clockwise as u8 as f32 - anticlockwise as u8 as f32) * 5e-2,
+                        held.roll() * 5e-2,
                     ));
 
                     let had_params = self.sim.params().is_some();
 
                     let this_frame = Instant::now();
-                    self.sim.step(this_frame - last_frame);
+                    let dt = this_frame - last_frame;
+                    self.sim.step(dt);
+                    self.effects.step(dt.as_secs_f32(), self.sim.speed());
                     last_frame = this_frame;
 
                     if !had_params {
@@ -184,37 +183,52 @@ impl Window {
                                 ..
                             },
                         ..
-                    } => match key {
-                        VirtualKeyCode::W => {
-                            forward = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::A => {
-                            left = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::S => {
-                            back = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::D => {
-                            right = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::Q => {
-                            anticlockwise = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::E => {
-                            clockwise = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::R => {
-                            up = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::F => {
-                            down = state == ElementState::Pressed;
-                        }
-                        VirtualKeyCode::Escape => {
-                            let _ = self.window.set_cursor_grab(false);
-                            self.window.set_cursor_visible(true);
+                    } => {
+                        let action = match self.config.bindings.action(key) {
+                            Some(x) => x,
+                            None => return,
+                        };
+                        let pressed = state == ElementState::Pressed;
+                        match action {
+                            Action::ReleaseCursor => {
+                                let _ = self.window.set_cursor_grab(false);
+                                self.window.set_cursor_visible(true);
+                            }
+                            Action::ToggleBoundaries => {
+                                if pressed {
+                                    if let Some(ref mut draw) = self.draw {
+                                        draw.toggle_boundaries();
+                                    }
+                                }
+                            }
+                            Action::Export => {
+                                if pressed {
+                                    self.export();
+                                }
+                            }
+                            Action::ToggleSpectator => {
+                                if pressed {
+                                    self.sim.toggle_spectator();
+                                }
+                            }
+                            Action::BreakVoxel => {
+                                if pressed {
+                                    self.sim.break_voxel();
+                                }
+                            }
+                            Action::PlaceVoxel => {
+                                if pressed {
+                                    self.sim.place_voxel();
+                                }
+                            }
+                            Action::Fire => {
+                                if pressed {
+                                    self.sim.fire();
+                                }
+                            }
+                            _ => held.set(action, pressed),
                         }
-                        _ => {}
-                    },
+                    }
                     WindowEvent::Focused(x) => {
                         focused = x;
                     }
@@ -227,6 +241,30 @@ impl Window {
             });
     }
 
+    /// Write the loaded terrain around the viewer to an OBJ file in the data directory
+    fn export(&self) {
+        let params = match self.sim.params() {
+            Some(x) => x,
+            None => return,
+        };
+        let mesh = export::Mesh::build(
+            &self.sim.graph,
+            params.chunk_size,
+            self.config.terrain_style,
+            &self.sim.view(),
+            f64::from(self.config.local_simulation.view_distance),
+            self.config.meshing_threads,
+        );
+        let path = self.config.data_dir.join("export.obj");
+        let result = fs::create_dir_all(&self.config.data_dir)
+            .and_then(|()| fs::File::create(&path))
+            .and_then(|file| mesh.write_obj(io::BufWriter::new(file)));
+        match result {
+            Ok(()) => info!(path = %path.display(), faces = mesh.faces.len(), "exported terrain"),
+            Err(e) => error!(path = %path.display(), "export failed: {}", e),
+        }
+    }
+
     /// Draw a new frame
     fn draw(&mut self) {
         let swapchain = self.swapchain.as_mut().unwrap();
@@ -260,7 +298,10 @@ impl Window {
             let aspect_ratio =
                 swapchain.state.extent.width as f32 / swapchain.state.extent.height as f32;
             let frame = &swapchain.state.frames[frame_id as usize];
-            let frustum = Frustum::from_vfov(f32::consts::FRAC_PI_4 * 1.2, aspect_ratio);
+            let frustum = Frustum::from_vfov(
+                f32::consts::FRAC_PI_4 * 1.2 * self.effects.fov_scale(),
+                aspect_ratio,
+            );
             // Render the frame
             draw.draw(
                 &mut self.sim,
@@ -269,6 +310,7 @@ impl Window {
                 swapchain.state.extent,
                 frame.present,
                 &frustum,
+                &self.effects,
             );
             // Submit the frame to be presented on the window
             match swapchain.queue_present(frame_id) {
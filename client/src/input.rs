@@ -0,0 +1,276 @@
+//! Mapping of physical keys to named actions
+
+use std::fmt;
+
+use fxhash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+/// Something the player can do with a key
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Action {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    RollClockwise,
+    RollAnticlockwise,
+    /// Release the cursor captured by clicking in the window
+    ReleaseCursor,
+    /// Toggle the node and chunk boundary overlay
+    ToggleBoundaries,
+    /// Export the loaded terrain around the viewer
+    Export,
+    /// Detach the camera from the character to fly freely, or reattach it
+    ToggleSpectator,
+    /// Break the voxel at the center of the view
+    BreakVoxel,
+    /// Place a voxel against the one at the center of the view
+    PlaceVoxel,
+    /// Launch a projectile along the line of sight
+    Fire,
+}
+
+impl Action {
+    pub const ALL: [Action; 15] = [
+        Action::Forward,
+        Action::Back,
+        Action::Left,
+        Action::Right,
+        Action::Up,
+        Action::Down,
+        Action::RollClockwise,
+        Action::RollAnticlockwise,
+        Action::ReleaseCursor,
+        Action::ToggleBoundaries,
+        Action::Export,
+        Action::ToggleSpectator,
+        Action::BreakVoxel,
+        Action::PlaceVoxel,
+        Action::Fire,
+    ];
+
+    fn default_key(self) -> VirtualKeyCode {
+        use Action::*;
+        match self {
+            Forward => VirtualKeyCode::W,
+            Back => VirtualKeyCode::S,
+            Left => VirtualKeyCode::A,
+            Right => VirtualKeyCode::D,
+            Up => VirtualKeyCode::R,
+            Down => VirtualKeyCode::F,
+            RollClockwise => VirtualKeyCode::E,
+            RollAnticlockwise => VirtualKeyCode::Q,
+            ReleaseCursor => VirtualKeyCode::Escape,
+            ToggleBoundaries => VirtualKeyCode::F3,
+            Export => VirtualKeyCode::F9,
+            ToggleSpectator => VirtualKeyCode::F4,
+            BreakVoxel => VirtualKeyCode::X,
+            PlaceVoxel => VirtualKeyCode::C,
+            Fire => VirtualKeyCode::G,
+        }
+    }
+}
+
+/// Which key triggers each action
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    actions: FxHashMap<VirtualKeyCode, Action>,
+}
+
+impl Bindings {
+    /// Bind each action to its key in `overrides`, or its default key if absent
+    pub fn new(overrides: &FxHashMap<Action, VirtualKeyCode>) -> Result<Self, Conflict> {
+        let mut actions = FxHashMap::<VirtualKeyCode, Action>::default();
+        for &action in &Action::ALL {
+            let key = overrides
+                .get(&action)
+                .cloned()
+                .unwrap_or_else(|| action.default_key());
+            if let Some(&other) = actions.get(&key) {
+                return Err(Conflict {
+                    key,
+                    actions: [other, action],
+                });
+            }
+            actions.insert(key, action);
+        }
+        Ok(Self { actions })
+    }
+
+    /// The action triggered by `key`, if any
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.actions.get(&key).cloned()
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::new(&FxHashMap::default()).expect("default bindings are unambiguous")
+    }
+}
+
+/// Key bindings as parsed directly out of the config file, omitting those left at their defaults
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawBindings {
+    forward: Option<VirtualKeyCode>,
+    back: Option<VirtualKeyCode>,
+    left: Option<VirtualKeyCode>,
+    right: Option<VirtualKeyCode>,
+    up: Option<VirtualKeyCode>,
+    down: Option<VirtualKeyCode>,
+    roll_clockwise: Option<VirtualKeyCode>,
+    roll_anticlockwise: Option<VirtualKeyCode>,
+    release_cursor: Option<VirtualKeyCode>,
+    toggle_boundaries: Option<VirtualKeyCode>,
+    export: Option<VirtualKeyCode>,
+    toggle_spectator: Option<VirtualKeyCode>,
+    break_voxel: Option<VirtualKeyCode>,
+    place_voxel: Option<VirtualKeyCode>,
+    fire: Option<VirtualKeyCode>,
+}
+
+impl RawBindings {
+    pub fn overrides(&self) -> FxHashMap<Action, VirtualKeyCode> {
+        use Action::*;
+        [
+            (Forward, self.forward),
+            (Back, self.back),
+            (Left, self.left),
+            (Right, self.right),
+            (Up, self.up),
+            (Down, self.down),
+            (RollClockwise, self.roll_clockwise),
+            (RollAnticlockwise, self.roll_anticlockwise),
+            (ReleaseCursor, self.release_cursor),
+            (ToggleBoundaries, self.toggle_boundaries),
+            (Export, self.export),
+            (ToggleSpectator, self.toggle_spectator),
+            (BreakVoxel, self.break_voxel),
+            (PlaceVoxel, self.place_voxel),
+            (Fire, self.fire),
+        ]
+        .iter()
+        .filter_map(|&(action, key)| Some((action, key?)))
+        .collect()
+    }
+}
+
+/// Two actions were bound to the same key
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    pub key: VirtualKeyCode,
+    pub actions: [Action; 2],
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is bound to both {:?} and {:?}",
+            self.key, self.actions[0], self.actions[1]
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Actions whose keys are currently held down
+#[derive(Debug, Default)]
+pub struct Held {
+    actions: FxHashSet<Action>,
+}
+
+impl Held {
+    pub fn set(&mut self, action: Action, pressed: bool) {
+        if pressed {
+            self.actions.insert(action);
+        } else {
+            self.actions.remove(&action);
+        }
+    }
+
+    fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.actions.contains(&positive) as u8 as f32
+            - self.actions.contains(&negative) as u8 as f32
+    }
+
+    /// Direction of requested movement, relative to the view
+    pub fn velocity(&self) -> na::Vector3<f32> {
+        na::Vector3::new(
+            self.axis(Action::Right, Action::Left),
+            self.axis(Action::Up, Action::Down),
+            self.axis(Action::Back, Action::Forward),
+        )
+    }
+
+    /// Requested roll, positive clockwise
+    pub fn roll(&self) -> f32 {
+        self.axis(Action::RollClockwise, Action::RollAnticlockwise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebind() {
+        let defaults = Bindings::default();
+        assert_eq!(defaults.action(VirtualKeyCode::W), Some(Action::Forward));
+        assert_eq!(defaults.action(VirtualKeyCode::Up), None);
+
+        let mut overrides = FxHashMap::default();
+        overrides.insert(Action::Forward, VirtualKeyCode::Up);
+        let bindings = Bindings::new(&overrides).unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::Up), Some(Action::Forward));
+        assert_eq!(bindings.action(VirtualKeyCode::W), None);
+        // Other actions are unaffected
+        assert_eq!(bindings.action(VirtualKeyCode::S), Some(Action::Back));
+    }
+
+    #[test]
+    fn parse() {
+        let raw: RawBindings = toml::from_str(
+            r#"
+forward = "Up"
+export = "S"
+"#,
+        )
+        .unwrap();
+        let conflict = Bindings::new(&raw.overrides()).unwrap_err();
+        assert_eq!(conflict.key, VirtualKeyCode::S);
+    }
+
+    #[test]
+    fn conflict() {
+        let mut overrides = FxHashMap::default();
+        overrides.insert(Action::Export, VirtualKeyCode::W);
+        let conflict = Bindings::new(&overrides).unwrap_err();
+        assert_eq!(conflict.key, VirtualKeyCode::W);
+        let mut actions = conflict.actions;
+        actions.sort_by_key(|x| format!("{:?}", x));
+        assert_eq!(actions, [Action::Export, Action::Forward]);
+
+        // Swapping two keys is fine
+        overrides.insert(Action::Forward, VirtualKeyCode::F9);
+        let bindings = Bindings::new(&overrides).unwrap();
+        assert_eq!(bindings.action(VirtualKeyCode::W), Some(Action::Export));
+        assert_eq!(bindings.action(VirtualKeyCode::F9), Some(Action::Forward));
+    }
+
+    #[test]
+    fn held_actions() {
+        let mut held = Held::default();
+        held.set(Action::Forward, true);
+        held.set(Action::Left, true);
+        assert_eq!(held.velocity(), na::Vector3::new(-1.0, 0.0, -1.0));
+        held.set(Action::Left, false);
+        held.set(Action::RollClockwise, true);
+        assert_eq!(held.velocity(), na::Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(held.roll(), 1.0);
+    }
+}
@@ -9,15 +9,28 @@ macro_rules! cstr {
     }};
 }
 
+pub mod audio;
+mod camera_effects;
+mod chunk_cache;
+mod chunk_mesh;
+mod clock;
 mod config;
+pub mod export;
 pub mod graphics;
+mod input;
 mod loader;
+mod mesh_cache;
+mod mesh_pool;
 pub mod metrics;
 pub mod net;
+mod occlusion;
+pub mod orbit;
+pub mod particles;
+mod precision;
 mod prediction;
 pub mod sim;
 
-pub use config::Config;
+pub use config::{Config, MovementFrame};
 pub use sim::Sim;
 
 use loader::{Asset, Loader};
@@ -44,10 +44,12 @@ pub struct Loader {
 
 impl Loader {
     pub fn new(cfg: Arc<Config>, gfx: Arc<Base>) -> Self {
-        let runtime = tokio::runtime::Builder::new()
-            .threaded_scheduler()
-            .build()
-            .unwrap();
+        let mut runtime = tokio::runtime::Builder::new();
+        runtime.threaded_scheduler();
+        if let Some(n) = cfg.worldgen_threads {
+            runtime.core_threads(n);
+        }
+        let runtime = runtime.build().unwrap();
         let (send, recv) = mpsc::unbounded_channel();
         let staging =
             StagingBuffer::new(gfx.device.clone(), &gfx.memory_properties, 32 * 1024 * 1024);
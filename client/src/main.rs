@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     net::{SocketAddr, UdpSocket},
     sync::Arc,
 };
@@ -26,6 +27,8 @@ fn main() {
         let key = cert.serialize_private_key_der();
         let cert = cert.serialize_der().unwrap();
         let sim_cfg = config.local_simulation.clone();
+        // Host every world up to the one selected, seeding each by its index
+        let worlds = (0..=u64::from(u32::from(config.world))).collect();
 
         std::thread::spawn(move || {
             let span = error_span!("server");
@@ -37,8 +40,18 @@ fn main() {
                     ),
                     private_key: quinn::PrivateKey::from_der(&key).unwrap(),
                     socket,
+                    max_players: 1,
+                    accounts: HashMap::new(),
+                    lockstep: false,
+                    idle_timeout: None,
+                    freeze_idle: false,
+                    edit_rate: None,
                 },
                 sim_cfg,
+                worlds,
+                None,
+                None,
+                None,
             ) {
                 eprintln!("{:#}", e);
                 std::process::exit(1);
@@ -54,7 +67,12 @@ fn main() {
 
     // Kick off networking
     let net = net::spawn(config.clone());
-    let sim = Sim::new(net);
+    let sim = Sim::new(
+        net,
+        config.movement_frame,
+        config.stabilize_roll,
+        config.precision_warning,
+    );
 
     // Finish creating the window, including the Vulkan resources used to render to it
     let window = graphics::Window::new(window, core.clone(), config, metrics, sim);
@@ -0,0 +1,175 @@
+//! On-disk cache of chunk surfaces extracted on the CPU
+//!
+//! Surfaces are keyed by a hash of the chunk's voxels, including the margin copied from each
+//! neighbor, so editing a chunk or its boundary simply changes the key, and a stale surface can never
+//! be found. Entries are never removed, but each is small, and identical chunks share one.
+//!
+//! Only surfaces extracted on the CPU, for terrain styles the GPU can't extract, are cached. Blocky
+//! surfaces are extracted on the GPU directly into the buffers they're drawn from, faster than they
+//! could be read back from disk.
+
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use fxhash::FxHasher64;
+use tracing::{debug, error};
+
+use crate::{
+    chunk_mesh::{ChunkMesh, Style},
+    mesh_pool::MeshPool,
+};
+use common::{codec, world::Material};
+
+#[derive(Clone)]
+pub struct MeshCache {
+    dir: PathBuf,
+}
+
+impl MeshCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cached surface of the chunk made of `voxels`, if any
+    pub fn get(&self, style: Style, dimension: u8, voxels: &[Material]) -> Option<ChunkMesh> {
+        let path = self.path(style, dimension, voxels);
+        let data = match fs::read(&path) {
+            Ok(x) => x,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                error!(path = %path.display(), "failed to read cached mesh: {}", e);
+                return None;
+            }
+        };
+        match codec::decode(&data) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                debug!(path = %path.display(), "discarding malformed cached mesh: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Record `mesh` as the surface of the chunk made of `voxels`
+    pub fn insert(
+        &self,
+        style: Style,
+        dimension: u8,
+        voxels: &[Material],
+        mesh: &ChunkMesh,
+    ) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(style, dimension, voxels);
+        // Write to a temporary file first so a concurrent reader never sees a partial mesh, named
+        // uniquely so that concurrent writers of the same mesh don't interleave
+        let temp =
+            path.with_extension(format!("{}.tmp", NEXT_TEMP.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&temp, &codec::encode(mesh))?;
+        fs::rename(&temp, &path)
+    }
+
+    /// The surface of the chunk made of `voxels`, extracting it into storage from `pool` and
+    /// caching it if necessary
+    pub fn get_or_extract(
+        &self,
+        style: Style,
+        dimension: u8,
+        voxels: &[Material],
+        pool: &MeshPool,
+    ) -> ChunkMesh {
+        if let Some(mesh) = self.get(style, dimension, voxels) {
+            return mesh;
+        }
+        let mut mesh = pool.take();
+        mesh.fill_styled(style, dimension, voxels);
+        if let Err(e) = self.insert(style, dimension, voxels, &mesh) {
+            error!(dir = %self.dir.display(), "failed to cache mesh: {}", e);
+        }
+        mesh
+    }
+
+    fn path(&self, style: Style, dimension: u8, voxels: &[Material]) -> PathBuf {
+        self.dir.join(file_name(style, dimension, voxels))
+    }
+}
+
+/// Name of the file holding the surface of the chunk made of `voxels`, shaped by `style`
+fn file_name(style: Style, dimension: u8, voxels: &[Material]) -> String {
+    // FxHash is unseeded, so keys are stable across runs
+    let mut hasher = FxHasher64::default();
+    FORMAT_VERSION.hash(&mut hasher);
+    style.hash(&mut hasher);
+    dimension.hash(&mut hasher);
+    voxels.hash(&mut hasher);
+    format!("{:016x}.mesh", hasher.finish())
+}
+
+/// Distinguishes temporary files written by concurrent insertions
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Changed whenever `ChunkMesh` or its extraction changes, so that outdated entries are ignored
+const FORMAT_VERSION: u32 = 3;
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const DIMENSION: u8 = 4;
+
+    /// A chunk containing a single solid voxel at `coords`, accounting for margins
+    fn voxels(coords: [usize; 3]) -> Vec<Material> {
+        let lwm = usize::from(DIMENSION) + 2;
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        voxels[(coords[0] + 1) + (coords[1] + 1) * lwm + (coords[2] + 1) * lwm.pow(2)] =
+            Material::Stone;
+        voxels
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let dir = env::temp_dir().join(format!("hypermine-mesh-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = MeshCache::new(dir.clone());
+        let pool = MeshPool::default();
+
+        let original = voxels([1, 1, 1]);
+        assert!(cache.get(Style::Blocky, DIMENSION, &original).is_none());
+        let extracted = cache.get_or_extract(Style::Blocky, DIMENSION, &original, &pool);
+        assert_eq!(extracted.faces.len(), 6);
+
+        // An identical chunk, such as the same one loaded again later, finds the same surface
+        let identical = original.clone();
+        assert_eq!(
+            cache.get(Style::Blocky, DIMENSION, &identical),
+            Some(extracted.clone())
+        );
+
+        // Editing the chunk, or only its margin, invalidates it
+        let edited = voxels([2, 1, 1]);
+        assert!(cache.get(Style::Blocky, DIMENSION, &edited).is_none());
+        let mut neighbor_edited = original.clone();
+        neighbor_edited[0] = Material::Stone;
+        assert!(cache
+            .get(Style::Blocky, DIMENSION, &neighbor_edited)
+            .is_none());
+        assert_ne!(
+            cache.get_or_extract(Style::Blocky, DIMENSION, &edited, &pool),
+            extracted
+        );
+        assert_eq!(
+            cache.get(Style::Blocky, DIMENSION, &original),
+            Some(extracted)
+        );
+        // Surfaces of different styles are distinct
+        assert!(cache.get(Style::Smooth, DIMENSION, &original).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
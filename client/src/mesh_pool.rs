@@ -0,0 +1,116 @@
+//! Reuse of the buffers chunk surfaces are extracted into on the CPU
+//!
+//! A chunk's surface fills vectors of vertices, normals, materials, and faces sized by its terrain,
+//! so extracting surfaces again and again as chunks are edited or come back into view would
+//! otherwise allocate and free large buffers every time. Meshing jobs instead fill meshes taken
+//! from a pool, which are returned once uploaded or discarded and keep their capacity for the next
+//! use.
+
+use std::sync::{Arc, Mutex};
+
+use crate::chunk_mesh::ChunkMesh;
+
+/// Meshes whose storage is available for reuse, shareable between threads
+#[derive(Clone, Default)]
+pub struct MeshPool {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    free: Vec<ChunkMesh>,
+    /// Number of meshes created because none was free
+    allocated: usize,
+}
+
+impl MeshPool {
+    /// An empty mesh, reusing the storage of one previously returned if possible
+    pub fn take(&self) -> ChunkMesh {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.free.pop() {
+            Some(mesh) => mesh,
+            None => {
+                inner.allocated += 1;
+                ChunkMesh::default()
+            }
+        }
+    }
+
+    /// Make `mesh`'s storage available to future calls to `take`
+    ///
+    /// The pool holds at most as many meshes as were ever in use at once.
+    pub fn put(&self, mut mesh: ChunkMesh) {
+        mesh.vertices.clear();
+        mesh.normals.clear();
+        mesh.materials.clear();
+        mesh.faces.clear();
+        self.inner.lock().unwrap().free.push(mesh);
+    }
+
+    /// Number of meshes created from scratch since the pool was constructed
+    pub fn allocated(&self) -> usize {
+        self.inner.lock().unwrap().allocated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_mesh::Style;
+    use common::world::Material;
+
+    const DIMENSION: u8 = 8;
+
+    /// A chunk, including margins, whose lower `height` layers are solid
+    fn terrain(height: usize) -> Vec<Material> {
+        let lwm = usize::from(DIMENSION) + 2;
+        (0..lwm.pow(3))
+            .map(|i| {
+                if i / lwm.pow(2) <= height {
+                    Material::Stone
+                } else {
+                    Material::Void
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_state_reuses_storage() {
+        let pool = MeshPool::default();
+        let chunks = (1..5).map(terrain).collect::<Vec<_>>();
+        let mut meshes = Vec::new();
+        let mut buffers = Vec::new();
+        for round in 0..10 {
+            // Extract every chunk, as when they're all edited, then upload and return the surfaces
+            for voxels in &chunks {
+                let mut mesh = pool.take();
+                mesh.fill(DIMENSION, voxels);
+                meshes.push(mesh);
+            }
+            let current = meshes
+                .iter()
+                .map(|mesh| (mesh.vertices.as_ptr(), mesh.faces.as_ptr()))
+                .collect::<Vec<_>>();
+            if round == 0 {
+                buffers = current;
+            } else {
+                for buffer in &current {
+                    assert!(buffers.contains(buffer), "fresh storage in round {}", round);
+                }
+            }
+            for mesh in meshes.drain(..) {
+                pool.put(mesh);
+            }
+            assert_eq!(pool.allocated(), chunks.len());
+        }
+
+        // Reused meshes hold exactly what fresh extraction produces
+        let mut mesh = pool.take();
+        mesh.fill(DIMENSION, &chunks[0]);
+        assert_eq!(
+            mesh,
+            ChunkMesh::extract_styled(Style::Blocky, DIMENSION, &chunks[0])
+        );
+    }
+}
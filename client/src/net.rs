@@ -1,4 +1,7 @@
-use std::{sync::Arc, thread};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
 
 use anyhow::{anyhow, Error, Result};
 use futures_util::{StreamExt, TryStreamExt};
@@ -68,24 +71,32 @@ async fn inner(
         connection,
         mut uni_streams,
         ..
-    } = endpoint.connect(&server, "localhost").unwrap().await?;
+    } = endpoint
+        .connect(&server, "localhost")
+        .unwrap()
+        .await
+        .map_err(explain_close)?;
 
     // Open the first stream for our hello message
-    let clienthello_stream = connection.open_uni().await?;
+    let clienthello_stream = connection.open_uni().await.map_err(explain_close)?;
+    // Shared between sending commands and receiving the server's acknowledgements of them
+    let channel = Arc::new(Mutex::new(proto::ClientChannel::new()));
     // Start sending commands asynchronously
-    tokio::spawn(handle_outgoing(outgoing, connection));
+    tokio::spawn(handle_outgoing(outgoing, connection, channel.clone()));
     // Actually send the hello message
     codec::send_whole(
         clienthello_stream,
         &proto::ClientHello {
             name: (*cfg.name).into(),
+            world: cfg.world,
+            token: cfg.token.clone(),
         },
     )
     .await?;
 
-    let mut ordered = uni_streams.next().await.unwrap()?;
+    let mut ordered = uni_streams.next().await.unwrap().map_err(explain_close)?;
     // Handle unordered messages
-    tokio::spawn(handle_unordered(incoming.clone(), uni_streams));
+    tokio::spawn(handle_unordered(incoming.clone(), uni_streams, channel));
 
     // Receive the server's hello message
     let hello = codec::recv::<proto::ServerHello>(&mut ordered)
@@ -103,15 +114,37 @@ async fn inner(
     }
 }
 
-/// Send commands to the server
+/// Describe the reason the server gave for closing the connection, if any
+fn explain_close(e: quinn::ConnectionError) -> Error {
+    if let quinn::ConnectionError::ApplicationClosed(ref close) = e {
+        if close.error_code == proto::ServerFull::CLOSE_CODE.into() {
+            if let Ok(full) = codec::decode::<proto::ServerFull>(&close.reason) {
+                return anyhow!("server is full ({} players)", full.max_players);
+            }
+        }
+    }
+    e.into()
+}
+
+/// Send commands to the server, one packet per command, which the sim sends once per tick
 async fn handle_outgoing(
     mut outgoing: mpsc::UnboundedReceiver<proto::Command>,
     connection: quinn::Connection,
+    channel: Arc<Mutex<proto::ClientChannel>>,
 ) -> Result<()> {
     while let Some(cmd) = outgoing.recv().await {
+        let packet = {
+            let mut channel = channel.lock().unwrap();
+            if cmd.is_critical() {
+                channel.send(cmd);
+            } else {
+                channel.send_update(cmd);
+            }
+            channel.flush()
+        };
         let stream = connection.open_uni().await?;
         // TODO: Don't silently die on parse errors
-        codec::send_whole(stream, &cmd).await?;
+        codec::send_whole(stream, &packet).await?;
     }
     Ok(())
 }
@@ -120,18 +153,25 @@ async fn handle_outgoing(
 async fn handle_unordered(
     incoming: mpsc::UnboundedSender<Message>,
     uni_streams: quinn::IncomingUniStreams,
+    channel: Arc<Mutex<proto::ClientChannel>>,
 ) -> Result<()> {
     let mut msgs = uni_streams
         .map(|stream| async {
             let stream = stream?;
-            Ok::<_, Error>(codec::recv_whole::<proto::StateDelta>(2usize.pow(16), stream).await?)
+            Ok::<_, Error>(codec::recv_whole::<proto::ServerPacket>(2usize.pow(16), stream).await?)
         })
         .buffer_unordered(128);
     // TODO: Don't silently die on parse errors
-    while let Some(msg) = msgs.try_next().await? {
-        // Ignore errors so we don't panic if the simulation thread goes away between checking
-        // `msgs` and here.
-        let _ = incoming.send(Message::StateDelta(msg));
+    while let Some(mut packet) = msgs.try_next().await? {
+        // The sim keeps what's only sent once from stale deltas before discarding the rest, so
+        // every delta is passed on rather than only the latest
+        let delta = packet.update.take();
+        channel.lock().unwrap().receive(packet);
+        if let Some(delta) = delta {
+            // Ignore errors so we don't panic if the simulation thread goes away between checking
+            // `msgs` and here.
+            let _ = incoming.send(Message::StateDelta(delta));
+        }
     }
     Ok(())
 }
@@ -0,0 +1,233 @@
+//! Culling of entities hidden from the viewer by terrain
+//!
+//! The viewer can only see into chunks holding air connected to the air around them, found by
+//! flood fill. Outdoors that region is effectively unbounded, so the fill gives up past a limit and
+//! nothing is culled; indoors, entities in rooms sealed off from the viewer are skipped. The same
+//! region is found from anywhere within it, so the fill is repeated only once the viewer leaves it,
+//! or terrain is edited or generated around it. After giving up, the fill is repeated only once the
+//! viewer enters another chunk or terrain is edited.
+
+use fxhash::FxHashSet;
+
+use common::{
+    graph::{GraphEvent, Subscription},
+    math,
+    node::{world_to_voxel, ChunkId, DualGraph},
+    proto::Position,
+    world::{connected_region, Material},
+};
+
+pub struct Occlusion {
+    /// Most voxels the flood fill may visit before giving up
+    limit: usize,
+    subscription: Option<Subscription>,
+    /// Chunk containing the viewer as of the last fill, if known
+    origin: Option<ChunkId>,
+    /// Open space connected to the viewer as of the last fill, if it was found
+    region: Option<FxHashSet<(ChunkId, [u8; 3])>>,
+    /// Chunks the viewer can see into, or `None` if nothing is known to be hidden
+    visible: Option<FxHashSet<ChunkId>>,
+}
+
+impl Occlusion {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            subscription: None,
+            origin: None,
+            region: None,
+            visible: None,
+        }
+    }
+
+    /// Bring the visible set up to date for a viewer at `view`
+    pub fn update(&mut self, graph: &mut DualGraph, dimension: u8, view: &Position) {
+        let (mut edited, mut generated) = (false, false);
+        match self.subscription {
+            Some(ref subscription) => {
+                for event in graph.events(subscription).iter() {
+                    match *event {
+                        GraphEvent::ChunkEdited(..) => edited = true,
+                        GraphEvent::ChunkGenerated(..) => generated = true,
+                        // Eviction only shrinks the region, so a stale one hides no more than it
+                        // should
+                        _ => {}
+                    }
+                }
+            }
+            None => {
+                self.subscription = Some(graph.subscribe());
+                edited = true;
+            }
+        }
+        let graph = &*graph;
+        let point = na::convert::<_, na::Vector4<f64>>(view.local * math::origin());
+        let origin = world_to_voxel(graph, dimension, view.node, &point);
+        let stale = edited
+            || match self.region {
+                // Newly generated chunks may extend the region
+                Some(ref region) => generated || !origin.map_or(false, |x| region.contains(&x)),
+                // Generation can't make an unbounded region bounded
+                None => origin.map(|(chunk, _)| chunk) != self.origin,
+            };
+        if !stale {
+            return;
+        }
+        self.origin = origin.map(|(chunk, _)| chunk);
+        let limit = self.limit;
+        // A viewer inside something solid, e.g. while spectating, is left to see what it can
+        self.region = origin
+            .and_then(|start| connected_region(graph, dimension, start, Material::is_void, limit));
+        self.visible = self
+            .region
+            .as_ref()
+            .map(|region| region.iter().map(|&(chunk, _)| chunk).collect());
+    }
+
+    /// Whether something at `position` might be visible
+    ///
+    /// Points within `grace` of `position` along each of its axes are considered too, so that
+    /// something partly in a visible chunk, or standing in an opening into one, isn't culled.
+    pub fn visible(
+        &self,
+        graph: &DualGraph,
+        dimension: u8,
+        position: &Position,
+        grace: f32,
+    ) -> bool {
+        let visible = match self.visible {
+            Some(ref x) => x,
+            None => return true,
+        };
+        let offsets = [
+            na::Vector3::x_axis(),
+            -na::Vector3::x_axis(),
+            na::Vector3::y_axis(),
+            -na::Vector3::y_axis(),
+            na::Vector3::z_axis(),
+            -na::Vector3::z_axis(),
+        ];
+        let points = offsets
+            .iter()
+            .map(|axis| position.local * math::translate_along(axis, grace) * math::origin())
+            .chain(Some(position.local * math::origin()));
+        for point in points {
+            let point = na::convert::<_, na::Vector4<f64>>(point);
+            match world_to_voxel(graph, dimension, position.node, &point) {
+                Some((chunk, _)) if visible.contains(&chunk) => return true,
+                // Beyond the known world, so it can't be judged
+                None => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{populate_fresh_nodes, voxel_index, voxel_to_world_center, Chunk, VoxelData},
+    };
+
+    const DIMENSION: u8 = 12;
+
+    fn at(chunk: ChunkId, coords: [u8; 3]) -> Position {
+        let center = voxel_to_world_center(DIMENSION, chunk, coords);
+        Position {
+            node: NodeId::ROOT,
+            local: na::convert(math::translate(&math::origin(), &center)),
+        }
+    }
+
+    /// A world solid but for a small sealed room, and open space in a neighboring chunk, returning
+    /// the room's chunk and points within the room and the open space
+    fn sealed_room() -> (DualGraph, ChunkId, Position, Position) {
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        let room = ChunkId::new(NodeId::ROOT, Vertex::A);
+        // The chunk of the same node on the other side of `room`'s x = 0 face
+        let beyond = math::lorentz_normalize(
+            &(room.vertex.chunk_to_node() * na::Vector4::new(-0.5, 2.5, 2.5, DIMENSION.into())),
+        );
+        let (outside, outside_coords) =
+            world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &beyond).unwrap();
+        assert_ne!(outside, room);
+
+        let mut voxels = VoxelData::Solid(Material::Stone);
+        for x in 4..7 {
+            for y in 4..7 {
+                for z in 4..7 {
+                    voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, [x, y, z])] = Material::Void;
+                }
+            }
+        }
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[room.vertex] = Chunk::populated(DIMENSION, voxels);
+        node.chunks[outside.vertex] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Void));
+        (
+            graph,
+            room,
+            at(room, [5, 5, 5]),
+            at(outside, outside_coords),
+        )
+    }
+
+    #[test]
+    fn sealed_room_hidden() {
+        let (mut graph, room, inside, outdoors) = sealed_room();
+        let grace = 1.5
+            * math::distance(
+                &voxel_to_world_center(DIMENSION, room, [0, 2, 2]),
+                &voxel_to_world_center(DIMENSION, room, [1, 2, 2]),
+            ) as f32;
+        let mut occlusion = Occlusion::new(10_000);
+
+        // Looking on from outside the room
+        occlusion.update(&mut graph, DIMENSION, &outdoors);
+        assert!(!occlusion.visible(&graph, DIMENSION, &inside, 0.0));
+        assert!(occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+        // Just inside the wall adjoining the viewer's chunk
+        let wall = at(room, [0, 2, 2]);
+        assert!(!occlusion.visible(&graph, DIMENSION, &wall, 0.0));
+        assert!(occlusion.visible(&graph, DIMENSION, &wall, grace));
+
+        // Sharing the room
+        occlusion.update(&mut graph, DIMENSION, &inside);
+        assert!(occlusion.visible(&graph, DIMENSION, &inside, 0.0));
+        assert!(!occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+
+        // Opening the room up to the outside
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[room.vertex] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Void));
+        graph.notify(GraphEvent::ChunkEdited(room.node, room.vertex));
+        occlusion.update(&mut graph, DIMENSION, &inside);
+        assert!(occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+
+        // Too much open space to be worth searching
+        let mut occlusion = Occlusion::new(100);
+        occlusion.update(&mut graph, DIMENSION, &outdoors);
+        assert!(occlusion.visible(&graph, DIMENSION, &inside, 0.0));
+    }
+
+    #[test]
+    fn fill_reused_within_region() {
+        let (mut graph, room, inside, outdoors) = sealed_room();
+        let mut occlusion = Occlusion::new(10_000);
+        occlusion.update(&mut graph, DIMENSION, &inside);
+        assert!(!occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+
+        // Open the room up without announcing it, so that only a fresh fill would notice
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[room.vertex] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Void));
+        // Moving about the room finds the same region as before
+        occlusion.update(&mut graph, DIMENSION, &at(room, [4, 6, 4]));
+        assert!(!occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+        // Leaving it doesn't
+        occlusion.update(&mut graph, DIMENSION, &at(room, [1, 1, 1]));
+        assert!(occlusion.visible(&graph, DIMENSION, &outdoors, 0.0));
+    }
+}
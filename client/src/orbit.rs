@@ -0,0 +1,108 @@
+//! A camera circling a point of interest, for inspecting builds from every side
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use common::{math, proto::Position};
+
+/// Closest the camera may come to its focus, in absolute units
+const MIN_DISTANCE: f32 = 0.05;
+/// Farthest the camera may retreat from its focus, beyond which precision suffers
+const MAX_DISTANCE: f32 = 8.0;
+/// Margin keeping the camera from passing over the poles, where "up" becomes ambiguous
+const POLE_MARGIN: f32 = 0.01;
+
+pub struct Orbit {
+    /// The point circled, at the origin of this frame, whose +y is up
+    pub focus: Position,
+    /// Radians about the focus's +y, with zero placing the camera along +z
+    yaw: f32,
+    /// Radians above the focus's horizon
+    pitch: f32,
+    /// Geodesic distance from the camera to the focus
+    distance: f32,
+}
+
+impl Orbit {
+    pub fn new(focus: Position, distance: f32) -> Self {
+        Self {
+            focus,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: distance.max(MIN_DISTANCE).min(MAX_DISTANCE),
+        }
+    }
+
+    /// Swing around the focus by `yaw` radians and up by `pitch` radians
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % (2.0 * PI);
+        let limit = FRAC_PI_2 - POLE_MARGIN;
+        self.pitch = (self.pitch + pitch).max(-limit).min(limit);
+    }
+
+    /// Move `delta` closer to the focus, or away for negative `delta`
+    ///
+    /// Distance changes additively rather than in proportion, since apparent size falls off
+    /// exponentially with hyperbolic distance.
+    pub fn zoom(&mut self, delta: f32) {
+        self.distance = (self.distance - delta).max(MIN_DISTANCE).min(MAX_DISTANCE);
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Pose of the camera relative to the focus
+    pub fn camera(&self) -> math::Isometry<f32> {
+        let direction = na::Unit::new_unchecked(na::Vector3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos(),
+        ));
+        let eye = math::translate_along(&direction, self.distance) * math::origin();
+        math::Isometry::look_at(&eye, &math::origin(), &na::Vector3::y())
+    }
+
+    /// Pose of the camera in the frame of the focus's node
+    ///
+    /// Not normalized, so the camera may lie outside that node.
+    pub fn view(&self) -> Position {
+        Position {
+            node: self.focus.node,
+            local: self.focus.local * self.camera().matrix(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::graph::NodeId;
+
+    #[test]
+    fn focus_centered() {
+        let focus = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::x_axis(), 0.5),
+        };
+        let mut orbit = Orbit::new(focus, 1.0);
+        for &(yaw, pitch, zoom) in &[
+            (0.0, 0.0, 0.0),
+            (0.7, 0.3, 0.5),
+            (2.0, -1.0, -2.0),
+            (-3.5, 2.0, 1.0),
+        ] {
+            orbit.rotate(yaw, pitch);
+            orbit.zoom(zoom);
+            let view = orbit.view();
+            let target = focus.local * math::origin();
+            // The focus lies straight ahead
+            let seen = view.local.try_inverse().unwrap() * target;
+            assert!(seen.xy().norm() < 1e-4, "{:?} off center", seen);
+            assert!(seen.z < 0.0);
+            // At the distance zoomed to
+            let distance = math::distance(&(view.local * math::origin()), &target);
+            assert!((distance - orbit.distance()).abs() < 1e-4);
+        }
+        assert!((orbit.distance() - 1.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,215 @@
+//! Cosmetic particles given off by certain materials
+//!
+//! Particles are purely local: they have no effect on the world and aren't synchronized with the
+//! server. Each lives in the frame of a node, like an entity, and coasts along a geodesic until it
+//! expires.
+
+use rand::Rng;
+
+use common::{
+    dodeca::Vertex,
+    graph::NodeId,
+    math::{self, Isometry, Velocity},
+    node::{self, Chunk, ChunkId, DualGraph, VoxelData},
+    proto::Position,
+    world::Material,
+};
+
+/// How a material gives off particles
+#[derive(Debug, Copy, Clone)]
+pub struct Emission {
+    /// Expected particles per second from each voxel
+    pub rate: f32,
+    /// Absolute units per second
+    pub speed: f32,
+    /// Seconds
+    pub lifetime: f32,
+}
+
+/// Particles given off by `material`, if any
+pub fn emission(material: Material) -> Option<Emission> {
+    match material {
+        Material::Lava => Some(Emission {
+            rate: 0.05,
+            speed: 0.02,
+            lifetime: 2.0,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Particle {
+    pub node: NodeId,
+    /// Relative to `node`
+    pub pose: Isometry<f32>,
+    /// In the particle's own frame, and hence constant along its geodesic
+    pub velocity: Velocity<f32>,
+    /// Seconds until the particle disappears
+    pub remaining: f32,
+}
+
+impl Particle {
+    /// Advance by `dt` seconds, returning whether the particle is still alive
+    ///
+    /// Uses the exponential map, so the particle follows a geodesic regardless of step size.
+    pub fn advance(&mut self, graph: &DualGraph, dt: f32) -> bool {
+        self.remaining -= dt;
+        if self.remaining <= 0.0 {
+            return false;
+        }
+        self.pose = self.velocity.integrate(&self.pose, dt);
+        let (node, transition) = graph.normalize_transform(self.node, self.pose.matrix());
+        if node != self.node {
+            self.node = node;
+            self.pose = Isometry::from_matrix_unchecked(transition) * self.pose;
+        }
+        true
+    }
+}
+
+/// Number of voxels sampled from each nearby chunk per step when looking for emitters
+const SAMPLES_PER_CHUNK: usize = 16;
+
+pub struct Particles {
+    particles: Vec<Particle>,
+    /// Particles are spawned only this far from the viewer, in absolute units
+    range: f32,
+}
+
+impl Particles {
+    pub fn new(range: f32) -> Self {
+        Self {
+            particles: Vec::new(),
+            range,
+        }
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &Particle> {
+        self.particles.iter()
+    }
+
+    /// Advance existing particles by `dt` seconds, then emit new ones around `view`
+    pub fn step<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+        graph: &DualGraph,
+        dimension: u8,
+        view: &Position,
+        dt: f32,
+    ) {
+        let mut i = 0;
+        while i < self.particles.len() {
+            if self.particles[i].advance(graph, dt) {
+                i += 1;
+            } else {
+                self.particles.swap_remove(i);
+            }
+        }
+
+        let total = (usize::from(dimension) + 2).pow(3);
+        // Each sample stands in for this many voxels
+        let weight = total as f32 / SAMPLES_PER_CHUNK as f32;
+        for (node, _) in graph.nearby_nodes(view, f64::from(self.range)) {
+            let chunks = match *graph.get(node) {
+                Some(ref x) => &x.chunks,
+                None => continue,
+            };
+            for vertex in Vertex::iter() {
+                let voxels = match chunks[vertex] {
+                    Chunk::Populated {
+                        voxels: VoxelData::Dense(ref voxels),
+                        ..
+                    } => voxels,
+                    _ => continue,
+                };
+                for _ in 0..SAMPLES_PER_CHUNK {
+                    let index = rng.gen_range(0, total);
+                    let emission = match emission(voxels[index]) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let coords = match node::voxel_coords(dimension, index) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    if !rng.gen_bool(f64::from((emission.rate * weight * dt).min(1.0))) {
+                        continue;
+                    }
+                    let center =
+                        node::voxel_to_world_center(dimension, ChunkId::new(node, vertex), coords);
+                    let center = na::convert::<_, na::Vector4<f32>>(center);
+                    self.particles.push(Particle {
+                        node,
+                        pose: Isometry::from_matrix_unchecked(math::translate(
+                            &math::origin(),
+                            &center,
+                        )),
+                        velocity: Velocity::new(random_direction(rng) * emission.speed, na::zero()),
+                        remaining: emission.lifetime,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Uniformly distributed unit vector
+fn random_direction<R: Rng + ?Sized>(rng: &mut R) -> na::Vector3<f32> {
+    // Archimedes' hat-box theorem: z is uniform for points uniform on the sphere
+    let z = 2.0 * rng.gen::<f32>() - 1.0;
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    let r = (1.0 - z * z).sqrt();
+    na::Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    fn particle(velocity: na::Vector3<f32>, lifetime: f32) -> Particle {
+        Particle {
+            node: NodeId::ROOT,
+            pose: Isometry::identity(),
+            velocity: Velocity::new(velocity, na::zero()),
+            remaining: lifetime,
+        }
+    }
+
+    #[test]
+    fn follows_geodesic() {
+        let graph = DualGraph::new();
+        let velocity = na::Vector3::new(0.1, -0.2, 0.15);
+        let mut p = particle(velocity, 10.0);
+        for i in 1..=10 {
+            assert!(p.advance(&graph, 0.1));
+            assert_eq!(p.node, NodeId::ROOT);
+            let expected = math::translate_along(
+                &na::Unit::new_normalize(velocity),
+                velocity.norm() * 0.1 * i as f32,
+            ) * math::origin();
+            assert_abs_diff_eq!(*p.pose.matrix() * math::origin(), expected, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn expires() {
+        let graph = DualGraph::new();
+        let mut p = particle(na::Vector3::x() * 0.1, 0.25);
+        assert!(p.advance(&graph, 0.1));
+        assert!(p.advance(&graph, 0.1));
+        assert!(!p.advance(&graph, 0.1));
+
+        let mut particles = Particles::new(0.0);
+        particles.particles.push(p);
+        particles.step(
+            &mut rand::thread_rng(),
+            &graph,
+            12,
+            &Position::origin(),
+            0.1,
+        );
+        assert_eq!(particles.iter().len(), 0);
+    }
+}
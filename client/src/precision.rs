@@ -0,0 +1,111 @@
+//! Diagnostics for the precision of view-relative transforms
+//!
+//! Positions are expressed relative to the origin of a node, so that the transforms used for
+//! rendering stay small however far the player travels. The entries of a transform grow with the
+//! cosine of the distance it translates by, however, so a position that strays far from its node's
+//! origin without being rebased onto a closer node loses `f32` precision exponentially.
+
+use metrics::gauge;
+use tracing::{debug, info, warn};
+
+use common::{graph::Graph, proto::Position};
+
+pub struct PrecisionMonitor {
+    /// Estimated relative error beyond which to warn
+    threshold: f32,
+    /// Greatest distance from the root, in nodes, that the view has reached
+    max_depth: u32,
+    /// Whether the last update found precision degraded
+    degraded: bool,
+}
+
+impl PrecisionMonitor {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            max_depth: 0,
+            degraded: false,
+        }
+    }
+
+    /// Account for the latest view position, returning whether its precision is degraded
+    ///
+    /// Warns once when precision first degrades, and again only after it's been recovered.
+    pub fn update<N>(&mut self, graph: &Graph<N>, view: &Position) -> bool {
+        let depth = graph.length(view.node);
+        if depth > self.max_depth {
+            self.max_depth = depth;
+            gauge!("sim.view.max_depth", i64::from(depth));
+            debug!(depth, "new maximum node depth");
+        }
+
+        let error = relative_error(view);
+        let degraded = error > self.threshold;
+        if degraded && !self.degraded {
+            warn!(
+                error,
+                node = ?view.node,
+                "view transform precision degraded; position should be rebased onto a nearer node"
+            );
+        } else if !degraded && self.degraded {
+            info!("view transform precision recovered");
+        }
+        self.degraded = degraded;
+        degraded
+    }
+}
+
+/// Estimated relative error of `f32` transforms computed relative to `view`
+///
+/// Rounding error in a matrix product is proportional to the magnitude of its operands, so the
+/// largest entry of `view.local` scales the machine epsilon.
+fn relative_error(view: &Position) -> f32 {
+    std::f32::EPSILON * view.local.amax()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{dodeca::Side, graph::NodeId};
+
+    #[test]
+    fn distant_view_warns_until_rebased() {
+        let mut graph = Graph::<()>::new();
+        let mut monitor = PrecisionMonitor::new(1e-4);
+        assert!(!monitor.update(&graph, &Position::origin()));
+
+        // Travel away from the root, without rebasing, until precision suffers
+        let mut node = NodeId::ROOT;
+        let mut local = na::Matrix4::<f64>::identity();
+        let mut degraded = false;
+        for _ in 0..20 {
+            let side = Side::iter()
+                .find(|&side| {
+                    let neighbor = graph.ensure_neighbor(node, side);
+                    graph.length(neighbor) > graph.length(node)
+                })
+                .unwrap();
+            node = graph.ensure_neighbor(node, side);
+            local *= side.reflection();
+            let unrebased = Position {
+                node: NodeId::ROOT,
+                local: na::convert(local),
+            };
+            degraded = monitor.update(&graph, &unrebased);
+            if degraded {
+                break;
+            }
+        }
+        assert!(degraded, "precision never degraded");
+        // The view never left the root
+        assert_eq!(monitor.max_depth, 0);
+
+        // The same position relative to the node it's actually in is precise
+        let rebased = Position {
+            node,
+            local: na::Matrix4::identity(),
+        };
+        assert!(!monitor.update(&graph, &rebased));
+        assert_eq!(monitor.max_depth, graph.length(node));
+    }
+}
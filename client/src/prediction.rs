@@ -12,6 +12,8 @@ use common::{math, proto::Position};
 pub struct PredictedMotion {
     log: VecDeque<Input>,
     generation: u16,
+    /// The server's state before the inputs in `log`
+    base: Position,
     predicted: Position,
 }
 
@@ -20,6 +22,7 @@ impl PredictedMotion {
         Self {
             log: VecDeque::new(),
             generation: 0,
+            base: initial,
             predicted: initial,
         }
     }
@@ -43,6 +46,7 @@ impl PredictedMotion {
             return;
         }
         self.log.drain(..obsolete);
+        self.base = position;
         self.predicted.node = position.node;
         self.predicted.local = self
             .log
@@ -50,6 +54,24 @@ impl PredictedMotion {
             .fold(position.local, |acc, x| acc * x.transform);
     }
 
+    /// Our estimate of the server's state after receiving inputs up to `generation`, if it's no
+    /// older than the latest state `reconcile`d
+    pub fn predicted_at(&self, generation: u16) -> Option<Position> {
+        let first_gen = self.generation.wrapping_sub(self.log.len() as u16);
+        let count = usize::from(generation.wrapping_sub(first_gen));
+        if count > self.log.len() {
+            return None;
+        }
+        Some(Position {
+            node: self.base.node,
+            local: self
+                .log
+                .iter()
+                .take(count)
+                .fold(self.base.local, |acc, x| acc * x.transform),
+        })
+    }
+
     /// Latest estimate of the server's state after receiving all `push`ed inputs.
     pub fn predicted(&self) -> &Position {
         &self.predicted
@@ -87,4 +109,33 @@ mod tests {
         pred.reconcile(0, pos());
         assert_eq!(pred.log.len(), 0);
     }
+
+    #[test]
+    fn predicted_at() {
+        let mut pred = PredictedMotion::new(pos());
+        let first = pred.push(&na::Vector3::x_axis(), 1.0);
+        let second = pred.push(&na::Vector3::x_axis(), 1.0);
+        let after = |distance| math::translate_along(&na::Vector3::x_axis(), distance);
+        assert_eq!(
+            pred.predicted_at(first.wrapping_sub(1)).unwrap().local,
+            na::one()
+        );
+        assert_eq!(pred.predicted_at(first).unwrap().local, after(1.0));
+        assert_eq!(
+            pred.predicted_at(second).unwrap().local,
+            pred.predicted().local
+        );
+        assert!(pred.predicted_at(second.wrapping_add(1)).is_none());
+
+        // Inputs the server has seen are predicted from where it says they led
+        pred.reconcile(
+            first,
+            Position {
+                node: pos().node,
+                local: after(0.5),
+            },
+        );
+        assert!(pred.predicted_at(first.wrapping_sub(1)).is_none());
+        assert_eq!(pred.predicted_at(first).unwrap().local, after(0.5));
+    }
 }
@@ -1,18 +1,31 @@
-use std::time::Duration;
+use std::{
+    collections::VecDeque,
+    mem,
+    time::{Duration, Instant},
+};
 
 use fxhash::FxHashMap;
 use hecs::Entity;
-use tracing::{debug, error, trace};
+use metrics::gauge;
+use tracing::{debug, error, info, trace, warn};
 
-use crate::{net, prediction::PredictedMotion, Net};
+use crate::{
+    clock::ClockSync, net, particles::Particles, precision::PrecisionMonitor,
+    prediction::PredictedMotion, MovementFrame, Net,
+};
 use common::{
-    graph::{Graph, NodeId},
+    chunk_hash, daylight,
+    graph::{Graph, GraphEvent, NodeId},
+    inventory::Inventory,
     math,
-    node::{DualGraph, Node},
-    proto::{self, Character, Command, Component, Position},
-    sanitize_motion_input,
-    worldgen::NodeState,
-    Chunks, EntityId, GraphEntities, Step,
+    node::{
+        populate_fresh_nodes, seed_root, voxel_index, Chunk, ChunkId, DualGraph, VoxelData,
+        VoxelMetadata,
+    },
+    proto::{self, Character, ChunkDiff, Command, Component, Position, TickId, VoxelChanges},
+    sanitize_motion_input, state_hash, voxel_diff,
+    world::{self, Material},
+    worldgen, EntityId, GraphEntities, Step,
 };
 
 /// Game state
@@ -22,14 +35,36 @@ pub struct Sim {
     // World state
     pub graph: DualGraph,
     pub graph_entities: GraphEntities,
+    /// Changes the server has made to each chunk, in order, to be replayed whenever it's generated
+    voxel_edits: FxHashMap<ChunkId, Vec<VoxelChanges>>,
+    /// `chunk_hash` of chunks in `voxel_edits`, as of their latest changes, once worked out
+    chunk_hashes: FxHashMap<ChunkId, u64>,
+    /// State attached to individual voxels, for chunks that have any
+    metadata: FxHashMap<ChunkId, VoxelMetadata>,
     entity_ids: FxHashMap<EntityId, Entity>,
+    /// Most recent ID seen in each server-side slot, for recognizing stale IDs
+    slots: FxHashMap<u32, EntityId>,
     pub world: hecs::World,
     pub params: Option<Parameters>,
     pub local_character: Option<Entity>,
+    /// Materials the local character holds, as last reported by the server
+    pub inventory: Inventory,
+    /// Step of the state update `inventory` came from
+    inventory_step: Option<Step>,
     orientation: na::UnitQuaternion<f32>,
     step: Option<Step>,
+    /// Server step at which remote entities are drawn, trailing `step` by
+    /// `Parameters::interpolation_delay`
+    playback: Option<f64>,
+    /// As of `step`, per `daylight::time_of_day`
+    time_of_day: f32,
+    pub particles: Particles,
+    precision: PrecisionMonitor,
 
     // Input state
+    movement_frame: MovementFrame,
+    /// Whether to cancel camera roll relative to the terrain each step
+    stabilize_roll: bool,
     since_input_sent: Duration,
     /// Most recent input
     ///
@@ -41,22 +76,64 @@ pub struct Sim {
     /// Units are relative to movement speed.
     average_velocity: na::Vector3<f32>,
     prediction: PredictedMotion,
+    /// Camera flying freely, without collision or gravity, while the character stays put
+    spectator: Option<Position>,
+    /// Administrative action to request with the next command
+    admin: Option<proto::AdminCommand>,
+    /// Voxel edit to request with the next command
+    edit: Option<proto::VoxelEdit>,
+    /// Whether the next command should launch a projectile
+    fire: bool,
+    /// Tick the next command is input for, if the world runs in lockstep
+    next_tick: Option<TickId>,
+
+    // Divergence detection
+    /// Number of consecutive state hashes from the server that didn't match our state
+    hash_mismatches: u32,
+    /// Whether the next command should request a resync
+    request_resync: bool,
+    /// Whether we're waiting on a requested resync
+    awaiting_resync: bool,
+
+    // Timing
+    /// Round-trip time and server clock offset
+    clock: ClockSync,
+    /// Reference point for the client times sent in `proto::Ping`
+    epoch: Instant,
+    since_ping: Duration,
 }
 
 impl Sim {
-    pub fn new(net: Net) -> Self {
+    pub fn new(
+        net: Net,
+        movement_frame: MovementFrame,
+        stabilize_roll: bool,
+        precision_threshold: f32,
+    ) -> Self {
         Self {
             net,
 
             graph: Graph::new(),
             graph_entities: GraphEntities::new(),
+            voxel_edits: FxHashMap::default(),
+            chunk_hashes: FxHashMap::default(),
+            metadata: FxHashMap::default(),
             entity_ids: FxHashMap::default(),
+            slots: FxHashMap::default(),
             world: hecs::World::new(),
             params: None,
             local_character: None,
+            inventory: Inventory::new(),
+            inventory_step: None,
             orientation: na::one(),
             step: None,
+            playback: None,
+            time_of_day: daylight::time_of_day(0, 0),
+            particles: Particles::new(PARTICLE_RANGE),
+            precision: PrecisionMonitor::new(precision_threshold),
 
+            movement_frame,
+            stabilize_roll,
             since_input_sent: Duration::new(0, 0),
             instantaneous_velocity: na::zero(),
             average_velocity: na::zero(),
@@ -64,11 +141,103 @@ impl Sim {
                 node: NodeId::ROOT,
                 local: na::one(),
             }),
+            spectator: None,
+            admin: None,
+            edit: None,
+            fire: false,
+            next_tick: None,
+
+            hash_mismatches: 0,
+            request_resync: false,
+            awaiting_resync: false,
+
+            clock: ClockSync::new(),
+            epoch: Instant::now(),
+            // Ping with the first command
+            since_ping: PING_INTERVAL,
         }
     }
 
     pub fn rotate(&mut self, delta: &na::UnitQuaternion<f32>) {
-        self.orientation *= delta;
+        match self.spectator {
+            Some(ref mut camera) => camera.local *= delta.to_homogeneous(),
+            None => self.orientation *= delta,
+        }
+    }
+
+    /// Detach the camera from the local character, leaving the character where it is, or return it
+    pub fn toggle_spectator(&mut self) {
+        self.spectator = match self.spectator {
+            Some(_) => None,
+            None => Some(self.view()),
+        };
+    }
+
+    /// Ask the server to perform `command`, which succeeds only if we're permitted to
+    ///
+    /// The outcome is logged when the server replies.
+    pub fn admin(&mut self, command: proto::AdminCommand) {
+        self.admin = Some(command);
+    }
+
+    /// Ask the server to break the voxel at the center of the view, if one is within reach
+    pub fn break_voxel(&mut self) {
+        if let Some(hit) = self.target() {
+            self.edit = Some(self.voxel_edit(hit.voxel, Material::Void));
+        }
+    }
+
+    /// Ask the server to place the most plentiful material held against the voxel at the center
+    /// of the view, if one is within reach
+    pub fn place_voxel(&mut self) {
+        let material = match self.inventory.iter().max_by_key(|&(_, count)| count) {
+            Some((material, _)) => material,
+            None => {
+                debug!("no material to place");
+                return;
+            }
+        };
+        if let Some(hit) = self.target() {
+            self.edit = Some(self.voxel_edit(hit.place, material));
+        }
+    }
+
+    /// Ask the server to launch a projectile from the character along its line of sight
+    pub fn fire(&mut self) {
+        self.fire = true;
+    }
+
+    /// The voxel at the center of the view, if any lies within reach
+    fn target(&self) -> Option<world::VoxelHit> {
+        let params = self.params.as_ref()?;
+        let view = self.view();
+        let ray =
+            math::Isometry::try_from_matrix(na::convert::<_, na::Matrix4<f64>>(view.local)).ok()?;
+        world::raycast(
+            &self.graph,
+            params.chunk_size,
+            view.node,
+            &ray,
+            f64::from(params.max_reach),
+        )
+    }
+
+    fn voxel_edit(
+        &self,
+        (chunk, coords): (ChunkId, [u8; 3]),
+        material: Material,
+    ) -> proto::VoxelEdit {
+        let dimension = self.params.as_ref().unwrap().chunk_size;
+        proto::VoxelEdit {
+            node: chunk.node,
+            chunk: chunk.vertex,
+            index: voxel_index(dimension, coords) as u32,
+            material,
+        }
+    }
+
+    pub fn is_spectating(&self) -> bool {
+        self.spectator.is_some()
     }
 
     pub fn velocity(&mut self, v: na::Vector3<f32>) {
@@ -82,10 +251,45 @@ impl Sim {
     pub fn step(&mut self, dt: Duration) {
         self.orientation.renormalize_fast();
 
+        if let Some(step_interval) = self.params.as_ref().map(|x| x.step_interval) {
+            // Stretch turns over jittery connections so the next update arrives before they end
+            let fraction = dt.as_secs_f32() / (step_interval + self.clock.jitter()).as_secs_f32();
+            for (_, look) in self.world.query::<&mut Look>().iter() {
+                look.advance(fraction);
+            }
+        }
+
         while let Ok(msg) = self.net.incoming.try_recv() {
             self.handle_net(msg);
         }
 
+        self.advance_playback(dt);
+
+        if self.spectator.is_some() {
+            self.fly(dt);
+        }
+
+        if self.stabilize_roll {
+            self.level_view();
+        }
+
+        if self.params.is_some() {
+            let view = self.view();
+            self.precision.update(&self.graph, &view);
+        }
+
+        if let Some(chunk_size) = self.params.as_ref().map(|x| x.chunk_size) {
+            let view = self.view();
+            self.particles.step(
+                &mut rand::thread_rng(),
+                &self.graph,
+                chunk_size,
+                &view,
+                dt.as_secs_f32(),
+            );
+        }
+
+        self.since_ping += dt;
         if let Some(step_interval) = self.params.as_ref().map(|x| x.step_interval) {
             self.since_input_sent += dt;
             if let Some(overflow) = self.since_input_sent.checked_sub(step_interval) {
@@ -129,32 +333,85 @@ impl Sim {
                 error!("connection lost: {}", e);
             }
             Hello(msg) => {
+                let step_interval = Duration::from_secs(1) / u32::from(msg.rate);
                 self.params = Some(Parameters {
                     character_id: msg.character,
-                    step_interval: Duration::from_secs(1) / u32::from(msg.rate),
+                    step_interval,
+                    interpolation_delay: step_interval.mul_f64(INTERPOLATION_DELAY),
                     chunk_size: msg.chunk_size,
                     meters_to_absolute: msg.meters_to_absolute,
                     movement_speed: msg.movement_speed,
+                    max_reach: msg.max_reach,
+                    worldgen_preset: msg.worldgen_preset,
                 });
+                self.next_tick = msg.lockstep;
                 // Populate the root node
+                seed_root(&mut self.graph, msg.seed);
                 populate_fresh_nodes(&mut self.graph);
+                self.graph.clear_fresh();
             }
             Spawns(msg) => self.handle_spawns(msg),
-            StateDelta(msg) => {
+            StateDelta(mut msg) => {
+                // Timing is useful even from otherwise stale messages
+                if let Some(ref pong) = msg.pong {
+                    self.clock.update(pong, self.client_time());
+                    if let Some(rtt) = self.clock.rtt() {
+                        gauge!("net.rtt_us", rtt.as_micros() as i64);
+                    }
+                }
+                // Each inventory is sent only once, so it's kept even from an otherwise stale message
+                // unless a later one has been seen
+                if let Some(inventory) = msg.inventory.take() {
+                    if self
+                        .inventory_step
+                        .map_or(true, |x| x.wrapping_sub(msg.step) < 0)
+                    {
+                        self.inventory = inventory;
+                        self.inventory_step = Some(msg.step);
+                    }
+                }
+                // Likewise each reply to an admin command
+                for reply in &msg.admin_replies {
+                    match reply.result {
+                        Ok(ref x) => info!(generation = reply.generation, "admin command: {}", x),
+                        Err(ref e) => {
+                            warn!(generation = reply.generation, "admin command failed: {}", e)
+                        }
+                    }
+                }
+                for rejected in &msg.edit_rejections {
+                    warn!(
+                        generation = rejected.generation,
+                        "voxel edit rejected: {:?}", rejected.reason
+                    );
+                }
                 // Discard out-of-order messages, taking care to account for step counter wrapping.
                 if self.step.map_or(false, |x| x.wrapping_sub(msg.step) >= 0) {
                     return;
                 }
                 self.step = Some(msg.step);
+                self.time_of_day = msg.time_of_day;
+                // Where we expected the server's hash to find our character, before reconciling
+                // with where it did
+                let predicted = msg
+                    .hash
+                    .and_then(|_| self.prediction.predicted_at(msg.latest_input));
                 for &(id, new_pos) in &msg.positions {
-                    self.update_position(msg.latest_input, id, new_pos);
+                    self.update_position(msg.latest_input, msg.step, id, new_pos);
                 }
                 for &(id, orientation) in &msg.character_orientations {
+                    if self.is_stale(id) {
+                        trace!(%id, "character orientation update for stale entity");
+                        continue;
+                    }
                     match self.entity_ids.get(&id) {
                         None => debug!(%id, "character orientation update for unknown entity"),
                         Some(&entity) => match self.world.get_mut::<Character>(entity) {
                             Ok(mut ch) => {
                                 ch.orientation = orientation;
+                                if let Ok(mut look) = self.world.get_mut::<Look>(entity) {
+                                    look.retarget(orientation);
+                                }
                             }
                             Err(e) => {
                                 error!(%id, "character orientation update for non-character entity {}", e)
@@ -162,11 +419,73 @@ impl Sim {
                         },
                     }
                 }
+                if let (Some(hash), Some(predicted)) = (msg.hash, predicted) {
+                    self.check_hash(hash, &predicted);
+                }
             }
         }
     }
 
-    fn update_position(&mut self, latest_input: u16, id: EntityId, new_pos: Position) {
+    /// Compare our state to the server's, requesting a resync if they've diverged
+    ///
+    /// `predicted` is where we expect our character to be, having moved per the inputs the server
+    /// had received when it computed `expected`.
+    fn check_hash(&mut self, expected: u64, predicted: &Position) {
+        if self.awaiting_resync {
+            return;
+        }
+        let character = match self.params {
+            Some(ref x) => x.character_id,
+            None => return,
+        };
+        // The server moves characters into whichever node they're nearest
+        let (node, transition) = self
+            .graph
+            .normalize_transform(predicted.node, &predicted.local);
+        let predicted = Position {
+            node,
+            local: transition * predicted.local,
+        };
+        let mut chunks = Vec::with_capacity(self.voxel_edits.len());
+        for &chunk in self.voxel_edits.keys() {
+            let contents = match self.chunk_hashes.get(&chunk) {
+                Some(&x) => x,
+                None => match self.edited_chunk_hash(chunk) {
+                    Some(x) => x,
+                    // Can't be checked until more of the graph is known
+                    None => return,
+                },
+            };
+            chunks.push((chunk, contents));
+        }
+        self.chunk_hashes.extend(chunks.iter().cloned());
+        let actual = state_hash(
+            self.world
+                .query::<(&EntityId, &Position)>()
+                .iter()
+                .map(|(_, (&id, _))| id),
+            (character, &predicted),
+            chunks,
+        );
+        if actual == expected {
+            self.hash_mismatches = 0;
+            return;
+        }
+        // Spawns and deltas travel on separate streams, so brief disagreement is normal
+        self.hash_mismatches += 1;
+        if self.hash_mismatches >= HASH_MISMATCH_TOLERANCE {
+            warn!("state diverged from server; resynchronizing");
+            self.hash_mismatches = 0;
+            self.request_resync = true;
+            self.awaiting_resync = true;
+        }
+    }
+
+    fn update_position(&mut self, latest_input: u16, step: Step, id: EntityId, new_pos: Position) {
+        if self.is_stale(id) {
+            trace!(%id, "position update for stale entity");
+            return;
+        }
         if self.params.as_ref().map_or(false, |x| x.character_id == id) {
             self.prediction.reconcile(latest_input, new_pos);
         }
@@ -179,6 +498,9 @@ impl Sim {
                         self.graph_entities.insert(new_pos.node, entity);
                     }
                     *pos = new_pos;
+                    if let Ok(mut trail) = self.world.get_mut::<Trail>(entity) {
+                        trail.points.push_back((step, new_pos));
+                    }
                 }
                 Err(e) => error!(%id, "position update for unpositioned entity {}", e),
             },
@@ -187,16 +509,45 @@ impl Sim {
 
     fn handle_spawns(&mut self, msg: proto::Spawns) {
         self.step = self.step.max(Some(msg.step));
+        if let (Some(x), Some(params)) = (msg.reconfigure, self.params.as_mut()) {
+            info!(
+                rate = x.rate,
+                movement_speed = x.movement_speed,
+                "server reconfigured"
+            );
+            params.step_interval = Duration::from_secs(1) / u32::from(x.rate);
+            params.interpolation_delay = params.step_interval.mul_f64(INTERPOLATION_DELAY);
+            params.movement_speed = x.movement_speed;
+            params.max_reach = x.max_reach;
+        }
+        if msg.resync {
+            let entities = self.entity_ids.values().cloned().collect::<Vec<_>>();
+            for entity in entities {
+                self.destroy(entity);
+            }
+            self.awaiting_resync = false;
+        }
         let mut builder = hecs::EntityBuilder::new();
-        for (id, components) in msg.spawns {
+        for (id, components) in msg.spawns.into_iter().chain(msg.enters) {
             self.spawn(&mut builder, id, components);
         }
+        for hit in &msg.hits {
+            debug!(projectile = %hit.projectile, target = ?hit.target, "projectile hit");
+        }
         for &id in &msg.despawns {
             match self.entity_ids.get(&id) {
                 Some(&entity) => self.destroy(entity),
+                None if self.is_stale(id) => trace!(%id, "despawned stale entity"),
                 None => error!(%id, "despawned unknown entity"),
             }
         }
+        for &id in &msg.leaves {
+            match self.entity_ids.get(&id) {
+                Some(&entity) => self.destroy(entity),
+                None if self.is_stale(id) => trace!(%id, "stale entity left view"),
+                None => error!(%id, "unknown entity left view"),
+            }
+        }
         if !msg.nodes.is_empty() {
             trace!(count = msg.nodes.len(), "adding nodes");
         }
@@ -204,6 +555,99 @@ impl Sim {
             self.graph.insert_child(node.parent, node.side);
         }
         populate_fresh_nodes(&mut self.graph);
+        self.graph.clear_fresh();
+        for diff in msg.voxels {
+            self.handle_chunk_diff(diff);
+        }
+        for x in msg.metadata {
+            let chunk = ChunkId::new(x.node, x.chunk);
+            if x.metadata.is_empty() {
+                self.metadata.remove(&chunk);
+            } else {
+                self.metadata.insert(chunk, x.metadata);
+            }
+        }
+    }
+
+    fn handle_chunk_diff(&mut self, diff: ChunkDiff) {
+        let dimension = match self.params {
+            Some(ref x) => x.chunk_size,
+            None => {
+                error!("voxel changes received before hello");
+                return;
+            }
+        };
+        if !self.graph.contains(diff.node) {
+            error!(node = ?diff.node, "voxel changes for unknown node");
+            return;
+        }
+        let chunk = match self.graph.get_mut(diff.node) {
+            Some(node) => &mut node.chunks[diff.chunk],
+            None => {
+                error!(node = ?diff.node, "voxel changes for unpopulated node");
+                return;
+            }
+        };
+        if let Chunk::Populated { ref voxels, .. } = *chunk {
+            let mut voxels = voxels.clone();
+            if let Err(e) = voxel_diff::apply(dimension, &mut voxels, &diff.changes) {
+                error!(node = ?diff.node, chunk = ?diff.chunk, "malformed voxel changes: {:#}", e);
+                return;
+            }
+            *chunk = Chunk::populated(dimension, voxels);
+            self.graph
+                .notify(GraphEvent::ChunkEdited(diff.node, diff.chunk));
+        }
+        let chunk = ChunkId::new(diff.node, diff.chunk);
+        self.chunk_hashes.remove(&chunk);
+        let edits = self.voxel_edits.entry(chunk).or_default();
+        if let VoxelChanges::Whole(_) = diff.changes {
+            // Supersedes everything before it
+            edits.clear();
+        }
+        edits.push(diff.changes);
+    }
+
+    /// `chunk_hash` of `chunk` with the server's edits applied, generating it if necessary
+    fn edited_chunk_hash(&self, chunk: ChunkId) -> Option<u64> {
+        let params = self.params.as_ref()?;
+        if let Chunk::Populated { ref voxels, .. } =
+            self.graph.get(chunk.node).as_ref()?.chunks[chunk.vertex]
+        {
+            return Some(chunk_hash(params.chunk_size, voxels));
+        }
+        let mut voxels = match self.voxel_edits.get(&chunk)?.first() {
+            // Replaces whatever would have been generated
+            Some(&VoxelChanges::Whole(_)) => VoxelData::Solid(Material::Void),
+            _ => worldgen::ChunkParams::new(
+                params.chunk_size,
+                params.worldgen_preset,
+                &self.graph,
+                chunk.node,
+                chunk.vertex,
+            )?
+            .generate_voxels(),
+        };
+        self.restore_edits(chunk, &mut voxels);
+        Some(chunk_hash(params.chunk_size, &voxels))
+    }
+
+    /// Metadata attached to the voxel at `coords`, if any
+    pub fn metadata(&self, chunk: ChunkId, coords: [u8; 3]) -> Option<&[u8]> {
+        self.metadata.get(&chunk)?.get(&coords).map(|x| &x[..])
+    }
+
+    /// Bring freshly generated `voxels` for `chunk` up to date with the server's edits
+    pub fn restore_edits(&self, chunk: ChunkId, voxels: &mut VoxelData) {
+        let dimension = match self.params {
+            Some(ref x) => x.chunk_size,
+            None => return,
+        };
+        for changes in self.voxel_edits.get(&chunk).into_iter().flatten() {
+            if let Err(e) = voxel_diff::apply(dimension, voxels, changes) {
+                error!(?chunk, "malformed voxel changes: {:#}", e);
+            }
+        }
     }
 
     fn spawn(
@@ -212,26 +656,48 @@ impl Sim {
         id: EntityId,
         components: Vec<Component>,
     ) {
+        if self.is_stale(id) {
+            debug!(%id, "ignoring spawn of stale entity");
+            return;
+        }
+        if let Some(&old) = self.slots.get(&id.index()) {
+            // We missed the previous occupant's departure
+            if let Some(&entity) = self.entity_ids.get(&old).filter(|_| old != id) {
+                debug!(%old, new = %id, "replacing stale entity");
+                self.destroy(entity);
+            }
+        }
+        self.slots.insert(id.index(), id);
         trace!(%id, "spawning entity");
         builder.add(id);
-        let mut node = None;
+        let mut position = None;
         for component in components {
             use common::proto::Component::*;
             match component {
                 Character(x) => {
+                    builder.add(Look::new(x.orientation));
                     builder.add(x);
                 }
                 Position(x) => {
-                    node = Some(x.node);
+                    position = Some(x);
+                    builder.add(x);
+                }
+                Projectile(x) => {
                     builder.add(x);
                 }
             };
         }
+        let local = id == self.params.as_ref().unwrap().character_id;
+        if let (Some(position), false) = (position, local) {
+            builder.add(Trail {
+                points: vec![(self.step.unwrap_or(0), position)].into(),
+            });
+        }
         let entity = self.world.spawn(builder.build());
-        if let Some(node) = node {
-            self.graph_entities.insert(node, entity);
+        if let Some(position) = position {
+            self.graph_entities.insert(position.node, entity);
         }
-        if id == self.params.as_ref().unwrap().character_id {
+        if local {
             self.local_character = Some(entity);
         }
         if let Some(x) = self.entity_ids.insert(id, entity) {
@@ -240,8 +706,80 @@ impl Sim {
         }
     }
 
+    /// Advance the step at which remote entities are drawn, keeping it `INTERPOLATION_DELAY` steps
+    /// behind the latest from the server
+    fn advance_playback(&mut self, dt: Duration) {
+        let (step_interval, latest) = match (self.params.as_ref(), self.step) {
+            (Some(params), Some(step)) => (params.step_interval, f64::from(step)),
+            _ => return,
+        };
+        let target = latest - INTERPOLATION_DELAY;
+        let elapsed = dt.as_secs_f64() / step_interval.as_secs_f64();
+        let playback = match self.playback {
+            // Skip over stalls and bursts rather than replaying them in slow or fast motion
+            Some(x) if (x - target).abs() <= INTERPOLATION_DELAY => {
+                // Ease towards the target to absorb jitter in when updates arrive
+                let x = x + elapsed;
+                (x + (target - x) * (elapsed * PLAYBACK_CORRECTION).min(1.0)).min(latest)
+            }
+            _ => target,
+        };
+        self.playback = Some(playback);
+        for (_, trail) in self.world.query::<&mut Trail>().iter() {
+            // Keep the last point at or before the playback step to move on from
+            while trail
+                .points
+                .get(1)
+                .map_or(false, |&(step, _)| f64::from(step) <= playback)
+            {
+                trail.points.pop_front();
+            }
+        }
+    }
+
+    /// Transform of `entity` as drawn, relative to the node of its `Position`
+    ///
+    /// Remote entities trail their latest known position by `Parameters::interpolation_delay`,
+    /// moving smoothly between the positions received from the server.
+    pub fn displayed_transform(&self, entity: Entity) -> Option<na::Matrix4<f32>> {
+        let position = *self.world.get::<Position>(entity).ok()?;
+        let (trail, playback) = match (self.world.get::<Trail>(entity), self.playback) {
+            (Ok(trail), Some(playback)) => (trail, playback),
+            _ => return Some(position.local),
+        };
+        // In the coordinates of `position.node`
+        let local = |p: &Position| -> Option<math::Isometry<f32>> {
+            let local = if p.node == position.node {
+                p.local
+            } else {
+                let transform = self.graph.relative_transform(position.node, p.node)?;
+                na::convert::<_, na::Matrix4<f32>>(*transform.matrix()) * p.local
+            };
+            Some(math::Isometry::from_matrix_unchecked(local))
+        };
+        let next = trail
+            .points
+            .iter()
+            .position(|&(step, _)| f64::from(step) > playback);
+        let (before, after) = match next {
+            Some(0) => return Some(*local(&trail.points[0].1)?.matrix()),
+            Some(i) => (&trail.points[i - 1], &trail.points[i]),
+            None => return Some(*local(&trail.points.back()?.1)?.matrix()),
+        };
+        let t = ((playback - f64::from(before.0)) / f64::from(after.0 - before.0)) as f32;
+        let (before, after) = (local(&before.1)?, local(&after.1)?);
+        let pose = before.lerp_geodesic(&after, t, math::RotationLerp::Slerp);
+        Some(*pose.matrix())
+    }
+
     fn send_input(&mut self) {
-        let (direction, speed) = sanitize_motion_input(self.orientation * self.average_velocity);
+        // Spectating leaves the character standing still
+        let velocity = if self.spectator.is_some() {
+            na::zero()
+        } else {
+            self.body_velocity()
+        };
+        let (direction, speed) = sanitize_motion_input(velocity);
         let params = self.params.as_ref().unwrap();
         let generation = self.prediction.push(
             &direction,
@@ -250,29 +788,132 @@ impl Sim {
                 * self.params.as_ref().unwrap().step_interval.as_secs_f32(),
         );
 
+        let ping = if self.since_ping >= PING_INTERVAL {
+            self.since_ping = Duration::new(0, 0);
+            Some(proto::Ping {
+                client_time: self.client_time(),
+            })
+        } else {
+            None
+        };
+
+        let tick = self.next_tick;
+        self.next_tick = tick.map(TickId::next);
+
         // Any failure here will be better handled in handle_net's ConnectionLost case
         let _ = self.net.outgoing.send(Command {
             generation,
             orientation: self.orientation,
             velocity: direction.into_inner() * speed,
+            resync: mem::replace(&mut self.request_resync, false),
+            ping,
+            admin: self.admin.take(),
+            edit: self.edit.take(),
+            fire: mem::replace(&mut self.fire, false),
+            tick,
         });
     }
 
+    /// Microseconds since `epoch`, for `proto::Ping`
+    fn client_time(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Smoothed round-trip time to the server, if measured
+    pub fn rtt(&self) -> Option<Duration> {
+        self.clock.rtt()
+    }
+
+    /// Estimated server clock minus ours, in seconds, if measured
+    pub fn clock_offset(&self) -> Option<f64> {
+        self.clock.offset()
+    }
+
+    /// Speed at which the local character is being moved, in meters per second
+    pub fn speed(&self) -> f32 {
+        match self.params {
+            Some(ref params) => {
+                let (_, speed) = sanitize_motion_input(self.instantaneous_velocity);
+                speed * params.movement_speed / params.meters_to_absolute
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Average input over the current step, relative to the character's body rather than the view
+    fn body_velocity(&self) -> na::Vector3<f32> {
+        match self.movement_frame {
+            MovementFrame::View => self.orientation * self.average_velocity,
+            MovementFrame::World => self.average_velocity,
+        }
+    }
+
     pub fn view(&self) -> Position {
+        if let Some(camera) = self.spectator {
+            return camera;
+        }
         let mut result = *self.prediction.predicted();
-        result.local *= self.orientation.to_homogeneous();
         if let Some(ref params) = self.params {
             // Apply input that hasn't been sent yet
-            let (direction, speed) = sanitize_motion_input(self.average_velocity);
+            let (direction, speed) = sanitize_motion_input(self.body_velocity());
             // We multiply by the entire timestep rather than the time so far because
             // self.average_velocity is always over the entire timestep, filling in zeroes for the
             // future.
             let distance = speed * params.movement_speed * params.step_interval.as_secs_f32();
             result.local *= math::translate_along(&direction, distance);
         }
+        result.local *= self.orientation.to_homogeneous();
         result
     }
 
+    /// The sun's ideal point in the coordinates of the view's node, and the brightness of its light
+    pub fn sun(&self) -> (na::Vector4<f32>, f32) {
+        let view = self.view();
+        let up = match *self.graph.get(view.node) {
+            Some(ref node) => node.state.surface().normal().map(|x| x as f32),
+            None => na::Vector4::y(),
+        };
+        let point = daylight::sun_point(&up, &(view.local * math::origin()), self.time_of_day);
+        (point, daylight::daylight(self.time_of_day))
+    }
+
+    /// Roll the camera so that its up direction lies in the plane of the terrain's up direction and
+    /// the view axis
+    fn level_view(&mut self) {
+        let view = self.view();
+        let up = match *self.graph.get(view.node) {
+            Some(ref node) => node.state.surface().normal().map(|x| x as f32),
+            None => return,
+        };
+        let roll = level(&view.local, &up);
+        match self.spectator {
+            Some(ref mut camera) => camera.local *= roll.to_homogeneous(),
+            None => self.orientation *= roll,
+        }
+    }
+
+    /// Move the spectator camera according to the latest input, passing through anything in its way
+    fn fly(&mut self, dt: Duration) {
+        let speed = match self.params {
+            Some(ref params) => params.movement_speed,
+            None => return,
+        };
+        let camera = self.spectator.as_mut().unwrap();
+        let (direction, input) = sanitize_motion_input(self.instantaneous_velocity);
+        camera.local *= math::translate_along(&direction, input * speed * dt.as_secs_f32());
+        // Stay in the nearest node to preserve precision
+        let (node, transform) = self.graph.normalize_transform(camera.node, &camera.local);
+        camera.node = node;
+        camera.local = math::renormalize_isometry(&(transform * camera.local));
+    }
+
+    /// Whether `id` has been superseded by a later occupant of its slot
+    fn is_stale(&self, id: EntityId) -> bool {
+        self.slots
+            .get(&id.index())
+            .map_or(false, |&latest| id.is_older_than(latest))
+    }
+
     /// Destroy all aspects of an entity
     fn destroy(&mut self, entity: Entity) {
         let id = *self
@@ -294,33 +935,808 @@ impl Sim {
     }
 }
 
+/// Positions of a remote entity recently received from the server, oldest first, with the steps
+/// they describe
+///
+/// Drawn `Parameters::interpolation_delay` late, so there's usually a later position to move
+/// towards. See `Sim::displayed_transform`.
+pub struct Trail {
+    points: VecDeque<(Step, Position)>,
+}
+
+/// Where a character is looking, smoothed between updates from the server
+///
+/// Turns are interpolated over one step, independently of the character's position.
+pub struct Look {
+    from: na::UnitQuaternion<f32>,
+    to: na::UnitQuaternion<f32>,
+    /// Fraction of the way from `from` to `to`
+    progress: f32,
+}
+
+impl Look {
+    fn new(orientation: na::UnitQuaternion<f32>) -> Self {
+        Self {
+            from: orientation,
+            to: orientation,
+            progress: 1.0,
+        }
+    }
+
+    /// Begin turning towards `target` from the current orientation
+    fn retarget(&mut self, target: na::UnitQuaternion<f32>) {
+        self.from = self.current();
+        self.to = target;
+        self.progress = 0.0;
+    }
+
+    /// Advance the turn by `fraction` of a step
+    fn advance(&mut self, fraction: f32) {
+        self.progress = (self.progress + fraction).min(1.0);
+    }
+
+    pub fn current(&self) -> na::UnitQuaternion<f32> {
+        self.from
+            .try_slerp(&self.to, self.progress, 1e-6)
+            .unwrap_or(self.to)
+    }
+}
+
+/// Distance from the viewer within which particles are emitted, in absolute units
+const PARTICLE_RANGE: f32 = 1.5;
+
+/// Rotation about the view axis of `view` which brings its up direction as close as possible to
+/// the direction away from the plane whose normal is `up`
+///
+/// Any loop in hyperbolic space rotates whatever travels around it in proportion to the area
+/// enclosed, so a camera that only ever yaws and pitches still picks up roll as it moves. Leveling
+/// against a plane fixed in the world avoids that drift.
+fn level(view: &na::Matrix4<f32>, up: &na::Vector4<f32>) -> na::UnitQuaternion<f32> {
+    // Direction of increasing distance from the plane, in the tangent space at the camera
+    let up = (view.try_inverse().unwrap() * up).xy();
+    if up.norm() < 1e-6 {
+        // Looking straight up or down; roll is arbitrary
+        return na::one();
+    }
+    let angle = up.y.atan2(up.x) - std::f32::consts::FRAC_PI_2;
+    na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), angle)
+}
+
+/// Number of consecutive mismatched state hashes after which we consider ourselves desynchronized
+const HASH_MISMATCH_TOLERANCE: u32 = 2;
+
+/// Minimum time between pings to the server
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Steps by which remote entities are drawn behind the latest state from the server
+///
+/// Enough that the next update has usually arrived before it's needed, even when it's late.
+const INTERPOLATION_DELAY: f64 = 1.5;
+
+/// Fraction of the difference between the playback step and its target made up per step
+const PLAYBACK_CORRECTION: f64 = 0.1;
+
 /// Simulation details received on connect
 pub struct Parameters {
     pub step_interval: Duration,
+    /// Time by which remote entities are drawn behind the latest state from the server, per
+    /// `INTERPOLATION_DELAY`
+    pub interpolation_delay: Duration,
     pub chunk_size: u8,
     pub meters_to_absolute: f32,
     /// Absolute units
     pub movement_speed: f32,
+    /// Absolute units
+    pub max_reach: f32,
     pub character_id: EntityId,
+    pub worldgen_preset: worldgen::Preset,
 }
 
-fn populate_fresh_nodes(graph: &mut DualGraph) {
-    let fresh = graph.fresh().to_vec();
-    graph.clear_fresh();
-    for &node in &fresh {
-        populate_node(graph, node);
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn sim(character: EntityId) -> (Sim, mpsc::UnboundedReceiver<Command>) {
+        sim_with_frame(character, MovementFrame::View)
     }
-}
 
-fn populate_node(graph: &mut DualGraph, node: NodeId) {
-    *graph.get_mut(node) = Some(Node {
-        state: graph
-            .parent(node)
-            .and_then(|i| {
-                let parent_state = &graph.get(graph.neighbor(node, i)?).as_ref()?.state;
-                Some(parent_state.child(graph, node, i))
+    fn sim_with_frame(
+        character: EntityId,
+        movement_frame: MovementFrame,
+    ) -> (Sim, mpsc::UnboundedReceiver<Command>) {
+        sim_with(character, movement_frame, 10)
+    }
+
+    fn sim_with(
+        character: EntityId,
+        movement_frame: MovementFrame,
+        rate: u16,
+    ) -> (Sim, mpsc::UnboundedReceiver<Command>) {
+        let (_, incoming) = mpsc::unbounded_channel();
+        let (outgoing, commands) = mpsc::unbounded_channel();
+        let mut sim = Sim::new(
+            Net {
+                incoming,
+                outgoing,
+                thread: thread::spawn(|| {}),
+            },
+            movement_frame,
+            false,
+            1e-4,
+        );
+        sim.handle_net(net::Message::Hello(proto::ServerHello {
+            character,
+            rate,
+            chunk_size: 12,
+            movement_speed: 1.0,
+            meters_to_absolute: 1.0,
+            max_reach: 10.0,
+            seed: 0,
+            worldgen_preset: worldgen::Preset::default(),
+            lockstep: None,
+        }));
+        (sim, commands)
+    }
+
+    fn spawns(step: Step, ids: &[EntityId], resync: bool) -> net::Message {
+        let components = || {
+            vec![
+                Component::Position(Position::origin()),
+                Component::Character(Character {
+                    name: "x".into(),
+                    orientation: na::one(),
+                }),
+            ]
+        };
+        net::Message::Spawns(proto::Spawns {
+            step,
+            spawns: ids.iter().map(|&id| (id, components())).collect(),
+            despawns: Vec::new(),
+            nodes: Vec::new(),
+            enters: Vec::new(),
+            leaves: Vec::new(),
+            hits: Vec::new(),
+            voxels: Vec::new(),
+            metadata: Vec::new(),
+            reconfigure: None,
+            resync,
+        })
+    }
+
+    /// A state update in which the server knows of `ids`, the first of which is the recipient's
+    /// character, all at the origin
+    fn delta(step: Step, ids: &[EntityId]) -> net::Message {
+        delta_with_chunks(step, ids, &[])
+    }
+
+    /// Like `delta`, with the server having changed `chunks` to have the given `chunk_hash`es
+    fn delta_with_chunks(step: Step, ids: &[EntityId], chunks: &[(ChunkId, u64)]) -> net::Message {
+        let origin = Position::origin();
+        state(step, 0, ids, &origin, chunks)
+    }
+
+    /// A state update in which the server knows of `ids`, the first of which is the recipient's
+    /// character, having moved to `position` per inputs up to `latest_input`
+    fn state(
+        step: Step,
+        latest_input: u16,
+        ids: &[EntityId],
+        position: &Position,
+        chunks: &[(ChunkId, u64)],
+    ) -> net::Message {
+        net::Message::StateDelta(proto::StateDelta {
+            step,
+            latest_input,
+            positions: ids.iter().map(|&id| (id, *position)).collect(),
+            character_orientations: Vec::new(),
+            time_of_day: 0.0,
+            pong: None,
+            hash: Some(state_hash(
+                ids.iter().cloned(),
+                (ids[0], position),
+                chunks.iter().cloned(),
+            )),
+            inventory: None,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn divergence_triggers_resync() {
+        let (a, b) = (EntityId::new(1, 0), EntityId::new(2, 0));
+        let (mut sim, mut commands) = sim(a);
+        sim.handle_net(spawns(0, &[a, b], false));
+        sim.handle_net(delta(1, &[a, b]));
+        assert_eq!(sim.hash_mismatches, 0);
+
+        // Miss the despawn of `b`
+        sim.handle_net(delta(2, &[a]));
+        assert!(!sim.request_resync);
+        sim.handle_net(delta(3, &[a]));
+        sim.send_input();
+        assert!(commands.try_recv().unwrap().resync);
+        // Only requested once
+        sim.send_input();
+        assert!(!commands.try_recv().unwrap().resync);
+
+        sim.handle_net(spawns(4, &[a], true));
+        assert_eq!(sim.entity_ids.len(), 1);
+        assert!(!sim.awaiting_resync);
+        sim.handle_net(delta(5, &[a]));
+        assert_eq!(sim.hash_mismatches, 0);
+        assert!(!sim.request_resync);
+    }
+
+    #[test]
+    fn missed_chunk_change_triggers_resync() {
+        use common::dodeca::Vertex;
+
+        let a = EntityId::new(1, 0);
+        let (mut sim, mut commands) = sim(a);
+        sim.handle_net(spawns(0, &[a], false));
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let change = |step, material| {
+            let mut msg = match spawns(step, &[], false) {
+                net::Message::Spawns(x) => x,
+                _ => unreachable!(),
+            };
+            msg.voxels.push(ChunkDiff {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                changes: voxel_diff::whole(12, &VoxelData::Solid(material)),
+            });
+            net::Message::Spawns(msg)
+        };
+        let contents = |material| (chunk, chunk_hash(12, &VoxelData::Solid(material)));
+        sim.handle_net(change(1, Material::Dirt));
+        sim.handle_net(delta_with_chunks(1, &[a], &[contents(Material::Dirt)]));
+        assert_eq!(sim.hash_mismatches, 0);
+
+        // Miss the second change
+        sim.handle_net(delta_with_chunks(2, &[a], &[contents(Material::Stone)]));
+        sim.handle_net(delta_with_chunks(3, &[a], &[contents(Material::Stone)]));
+        sim.send_input();
+        assert!(commands.try_recv().unwrap().resync);
+        // The chunk is resent in full along with everything else
+        sim.handle_net(spawns(4, &[a], true));
+        sim.handle_net(change(4, Material::Stone));
+        sim.handle_net(delta_with_chunks(5, &[a], &[contents(Material::Stone)]));
+        assert!(!sim.awaiting_resync);
+        assert_eq!(sim.hash_mismatches, 0);
+    }
+
+    #[test]
+    fn prediction_ignored_by_hash() {
+        let a = EntityId::new(1, 0);
+        let (mut sim, _commands) = sim(a);
+        sim.handle_net(spawns(0, &[a], false));
+        // Moving locally before the server has heard of it
+        sim.velocity(-na::Vector3::z());
+        sim.step(Duration::from_millis(100));
+        assert!(sim.view().local != na::Matrix4::identity());
+        sim.handle_net(delta(1, &[a]));
+        sim.handle_net(delta(2, &[a]));
+        assert_eq!(sim.hash_mismatches, 0);
+    }
+
+    #[test]
+    fn position_divergence_triggers_resync() {
+        let a = EntityId::new(1, 0);
+        let (mut sim, mut commands) = sim(a);
+        sim.handle_net(spawns(0, &[a], false));
+        sim.velocity(-na::Vector3::z());
+
+        // The server moves the character as predicted
+        sim.step(Duration::from_millis(100));
+        let input = commands.try_recv().unwrap().generation;
+        let walked = *sim.prediction.predicted();
+        assert!(walked.local != na::Matrix4::identity());
+        sim.handle_net(state(1, input, &[a], &walked, &[]));
+        assert_eq!(sim.hash_mismatches, 0);
+
+        // The server stops the character, e.g. at an obstacle the client doesn't know of, while
+        // the client keeps predicting that it walks on
+        for step in 2..4 {
+            sim.step(Duration::from_millis(100));
+            let input = commands.try_recv().unwrap().generation;
+            sim.handle_net(state(step, input, &[a], &walked, &[]));
+        }
+        sim.send_input();
+        assert!(commands.try_recv().unwrap().resync);
+    }
+
+    #[test]
+    fn edits_replayed_after_generation() {
+        use common::{dodeca::Vertex, node::voxel_index, world::Material};
+
+        let character = EntityId::new(0, 0);
+        let (mut sim, _commands) = sim(character);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let index = voxel_index(12, [1, 2, 3]);
+        let edit = |changes| {
+            let mut msg = match spawns(0, &[], false) {
+                net::Message::Spawns(x) => x,
+                _ => unreachable!(),
+            };
+            msg.voxels.push(ChunkDiff {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                changes,
+            });
+            net::Message::Spawns(msg)
+        };
+        let generated = |sim: &Sim| {
+            let mut voxels = VoxelData::Solid(Material::Void);
+            sim.restore_edits(chunk, &mut voxels);
+            voxels
+        };
+
+        // Not yet generated when the edit arrives
+        let stone = VoxelChanges::Sparse(vec![(index as u32, Material::Stone)]);
+        sim.handle_net(edit(stone));
+        let voxels = generated(&sim);
+        assert_eq!(voxels.get(index), Material::Stone);
+        assert_eq!(voxels.get(index + 1), Material::Void);
+
+        // Whole contents supersede earlier edits
+        let whole = voxel_diff::whole(12, &VoxelData::Solid(Material::Dirt));
+        sim.handle_net(edit(whole));
+        let voxels = generated(&sim);
+        assert_eq!(voxels.get(index), Material::Dirt);
+        assert_eq!(sim.voxel_edits[&chunk].len(), 1);
+    }
+
+    #[test]
+    fn metadata_replaced_per_chunk() {
+        use common::dodeca::Vertex;
+
+        let (mut sim, _commands) = sim(EntityId::new(0, 0));
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let update = |entries: &[([u8; 3], &[u8])]| {
+            let mut msg = match spawns(0, &[], false) {
+                net::Message::Spawns(x) => x,
+                _ => unreachable!(),
+            };
+            msg.metadata.push(proto::ChunkMetadata {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                metadata: entries
+                    .iter()
+                    .map(|&(coords, data)| (coords, data.to_vec()))
+                    .collect(),
+            });
+            net::Message::Spawns(msg)
+        };
+
+        sim.handle_net(update(&[([1, 2, 3], b"chest"), ([4, 5, 6], b"sign")]));
+        assert_eq!(sim.metadata(chunk, [1, 2, 3]), Some(&b"chest"[..]));
+        assert_eq!(sim.metadata(chunk, [4, 5, 6]), Some(&b"sign"[..]));
+        // Entries missing from a later update are gone
+        sim.handle_net(update(&[([4, 5, 6], b"sign")]));
+        assert_eq!(sim.metadata(chunk, [1, 2, 3]), None);
+        sim.handle_net(update(&[]));
+        assert_eq!(sim.metadata(chunk, [4, 5, 6]), None);
+        assert!(sim.metadata.is_empty());
+    }
+
+    #[test]
+    fn sun_follows_server_time() {
+        let character = EntityId::new(0, 0);
+        let at = |step, time_of_day| {
+            net::Message::StateDelta(proto::StateDelta {
+                step,
+                latest_input: 0,
+                positions: Vec::new(),
+                character_orientations: Vec::new(),
+                time_of_day,
+                pong: None,
+                hash: None,
+                inventory: None,
+                admin_replies: Vec::new(),
+                edit_rejections: Vec::new(),
             })
-            .unwrap_or_else(NodeState::root),
-        chunks: Chunks::default(),
-    });
+        };
+        let (mut a, _a_commands) = sim(character);
+        let (mut b, _b_commands) = sim(character);
+        a.handle_net(at(1, 0.1));
+        b.handle_net(at(1, 0.1));
+        let morning = a.sun();
+        assert_eq!(morning, b.sun());
+
+        a.handle_net(at(2, 0.3));
+        assert_ne!(a.sun(), morning);
+        // Late messages don't turn back the clock
+        a.handle_net(at(1, 0.1));
+        assert_ne!(a.sun(), morning);
+    }
+
+    #[test]
+    fn pong_measures_rtt() {
+        let character = EntityId::new(0, 0);
+        let (mut sim, mut commands) = sim(character);
+        sim.send_input();
+        let ping = commands.try_recv().unwrap().ping.unwrap();
+        // Not again until the interval has passed
+        sim.send_input();
+        assert!(commands.try_recv().unwrap().ping.is_none());
+        assert!(sim.rtt().is_none());
+
+        let server_time = 10_000_000;
+        let mut delta = proto::StateDelta {
+            step: 1,
+            latest_input: 0,
+            positions: Vec::new(),
+            character_orientations: Vec::new(),
+            time_of_day: 0.0,
+            pong: None,
+            hash: None,
+            inventory: None,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+        };
+        sim.handle_net(net::Message::StateDelta(delta.clone()));
+        // A late message still carries useful timing
+        delta.pong = Some(proto::Pong {
+            client_time: ping.client_time,
+            received: server_time,
+            sent: server_time,
+        });
+        sim.handle_net(net::Message::StateDelta(delta));
+        assert!(sim.rtt().unwrap() < Duration::from_secs(1));
+        // Our clock started just now, and the server's ten seconds ago
+        assert!((sim.clock_offset().unwrap() - 10.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn stale_ids_ignored() {
+        let character = EntityId::new(0, 0);
+        let (old, new) = (EntityId::new(3, 0), EntityId::new(3, 1));
+        let (mut sim, _commands) = sim(character);
+        sim.handle_net(spawns(0, &[character, old], false));
+
+        // The despawn of `old` is lost, and its slot reused
+        sim.handle_net(spawns(1, &[new], false));
+        assert!(!sim.entity_ids.contains_key(&old));
+        let entity = sim.entity_ids[&new];
+
+        // A straggling update addressed to the old occupant
+        let moved = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::x_axis(), 1.0),
+        };
+        sim.handle_net(net::Message::StateDelta(proto::StateDelta {
+            step: 2,
+            latest_input: 0,
+            positions: vec![(old, moved)],
+            character_orientations: vec![(old, na::one())],
+            time_of_day: 0.0,
+            pong: None,
+            hash: None,
+            inventory: None,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+        }));
+        let pos = *sim.world.get::<Position>(entity).unwrap();
+        assert_eq!(pos.local, Position::origin().local);
+
+        // Late messages about the old occupant don't disturb the new one
+        sim.handle_net(spawns(3, &[old], false));
+        assert!(!sim.entity_ids.contains_key(&old));
+        assert_eq!(sim.entity_ids.len(), 2);
+    }
+
+    /// Velocity sent to the server for steady forward input after turning by `angle` radians
+    fn turned_velocity(movement_frame: MovementFrame, angle: f32) -> na::Vector3<f32> {
+        let (mut sim, mut commands) = sim_with_frame(EntityId::new(0, 0), movement_frame);
+        sim.rotate(&na::UnitQuaternion::from_axis_angle(
+            &na::Vector3::y_axis(),
+            angle,
+        ));
+        sim.average_velocity = -na::Vector3::z();
+        sim.send_input();
+        commands.try_recv().unwrap().velocity
+    }
+
+    #[test]
+    fn view_relative_movement() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        let forward = turned_velocity(MovementFrame::View, 0.0);
+        assert!((forward - -na::Vector3::z()).norm() < 1e-5);
+        // Turning left a quarter turn makes forward input move along -x
+        let turned = turned_velocity(MovementFrame::View, quarter);
+        assert!((turned - -na::Vector3::x()).norm() < 1e-5);
+
+        // World-relative movement is unaffected by the view
+        let fixed = turned_velocity(MovementFrame::World, quarter);
+        assert!((fixed - -na::Vector3::z()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn look_interpolated_independently() {
+        let (local, remote) = (EntityId::new(0, 0), EntityId::new(1, 0));
+        let (mut sim, _commands) = sim(local);
+        sim.handle_net(spawns(0, &[local, remote], false));
+        let entity = sim.entity_ids[&remote];
+        let look = |sim: &Sim| sim.world.get::<Look>(entity).unwrap().current();
+        let position = |sim: &Sim| sim.world.get::<Position>(entity).unwrap().local;
+
+        // Moving doesn't disturb where a character is looking
+        let moved = math::translate_along(&na::Vector3::x_axis(), 1.0);
+        sim.handle_net(net::Message::StateDelta(proto::StateDelta {
+            step: 1,
+            latest_input: 0,
+            positions: vec![(
+                remote,
+                Position {
+                    node: NodeId::ROOT,
+                    local: moved,
+                },
+            )],
+            character_orientations: vec![(remote, na::one())],
+            time_of_day: 0.0,
+            pong: None,
+            hash: None,
+            inventory: None,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+        }));
+        assert_eq!(position(&sim), moved);
+        assert_eq!(look(&sim), na::one());
+
+        // Turning is smoothed over a step while the position stays put
+        let turned = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.0);
+        sim.handle_net(net::Message::StateDelta(proto::StateDelta {
+            step: 2,
+            latest_input: 0,
+            positions: Vec::new(),
+            character_orientations: vec![(remote, turned)],
+            time_of_day: 0.0,
+            pong: None,
+            hash: None,
+            inventory: None,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+        }));
+        assert!(look(&sim).angle() < 1e-4);
+        sim.step(Duration::from_millis(50));
+        assert!((look(&sim).angle() - 0.5).abs() < 1e-4);
+        assert_eq!(position(&sim), moved);
+        sim.step(Duration::from_millis(50));
+        assert!(look(&sim).angle_to(&turned) < 1e-4);
+        assert_eq!(
+            sim.world.get::<Character>(entity).unwrap().orientation,
+            turned
+        );
+    }
+
+    #[test]
+    fn interpolation_delay_follows_rate() {
+        let delay = |rate| {
+            let (sim, _commands) = sim_with(EntityId::new(0, 0), MovementFrame::View, rate);
+            sim.params().unwrap().interpolation_delay.as_secs_f64()
+        };
+        assert!((delay(10) - 0.150).abs() < 1e-6);
+        assert!((delay(30) - 0.050).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reconfigured_by_server() {
+        let (mut sim, _commands) = sim_with(EntityId::new(0, 0), MovementFrame::View, 10);
+        let mut msg = match spawns(0, &[], false) {
+            net::Message::Spawns(x) => x,
+            _ => unreachable!(),
+        };
+        msg.reconfigure = Some(proto::Reconfigure {
+            rate: 30,
+            movement_speed: 2.0,
+            max_reach: 5.0,
+        });
+        sim.handle_net(net::Message::Spawns(msg));
+        let params = sim.params().unwrap();
+        assert_eq!(params.step_interval, Duration::from_secs(1) / 30);
+        assert!((params.interpolation_delay.as_secs_f64() - 0.050).abs() < 1e-6);
+        assert_eq!(params.movement_speed, 2.0);
+        assert_eq!(params.max_reach, 5.0);
+    }
+
+    #[test]
+    fn remote_motion_smooth() {
+        const SPEED: f64 = 0.5;
+        for &rate in &[10, 30] {
+            let (local, remote) = (EntityId::new(0, 0), EntityId::new(1, 0));
+            let (mut sim, _commands) = sim_with(local, MovementFrame::View, rate);
+            sim.handle_net(spawns(0, &[local, remote], false));
+            let entity = sim.entity_ids[&remote];
+            let step_interval = sim.params().unwrap().step_interval;
+            let dt = step_interval / 4;
+            // Distance along x as drawn
+            let displayed = |sim: &Sim| {
+                let p = sim.displayed_transform(entity).unwrap() * math::origin();
+                f64::from(p.x).asinh()
+            };
+
+            let mut previous: Option<f64> = None;
+            for step in 1..40 {
+                let distance = SPEED * step_interval.as_secs_f64() * f64::from(step);
+                sim.handle_net(net::Message::StateDelta(proto::StateDelta {
+                    step,
+                    latest_input: 0,
+                    positions: vec![(
+                        remote,
+                        Position {
+                            node: NodeId::ROOT,
+                            local: math::translate_along(&na::Vector3::x_axis(), distance as f32),
+                        },
+                    )],
+                    character_orientations: Vec::new(),
+                    time_of_day: 0.0,
+                    pong: None,
+                    hash: None,
+                    inventory: None,
+                    admin_replies: Vec::new(),
+                    edit_rejections: Vec::new(),
+                }));
+                for _ in 0..4 {
+                    sim.step(dt);
+                    let current = displayed(&sim);
+                    // Drawn behind the latest position, but moving at a steady pace
+                    assert!(current < distance);
+                    if let (Some(previous), true) = (previous, step > 10) {
+                        let expected = SPEED * dt.as_secs_f64();
+                        let moved = current - previous;
+                        assert!(
+                            (moved - expected).abs() < 0.1 * expected,
+                            "moved {} in a frame at {} Hz, expected {}",
+                            moved,
+                            rate,
+                            expected
+                        );
+                    }
+                    previous = Some(current);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spectator_passes_through_terrain() {
+        use common::{
+            dodeca::Vertex,
+            node::{Chunk, Node, VoxelData},
+            world::{material_at, Material},
+            Chunks,
+        };
+
+        let (mut sim, mut commands) = sim(EntityId::new(0, 0));
+        // Bury the view in stone
+        let mut chunks = Chunks::default();
+        for vertex in Vertex::iter() {
+            chunks[vertex] = Chunk::populated(12, VoxelData::Solid(Material::Stone));
+        }
+        *sim.graph.get_mut(NodeId::ROOT) = Some(Node {
+            state: worldgen::NodeState::root(),
+            chunks,
+        });
+        let body = sim.view();
+
+        sim.toggle_spectator();
+        assert!(sim.is_spectating());
+        sim.velocity(-na::Vector3::z());
+        for _ in 0..3 {
+            sim.step(Duration::from_millis(100));
+        }
+        let camera = sim.view();
+        let position = camera.local * math::origin();
+        assert!((math::distance(&math::origin(), &position) - 0.3).abs() < 1e-4);
+        assert_eq!(
+            material_at(&sim.graph, 12, camera.node, &position.map(f64::from)),
+            Some(Material::Stone)
+        );
+
+        // The character was held still throughout
+        let mut sent = 0;
+        while let Ok(command) = commands.try_recv() {
+            assert_eq!(command.velocity, na::Vector3::zeros());
+            sent += 1;
+        }
+        assert!(sent > 0);
+        assert_eq!(sim.prediction.predicted().local, body.local);
+
+        // Returning puts the camera back with the character
+        sim.toggle_spectator();
+        sim.velocity(na::zero());
+        assert_eq!(sim.view().node, body.node);
+        assert!((sim.view().local - body.local).amax() < 1e-5);
+    }
+
+    #[test]
+    fn voxel_edits_sent() {
+        use common::{
+            dodeca::Vertex,
+            node::{Chunk, Node},
+            Chunks,
+        };
+
+        let (mut sim, mut commands) = sim(EntityId::new(0, 0));
+        // Stone everywhere but a small pocket around the view
+        let mut voxels = VoxelData::Solid(Material::Stone);
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    voxels.data_mut(12)[voxel_index(12, [x, y, z])] = Material::Void;
+                }
+            }
+        }
+        let mut chunks = Chunks::default();
+        for vertex in Vertex::iter() {
+            chunks[vertex] = Chunk::populated(12, voxels.clone());
+        }
+        *sim.graph.get_mut(NodeId::ROOT) = Some(Node {
+            state: worldgen::NodeState::root(),
+            chunks,
+        });
+        let material = |sim: &Sim, edit: &proto::VoxelEdit| match sim.graph.get(edit.node) {
+            Some(node) => match node.chunks[edit.chunk] {
+                Chunk::Populated { ref voxels, .. } => voxels.get(edit.index as usize),
+                _ => panic!("unpopulated chunk"),
+            },
+            None => panic!("missing node"),
+        };
+
+        sim.break_voxel();
+        sim.send_input();
+        let edit = commands.try_recv().unwrap().edit.unwrap();
+        assert_eq!(edit.material, Material::Void);
+        assert_eq!(material(&sim, &edit), Material::Stone);
+        // Each edit is sent only once
+        sim.send_input();
+        assert_eq!(commands.try_recv().unwrap().edit, None);
+
+        // Nothing to place yet
+        sim.place_voxel();
+        sim.send_input();
+        assert_eq!(commands.try_recv().unwrap().edit, None);
+
+        sim.inventory.add(Material::Dirt, 1);
+        sim.place_voxel();
+        sim.send_input();
+        let edit = commands.try_recv().unwrap().edit.unwrap();
+        assert_eq!(edit.material, Material::Dirt);
+        assert_eq!(material(&sim, &edit), Material::Void);
+    }
+
+    #[test]
+    fn loop_roll_leveled() {
+        // Terrain plane through the origin, with up along y
+        let up = na::Vector4::y();
+        // Angle between the camera's up axis and the terrain's up direction
+        let roll = |view: &na::Matrix4<f32>| {
+            let up = (view.try_inverse().unwrap() * up).xy();
+            up.x.atan2(up.y).abs()
+        };
+        // Walk a loop spanning the camera's right and up axes, which rolls it via holonomy
+        let mut view = na::Matrix4::identity();
+        for &(axis, distance) in &[
+            (na::Vector3::x_axis(), 1.0),
+            (na::Vector3::y_axis(), 1.0),
+            (na::Vector3::x_axis(), -1.0),
+            (na::Vector3::y_axis(), -1.0),
+        ] {
+            view *= math::translate_along(&axis, distance);
+        }
+        assert!(roll(&view) > 0.1);
+
+        let leveled = view * level(&view, &up).to_homogeneous();
+        assert!(roll(&leveled) < 1e-4, "{}", roll(&leveled));
+        // Only roll is affected
+        let forward = -na::Vector4::z();
+        assert!((leveled * forward - view * forward).amax() < 1e-4);
+        // Leveling is idempotent
+        assert!(level(&leveled, &up).angle() < 1e-4);
+    }
 }
@@ -1 +1,340 @@
+//! Reduced-detail views of a chunk's voxels
+//!
+//! Distant chunks can be drawn from coarser voxels without visible loss. Rather than downsample
+//! every time such a view is needed, each populated chunk keeps a `Pyramid` of successively halved
+//! resolutions, which edits update incrementally.
 
+use crate::{
+    node::{voxel_index, VoxelData},
+    world::Material,
+};
+
+/// A chunk's voxels at successively halved resolutions
+///
+/// Level 0 is the chunk itself, and each further level halves the side length, rounding up, until
+/// a single cell remains. A cell takes the most common material among the cells of the previous
+/// level it covers, of which there are eight unless the previous level's side is odd, breaking ties
+/// in favor of solid materials, then in favor of materials that compare lower.
+#[derive(Clone)]
+pub struct Pyramid {
+    /// Side length of level 0
+    dimension: u8,
+    /// Levels 1 and up without margins, or `None` if the chunk is uniform and so is every level
+    levels: Option<Vec<Box<[Material]>>>,
+}
+
+impl Pyramid {
+    pub fn new(dimension: u8, voxels: &VoxelData) -> Self {
+        let levels = match *voxels {
+            VoxelData::Solid(_) => None,
+            VoxelData::Dense(_) => {
+                let mut levels = Vec::<Box<[Material]>>::new();
+                for level in 1..count_levels(dimension) {
+                    let side = level_dimension(dimension, level);
+                    let below = view(dimension, voxels, &levels, level - 1);
+                    let mut cells = Vec::with_capacity(usize::from(side).pow(3));
+                    for z in 0..side {
+                        for y in 0..side {
+                            for x in 0..side {
+                                cells.push(below.downsample([x, y, z]));
+                            }
+                        }
+                    }
+                    levels.push(cells.into());
+                }
+                Some(levels)
+            }
+        };
+        Self { dimension, levels }
+    }
+
+    /// Number of levels, including level 0
+    pub fn level_count(&self) -> u32 {
+        count_levels(self.dimension)
+    }
+
+    /// View of `level`, where `voxels` are the chunk's current contents
+    ///
+    /// Returns `None` if `level` is beyond the single-cell top of the pyramid.
+    pub fn level<'a>(&'a self, voxels: &'a VoxelData, level: u32) -> Option<VoxelView<'a>> {
+        if level >= self.level_count() {
+            return None;
+        }
+        Some(match self.levels {
+            Some(ref levels) => view(self.dimension, voxels, levels, level),
+            None if level == 0 => view(self.dimension, voxels, &[], level),
+            // Uniform chunks look the same at every level
+            None => VoxelView {
+                dimension: level_dimension(self.dimension, level),
+                data: ViewData::Solid(voxels.get(0)),
+            },
+        })
+    }
+
+    /// Bring the pyramid up to date with `voxels` after the voxel at `coords` changed
+    ///
+    /// Only the single cell covering `coords` at each level is recomputed, stopping early at the
+    /// first level where it's unchanged.
+    pub fn update(&mut self, voxels: &VoxelData, coords: [u8; 3]) {
+        if self.levels.is_none() {
+            *self = Self::new(self.dimension, voxels);
+            return;
+        }
+        let levels = self.levels.as_mut().unwrap();
+        let mut coords = coords;
+        for level in 1..count_levels(self.dimension) {
+            coords = [coords[0] / 2, coords[1] / 2, coords[2] / 2];
+            let side = level_dimension(self.dimension, level);
+            let material = view(self.dimension, voxels, levels, level - 1).downsample(coords);
+            let cell = &mut levels[level as usize - 1][cell_index(side, coords)];
+            if *cell == material {
+                break;
+            }
+            *cell = material;
+        }
+    }
+}
+
+/// One level of a `Pyramid`
+#[derive(Copy, Clone)]
+pub struct VoxelView<'a> {
+    dimension: u8,
+    data: ViewData<'a>,
+}
+
+#[derive(Copy, Clone)]
+enum ViewData<'a> {
+    Solid(Material),
+    /// Level 0, read directly from the chunk's voxels
+    Voxels(&'a VoxelData),
+    Cells(&'a [Material]),
+}
+
+impl<'a> VoxelView<'a> {
+    /// Number of cells along each edge
+    pub fn dimension(&self) -> u8 {
+        self.dimension
+    }
+
+    /// Material of the cell at `coords`, each of which must be less than `dimension`
+    pub fn get(&self, coords: [u8; 3]) -> Material {
+        debug_assert!(coords.iter().all(|&x| x < self.dimension));
+        match self.data {
+            ViewData::Solid(material) => material,
+            ViewData::Voxels(voxels) => voxels.get(voxel_index(self.dimension, coords)),
+            ViewData::Cells(cells) => cells[cell_index(self.dimension, coords)],
+        }
+    }
+
+    /// Material of the cell at `coords` in the next level up
+    fn downsample(&self, coords: [u8; 3]) -> Material {
+        let mut children = [Material::Void; 8];
+        let mut count = 0;
+        for i in 0..8u8 {
+            let child = [
+                coords[0] * 2 + (i & 1),
+                coords[1] * 2 + ((i >> 1) & 1),
+                coords[2] * 2 + (i >> 2),
+            ];
+            if child.iter().all(|&x| x < self.dimension) {
+                children[count] = self.get(child);
+                count += 1;
+            }
+        }
+        majority(&children[..count])
+    }
+}
+
+/// View of `level` of a pyramid over `voxels` whose levels above 0 are `levels`
+fn view<'a>(
+    dimension: u8,
+    voxels: &'a VoxelData,
+    levels: &'a [Box<[Material]>],
+    level: u32,
+) -> VoxelView<'a> {
+    VoxelView {
+        dimension: level_dimension(dimension, level),
+        data: if level == 0 {
+            ViewData::Voxels(voxels)
+        } else {
+            ViewData::Cells(&levels[level as usize - 1])
+        },
+    }
+}
+
+/// Most common of `materials`, preferring solid materials, then lower ones, in case of a tie
+pub fn majority(materials: &[Material]) -> Material {
+    materials
+        .iter()
+        .map(|&m| (materials.iter().filter(|&&x| x == m).count(), m))
+        .max_by(|&(a_count, a), &(b_count, b)| {
+            a_count
+                .cmp(&b_count)
+                .then_with(|| a.is_void().cmp(&b.is_void()).reverse())
+                .then_with(|| a.cmp(&b).reverse())
+        })
+        .map_or(Material::Void, |(_, m)| m)
+}
+
+/// Number of levels in a pyramid over a chunk of side `dimension`, including level 0
+pub fn count_levels(dimension: u8) -> u32 {
+    let mut count = 1;
+    while level_dimension(dimension, count - 1) > 1 {
+        count += 1;
+    }
+    count
+}
+
+/// Side length of `level` of a pyramid over a chunk of side `dimension`
+fn level_dimension(dimension: u8, level: u32) -> u8 {
+    (0..level).fold(dimension, |side, _| (side + 1) / 2)
+}
+
+/// Index of the cell at `coords` in a level of side `dimension`, which lacks margins
+fn cell_index(dimension: u8, coords: [u8; 3]) -> usize {
+    let dim = usize::from(dimension);
+    usize::from(coords[0]) + usize::from(coords[1]) * dim + usize::from(coords[2]) * dim.pow(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIMENSION: u8 = 12;
+
+    /// Scattered materials, with regions of differing density so that downsampling matters
+    fn voxels() -> VoxelData {
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let data = voxels.data_mut(DIMENSION);
+        for z in 0..DIMENSION {
+            for y in 0..DIMENSION {
+                for x in 0..DIMENSION {
+                    let hash = (u32::from(x) * 7 + u32::from(y) * 13 + u32::from(z) * 29) % 11;
+                    data[voxel_index(DIMENSION, [x, y, z])] = match hash {
+                        _ if y > 8 => Material::Void,
+                        0..=3 => Material::Void,
+                        4..=7 => Material::Dirt,
+                        _ => Material::Stone,
+                    };
+                }
+            }
+        }
+        voxels
+    }
+
+    /// Every cell of `view`
+    fn cells(view: VoxelView<'_>) -> Vec<Material> {
+        let side = view.dimension();
+        let mut result = Vec::new();
+        for z in 0..side {
+            for y in 0..side {
+                for x in 0..side {
+                    result.push(view.get([x, y, z]));
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn level_one_is_majority() {
+        let voxels = voxels();
+        let pyramid = Pyramid::new(DIMENSION, &voxels);
+        assert_eq!(pyramid.level_count(), 5);
+        assert_eq!(
+            (0..5)
+                .map(|level| pyramid.level(&voxels, level).unwrap().dimension())
+                .collect::<Vec<_>>(),
+            [12, 6, 3, 2, 1]
+        );
+        assert!(pyramid.level(&voxels, 5).is_none());
+
+        let level = pyramid.level(&voxels, 1).unwrap();
+        for z in 0..6 {
+            for y in 0..6 {
+                for x in 0..6 {
+                    let mut block = Vec::new();
+                    for i in 0..8 {
+                        let child = [2 * x + (i & 1), 2 * y + (i >> 1 & 1), 2 * z + (i >> 2)];
+                        block.push(voxels.get(voxel_index(DIMENSION, child)));
+                    }
+                    assert_eq!(level.get([x, y, z]), majority(&block));
+                }
+            }
+        }
+        // Air dominates above the ground
+        assert_eq!(level.get([0, 5, 0]), Material::Void);
+    }
+
+    #[test]
+    fn majority_ties() {
+        use Material::*;
+        assert_eq!(majority(&[Void, Void, Stone]), Void);
+        assert_eq!(majority(&[Void, Stone]), Stone);
+        assert_eq!(majority(&[Dirt, Stone, Void, Void, Dirt, Stone]), Stone);
+    }
+
+    #[test]
+    fn incremental_update() {
+        let mut voxels = voxels();
+        let mut pyramid = Pyramid::new(DIMENSION, &voxels);
+        let before = (0..pyramid.level_count())
+            .map(|level| cells(pyramid.level(&voxels, level).unwrap()))
+            .collect::<Vec<_>>();
+
+        // Fill a whole block of level 1 with wood, one voxel at a time
+        for i in 0..8 {
+            let coords = [4 + (i & 1), 2 + (i >> 1 & 1), 6 + (i >> 2)];
+            voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, coords)] = Material::Wood;
+            pyramid.update(&voxels, coords);
+        }
+        let after = (0..pyramid.level_count())
+            .map(|level| cells(pyramid.level(&voxels, level).unwrap()))
+            .collect::<Vec<_>>();
+
+        // Matches a pyramid built from scratch
+        let fresh = Pyramid::new(DIMENSION, &voxels);
+        for level in 0..pyramid.level_count() {
+            assert_eq!(
+                cells(fresh.level(&voxels, level).unwrap()),
+                after[level as usize]
+            );
+        }
+
+        // Only the cell covering the edit changed on level 1, and at most one cell above it
+        let changed = |level: usize| {
+            before[level]
+                .iter()
+                .zip(&after[level])
+                .enumerate()
+                .filter(|&(_, (a, b))| a != b)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(changed(1), [cell_index(6, [2, 1, 3])]);
+        assert_eq!(after[1][cell_index(6, [2, 1, 3])], Material::Wood);
+        for level in 2..before.len() {
+            assert!(changed(level).len() <= 1);
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let mut voxels = VoxelData::Solid(Material::Stone);
+        let mut pyramid = Pyramid::new(DIMENSION, &voxels);
+        assert_eq!(
+            cells(pyramid.level(&voxels, 2).unwrap()),
+            vec![Material::Stone; 27]
+        );
+        voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, [0, 0, 0])] = Material::Void;
+        pyramid.update(&voxels, [0, 0, 0]);
+        assert_eq!(
+            pyramid.level(&voxels, 0).unwrap().get([0, 0, 0]),
+            Material::Void
+        );
+        assert_eq!(
+            pyramid.level(&voxels, 1).unwrap().get([0, 0, 0]),
+            Material::Stone
+        );
+    }
+}
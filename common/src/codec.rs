@@ -33,6 +33,16 @@ pub async fn recv<T: DeserializeOwned>(stream: &mut quinn::RecvStream) -> Result
     Ok(Some(bincode::deserialize(&buf)?))
 }
 
+/// Serialize a message for use outside of a stream, e.g. as the reason for closing a connection
+pub fn encode<T: Serialize + ?Sized>(msg: &T) -> Vec<u8> {
+    bincode::serialize(msg).unwrap()
+}
+
+/// Deserialize a message produced by `encode`
+pub fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T> {
+    Ok(bincode::deserialize(buf)?)
+}
+
 /// Send a message as the entirety of `stream`
 pub async fn send_whole<T: Serialize + ?Sized>(
     mut stream: quinn::SendStream,
@@ -0,0 +1,440 @@
+use crate::{
+    node::{VoxelData, VoxelFace},
+    world::VoxelShape,
+};
+
+/// A chunk's solid voxels, merged into a small number of boxes for cheap collision queries
+///
+/// Boxes are found by greedy meshing: each one is grown from its lowest unclaimed full voxel
+/// along x, then y, then z, for as long as every voxel it would take in is full and unclaimed.
+/// Voxels whose material only partly fills them are kept separately, in their exact shapes. The
+/// result covers exactly the solid geometry, so queries agree with the voxel data it was built
+/// from, and must be rebuilt whenever that data changes.
+///
+/// All coordinates are in units of voxels within the chunk, such that the voxel at `[x, y, z]`
+/// spans `[x, x+1) × [y, y+1) × [z, z+1)`, and margins are excluded.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkCollision {
+    boxes: Vec<VoxelBox>,
+    partial: Vec<([u8; 3], VoxelShape)>,
+}
+
+/// An overlap between a shape and the solid geometry of a chunk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Contact {
+    /// Coordinates of the overlapped voxel, or of the one nearest the shape if several are merged
+    pub voxel: [u8; 3],
+    /// Face of the voxel through which the shape should be pushed out, which is also the contact
+    /// normal
+    pub face: VoxelFace,
+    /// Distance the shape must move along the face's normal to stop overlapping
+    pub depth: f32,
+}
+
+/// An axis-aligned block of voxels, from `min` inclusive to `max` exclusive
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VoxelBox {
+    pub min: [u8; 3],
+    pub max: [u8; 3],
+}
+
+impl ChunkCollision {
+    pub fn new(dimension: u8, voxels: &VoxelData) -> Self {
+        let dim = usize::from(dimension);
+        // Length (of cube sides) with margins
+        let lwm = dim + 2;
+        let material = |x: usize, y: usize, z: usize| {
+            voxels.get((x + 1) + (y + 1) * lwm + (z + 1) * lwm.pow(2))
+        };
+        let full = |x, y, z| {
+            let m = material(x, y, z);
+            !m.is_void() && m.shape() == VoxelShape::Full
+        };
+        let mut claimed = vec![false; dim.pow(3)];
+        let idx = |x: usize, y: usize, z: usize| x + y * dim + z * dim.pow(2);
+        let mut boxes = Vec::new();
+        let mut partial = Vec::new();
+        for z in 0..dim {
+            for y in 0..dim {
+                for x in 0..dim {
+                    if claimed[idx(x, y, z)] {
+                        continue;
+                    }
+                    if !full(x, y, z) {
+                        let m = material(x, y, z);
+                        if !m.is_void() {
+                            partial.push(([x as u8, y as u8, z as u8], m.shape()));
+                        }
+                        continue;
+                    }
+                    let free = |x, y, z| !claimed[idx(x, y, z)] && full(x, y, z);
+                    let mut x1 = x + 1;
+                    while x1 < dim && free(x1, y, z) {
+                        x1 += 1;
+                    }
+                    let mut y1 = y + 1;
+                    while y1 < dim && (x..x1).all(|x| free(x, y1, z)) {
+                        y1 += 1;
+                    }
+                    let mut z1 = z + 1;
+                    while z1 < dim && (y..y1).all(|y| (x..x1).all(|x| free(x, y, z1))) {
+                        z1 += 1;
+                    }
+                    for cz in z..z1 {
+                        for cy in y..y1 {
+                            for cx in x..x1 {
+                                claimed[idx(cx, cy, cz)] = true;
+                            }
+                        }
+                    }
+                    boxes.push(VoxelBox {
+                        min: [x as u8, y as u8, z as u8],
+                        max: [x1 as u8, y1 as u8, z1 as u8],
+                    });
+                }
+            }
+        }
+        Self { boxes, partial }
+    }
+
+    /// Merged blocks of full voxels
+    pub fn boxes(&self) -> &[VoxelBox] {
+        &self.boxes
+    }
+
+    /// Whether `point` lies within solid geometry
+    pub fn contains(&self, point: &na::Vector3<f32>) -> bool {
+        self.aabbs().any(|(lo, hi)| aabb_contains(&lo, &hi, point))
+    }
+
+    /// Distance along `direction`, in multiples of its length, at which a ray from `origin` first
+    /// enters solid geometry, if it does so within `max`
+    pub fn ray(
+        &self,
+        origin: &na::Vector3<f32>,
+        direction: &na::Vector3<f32>,
+        max: f32,
+    ) -> Option<f32> {
+        self.aabbs()
+            .filter_map(|(lo, hi)| aabb_ray(&lo, &hi, origin, direction, max))
+            .fold(None, |best, t| Some(best.map_or(t, |best: f32| best.min(t))))
+    }
+
+    /// Whether a sphere intersects any solid geometry
+    pub fn sphere(&self, center: &na::Vector3<f32>, radius: f32) -> bool {
+        self.aabbs().any(|(lo, hi)| aabb_sphere(&lo, &hi, center, radius))
+    }
+
+    /// Contacts between the solid geometry and a capsule upright along y, one for each overlapping
+    /// box
+    ///
+    /// `center` is the middle of the capsule's axis, which extends `half_axis` either way. Each
+    /// contact pushes the capsule out through whichever face of its box frees it with the least
+    /// motion, so a capsule sinking into the ground is pushed straight up.
+    pub fn capsule_contacts(
+        &self,
+        center: &na::Vector3<f32>,
+        radius: f32,
+        half_axis: f32,
+    ) -> Vec<Contact> {
+        // Half-extent of the capsule's axis along each coordinate axis
+        let extent = na::Vector3::new(0.0, half_axis, 0.0);
+        self.aabbs()
+            .filter_map(|(lo, hi)| {
+                // Distance from the axis to the box along each coordinate axis
+                let gap = na::Vector3::from_fn(|i, _| {
+                    (lo[i] - center[i] - extent[i])
+                        .max(center[i] - extent[i] - hi[i])
+                        .max(0.0)
+                });
+                if gap.norm_squared() >= radius.powi(2) {
+                    return None;
+                }
+                let (face, depth) = VoxelFace::iter()
+                    .map(|face| {
+                        let axis = face.axis();
+                        // Moving along `axis` leaves the other gaps unchanged, so clearance is
+                        // reached once the gap along it makes up the rest of the radius
+                        let others = gap.norm_squared() - gap[axis].powi(2);
+                        let reach = (radius.powi(2) - others).sqrt() + extent[axis];
+                        let depth = if face.is_positive() {
+                            hi[axis] + reach - center[axis]
+                        } else {
+                            center[axis] - (lo[axis] - reach)
+                        };
+                        (face, depth)
+                    })
+                    .fold((VoxelFace::PosY, std::f32::INFINITY), |best, x| {
+                        if x.1 < best.1 {
+                            x
+                        } else {
+                            best
+                        }
+                    });
+                // The voxel of the box nearest the capsule
+                let voxel = na::Vector3::from_fn(|i, _| {
+                    center[i].floor().max(lo[i].floor()).min(hi[i].ceil() - 1.0) as u8
+                });
+                Some(Contact {
+                    voxel: [voxel.x, voxel.y, voxel.z],
+                    face,
+                    depth,
+                })
+            })
+            .collect()
+    }
+
+    /// Minimum and maximum corners of every box making up the solid geometry
+    fn aabbs(&self) -> impl Iterator<Item = (na::Vector3<f32>, na::Vector3<f32>)> + '_ {
+        let boxes = self.boxes.iter().map(|b| (b.min(), b.max()));
+        let partial = self.partial.iter().flat_map(|&(coords, shape)| {
+            let base = na::Vector3::new(coords[0], coords[1], coords[2]).map(f32::from);
+            shape
+                .boxes()
+                .iter()
+                .map(move |&(lo, hi)| (base + na::Vector3::from(lo), base + na::Vector3::from(hi)))
+        });
+        boxes.chain(partial)
+    }
+}
+
+impl VoxelBox {
+    fn min(&self) -> na::Vector3<f32> {
+        na::Vector3::new(self.min[0], self.min[1], self.min[2]).map(f32::from)
+    }
+
+    fn max(&self) -> na::Vector3<f32> {
+        na::Vector3::new(self.max[0], self.max[1], self.max[2]).map(f32::from)
+    }
+
+    pub fn contains(&self, point: &na::Vector3<f32>) -> bool {
+        aabb_contains(&self.min(), &self.max(), point)
+    }
+
+    pub fn ray(
+        &self,
+        origin: &na::Vector3<f32>,
+        direction: &na::Vector3<f32>,
+        max: f32,
+    ) -> Option<f32> {
+        aabb_ray(&self.min(), &self.max(), origin, direction, max)
+    }
+
+    pub fn sphere(&self, center: &na::Vector3<f32>, radius: f32) -> bool {
+        aabb_sphere(&self.min(), &self.max(), center, radius)
+    }
+}
+
+fn aabb_contains(lo: &na::Vector3<f32>, hi: &na::Vector3<f32>, point: &na::Vector3<f32>) -> bool {
+    (0..3).all(|i| point[i] >= lo[i] && point[i] < hi[i])
+}
+
+/// Slab test
+fn aabb_ray(
+    lo: &na::Vector3<f32>,
+    hi: &na::Vector3<f32>,
+    origin: &na::Vector3<f32>,
+    direction: &na::Vector3<f32>,
+    max: f32,
+) -> Option<f32> {
+    let mut enter = 0.0f32;
+    let mut exit = max;
+    for i in 0..3 {
+        if direction[i] == 0.0 {
+            if origin[i] < lo[i] || origin[i] >= hi[i] {
+                return None;
+            }
+            continue;
+        }
+        let a = (lo[i] - origin[i]) / direction[i];
+        let b = (hi[i] - origin[i]) / direction[i];
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    if enter <= exit {
+        Some(enter)
+    } else {
+        None
+    }
+}
+
+fn aabb_sphere(
+    lo: &na::Vector3<f32>,
+    hi: &na::Vector3<f32>,
+    center: &na::Vector3<f32>,
+    radius: f32,
+) -> bool {
+    let nearest = na::Vector3::from_fn(|i, _| center[i].max(lo[i]).min(hi[i]));
+    (nearest - center).norm_squared() < radius.powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Material;
+    use approx::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64Mcg;
+
+    const DIMENSION: u8 = 8;
+
+    /// Every solid voxel as its own box
+    fn per_voxel(voxels: &VoxelData) -> Vec<VoxelBox> {
+        let lwm = usize::from(DIMENSION) + 2;
+        let mut result = Vec::new();
+        for z in 0..DIMENSION {
+            for y in 0..DIMENSION {
+                for x in 0..DIMENSION {
+                    let index = |x: u8| usize::from(x) + 1;
+                    if !voxels
+                        .get(index(x) + index(y) * lwm + index(z) * lwm.pow(2))
+                        .is_void()
+                    {
+                        result.push(VoxelBox {
+                            min: [x, y, z],
+                            max: [x + 1, y + 1, z + 1],
+                        });
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn random_voxels(rng: &mut Pcg64Mcg) -> VoxelData {
+        let mut voxels = VoxelData::Solid(Material::Void);
+        for x in voxels.data_mut(DIMENSION) {
+            if rng.gen_bool(0.4) {
+                *x = Material::Stone;
+            }
+        }
+        voxels
+    }
+
+    fn random_point(rng: &mut Pcg64Mcg) -> na::Vector3<f32> {
+        let range = -1.0..f32::from(DIMENSION) + 1.0;
+        na::Vector3::new(
+            rng.gen_range(range.start, range.end),
+            rng.gen_range(range.start, range.end),
+            rng.gen_range(range.start, range.end),
+        )
+    }
+
+    #[test]
+    fn covers_solid_voxels() {
+        let mut rng = Pcg64Mcg::seed_from_u64(0);
+        let voxels = random_voxels(&mut rng);
+        let collision = ChunkCollision::new(DIMENSION, &voxels);
+        let reference = per_voxel(&voxels);
+        assert!(collision.boxes().len() < reference.len());
+        let volume = |b: &VoxelBox| -> u32 {
+            (0..3).map(|i| u32::from(b.max[i] - b.min[i])).product()
+        };
+        assert_eq!(
+            collision.boxes().iter().map(volume).sum::<u32>(),
+            reference.len() as u32
+        );
+        for b in &reference {
+            let center = b.min() + na::Vector3::repeat(0.5);
+            assert!(collision.contains(&center));
+        }
+    }
+
+    #[test]
+    fn matches_per_voxel() {
+        let mut rng = Pcg64Mcg::seed_from_u64(1);
+        let voxels = random_voxels(&mut rng);
+        let collision = ChunkCollision::new(DIMENSION, &voxels);
+        let reference = per_voxel(&voxels);
+        for _ in 0..64 {
+            let origin = random_point(&mut rng);
+            let direction = random_point(&mut rng) - origin;
+            let expected = reference
+                .iter()
+                .filter_map(|b| b.ray(&origin, &direction, 1.0))
+                .fold(None, |best, t| Some(best.map_or(t, |best: f32| best.min(t))));
+            match (collision.ray(&origin, &direction, 1.0), expected) {
+                (Some(actual), Some(expected)) => {
+                    assert_abs_diff_eq!(actual, expected, epsilon = 1e-5)
+                }
+                (actual, expected) => assert_eq!(actual, expected),
+            }
+
+            let radius = rng.gen_range(0.1, 2.0);
+            assert_eq!(
+                collision.sphere(&origin, radius),
+                reference.iter().any(|b| b.sphere(&origin, radius))
+            );
+        }
+    }
+
+    #[test]
+    fn slab() {
+        let lwm = usize::from(DIMENSION) + 2;
+        let index = |x: usize, y: usize, z: usize| (x + 1) + (y + 1) * lwm + (z + 1) * lwm.pow(2);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(DIMENSION)[index(2, 0, 2)] = Material::GreyBrickSlab;
+        voxels.data_mut(DIMENSION)[index(5, 0, 5)] = Material::Stone;
+        let collision = ChunkCollision::new(DIMENSION, &voxels);
+
+        // Half-height geometry
+        assert!(collision.contains(&na::Vector3::new(2.5, 0.25, 2.5)));
+        assert!(!collision.contains(&na::Vector3::new(2.5, 0.75, 2.5)));
+        assert_abs_diff_eq!(
+            collision
+                .ray(&na::Vector3::new(2.5, 2.0, 2.5), &-na::Vector3::y(), 2.0)
+                .unwrap(),
+            1.5
+        );
+
+        // A capsule comes to rest on top
+        let (radius, half_axis) = (0.4, 0.5);
+        let contacts =
+            collision.capsule_contacts(&na::Vector3::new(2.5, 0.6, 2.5), radius, half_axis);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].voxel, [2, 0, 2]);
+        assert_eq!(contacts[0].face, VoxelFace::PosY);
+        assert_abs_diff_eq!(0.6 + contacts[0].depth, 0.5 + radius + half_axis);
+        let contacts =
+            collision.capsule_contacts(&na::Vector3::new(5.5, 1.2, 5.5), radius, half_axis);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].face, VoxelFace::PosY);
+        assert_abs_diff_eq!(1.2 + contacts[0].depth, 1.0 + radius + half_axis);
+        // Standing on top already, or clear of everything
+        assert!(collision
+            .capsule_contacts(&na::Vector3::new(2.5, 1.5, 2.5), radius, half_axis)
+            .is_empty());
+        assert!(collision
+            .capsule_contacts(&na::Vector3::new(7.5, 0.6, 0.5), radius, half_axis)
+            .is_empty());
+    }
+
+    #[test]
+    fn landing() {
+        let lwm = usize::from(DIMENSION) + 2;
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let data = voxels.data_mut(DIMENSION);
+        for z in 0..DIMENSION {
+            for x in 0..DIMENSION {
+                data[usize::from(x + 1) + lwm + usize::from(z + 1) * lwm.pow(2)] = Material::Dirt;
+            }
+        }
+        let collision = ChunkCollision::new(DIMENSION, &voxels);
+
+        // Sunk 0.1 into the floor. Dimensions are exactly representable, so that resting exactly on
+        // the floor can be expressed.
+        let (radius, half_axis) = (0.375, 0.5);
+        let center = na::Vector3::new(3.2, 1.0 + radius + half_axis - 0.1, 4.7);
+        let contacts = collision.capsule_contacts(&center, radius, half_axis);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].voxel, [3, 0, 4]);
+        assert_eq!(contacts[0].face, VoxelFace::PosY);
+        assert_abs_diff_eq!(contacts[0].depth, 0.1, epsilon = 1e-5);
+
+        // Resting exactly on the floor
+        let center = na::Vector3::new(3.5, 1.0 + radius + half_axis, 4.5);
+        assert!(collision
+            .capsule_contacts(&center, radius, half_axis)
+            .is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Compact, graph-independent encodings of node and chunk identifiers
+//!
+//! A `NodeId` is only meaningful relative to the order in which a particular graph was populated,
+//! so it's encoded as the canonical path of sides leading to it from the root instead. The path's
+//! length is stored as a varint followed by one nibble per side, so nodes near the origin take
+//! very few bytes.
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    dodeca::{Side, Vertex},
+    graph::{Graph, NodeId},
+    node::ChunkId,
+};
+
+/// Number of distinct vertices, used to fold a chunk's vertex into its path length
+const VERTEX_COUNT: u64 = 20;
+
+/// Append the compact encoding of `node` to `buf`
+pub fn encode_node<N>(graph: &Graph<N>, node: NodeId, buf: &mut Vec<u8>) {
+    let path = graph.path(node).0;
+    write_varint(path.len() as u64, buf);
+    write_sides(&path, buf);
+}
+
+/// Read a node encoded by `encode_node` from the front of `buf`, creating it if necessary
+pub fn decode_node<N>(graph: &mut Graph<N>, buf: &mut &[u8]) -> Result<NodeId> {
+    let len = read_varint(buf)?;
+    follow(graph, len, buf)
+}
+
+/// Append the compact encoding of `chunk` to `buf`
+pub fn encode_chunk<N>(graph: &Graph<N>, chunk: ChunkId, buf: &mut Vec<u8>) {
+    let path = graph.path(chunk.node).0;
+    write_varint(path.len() as u64 * VERTEX_COUNT + chunk.vertex as u64, buf);
+    write_sides(&path, buf);
+}
+
+/// Read a chunk encoded by `encode_chunk` from the front of `buf`, creating its node if necessary
+pub fn decode_chunk<N>(graph: &mut Graph<N>, buf: &mut &[u8]) -> Result<ChunkId> {
+    let header = read_varint(buf)?;
+    let vertex = Vertex::iter()
+        .nth((header % VERTEX_COUNT) as usize)
+        .unwrap();
+    let node = follow(graph, header / VERTEX_COUNT, buf)?;
+    Ok(ChunkId::new(node, vertex))
+}
+
+/// Walk `len` sides read from `buf` out from the root
+fn follow<N>(graph: &mut Graph<N>, len: u64, buf: &mut &[u8]) -> Result<NodeId> {
+    if len > 2 * buf.len() as u64 {
+        bail!("truncated node path");
+    }
+    let bytes = ((len + 1) / 2) as usize;
+    let (sides, rest) = buf.split_at(bytes);
+    *buf = rest;
+    let mut node = NodeId::ROOT;
+    for i in 0..len as usize {
+        let index = (sides[i / 2] >> (4 * (i % 2))) & 0xF;
+        if usize::from(index) >= Side::COUNT {
+            bail!("invalid side {}", index);
+        }
+        let side = Side::from_index(index.into());
+        let parent_length = graph.length(node);
+        node = graph.ensure_neighbor(node, side);
+        if graph.parent(node) != Some(side) || graph.length(node) != parent_length + 1 {
+            bail!("node path is not canonical");
+        }
+    }
+    Ok(node)
+}
+
+fn write_sides(sides: &[Side], buf: &mut Vec<u8>) {
+    for pair in sides.chunks(2) {
+        let low = pair[0] as u8;
+        let high = pair.get(1).map_or(0, |&x| x as u8);
+        buf.push(low | high << 4);
+    }
+}
+
+/// LEB128
+fn write_varint(mut x: u64, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (x & 0x7F) as u8;
+        x >>= 7;
+        if x == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut x = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated varint"))?;
+        *buf = rest;
+        x |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    bail!("varint too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Position;
+
+    fn graph() -> Graph<()> {
+        let mut graph = Graph::new();
+        graph.ensure_nearby(&Position::origin(), 3.0);
+        graph
+    }
+
+    #[test]
+    fn node_round_trip() {
+        let graph = graph();
+        // Decode into a fresh graph, so that node indices needn't agree
+        let mut other = Graph::<()>::new();
+        for node in std::iter::once(NodeId::ROOT).chain(
+            graph
+                .tree()
+                .map(|(side, parent)| graph.neighbor(parent, side).unwrap()),
+        ) {
+            let mut buf = Vec::new();
+            encode_node(&graph, node, &mut buf);
+            let mut cursor = &buf[..];
+            let decoded = decode_node(&mut other, &mut cursor).unwrap();
+            assert!(cursor.is_empty());
+            assert_eq!(other.path(decoded), graph.path(node));
+        }
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let mut graph = graph();
+        let node = graph.ensure_neighbor(NodeId::ROOT, Side::D);
+        let node = graph.ensure_neighbor(node, Side::G);
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            let mut buf = Vec::new();
+            encode_chunk(&graph, chunk, &mut buf);
+            let mut cursor = &buf[..];
+            assert_eq!(decode_chunk(&mut graph, &mut cursor).unwrap(), chunk);
+            assert!(cursor.is_empty());
+        }
+    }
+
+    #[test]
+    fn smaller_than_naive() {
+        let graph = graph();
+        let mut buf = Vec::new();
+        encode_node(&graph, NodeId::ROOT, &mut buf);
+        assert_eq!(buf.len(), 1);
+        for (side, parent) in graph.tree() {
+            let node = graph.neighbor(parent, side).unwrap();
+            if graph.length(node) > 4 {
+                continue;
+            }
+            let naive = bincode::serialized_size(&node).unwrap() as usize;
+            buf.clear();
+            encode_node(&graph, node, &mut buf);
+            assert!(buf.len() < naive, "{} >= {}", buf.len(), naive);
+
+            let naive = naive + bincode::serialized_size(&Vertex::T).unwrap() as usize;
+            buf.clear();
+            encode_chunk(&graph, ChunkId::new(node, Vertex::T), &mut buf);
+            assert!(buf.len() < naive, "{} >= {}", buf.len(), naive);
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let mut graph = Graph::<()>::new();
+        // Truncated
+        assert!(decode_node(&mut graph, &mut &[0x80][..]).is_err());
+        assert!(decode_node(&mut graph, &mut &[3, 0x00][..]).is_err());
+        // Side out of range
+        assert!(decode_node(&mut graph, &mut &[1, 0x0C][..]).is_err());
+        // Doubling back to the root
+        assert!(decode_node(&mut graph, &mut &[2, 0x00][..]).is_err());
+    }
+}
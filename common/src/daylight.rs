@@ -0,0 +1,92 @@
+//! The day/night cycle
+//!
+//! Time of day is a function of the server's step counter, so every client that has heard of a
+//! given step agrees on where the sun is. The sun is an ideal point, infinitely far away, which
+//! rises and sets along a great circle through the zenith of whoever observes it. Hyperbolic space
+//! has no global notion of parallel directions, so the east-west axis of that circle is taken from
+//! the observer's node, and may turn slightly when crossing into another.
+
+use crate::{math, Step};
+
+/// Fraction of the way through the day at `step`, where 0 is sunrise and 0.5 is sunset
+pub fn time_of_day(step: Step, steps_per_day: u32) -> f32 {
+    if steps_per_day == 0 {
+        // No cycle; eternal noon
+        return 0.25;
+    }
+    (i64::from(step).rem_euclid(i64::from(steps_per_day)) as f64 / f64::from(steps_per_day)) as f32
+}
+
+/// Direction of the sun at `time_of_day`, where +Y is up and +X is east
+pub fn sun_direction(time_of_day: f32) -> na::Unit<na::Vector3<f32>> {
+    let angle = time_of_day * 2.0 * std::f32::consts::PI;
+    na::Unit::new_normalize(na::Vector3::new(angle.cos(), angle.sin(), 0.0))
+}
+
+/// Brightness of direct sunlight at `time_of_day`, from 0 at night to 1 at midday
+pub fn daylight(time_of_day: f32) -> f32 {
+    // Fade over dawn and dusk rather than cutting off exactly at the horizon
+    (sun_direction(time_of_day).y * 4.0 + 0.5).max(0.0).min(1.0)
+}
+
+/// The sun's ideal point, as seen from `observer`, relative to the plane whose normal is `up`
+///
+/// Both arguments and the result are in the same node's coordinates. The result is lightlike.
+pub fn sun_point(
+    up: &na::Vector4<f32>,
+    observer: &na::Vector4<f32>,
+    time_of_day: f32,
+) -> na::Vector4<f32> {
+    let p = math::lorentz_normalize(observer);
+    let tangent = |v: na::Vector4<f32>| {
+        let t = v + p * math::mip(&v, &p);
+        t / math::mip(&t, &t).sqrt()
+    };
+    let up = tangent(*up);
+    // Any tangent direction not parallel to `up` would do, so long as it's consistent
+    let reference = if up.x.abs() < 0.9 {
+        na::Vector4::x()
+    } else {
+        na::Vector4::z()
+    };
+    let east = tangent(reference - up * math::mip(&reference, &up));
+    let direction = sun_direction(time_of_day);
+    p + east * direction.x + up * direction.y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    #[test]
+    fn cycle() {
+        assert_eq!(time_of_day(0, 100), 0.0);
+        assert_eq!(time_of_day(25, 100), 0.25);
+        assert_eq!(time_of_day(125, 100), 0.25);
+        assert_eq!(time_of_day(-25, 100), 0.75);
+        assert_abs_diff_eq!(sun_direction(0.25).y, 1.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(sun_direction(0.75).y, -1.0, epsilon = 1e-6);
+        assert_eq!(daylight(0.25), 1.0);
+        assert_eq!(daylight(0.75), 0.0);
+    }
+
+    #[test]
+    fn sun_point_follows_time() {
+        let up = na::Vector4::y();
+        let observer = math::translate_along(&na::Vector3::z_axis(), 0.5) * math::origin();
+        let morning = sun_point(&up, &observer, time_of_day(10, 100));
+        let noon = sun_point(&up, &observer, time_of_day(25, 100));
+        assert_abs_diff_eq!(math::mip(&morning, &morning), 0.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(math::mip(&noon, &noon), 0.0, epsilon = 1e-5);
+        assert!((morning - noon).norm() > 0.1);
+        // At noon the sun is directly overhead
+        let observer = math::lorentz_normalize(&observer);
+        let to_sun = noon + observer * math::mip(&noon, &observer);
+        assert_abs_diff_eq!(
+            math::mip(&to_sun, &up) / math::mip(&to_sun, &to_sun).sqrt(),
+            1.0,
+            epsilon = 1e-5
+        );
+    }
+}
@@ -23,6 +23,9 @@ pub enum Side {
 }
 
 impl Side {
+    /// Number of sides
+    pub const COUNT: usize = SIDE_COUNT;
+
     #[inline]
     pub fn from_index(x: usize) -> Self {
         use Side::*;
@@ -30,9 +33,14 @@ impl Side {
         VALUES[x]
     }
 
+    /// Position of `self` in `iter`, suitable for indexing per-side arrays
+    #[inline]
+    pub fn to_index(self) -> usize {
+        self as usize
+    }
+
     pub fn iter() -> impl ExactSizeIterator<Item = Self> {
-        use Side::*;
-        [A, B, C, D, E, F, G, H, I, J, K, L].iter().cloned()
+        (0..Self::COUNT).map(Self::from_index)
     }
 
     /// Whether `self` and `other` share an edge
@@ -58,7 +66,7 @@ impl Side {
 }
 
 /// Vertices of a right dodecahedron
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Vertex {
     A,
     B,
@@ -137,6 +145,11 @@ impl Vertex {
         ]) * na::Matrix4::new_scaling(0.5)
     }
 
+    /// Transform from hyperbolic node space to euclidean chunk coordinates
+    pub fn node_to_chunk(self) -> &'static na::Matrix4<f64> {
+        &NODE_TO_CHUNK[self as usize]
+    }
+
     /// Convenience method for `self.cube_to_node().determinant() < 0`.
     pub fn parity(self) -> bool {
         CHUNK_TO_NODE_PARITY[self as usize]
@@ -145,9 +158,22 @@ impl Vertex {
 
 pub const VERTEX_COUNT: usize = 20;
 pub const SIDE_COUNT: usize = 12;
+/// Equal to `circumradius()`, for use in constant expressions
 #[allow(clippy::unreadable_literal)]
 pub const BOUNDING_SPHERE_RADIUS: f64 = 1.2264568712514068;
 
+/// Distance from the center of a cell to each of its vertices, i.e. the radius of the smallest
+/// sphere enclosing it
+pub fn circumradius() -> f64 {
+    *CIRCUMRADIUS
+}
+
+/// Distance from the center of a cell to the center of each of its faces, i.e. the radius of the
+/// largest sphere it encloses
+pub fn inradius() -> f64 {
+    *INRADIUS
+}
+
 lazy_static! {
     /// Whether two sides share an edge
     static ref ADJACENT: [[bool; SIDE_COUNT]; SIDE_COUNT] = {
@@ -248,6 +274,29 @@ lazy_static! {
 
         result
     };
+
+    static ref CIRCUMRADIUS: f64 = {
+        // The far corner of any chunk is a vertex of the cell
+        let vertex = Vertex::A.chunk_to_node() * na::Vector4::repeat(1.0);
+        math::distance(&math::origin(), &vertex)
+    };
+
+    static ref INRADIUS: f64 = {
+        // Each face bisects the geodesic between the centers of the cells it separates
+        let neighbor = Side::A.reflection() * math::origin();
+        math::distance(&math::origin(), &neighbor) / 2.0
+    };
+
+    /// Inverses of the chunk-to-node transforms
+    static ref NODE_TO_CHUNK: [na::Matrix4<f64>; VERTEX_COUNT] = {
+        let mut result = [na::zero(); VERTEX_COUNT];
+
+        for v in Vertex::iter() {
+            result[v as usize] = v.chunk_to_node().try_inverse().unwrap();
+        }
+
+        result
+    };
 }
 
 #[cfg(test)]
@@ -255,6 +304,17 @@ mod tests {
     use super::*;
     use approx::*;
 
+    #[test]
+    fn side_iteration() {
+        assert_eq!(Side::iter().len(), Side::COUNT);
+        for (i, side) in Side::iter().enumerate() {
+            assert_eq!(side.to_index(), i);
+            assert_eq!(Side::from_index(i), side);
+        }
+        let distinct = Side::iter().collect::<std::collections::HashSet<_>>();
+        assert_eq!(distinct.len(), Side::COUNT);
+    }
+
     #[test]
     fn vertex_sides() {
         use std::collections::HashSet;
@@ -306,4 +366,28 @@ mod tests {
             epsilon = 1e-10
         );
     }
+
+    #[test]
+    fn circumradius_inradius() {
+        for vertex in Vertex::iter() {
+            let corner = vertex.chunk_to_node() * na::Vector4::repeat(1.0);
+            assert_abs_diff_eq!(
+                math::distance(&math::origin(), &corner),
+                circumradius(),
+                epsilon = 1e-10
+            );
+        }
+        assert_abs_diff_eq!(circumradius(), BOUNDING_SPHERE_RADIUS, epsilon = 1e-10);
+        for side in Side::iter() {
+            let center = math::midpoint(&math::origin(), &(side.reflection() * math::origin()));
+            // Fixed by the side's reflection, so on the face
+            assert_abs_diff_eq!(side.reflection() * center, center, epsilon = 1e-10);
+            assert_abs_diff_eq!(
+                math::distance(&math::origin(), &center),
+                inradius(),
+                epsilon = 1e-10
+            );
+        }
+        assert!(inradius() < circumradius());
+    }
 }
@@ -3,12 +3,16 @@
 use std::convert::TryFrom;
 use std::fmt;
 use std::num::NonZeroU32;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
-use fxhash::FxHashSet;
+use anyhow::{bail, Error};
+use fxhash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    dodeca::{Side, Vertex, SIDE_COUNT},
+    dodeca::{Side, Vertex},
+    lru_slab::{LruSlab, SlotId},
     math,
     proto::Position,
 };
@@ -20,6 +24,11 @@ pub struct Graph<N> {
     /// This field stores implicitly added nodes to ensure that they're initialized in the correct
     /// order
     fresh: Vec<NodeId>,
+    /// Events not yet seen by every subscriber, the first being event number `events_start`
+    events: Vec<GraphEvent>,
+    events_start: u64,
+    /// Number of the next event each subscriber will see, or `None` where unsubscribed
+    subscribers: Vec<Option<u64>>,
 }
 
 impl<N> Graph<N> {
@@ -27,6 +36,9 @@ impl<N> Graph<N> {
         Self {
             nodes: vec![Node::new(None, 0)],
             fresh: vec![NodeId::ROOT],
+            events: Vec::new(),
+            events_start: 0,
+            subscribers: Vec::new(),
         }
     }
 
@@ -40,6 +52,39 @@ impl<N> Graph<N> {
         node.0.get() as usize <= self.nodes.len()
     }
 
+    /// Transform from the coordinates of `node`'s neighbor across `side` to those of `node`, if
+    /// that neighbor exists
+    #[inline]
+    pub fn neighbor_transform(&self, node: NodeId, side: Side) -> Option<math::Isometry<f64>> {
+        self.neighbor(node, side)?;
+        Some(math::Isometry::from_matrix_unchecked(*side.reflection()))
+    }
+
+    /// Transform from the coordinates of `to` to those of `from`, if both exist
+    ///
+    /// Composes reflections along parent edges through the nodes' nearest common ancestor, so cost
+    /// and error grow with their separation in the tree. See `TransformCache` for repeated queries.
+    pub fn relative_transform(&self, from: NodeId, to: NodeId) -> Option<math::Isometry<f64>> {
+        if !self.contains(from) || !self.contains(to) {
+            return None;
+        }
+        // Transforms from the coordinates of the original nodes to those of `from` and `to`
+        let (mut from, mut from_transform) = (from, math::Isometry::identity());
+        let (mut to, mut to_transform) = (to, math::Isometry::identity());
+        while from != to {
+            // Ascend from whichever is longer, so both reach the common ancestor together
+            let (node, transform) = if self.length(from) >= self.length(to) {
+                (&mut from, &mut from_transform)
+            } else {
+                (&mut to, &mut to_transform)
+            };
+            let side = self.parent(*node).expect("non-root nodes have parents");
+            *transform = math::Isometry::from_matrix_unchecked(*side.reflection()) * *transform;
+            *node = self.neighbor(*node, side).unwrap();
+        }
+        Some(from_transform.inverse() * to_transform)
+    }
+
     /// Nodes created since the last call to `clear_fresh`
     #[inline]
     pub fn fresh(&self) -> &[NodeId] {
@@ -119,7 +164,7 @@ impl<N> Graph<N> {
             result.push((current.id, na::convert(current.transform)));
 
             for side in Side::iter() {
-                let neighbor = match node.neighbors[side as usize] {
+                let neighbor = match node.neighbors[side.to_index()] {
                     None => continue,
                     Some(x) => x,
                 };
@@ -163,6 +208,61 @@ impl<N> Graph<N> {
         }
     }
 
+    /// Begin recording events for a new consumer, to be collected with `events`
+    ///
+    /// Events are only recorded while there's a subscriber, and only kept until every subscriber
+    /// has collected them.
+    pub fn subscribe(&mut self) -> Subscription {
+        let next = Some(self.events_start + self.events.len() as u64);
+        match self.subscribers.iter().position(|x| x.is_none()) {
+            Some(i) => {
+                self.subscribers[i] = next;
+                Subscription(i)
+            }
+            None => {
+                self.subscribers.push(next);
+                Subscription(self.subscribers.len() - 1)
+            }
+        }
+    }
+
+    /// Stop recording events for `subscription`
+    pub fn unsubscribe(&mut self, subscription: Subscription) {
+        self.subscribers[subscription.0] = None;
+        self.discard_seen_events();
+    }
+
+    /// Events that have occurred since `subscription` last collected them, in order
+    pub fn events(&mut self, subscription: &Subscription) -> &[GraphEvent] {
+        self.discard_seen_events();
+        let end = self.events_start + self.events.len() as u64;
+        let next = self.subscribers[subscription.0]
+            .replace(end)
+            .expect("unsubscribed");
+        &self.events[(next - self.events_start) as usize..]
+    }
+
+    /// Record that `event` has occurred
+    ///
+    /// Nodes report their own creation. Chunks are changed by the graph's users, who must report
+    /// it themselves.
+    pub fn notify(&mut self, event: GraphEvent) {
+        if self.subscribers.iter().any(|x| x.is_some()) {
+            self.events.push(event);
+        }
+    }
+
+    fn discard_seen_events(&mut self) {
+        let seen = self
+            .subscribers
+            .iter()
+            .filter_map(|&x| x)
+            .min()
+            .unwrap_or(self.events_start + self.events.len() as u64);
+        self.events.drain(..(seen - self.events_start) as usize);
+        self.events_start = seen;
+    }
+
     #[inline]
     pub fn get(&self, node: NodeId) -> &Option<N> {
         &self.nodes[node.idx()].value
@@ -175,7 +275,7 @@ impl<N> Graph<N> {
 
     #[inline]
     pub fn neighbor(&self, node: NodeId, which: Side) -> Option<NodeId> {
-        self.nodes[node.idx()].neighbors[which as usize]
+        self.nodes[node.idx()].neighbors[which.to_index()]
     }
 
     #[inline]
@@ -216,6 +316,33 @@ impl<N> Graph<N> {
         self.nodes[node.idx()].parent_side
     }
 
+    /// Sides leading from the root to `node` along parent edges
+    pub fn path(&self, mut node: NodeId) -> NodePath {
+        let mut sides = Vec::with_capacity(self.length(node) as usize);
+        while let Some(side) = self.parent(node) {
+            sides.push(side);
+            node = self.neighbor(node, side).unwrap();
+        }
+        sides.reverse();
+        NodePath(sides)
+    }
+
+    /// Find the node reached by following `path` out from the root, creating nodes as necessary
+    ///
+    /// The path needn't be the node's shortest.
+    pub fn lookup_path(&mut self, path: &NodePath) -> NodeId {
+        path.0
+            .iter()
+            .fold(NodeId::ROOT, |node, &side| self.ensure_neighbor(node, side))
+    }
+
+    /// Find the node reached by following `path` out from the root, if it exists
+    pub fn find_path(&self, path: &NodePath) -> Option<NodeId> {
+        path.0
+            .iter()
+            .try_fold(NodeId::ROOT, |node, &side| self.neighbor(node, side))
+    }
+
     /// Iterate over every node and its parent
     pub fn tree(&self) -> TreeIter<'_, N> {
         TreeIter {
@@ -226,7 +353,7 @@ impl<N> Graph<N> {
 
     pub fn ensure_neighbor(&mut self, node: NodeId, side: Side) -> NodeId {
         let v = &self.nodes[node.idx()];
-        if let Some(x) = v.neighbors[side as usize] {
+        if let Some(x) = v.neighbors[side.to_index()] {
             // Neighbor already exists
             return x;
         }
@@ -243,7 +370,7 @@ impl<N> Graph<N> {
         }
 
         // Neighbor is closer to the origin; find it, backfilling if necessary
-        let x = self.nodes[v.parent().unwrap().idx()].neighbors[side as usize].unwrap();
+        let x = self.nodes[v.parent().unwrap().idx()].neighbors[side.to_index()].unwrap();
         let parent_side = v.parent_side.unwrap();
         let neighbor = self.ensure_neighbor(x, parent_side);
         self.link_neighbors(node, neighbor, side);
@@ -253,7 +380,7 @@ impl<N> Graph<N> {
     /// Whether `node`'s neighbor along `side` is closer than it to the origin
     fn is_near_side(&self, node: NodeId, side: Side) -> bool {
         let v = &self.nodes[node.idx()];
-        v.neighbors[side as usize].map_or(false, |x| self.nodes[x.idx()].length < v.length)
+        v.neighbors[side.to_index()].map_or(false, |x| self.nodes[x.idx()].length < v.length)
     }
 
     pub fn insert_child(&mut self, parent: NodeId, side: Side) -> NodeId {
@@ -268,6 +395,7 @@ impl<N> Graph<N> {
             self.link_neighbors(id, neighbor, side);
         }
         self.fresh.push(id);
+        self.notify(GraphEvent::NodeCreated(id));
         id
     }
 
@@ -286,7 +414,7 @@ impl<N> Graph<N> {
             {
                 continue;
             }
-            let x = self.nodes[parent.idx()].neighbors[neighbor_side as usize].unwrap();
+            let x = self.nodes[parent.idx()].neighbors[neighbor_side.to_index()].unwrap();
             let neighbor = self.ensure_neighbor(x, parent_side);
             neighbors[count] = Some((neighbor_side, neighbor));
             count += 1;
@@ -297,11 +425,21 @@ impl<N> Graph<N> {
     /// Register `a` and `b` as adjacent along `side`
     fn link_neighbors(&mut self, a: NodeId, b: NodeId, side: Side) {
         debug_assert!(
-            self.nodes[a.idx()].neighbors[side as usize].is_none()
-                && self.nodes[b.idx()].neighbors[side as usize].is_none()
+            self.nodes[a.idx()].neighbors[side.to_index()].is_none()
+                && self.nodes[b.idx()].neighbors[side.to_index()].is_none()
         );
-        self.nodes[a.idx()].neighbors[side as usize] = Some(b);
-        self.nodes[b.idx()].neighbors[side as usize] = Some(a);
+        self.nodes[a.idx()].neighbors[side.to_index()] = Some(b);
+        self.nodes[b.idx()].neighbors[side.to_index()] = Some(a);
+    }
+}
+
+impl<N: Clone> Graph<N> {
+    /// Copy the graph for speculative use, e.g. simulating ahead without disturbing the real world
+    ///
+    /// Node values are cloned, so bulky data within them should be shared until written, as with
+    /// `VoxelData`, for this to be cheap.
+    pub fn snapshot(&self) -> GraphSnapshot<N> {
+        GraphSnapshot(self.clone())
     }
 }
 
@@ -311,6 +449,105 @@ impl<N> Default for Graph<N> {
     }
 }
 
+/// A change to a node or one of its chunks, per `Graph::subscribe`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GraphEvent {
+    NodeCreated(NodeId),
+    /// A chunk's voxels were generated, or restored from elsewhere
+    ChunkGenerated(NodeId, Vertex),
+    /// Voxels of a populated chunk changed
+    ChunkEdited(NodeId, Vertex),
+    /// A chunk's voxels were discarded, to be generated afresh if needed again
+    ChunkEvicted(NodeId, Vertex),
+}
+
+/// A consumer of `GraphEvent`s
+#[derive(Debug)]
+pub struct Subscription(usize);
+
+/// A copy of a `Graph` which may be modified independently of the original and discarded
+pub struct GraphSnapshot<N>(Graph<N>);
+
+impl<N> Deref for GraphSnapshot<N> {
+    type Target = Graph<N>;
+    fn deref(&self) -> &Graph<N> {
+        &self.0
+    }
+}
+
+impl<N> DerefMut for GraphSnapshot<N> {
+    fn deref_mut(&mut self) -> &mut Graph<N> {
+        &mut self.0
+    }
+}
+
+/// Bounded cache of `Graph::relative_transform` results, discarding the least recently used
+///
+/// Nodes are never removed from a `Graph`, so entries stay valid for as long as the same graph is
+/// queried. Anything that discards nodes, or rebuilds the graph such that a `NodeId` identifies a
+/// different node, must `invalidate` or `clear` the affected entries.
+pub struct TransformCache {
+    capacity: u32,
+    slots: FxHashMap<(NodeId, NodeId), SlotId>,
+    entries: LruSlab<((NodeId, NodeId), math::Isometry<f64>)>,
+}
+
+impl TransformCache {
+    pub fn new(capacity: u32) -> Self {
+        assert!(capacity > 0, "transform cache must have nonzero capacity");
+        Self {
+            capacity,
+            slots: FxHashMap::default(),
+            entries: LruSlab::with_capacity(capacity),
+        }
+    }
+
+    /// Equivalent to `graph.relative_transform(from, to)`
+    pub fn get<N>(
+        &mut self,
+        graph: &Graph<N>,
+        from: NodeId,
+        to: NodeId,
+    ) -> Option<math::Isometry<f64>> {
+        if let Some(&slot) = self.slots.get(&(from, to)) {
+            return Some(self.entries.get_mut(slot).1);
+        }
+        let transform = graph.relative_transform(from, to)?;
+        if self.entries.len() == self.capacity {
+            let lru = self.entries.lru().unwrap();
+            let (key, _) = self.entries.remove(lru);
+            self.slots.remove(&key);
+        }
+        let slot = self.entries.insert(((from, to), transform));
+        self.slots.insert((from, to), slot);
+        Some(transform)
+    }
+
+    /// Discard every entry involving `node`
+    pub fn invalidate(&mut self, node: NodeId) {
+        let stale = self
+            .slots
+            .iter()
+            .filter(|&(&(from, to), _)| from == node || to == node)
+            .map(|(&key, &slot)| (key, slot))
+            .collect::<Vec<_>>();
+        for (key, slot) in stale {
+            self.slots.remove(&key);
+            self.entries.remove(slot);
+        }
+    }
+
+    /// Discard every entry
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.entries = LruSlab::with_capacity(self.capacity);
+    }
+
+    pub fn len(&self) -> u32 {
+        self.entries.len()
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct NodeId(NonZeroU32);
 
@@ -338,13 +575,43 @@ impl fmt::Debug for NodeId {
     }
 }
 
+/// A sequence of sides leading out from the root, identifying a node independently of the order in
+/// which a graph was populated
+///
+/// Written as a string of side letters, e.g. `DGA`, with the empty string denoting the root.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct NodePath(pub Vec<Side>);
+
+impl FromStr for NodePath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        s.chars()
+            .map(|c| match c {
+                'A'..='L' => Ok(Side::from_index(c as usize - 'A' as usize)),
+                _ => bail!("invalid side {:?}", c),
+            })
+            .collect::<Result<_, _>>()
+            .map(NodePath)
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &side in &self.0 {
+            write!(f, "{:?}", side)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Node<N> {
     value: Option<N>,
     parent_side: Option<Side>,
     /// Distance to origin via parents
     length: u32,
-    neighbors: [Option<NodeId>; SIDE_COUNT],
+    neighbors: [Option<NodeId>; Side::COUNT],
 }
 
 impl<N> Node<N> {
@@ -353,12 +620,12 @@ impl<N> Node<N> {
             value: None,
             parent_side,
             length,
-            neighbors: [None; SIDE_COUNT],
+            neighbors: [None; Side::COUNT],
         }
     }
 
     fn parent(&self) -> Option<NodeId> {
-        Some(self.neighbors[self.parent_side? as usize].expect("parent edge unpopulated"))
+        Some(self.neighbors[self.parent_side?.to_index()].expect("parent edge unpopulated"))
     }
 }
 
@@ -375,7 +642,7 @@ impl<N> Iterator for TreeIter<'_, N> {
         self.remaining = rest;
         self.id = NodeId::from_idx(self.id.idx() + 1);
         let side = node.parent_side.unwrap();
-        Some((side, node.neighbors[side as usize].unwrap()))
+        Some((side, node.neighbors[side.to_index()].unwrap()))
     }
 }
 
@@ -384,6 +651,39 @@ mod tests {
     use super::*;
     use approx::*;
 
+    #[test]
+    fn events_per_subscriber() {
+        let mut graph = Graph::<()>::default();
+        graph.notify(GraphEvent::ChunkEvicted(NodeId::ROOT, Vertex::A));
+        let a = graph.subscribe();
+        let x = graph.ensure_neighbor(NodeId::ROOT, Side::A);
+        let b = graph.subscribe();
+        graph.notify(GraphEvent::ChunkGenerated(x, Vertex::B));
+        assert_eq!(
+            graph.events(&a),
+            &[
+                GraphEvent::NodeCreated(x),
+                GraphEvent::ChunkGenerated(x, Vertex::B)
+            ]
+        );
+        assert!(graph.events(&a).is_empty());
+        // Kept until every subscriber has seen them
+        assert_eq!(graph.events.len(), 1);
+        graph.notify(GraphEvent::ChunkEdited(x, Vertex::B));
+        assert_eq!(
+            graph.events(&b),
+            &[
+                GraphEvent::ChunkGenerated(x, Vertex::B),
+                GraphEvent::ChunkEdited(x, Vertex::B)
+            ]
+        );
+        graph.unsubscribe(a);
+        assert!(graph.events.is_empty());
+        graph.unsubscribe(b);
+        graph.notify(GraphEvent::ChunkEdited(x, Vertex::B));
+        assert!(graph.events.is_empty(), "recorded with no subscribers");
+    }
+
     #[test]
     fn parent_child_relationships() {
         let mut graph = Graph::<()>::default();
@@ -404,6 +704,39 @@ mod tests {
         assert_eq!(graph.nodes[c.idx()].length, 2);
     }
 
+    #[test]
+    fn node_path_round_trip() {
+        let mut graph = Graph::<()>::default();
+        let path = "DGA".parse::<NodePath>().unwrap();
+        assert_eq!(path.to_string(), "DGA");
+        let node = graph.lookup_path(&path);
+        assert_eq!(graph.lookup_path(&graph.path(node)), node);
+        assert_eq!(graph.lookup_path(&NodePath::default()), NodeId::ROOT);
+        assert_eq!(graph.find_path(&path), Some(node));
+        assert_eq!(graph.find_path(&"DGADGADGA".parse().unwrap()), None);
+        assert!("DgA".parse::<NodePath>().is_err());
+    }
+
+    #[test]
+    fn neighbor_transform_cycle() {
+        let mut graph = Graph::<()>::default();
+        // Four nodes surround each edge
+        let b = Side::iter().find(|&s| Side::A.adjacent_to(s)).unwrap();
+        let mut node = NodeId::ROOT;
+        let mut transform = math::Isometry::identity();
+        for &side in &[Side::A, b, Side::A, b] {
+            let next = graph.ensure_neighbor(node, side);
+            transform = transform * graph.neighbor_transform(node, side).unwrap();
+            node = next;
+        }
+        assert_eq!(node, NodeId::ROOT);
+        assert_abs_diff_eq!(
+            transform.matrix(),
+            &na::Matrix4::identity(),
+            epsilon = 1e-9
+        );
+    }
+
     #[test]
     fn children_have_common_neighbor() {
         let mut graph = Graph::<()>::default();
@@ -448,6 +781,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn relative_transforms() {
+        let mut graph = Graph::<()>::default();
+        graph.ensure_nearby(&Position::origin(), 3.0);
+        let nodes = (0..graph.len() as usize)
+            .map(NodeId::from_idx)
+            .collect::<Vec<_>>();
+        // Agrees with composing neighbor transforms
+        for &node in &nodes {
+            for side in Side::iter() {
+                let neighbor = match graph.neighbor(node, side) {
+                    None => continue,
+                    Some(x) => x,
+                };
+                let relative = graph.relative_transform(node, neighbor).unwrap();
+                let expected = graph.neighbor_transform(node, side).unwrap();
+                assert_abs_diff_eq!(relative.matrix(), expected.matrix(), epsilon = 1e-9);
+            }
+        }
+
+        let mut cache = TransformCache::new(8);
+        for _ in 0..2 {
+            for &from in nodes.iter().step_by(7) {
+                for &to in nodes.iter().step_by(5).take(4) {
+                    assert_eq!(
+                        cache.get(&graph, from, to).unwrap().matrix(),
+                        graph.relative_transform(from, to).unwrap().matrix()
+                    );
+                }
+            }
+        }
+        assert_eq!(cache.len(), 8);
+    }
+
+    #[test]
+    fn transform_cache_invalidation() {
+        let mut a = Graph::<()>::default();
+        let a1 = a.ensure_neighbor(NodeId::ROOT, Side::A);
+        let a2 = a.ensure_neighbor(NodeId::ROOT, Side::B);
+        let mut cache = TransformCache::new(8);
+        cache.get(&a, NodeId::ROOT, a1).unwrap();
+        cache.get(&a, NodeId::ROOT, a2).unwrap();
+        cache.get(&a, a1, a2).unwrap();
+
+        // A graph built in a different order, reusing the same IDs for different nodes
+        let mut b = Graph::<()>::default();
+        let b1 = b.ensure_neighbor(NodeId::ROOT, Side::B);
+        assert_eq!(b1, a1);
+        let fresh = b.relative_transform(NodeId::ROOT, b1).unwrap();
+        assert_ne!(
+            cache.get(&b, NodeId::ROOT, b1).unwrap().matrix(),
+            fresh.matrix()
+        );
+
+        cache.invalidate(b1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.get(&b, NodeId::ROOT, b1).unwrap().matrix(),
+            fresh.matrix()
+        );
+        // Nodes absent from the graph have no transform
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(cache.get(&b, NodeId::ROOT, a2).is_none());
+    }
+
     #[test]
     fn rebuild_from_tree() {
         let mut a = Graph::<()>::default();
@@ -460,7 +859,7 @@ mod tests {
         for (a, b) in a.nodes.iter().zip(b.nodes.iter()) {
             assert_eq!(a.parent_side, b.parent_side);
             if let Some(side) = a.parent_side {
-                assert_eq!(a.neighbors[side as usize], b.neighbors[side as usize]);
+                assert_eq!(a.neighbors[side.to_index()], b.neighbors[side.to_index()]);
             }
         }
     }
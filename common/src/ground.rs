@@ -0,0 +1,95 @@
+//! How a character's motion responds to the ground beneath it
+//!
+//! Characters move at whatever velocity their input asks for. Once input stops, a character on
+//! walkable ground is slowed to rest by friction. Ground steeper than `SimConfig::max_slope` can't
+//! be stood on: characters there slide downhill, and can't walk uphill.
+
+use crate::SimConfig;
+
+/// Velocity of a character after `dt` seconds
+///
+/// All vectors are in the character's frame, where +Y is up. `velocity` is the current velocity,
+/// `input` the velocity asked for, and `ground` the unit normal of the surface underfoot, if any.
+/// A character with nothing underfoot moves only as its input asks.
+pub fn velocity(
+    cfg: &SimConfig,
+    velocity: &na::Vector3<f32>,
+    input: &na::Vector3<f32>,
+    ground: Option<&na::Unit<na::Vector3<f32>>>,
+    dt: f32,
+) -> na::Vector3<f32> {
+    let normal = match ground {
+        None => return *input,
+        Some(x) => x,
+    };
+    let slope = normal.angle(&na::Vector3::y());
+    if slope <= cfg.max_slope {
+        if *input != na::zero() {
+            return *input;
+        }
+        // Friction acts only along the ground
+        let sliding = velocity - normal.into_inner() * normal.dot(velocity);
+        let speed = sliding.norm();
+        let slowed = speed - cfg.ground_friction * dt;
+        if slowed <= 0.0 {
+            return na::zero();
+        }
+        return sliding * (slowed / speed);
+    }
+    // Straight down the slope
+    let downhill = na::Unit::new_normalize(normal.into_inner() * normal.y - na::Vector3::y());
+    let speed = velocity.dot(&downhill).max(0.0) + cfg.slide_acceleration * slope.sin() * dt;
+    // Input may steer across the slope, but not up or down it
+    let across = input - downhill.into_inner() * downhill.dot(input);
+    let across = across - normal.into_inner() * normal.dot(&across);
+    across + downhill.into_inner() * speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimConfigRaw;
+
+    fn cfg() -> SimConfig {
+        SimConfig::from_raw(&SimConfigRaw::default())
+    }
+
+    #[test]
+    fn friction_stops() {
+        let cfg = cfg();
+        let level = na::Vector3::y_axis();
+        let mut v = na::Vector3::new(0.0, 0.0, -cfg.movement_speed);
+        let mut speeds = Vec::new();
+        for _ in 0..100 {
+            v = velocity(&cfg, &v, &na::zero(), Some(&level), 0.1);
+            speeds.push(v.norm());
+        }
+        assert!(speeds.windows(2).all(|w| w[1] <= w[0]));
+        assert!(speeds[0] > 0.0 && speeds[0] < cfg.movement_speed);
+        assert_eq!(*speeds.last().unwrap(), 0.0);
+        // Input is obeyed immediately
+        let input = na::Vector3::new(cfg.movement_speed, 0.0, 0.0);
+        assert_eq!(velocity(&cfg, &v, &input, Some(&level), 0.1), input);
+    }
+
+    #[test]
+    fn steep_slides() {
+        let cfg = cfg();
+        let tilt = |angle: f32| na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), angle);
+        let gentle = tilt(cfg.max_slope * 0.5) * na::Vector3::y_axis();
+        let steep = tilt(cfg.max_slope * 1.5) * na::Vector3::y_axis();
+        let still = na::Vector3::zeros();
+        assert_eq!(velocity(&cfg, &still, &still, Some(&gentle), 0.1), still);
+        let v = velocity(&cfg, &still, &still, Some(&steep), 0.1);
+        // Down and along the slope
+        assert!(v.y < 0.0);
+        assert!(steep.dot(&v).abs() < 1e-5);
+        // Ever faster
+        let faster = velocity(&cfg, &v, &still, Some(&steep), 0.1);
+        assert!(faster.norm() > v.norm());
+        assert!((faster.normalize() - v.normalize()).norm() < 1e-5);
+        // Walking uphill doesn't help
+        let uphill = -v.normalize() * cfg.movement_speed;
+        assert!(velocity(&cfg, &v, &uphill, Some(&steep), 0.1).dot(&v) > 0.0);
+    }
+}
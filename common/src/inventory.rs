@@ -0,0 +1,57 @@
+//! Materials carried by a character, to be spent on building
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::Material;
+
+/// How many voxels of each material are held
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Inventory {
+    /// Only materials with a nonzero count are present
+    counts: BTreeMap<Material, u32>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of voxels of `material` held
+    pub fn count(&self, material: Material) -> u32 {
+        self.counts.get(&material).copied().unwrap_or(0)
+    }
+
+    /// Gain `count` voxels of `material`
+    ///
+    /// Void can't be held, so adding it has no effect.
+    pub fn add(&mut self, material: Material, count: u32) {
+        if material.is_void() || count == 0 {
+            return;
+        }
+        let held = self.counts.entry(material).or_insert(0);
+        *held = held.saturating_add(count);
+    }
+
+    /// Spend a single voxel of `material`, returning `false` if none are held
+    pub fn take(&mut self, material: Material) -> bool {
+        match self.counts.get_mut(&material) {
+            None => false,
+            Some(held) => {
+                *held -= 1;
+                if *held == 0 {
+                    self.counts.remove(&material);
+                }
+                true
+            }
+        }
+    }
+
+    /// Every material held, with its count
+    pub fn iter(&self) -> impl Iterator<Item = (Material, u32)> + '_ {
+        self.counts
+            .iter()
+            .map(|(&material, &count)| (material, count))
+    }
+}
@@ -1,24 +1,34 @@
-use rand::{
-    distributions::{Distribution, Standard},
-    Rng,
-};
+use std::hash::{Hash, Hasher};
 
 #[macro_use]
 mod id;
 
+#[cfg(feature = "graphics")]
 pub mod chunk;
 mod chunks;
 pub mod codec;
+pub mod collision;
+pub mod compact;
 pub mod cursor;
+pub mod daylight;
 pub mod dodeca;
 pub mod graph;
 mod graph_entities;
+pub mod ground;
+pub mod inventory;
+#[cfg(feature = "graphics")]
+pub mod light;
 pub mod lru_slab;
 pub mod math;
+pub mod merge;
 pub mod node;
+pub mod parallel;
 mod plane;
 pub mod proto;
+pub mod reliable;
 mod sim_config;
+pub mod snap;
+pub mod voxel_diff;
 pub mod world;
 pub mod worldgen;
 
@@ -26,20 +36,54 @@ pub use chunks::Chunks;
 pub use graph_entities::GraphEntities;
 pub use lru_slab::LruSlab;
 pub use plane::Plane;
-pub use sim_config::{SimConfig, SimConfigRaw};
+pub use sim_config::{
+    ConfigVersionError, ImmutableParameter, SimConfig, SimConfigRaw, WorldSeed, SIM_CONFIG_VERSION,
+};
 
-// Stable IDs made of 8 random bytes for easy persistent references
+// IDs made of a slot index and a generation, so that a reused slot yields a distinct ID
 mkid!(EntityId: u64);
 
+impl EntityId {
+    pub fn new(index: u32, generation: u32) -> Self {
+        EntityId(u64::from(generation) << 32 | u64::from(index))
+    }
+
+    /// Server-side slot occupied by the entity, which may later be reused by another
+    #[inline]
+    pub fn index(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Distinguishes successive occupants of the same slot
+    #[inline]
+    pub fn generation(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    /// Whether `self` identifies an earlier occupant of `other`'s slot, accounting for wrapping
+    pub fn is_older_than(self, other: EntityId) -> bool {
+        let age = other.generation().wrapping_sub(self.generation());
+        self.index() == other.index() && age != 0 && age < u32::max_value() / 2
+    }
+}
+
 impl std::fmt::Display for EntityId {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:016x}", self.0)
     }
 }
 
-impl Distribution<EntityId> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> EntityId {
-        EntityId(rng.gen())
+// Identifies one of the independent worlds hosted by a server
+mkid!(WorldId: u32);
+
+impl WorldId {
+    /// The world players join unless they ask for another
+    pub const DEFAULT: Self = WorldId(0);
+}
+
+impl std::fmt::Display for WorldId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:x}", self.0)
     }
 }
 
@@ -86,6 +130,51 @@ pub fn sanitize_motion_input(v: na::Vector3<f32>) -> (na::Unit<na::Vector3<f32>>
     }
 }
 
+/// Order-independent hash of the entities a client knows of, of where its `character` stands, and
+/// of the contents of each chunk the server has changed, per `chunk_hash`, for detecting clients
+/// diverging from the server
+///
+/// Every part is something the client works out for itself: it tracks entities as they spawn and
+/// despawn, predicts its character's motion, and applies voxel changes to terrain it generated. The
+/// position is quantized so that insignificant differences in representation, e.g. the sign of
+/// zero, don't produce false positives.
+pub fn state_hash(
+    entities: impl IntoIterator<Item = EntityId>,
+    character: (EntityId, &proto::Position),
+    chunks: impl IntoIterator<Item = (node::ChunkId, u64)>,
+) -> u64 {
+    let entities = entities.into_iter().fold(0u64, |acc, id| {
+        let mut hasher = fxhash::FxHasher64::default();
+        id.hash(&mut hasher);
+        acc.wrapping_add(hasher.finish())
+    });
+    let chunks = chunks.into_iter().fold(0u64, |acc, (chunk, contents)| {
+        let mut hasher = fxhash::FxHasher64::default();
+        chunk.hash(&mut hasher);
+        contents.hash(&mut hasher);
+        acc.wrapping_add(hasher.finish())
+    });
+    let (id, pos) = character;
+    let mut hasher = fxhash::FxHasher64::default();
+    entities.hash(&mut hasher);
+    chunks.hash(&mut hasher);
+    id.hash(&mut hasher);
+    pos.node.hash(&mut hasher);
+    for &x in pos.local.iter() {
+        ((x * 1024.0).round() as i32).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Hash of a chunk's voxels, including margins, regardless of how they're represented
+pub fn chunk_hash(dimension: u8, voxels: &node::VoxelData) -> u64 {
+    let mut hasher = fxhash::FxHasher64::default();
+    for i in 0..(usize::from(dimension) + 2).pow(3) {
+        voxels.get(i).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
 pub fn tracing_guard() -> tracing::dispatcher::DefaultGuard {
     use tracing_subscriber::util::SubscriberInitExt;
     tracing_subscriber().set_default()
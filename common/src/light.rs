@@ -0,0 +1,350 @@
+//! Light levels of the voxels of a chunk, spread by flood fill from emissive materials
+//!
+//! Each void voxel is lit one level less than its brightest neighbor, or as brightly as it emits
+//! itself, whichever is greater. Solid voxels block light, though those that emit still light their
+//! neighbors. Light doesn't yet spread across chunk boundaries, but a chunk's margins take the light
+//! of the neighboring voxels they duplicate, so that surfaces on a boundary are lit from both sides.
+
+use std::collections::VecDeque;
+
+use fxhash::FxHashMap;
+
+use crate::{
+    node::{voxel_coords, voxel_index, ChunkId, DualGraph, VoxelData},
+    world::{self, Material},
+};
+
+/// Level of the brightest light
+pub const MAX_LIGHT: u8 = 15;
+
+/// Level of the light `material` gives off
+pub fn emission(material: Material) -> u8 {
+    match material {
+        Material::Lava => MAX_LIGHT,
+        _ => 0,
+    }
+}
+
+/// Light level of each voxel of a chunk, excluding margins
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LightField {
+    dimension: u8,
+    levels: Vec<u8>,
+}
+
+impl LightField {
+    /// Light the chunk made of `voxels` from scratch
+    pub fn compute(dimension: u8, voxels: &VoxelData) -> Self {
+        let mut result = Self {
+            dimension,
+            levels: vec![0; usize::from(dimension).pow(3)],
+        };
+        let mut pending = VecDeque::new();
+        for coords in result.coords() {
+            let level = emission(voxels.get(voxel_index(dimension, coords)));
+            if level > 0 {
+                result.set(coords, level);
+                pending.push_back(coords);
+            }
+        }
+        result.propagate(voxels, pending, &mut FxHashMap::default());
+        result
+    }
+
+    pub fn get(&self, coords: [u8; 3]) -> u8 {
+        self.levels[self.index(coords)]
+    }
+
+    /// Light level of each voxel of the chunk's data, including margins, which are dark
+    pub fn padded(&self) -> Vec<u8> {
+        let mut result = vec![0; (usize::from(self.dimension) + 2).pow(3)];
+        for coords in self.coords() {
+            result[voxel_index(self.dimension, coords)] = self.get(coords);
+        }
+        result
+    }
+
+    /// Whether any voxel on the chunk's faces is lit, and so might light its neighbors' margins
+    pub fn lights_boundary(&self) -> bool {
+        let max = self.dimension - 1;
+        self.coords()
+            .any(|x| x.iter().any(|&x| x == 0 || x == max) && self.get(x) > 0)
+    }
+
+    /// Bring light up to date after the voxel at `coords` changed to its material in `voxels`
+    ///
+    /// Only light from the changed voxel outward is revisited: first that which the voxel may
+    /// have supplied is removed, then light is spread back into the darkened region and the voxel
+    /// itself from whatever still lights their surroundings. The result is the same as that of
+    /// `compute`. Returns the voxels whose light level changed, whose surfaces must be redrawn.
+    pub fn update(&mut self, voxels: &VoxelData, coords: [u8; 3]) -> Vec<[u8; 3]> {
+        let mut original = FxHashMap::default();
+        let mut relight = VecDeque::new();
+
+        // Darken everything that might have been lit by way of the changed voxel
+        let mut darken = VecDeque::new();
+        darken.push_back((coords, self.get(coords)));
+        self.record(&mut original, coords, 0);
+        while let Some((voxel, level)) = darken.pop_front() {
+            for neighbor in self.neighbors(voxel) {
+                let neighbor_level = self.get(neighbor);
+                if neighbor_level == 0 {
+                    continue;
+                }
+                let own = emission(voxels.get(voxel_index(self.dimension, neighbor)));
+                if neighbor_level < level && neighbor_level > own {
+                    self.record(&mut original, neighbor, own);
+                    darken.push_back((neighbor, neighbor_level));
+                    if own > 0 {
+                        relight.push_back(neighbor);
+                    }
+                } else {
+                    // Lit independently, so a source to spread light back from
+                    relight.push_back(neighbor);
+                }
+            }
+        }
+
+        // Restore light from the changed voxel itself and from what surrounds the darkened region
+        let level = emission(voxels.get(voxel_index(self.dimension, coords)));
+        self.record(&mut original, coords, level);
+        relight.push_back(coords);
+        self.propagate(voxels, relight, &mut original);
+
+        let mut changed = original
+            .into_iter()
+            .filter(|&(index, level)| self.levels[index] != level)
+            .map(|(index, _)| self.coords_of(index))
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+        changed
+    }
+
+    /// Spread light from each of `pending` into darker void neighbors, until nothing changes
+    fn propagate(
+        &mut self,
+        voxels: &VoxelData,
+        mut pending: VecDeque<[u8; 3]>,
+        original: &mut FxHashMap<usize, u8>,
+    ) {
+        while let Some(voxel) = pending.pop_front() {
+            let level = self.get(voxel);
+            if level <= 1 {
+                continue;
+            }
+            for neighbor in self.neighbors(voxel) {
+                let transparent = voxels.get(voxel_index(self.dimension, neighbor)).is_void();
+                if transparent && self.get(neighbor) < level - 1 {
+                    self.record(original, neighbor, level - 1);
+                    pending.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    /// Set the level of `coords`, remembering what it was before the first change
+    fn record(&mut self, original: &mut FxHashMap<usize, u8>, coords: [u8; 3], level: u8) {
+        let index = self.index(coords);
+        original.entry(index).or_insert(self.levels[index]);
+        self.levels[index] = level;
+    }
+
+    fn set(&mut self, coords: [u8; 3], level: u8) {
+        let index = self.index(coords);
+        self.levels[index] = level;
+    }
+
+    fn index(&self, coords: [u8; 3]) -> usize {
+        let dim = usize::from(self.dimension);
+        usize::from(coords[0]) + usize::from(coords[1]) * dim + usize::from(coords[2]) * dim.pow(2)
+    }
+
+    fn coords_of(&self, index: usize) -> [u8; 3] {
+        let dim = usize::from(self.dimension);
+        [
+            (index % dim) as u8,
+            (index / dim % dim) as u8,
+            (index / dim.pow(2)) as u8,
+        ]
+    }
+
+    fn coords(&self) -> impl Iterator<Item = [u8; 3]> {
+        let dim = self.dimension;
+        (0..dim).flat_map(move |z| (0..dim).flat_map(move |y| (0..dim).map(move |x| [x, y, z])))
+    }
+
+    /// Voxels of the chunk sharing a face with `coords`
+    fn neighbors(&self, coords: [u8; 3]) -> impl Iterator<Item = [u8; 3]> {
+        let dim = self.dimension;
+        (0..3).flat_map(move |axis| {
+            let below = coords[axis].checked_sub(1);
+            let above = Some(coords[axis] + 1).filter(|&x| x < dim);
+            below.into_iter().chain(above).map(move |x| {
+                let mut neighbor = coords;
+                neighbor[axis] = x;
+                neighbor
+            })
+        })
+    }
+}
+
+/// `LightField::padded` for `chunk`, with each margin voxel taking the light of the voxel of a
+/// neighboring chunk it duplicates, or `None` if `chunk` isn't populated
+///
+/// Margins duplicating chunks that aren't populated are dark.
+pub fn padded_light(graph: &DualGraph, dimension: u8, chunk: ChunkId) -> Option<Vec<u8>> {
+    let light = |chunk: ChunkId| graph.get(chunk.node).as_ref()?.chunks[chunk.vertex].light();
+    let mut result = light(chunk)?.padded();
+    // Finding the voxel each margin voxel duplicates is costly, and seldom makes a difference
+    let lit = world::neighboring_chunks(graph, dimension, chunk)
+        .into_iter()
+        .any(|x| light(x).map_or(false, |x| x.lights_boundary()));
+    if !lit {
+        return Some(result);
+    }
+    let lwm = i16::from(dimension) + 2;
+    for (index, out) in result.iter_mut().enumerate() {
+        if voxel_coords(dimension, index).is_some() {
+            continue;
+        }
+        let index = index as i16;
+        let coords = [
+            index % lwm - 1,
+            index / lwm % lwm - 1,
+            index / lwm.pow(2) - 1,
+        ];
+        if let Some((other, coords)) = world::voxel_near(graph, dimension, chunk, coords) {
+            *out = light(other).map_or(0, |x| x.get(coords));
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIMENSION: u8 = 12;
+
+    fn set(voxels: &mut VoxelData, coords: [u8; 3], material: Material) {
+        voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, coords)] = material;
+    }
+
+    #[test]
+    fn breaking_block_lets_light_in() {
+        // Two rooms separated by a single voxel of wall, one lit by lava
+        let mut voxels = VoxelData::Solid(Material::Stone);
+        for x in 1..11 {
+            for y in 1..4 {
+                for z in 1..4 {
+                    set(&mut voxels, [x, y, z], Material::Void);
+                }
+            }
+        }
+        set(&mut voxels, [1, 2, 2], Material::Lava);
+        let wall = [6, 2, 2];
+        for y in 1..4 {
+            for z in 1..4 {
+                set(&mut voxels, [6, y, z], Material::Stone);
+            }
+        }
+        let mut light = LightField::compute(DIMENSION, &voxels);
+        assert_eq!(light.get([1, 2, 2]), MAX_LIGHT);
+        assert_eq!(light.get([2, 2, 2]), MAX_LIGHT - 1);
+        assert_eq!(light.get([5, 2, 2]), MAX_LIGHT - 4);
+        assert_eq!(light.get(wall), 0);
+        assert_eq!(light.get([7, 2, 2]), 0, "sealed off");
+
+        // Breaking the wall
+        set(&mut voxels, wall, Material::Void);
+        let before = light.clone();
+        let changed = light.update(&voxels, wall);
+        assert_eq!(light, LightField::compute(DIMENSION, &voxels));
+        assert_eq!(light.get(wall), MAX_LIGHT - 5);
+        assert_eq!(light.get([7, 2, 2]), MAX_LIGHT - 6);
+        // Only the wall and the room beyond it were affected, and all of them are reported
+        assert!(!changed.is_empty());
+        for coords in light.coords() {
+            let reported = changed.contains(&coords);
+            assert_eq!(reported, light.get(coords) != before.get(coords));
+            if reported {
+                assert!(coords[0] >= 6, "{:?} changed", coords);
+            }
+        }
+
+        // Sealing it again removes exactly the light that came through
+        set(&mut voxels, wall, Material::Stone);
+        let changed = light.update(&voxels, wall);
+        assert_eq!(light, before);
+        assert!(changed.iter().all(|coords| coords[0] >= 6));
+
+        // Removing the source darkens everything
+        set(&mut voxels, [1, 2, 2], Material::Void);
+        light.update(&voxels, [1, 2, 2]);
+        assert_eq!(light, LightField::compute(DIMENSION, &voxels));
+        assert!(light.coords().all(|coords| light.get(coords) == 0));
+    }
+
+    #[test]
+    fn light_reaches_neighbor_surfaces() {
+        use crate::{
+            dodeca::Vertex,
+            graph::NodeId,
+            node::{populate_fresh_nodes, Chunk},
+        };
+
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        // Open space with lava on its x = 0 face, and the solid chunk beyond that face
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let lava = [0, 2, 2];
+        let (other, other_coords) =
+            world::voxel_near(&graph, DIMENSION, chunk, [-1, 2, 2]).unwrap();
+        assert_ne!(other, chunk);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        set(&mut voxels, lava, Material::Lava);
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[chunk.vertex] = Chunk::populated(DIMENSION, voxels);
+        let neighbor = graph.get_mut(other.node).as_mut().unwrap();
+        neighbor.chunks[other.vertex] =
+            Chunk::populated(DIMENSION, VoxelData::Solid(Material::Stone));
+
+        // The margin voxel beyond the face of the neighbor's voxel that adjoins the lava
+        let lwm = usize::from(DIMENSION) + 2;
+        let margin = (0..3)
+            .flat_map(|axis| [-1, 1].iter().map(move |&offset| (axis, offset)))
+            .map(|(axis, offset)| {
+                let mut coords = [
+                    i16::from(other_coords[0]),
+                    i16::from(other_coords[1]),
+                    i16::from(other_coords[2]),
+                ];
+                coords[axis] += offset;
+                coords
+            })
+            .find(|&coords| {
+                world::voxel_near(&graph, DIMENSION, other, coords) == Some((chunk, lava))
+            })
+            .expect("the lava adjoins the neighbor");
+        let margin = margin
+            .iter()
+            .rev()
+            .fold(0, |acc, &x| acc * lwm + (x + 1) as usize);
+        assert!(voxel_coords(DIMENSION, margin).is_none());
+
+        // Though light doesn't spread into the neighbor, its surface there is lit
+        let padded = padded_light(&graph, DIMENSION, other).unwrap();
+        assert_eq!(padded[margin], MAX_LIGHT);
+        assert_eq!(padded[voxel_index(DIMENSION, other_coords)], 0);
+
+        // Without the lava, it's dark
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[chunk.vertex].set_voxel(
+            DIMENSION,
+            voxel_index(DIMENSION, lava),
+            Material::Void,
+        );
+        let padded = padded_light(&graph, DIMENSION, other).unwrap();
+        assert!(padded.iter().all(|&x| x == 0));
+    }
+}
@@ -3,7 +3,15 @@
 //! Vector4 values are assumed to be homogeneous Klein model coordinates unless otherwise
 //! stated. Note that Minkowski model coordinates are valid Klein coordinates, but not vis versa.
 
+pub mod h2;
+
+use std::{fmt, ops::Mul, str::FromStr};
+
 use na::{RealField, Scalar};
+use rand::{
+    distributions::{Distribution, Standard},
+    Rng,
+};
 use serde::{Deserialize, Serialize};
 
 /// A point on the surface of the 3D hyperboloid in Minkowski coordinates with an implicit w
@@ -36,6 +44,530 @@ impl<N: RealField> HPoint<N> {
     }
 }
 
+/// An isometry of hyperbolic space, acting on Minkowski coordinates
+///
+/// Orientation-preserving isometries are formatted as a rotation about the origin followed by a
+/// translation away from it, which is much easier to read than a raw matrix, e.g. `translate 1.5
+/// along [0, 1, 0], rotate 0.5 about [1, 0, 0]`. The formatter's precision, if any, applies to each
+/// number. The same form can be parsed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Isometry<N: Scalar>(na::Matrix4<N>);
+
+impl<N: RealField> Isometry<N> {
+    pub fn identity() -> Self {
+        Self(na::Matrix4::identity())
+    }
+
+    /// Construct from a matrix, which must be an isometry
+    pub fn from_matrix_unchecked(m: na::Matrix4<N>) -> Self {
+        Self(m)
+    }
+
+    /// Construct from a matrix from an untrusted source, e.g. the network
+    ///
+    /// Fails unless the matrix is finite and carries the origin to a point on the upper sheet of
+    /// the hyperboloid, without which no meaningful position can be recovered.
+    pub fn try_from_matrix(m: na::Matrix4<N>) -> Result<Self, MathError> {
+        if !m.iter().all(|x| x.is_finite()) {
+            return Err(MathError::NonFinite);
+        }
+        check_on_hyperboloid(&m.column(3).clone_owned())?;
+        Ok(Self(m))
+    }
+
+    /// Rotate by `rotation` about the origin, then translate `distance` along `direction`
+    pub fn from_parts(
+        direction: &na::Unit<na::Vector3<N>>,
+        distance: N,
+        rotation: &na::UnitQuaternion<N>,
+    ) -> Self {
+        Self(translate_along(direction, distance) * rotation.to_homogeneous())
+    }
+
+    /// Rotate by `rotation` about the origin, then translate the origin to `translation`, a point
+    /// from an untrusted source, e.g. the network
+    ///
+    /// Returns `None` unless `translation` is finite and lies on the upper sheet of the hyperboloid,
+    /// so in particular has positive w.
+    pub fn try_from_parts(
+        rotation: &na::UnitQuaternion<N>,
+        translation: &na::Vector4<N>,
+    ) -> Option<Self> {
+        check_on_hyperboloid(translation).ok()?;
+        Some(Self::from_rotate_then_translate(rotation, translation))
+    }
+
+    /// Inverse of `from_parts`
+    ///
+    /// The direction is arbitrary when the distance is zero. Meaningless for isometries that
+    /// reverse orientation.
+    pub fn to_parts(&self) -> (na::Unit<na::Vector3<N>>, N, na::UnitQuaternion<N>) {
+        let p = lorentz_normalize(&self.0.column(3).clone_owned());
+        let norm = p.xyz().norm();
+        let (direction, distance) = if norm == na::zero() {
+            (na::Vector3::x_axis(), na::zero())
+        } else {
+            (na::Unit::new_unchecked(p.xyz() / norm), norm.asinh())
+        };
+        let rotation = translate_along(&direction, -distance) * self.0;
+        let rotation = na::Rotation3::from_matrix_unchecked(
+            rotation.fixed_slice::<na::U3, na::U3>(0, 0).clone_owned(),
+        );
+        (
+            direction,
+            distance,
+            na::UnitQuaternion::from_rotation_matrix(&rotation),
+        )
+    }
+
+    /// Rotate by `rotation` about the origin, then translate the origin to `translation`
+    pub fn from_rotate_then_translate(
+        rotation: &na::UnitQuaternion<N>,
+        translation: &na::Vector4<N>,
+    ) -> Self {
+        Self(translate(&origin(), translation) * rotation.to_homogeneous())
+    }
+
+    /// Translate the origin to `translation`, then rotate by `rotation` about the origin
+    pub fn from_translate_then_rotate(
+        translation: &na::Vector4<N>,
+        rotation: &na::UnitQuaternion<N>,
+    ) -> Self {
+        Self(rotation.to_homogeneous() * translate(&origin(), translation))
+    }
+
+    /// Inverse of `from_rotate_then_translate`, matching the stored convention
+    ///
+    /// Meaningless for isometries that reverse orientation.
+    pub fn as_rotate_then_translate(&self) -> (na::UnitQuaternion<N>, na::Vector4<N>) {
+        let (direction, distance, rotation) = self.to_parts();
+        (rotation, translate_along(&direction, distance) * origin())
+    }
+
+    /// Inverse of `from_translate_then_rotate`
+    ///
+    /// The rotation is the same as that of `as_rotate_then_translate`, since rotating a translation
+    /// yields a translation to the rotated point. Meaningless for isometries that reverse
+    /// orientation.
+    pub fn as_translate_then_rotate(&self) -> (na::Vector4<N>, na::UnitQuaternion<N>) {
+        let (rotation, translation) = self.as_rotate_then_translate();
+        let translation = (rotation.inverse() * translation.xyz()).push(translation.w);
+        (translation, rotation)
+    }
+
+    /// The translation of `as_rotate_then_translate` alone, as an isometry
+    ///
+    /// `translation_part() * rotation_part()` reconstructs `self`. Meaningless for isometries that
+    /// reverse orientation.
+    pub fn translation_part(&self) -> Self {
+        let (direction, distance, _) = self.to_parts();
+        Self(translate_along(&direction, distance))
+    }
+
+    /// The rotation about the origin of `as_rotate_then_translate` alone, as an isometry
+    ///
+    /// Meaningless for isometries that reverse orientation.
+    pub fn rotation_part(&self) -> Self {
+        let (_, _, rotation) = self.to_parts();
+        Self(rotation.to_homogeneous())
+    }
+
+    /// Pose at `eye` looking along -z towards `target`, with +y leaning towards `up`
+    ///
+    /// `up` is a direction in the frame of `translate(&origin(), eye)`, as with
+    /// `parallel_transport`. When `target` coincides with `eye`, looks along that frame's -z.
+    pub fn look_at(eye: &na::Vector4<N>, target: &na::Vector4<N>, up: &na::Vector3<N>) -> Self {
+        let to_eye = translate(&origin(), eye);
+        // The target, as seen from the eye
+        let target = translate(eye, &origin()) * target;
+        let epsilon = na::convert::<_, N>(1e-6);
+        let back = na::Unit::try_new(-target.xyz(), epsilon).unwrap_or_else(na::Vector3::z_axis);
+        let right = [*up, na::Vector3::y(), na::Vector3::x()]
+            .iter()
+            .find_map(|x| na::Unit::try_new(x.cross(&*back), na::convert(1e-3)))
+            .unwrap();
+        let top = back.cross(&*right);
+        let rotation = na::Matrix4::from_columns(&[
+            right.into_inner().push(na::zero()),
+            top.push(na::zero()),
+            back.into_inner().push(na::zero()),
+            na::Vector4::w(),
+        ]);
+        Self(to_eye * rotation)
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self(i31::<N>() * self.0.transpose() * i31::<N>())
+    }
+
+    #[inline]
+    pub fn matrix(&self) -> &na::Matrix4<N> {
+        &self.0
+    }
+
+    #[inline]
+    pub fn to_homogeneous(&self) -> na::Matrix4<N> {
+        self.0
+    }
+
+    /// Weighted Karcher mean of orientation-preserving `poses`
+    ///
+    /// The position is the point minimizing the weighted sum of squared distances to the poses'
+    /// positions. The orientation likewise minimizes squared angles to the poses' orientations,
+    /// once each is carried to the mean position along a geodesic. Weights need not sum to one.
+    ///
+    /// Well-defined so long as the orientations, so transported, lie within a half-turn of each
+    /// other. Panics if `poses` is empty or `weights` differs in length.
+    pub fn mean(poses: &[Self], weights: &[N]) -> Self {
+        Self::mean_iterations(poses, weights).0
+    }
+
+    /// The pose a fraction `t` of the way from `self` to `other`
+    ///
+    /// The position moves along the geodesic between the two at constant speed, and the orientation
+    /// turns the short way round, as chosen by `rotation`. Meaningless for isometries that reverse
+    /// orientation.
+    pub fn lerp_geodesic(&self, other: &Self, t: N, rotation: RotationLerp) -> Self {
+        let (direction, distance, turn) = (self.inverse() * *other).to_parts();
+        // Either sign denotes the same rotation, but only the positive one is the short way round
+        let turn = if turn.w < N::zero() {
+            na::UnitQuaternion::new_unchecked(-turn.into_inner())
+        } else {
+            turn
+        };
+        let turn = match rotation {
+            RotationLerp::Slerp => na::UnitQuaternion::identity().slerp(&turn, t),
+            RotationLerp::Nlerp => na::UnitQuaternion::identity().nlerp(&turn, t),
+        };
+        *self * Self::from_parts(&direction, distance * t, &turn)
+    }
+
+    /// `mean`, along with the number of refinement steps taken
+    fn mean_iterations(poses: &[Self], weights: &[N]) -> (Self, u32) {
+        assert!(!poses.is_empty(), "mean of no poses");
+        assert_eq!(poses.len(), weights.len(), "each pose needs a weight");
+        let total = weights.iter().fold(N::zero(), |acc, &w| acc + w);
+        let tolerance = N::default_epsilon().sqrt();
+
+        // Position, by gradient descent from the normalized weighted sum of the positions
+        let points = poses
+            .iter()
+            .map(|x| lorentz_normalize(&x.0.column(3).clone_owned()))
+            .collect::<Vec<_>>();
+        let mut center = lorentz_normalize(
+            &points
+                .iter()
+                .zip(weights)
+                .fold(na::Vector4::zeros(), |acc, (p, &w)| acc + p * w),
+        );
+        let mut position_iterations = 0;
+        loop {
+            let step = points
+                .iter()
+                .zip(weights)
+                .fold(na::Vector4::zeros(), |acc, (p, &w)| {
+                    acc + log_point(&center, p) * (w / total)
+                });
+            center = lorentz_normalize(&exp_point(&center, &step));
+            position_iterations += 1;
+            if mip(&step, &step) <= tolerance * tolerance || position_iterations == MEAN_ITERATIONS
+            {
+                break;
+            }
+        }
+
+        // Orientation, by the same method applied to each pose carried to the origin
+        let to_origin = translate(&center, &origin());
+        let rotations = poses
+            .iter()
+            .zip(&points)
+            .map(|(pose, point)| {
+                let m = to_origin * translate(point, &center) * pose.0;
+                na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(
+                    m.fixed_slice::<na::U3, na::U3>(0, 0).clone_owned(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut rotation = rotations[0];
+        let mut rotation_iterations = 0;
+        loop {
+            let inverse = rotation.inverse();
+            let step = rotations
+                .iter()
+                .zip(weights)
+                .fold(na::Vector3::zeros(), |acc, (r, &w)| {
+                    acc + (inverse * r).scaled_axis() * (w / total)
+                });
+            rotation *= na::UnitQuaternion::from_scaled_axis(step);
+            rotation_iterations += 1;
+            if step.norm() <= tolerance || rotation_iterations == MEAN_ITERATIONS {
+                break;
+            }
+        }
+
+        (
+            Self(translate(&origin(), &center) * rotation.to_homogeneous()),
+            position_iterations.max(rotation_iterations),
+        )
+    }
+}
+
+/// How `Isometry::lerp_geodesic` interpolates orientation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RotationLerp {
+    /// Spherical linear interpolation, turning at constant speed
+    Slerp,
+    /// Normalized linear interpolation of quaternions, which avoids trigonometry
+    ///
+    /// Follows the same arc as `Slerp` but turns faster through the middle. For endpoints an angle
+    /// `θ` apart, the orientation deviates from `Slerp`'s by no more than `θ³ / 200` radians: under
+    /// a third of a degree up to 60°, about a degree at 90°, and 8° at the extreme of 180°. Suits
+    /// visual smoothing between nearby orientations, but not anything simulated.
+    Nlerp,
+}
+
+/// Upper bound on the refinement steps `Isometry::mean` takes for each of position and orientation
+const MEAN_ITERATIONS: u32 = 32;
+
+impl<N: RealField> Isometry<N>
+where
+    Standard: Distribution<N>,
+{
+    /// Sample a random orientation-preserving isometry that moves the origin no further than
+    /// `max_distance`
+    ///
+    /// The rotation is uniformly distributed, and is followed by a translation in a uniformly
+    /// distributed direction by a distance drawn uniformly from `[0, max_distance)`. Note that the
+    /// translated origin is therefore *not* uniformly distributed by volume within the ball of
+    /// radius `max_distance`, which in hyperbolic space is dominated by its outermost shell.
+    pub fn random<R: Rng + ?Sized>(rng: &mut R, max_distance: N) -> Self {
+        let one = N::one();
+        // Shoemake, "Uniform Random Rotations", Graphics Gems III
+        let u = rng.gen::<N>();
+        let (a, b) = ((one - u).sqrt(), u.sqrt());
+        let (t1, t2) = (N::two_pi() * rng.gen::<N>(), N::two_pi() * rng.gen::<N>());
+        let rotation = na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+            b * t2.cos(),
+            a * t1.sin(),
+            a * t1.cos(),
+            b * t2.sin(),
+        ));
+        // Archimedes' hat-box theorem: z is uniform for points uniform on the sphere
+        let z = (one + one) * rng.gen::<N>() - one;
+        let phi = N::two_pi() * rng.gen::<N>();
+        let r = (one - z * z).sqrt();
+        let direction = na::Unit::new_normalize(na::Vector3::new(r * phi.cos(), r * phi.sin(), z));
+        Self::from_parts(&direction, max_distance * rng.gen::<N>(), &rotation)
+    }
+}
+
+/// Composition, computed as a plain matrix product with no corrective terms
+///
+/// No `triangle_defect` correction is applied: the rotation a chain of translations picks up is
+/// already exact in the product, so there's nothing to compute in one frame or another, and
+/// `(a * b) * c` differs from `a * (b * c)` by rounding alone. That difference can't be removed.
+/// Floating-point arithmetic isn't associative, and the two groupings round different intermediate
+/// products. Moreover, an isometry translating by distance `d` has entries as large as `cosh d`
+/// that mostly cancel, so each rounding contributes error in proportion to the entries rather than
+/// the result. Each entry of the two groupings therefore differs by up to a small multiple of
+/// machine epsilon times the product of the operands' largest entries, a bound
+/// `isometry_associativity` enforces. Long chains of distant transforms should still be
+/// renormalized or rebased onto nearby nodes.
+impl<N: RealField> Mul for Isometry<N> {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: Self) -> Self {
+        Self(self.0 * rhs.0)
+    }
+}
+
+impl<N: RealField> Mul<na::Vector4<N>> for Isometry<N> {
+    type Output = na::Vector4<N>;
+
+    #[inline]
+    fn mul(self, rhs: na::Vector4<N>) -> na::Vector4<N> {
+        self.0 * rhs
+    }
+}
+
+impl<N: RealField + fmt::Display> fmt::Display for Isometry<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (direction, distance, rotation) = self.to_parts();
+        let (axis, angle) = rotation
+            .axis_angle()
+            .unwrap_or_else(|| (na::Vector3::x_axis(), na::zero()));
+        f.write_str("translate ")?;
+        fmt::Display::fmt(&distance, f)?;
+        f.write_str(" along ")?;
+        fmt_vector(&direction, f)?;
+        f.write_str(", rotate ")?;
+        fmt::Display::fmt(&angle, f)?;
+        f.write_str(" about ")?;
+        fmt_vector(&axis, f)
+    }
+}
+
+fn fmt_vector<N: Scalar + fmt::Display>(
+    v: &na::Vector3<N>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    f.write_str("[")?;
+    fmt::Display::fmt(&v.x, f)?;
+    f.write_str(", ")?;
+    fmt::Display::fmt(&v.y, f)?;
+    f.write_str(", ")?;
+    fmt::Display::fmt(&v.z, f)?;
+    f.write_str("]")
+}
+
+impl<N: RealField + FromStr> FromStr for Isometry<N> {
+    type Err = ParseIsometryError;
+
+    fn from_str(s: &str) -> Result<Self, ParseIsometryError> {
+        let s = expect(s, "translate")?;
+        let (distance, s) = parse_scalar::<N>(s)?;
+        let s = expect(s, "along")?;
+        let (direction, s) = parse_vector(s)?;
+        let s = expect(s, ",")?;
+        let s = expect(s, "rotate")?;
+        let (angle, s) = parse_scalar::<N>(s)?;
+        let s = expect(s, "about")?;
+        let (axis, s) = parse_vector(s)?;
+        if !s.trim().is_empty() {
+            return Err(ParseIsometryError);
+        }
+        Ok(Self::from_parts(
+            &parse_unit(direction, distance)?,
+            distance,
+            &na::UnitQuaternion::from_axis_angle(&parse_unit(axis, angle)?, angle),
+        ))
+    }
+}
+
+/// Normalize a direction, which may only be degenerate if the associated magnitude is zero
+fn parse_unit<N: RealField>(
+    v: na::Vector3<N>,
+    magnitude: N,
+) -> Result<na::Unit<na::Vector3<N>>, ParseIsometryError> {
+    match na::Unit::try_new(v, N::default_epsilon()) {
+        Some(x) => Ok(x),
+        None if magnitude == na::zero() => Ok(na::Vector3::x_axis()),
+        None => Err(ParseIsometryError),
+    }
+}
+
+fn expect<'a>(s: &'a str, prefix: &str) -> Result<&'a str, ParseIsometryError> {
+    let s = s.trim_start();
+    if s.starts_with(prefix) {
+        Ok(&s[prefix.len()..])
+    } else {
+        Err(ParseIsometryError)
+    }
+}
+
+fn parse_scalar<N: FromStr>(s: &str) -> Result<(N, &str), ParseIsometryError> {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ']')
+        .unwrap_or_else(|| s.len());
+    let x = s[..end].parse().map_err(|_| ParseIsometryError)?;
+    Ok((x, &s[end..]))
+}
+
+fn parse_vector<N: RealField + FromStr>(
+    s: &str,
+) -> Result<(na::Vector3<N>, &str), ParseIsometryError> {
+    let s = expect(s, "[")?;
+    let (x, s) = parse_scalar(s)?;
+    let s = expect(s, ",")?;
+    let (y, s) = parse_scalar(s)?;
+    let s = expect(s, ",")?;
+    let (z, s) = parse_scalar(s)?;
+    let s = expect(s, "]")?;
+    Ok((na::Vector3::new(x, y, z), s))
+}
+
+/// Error produced when parsing a malformed `Isometry`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ParseIsometryError;
+
+impl fmt::Display for ParseIsometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("malformed isometry")
+    }
+}
+
+impl std::error::Error for ParseIsometryError {}
+
+/// Rate of change of a pose, expressed in the frame of the moving entity
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Velocity<N: Scalar> {
+    /// Tangent vector at the entity's origin, in absolute units per second
+    pub linear: na::Vector3<N>,
+    /// Axis of rotation about the entity's origin, scaled by radians per second
+    pub angular: na::Vector3<N>,
+}
+
+impl<N: RealField> Velocity<N> {
+    pub fn new(linear: na::Vector3<N>, angular: na::Vector3<N>) -> Self {
+        Self { linear, angular }
+    }
+
+    pub fn zero() -> Self {
+        Self::new(na::zero(), na::zero())
+    }
+
+    /// Motion over `dt`, in the entity's frame
+    ///
+    /// Computed with the exponential map, so constant linear velocity traces a geodesic.
+    pub fn displacement(&self, dt: N) -> Isometry<N> {
+        Isometry(exp(&(self.generator() * dt)))
+    }
+
+    /// Pose after moving at this velocity for `dt`, starting from `pose`
+    pub fn integrate(&self, pose: &Isometry<N>, dt: N) -> Isometry<N> {
+        Isometry(renormalize_isometry(&(pose.0 * self.displacement(dt).0)))
+    }
+
+    /// Element of the Lie algebra of the Lorentz group corresponding to this velocity
+    #[rustfmt::skip]
+    fn generator(&self) -> na::Matrix4<N> {
+        let (v, w) = (&self.linear, &self.angular);
+        let zero = na::zero::<N>();
+        na::Matrix4::new(
+             zero, -w.z,  w.y, v.x,
+              w.z, zero, -w.x, v.y,
+             -w.y,  w.x, zero, v.z,
+              v.x,  v.y,  v.z, zero)
+    }
+}
+
+/// Matrix exponential, by scaling and squaring of a truncated Taylor series
+fn exp<N: RealField>(m: &na::Matrix4<N>) -> na::Matrix4<N> {
+    let half = na::convert::<_, N>(0.5);
+    let mut scaled = *m;
+    let mut squarings = 0;
+    while scaled.norm() > half && squarings < 64 {
+        scaled *= half;
+        squarings += 1;
+    }
+    let mut result = na::Matrix4::identity();
+    let mut term = na::Matrix4::identity();
+    for k in 1..=EXP_TERMS {
+        term = term * scaled / na::convert::<_, N>(k as f64);
+        result += term;
+    }
+    for _ in 0..squarings {
+        result = result * result;
+    }
+    result
+}
+
+/// Number of Taylor series terms used by `exp`, sufficient for double precision given a scaled
+/// argument of norm at most 1/2
+const EXP_TERMS: u32 = 14;
+
 /// Point reflection around `p`
 pub fn reflect<N: RealField>(p: &na::Vector4<N>) -> na::Matrix4<N> {
     na::Matrix4::<N>::identity()
@@ -47,16 +579,86 @@ pub fn translate<N: RealField>(a: &na::Vector4<N>, b: &na::Vector4<N>) -> na::Ma
     reflect(&midpoint(a, b)) * reflect(a)
 }
 
+/// Carry the tangent vector `v` at `from` along the geodesic to `to` without rotating it
+///
+/// Tangent vectors at a point `p` are expressed in the frame of `translate(&origin(), p)`, i.e.
+/// that of the origin carried to `p` along a geodesic.
+pub fn parallel_transport<N: RealField>(
+    v: &na::Vector3<N>,
+    from: &na::Vector4<N>,
+    to: &na::Vector4<N>,
+) -> na::Vector3<N> {
+    let transport = translate(to, &origin()) * translate(from, to) * translate(&origin(), from);
+    (transport * v.push(na::zero())).xyz()
+}
+
+/// Angle defect of the geodesic triangle `abc`, which is equal to its area
+pub fn triangle_defect<N: RealField>(
+    a: &na::Vector4<N>,
+    b: &na::Vector4<N>,
+    c: &na::Vector4<N>,
+) -> N {
+    N::pi() - angle_between(a, b, c) - angle_between(b, c, a) - angle_between(c, a, b)
+}
+
+/// Angle at `base` between the geodesics to `a` and `b`, i.e. the interior angle at `base` of the
+/// triangle `(base, a, b)`, in [0, π]
+///
+/// Zero if either `a` or `b` coincides with `base`, since no direction leads there.
+pub fn angle_between<N: RealField>(
+    base: &na::Vector4<N>,
+    a: &na::Vector4<N>,
+    b: &na::Vector4<N>,
+) -> N {
+    match (try_direction(base, a), try_direction(base, b)) {
+        (Some(u), Some(v)) => u.dot(&v).max(-na::one::<N>()).min(na::one()).acos(),
+        _ => na::zero(),
+    }
+}
+
+/// Unit tangent at `from` of the geodesic towards `to`, in the frame used by `parallel_transport`
+///
+/// Returns `None` if `to` is too near `from` for the direction to be meaningful.
+fn try_direction<N: RealField>(
+    from: &na::Vector4<N>,
+    to: &na::Vector4<N>,
+) -> Option<na::Vector3<N>> {
+    (translate(from, &origin()) * to)
+        .xyz()
+        .try_normalize(na::convert(1e-6))
+}
+
+/// Tangent vector at `base` of the geodesic to `p`, with length equal to their distance
+///
+/// Both points, and the result, are in Minkowski coordinates, and the points must be normalized.
+fn log_point<N: RealField>(base: &na::Vector4<N>, p: &na::Vector4<N>) -> na::Vector4<N> {
+    let tangent = p + base * mip(base, p);
+    // sinh of the distance, which unlike cosh remains precise for nearby points
+    let sinh = mip(&tangent, &tangent).max(na::zero()).sqrt();
+    if sinh == na::zero() {
+        return na::zero();
+    }
+    tangent * (sinh.asinh() / sinh)
+}
+
+/// Inverse of `log_point`: the point reached by following the geodesic from `base` along `v`
+fn exp_point<N: RealField>(base: &na::Vector4<N>, v: &na::Vector4<N>) -> na::Vector4<N> {
+    let length = mip(v, v).max(na::zero()).sqrt();
+    if length == na::zero() {
+        return *base;
+    }
+    base * length.cosh() + v * (length.sinh() / length)
+}
+
 #[rustfmt::skip]
 pub fn translate_along<N: RealField>(v: &na::Unit<na::Vector3<N>>, distance: N) -> na::Matrix4<N> {
     if distance == na::zero() {
         return na::Matrix4::identity();
     }
-    // g = Lorentz gamma factor
-    let g = distance.cosh();
     let one = na::one::<N>();
-    let gm = g - one;
-    let bg = distance.sinh();
+    let (gm, bg) = boost_factors(distance);
+    // g = Lorentz gamma factor
+    let g = one + gm;
     // TODO: Make this more elegant
     na::Matrix4::new(
         one + gm * v.x * v.x,       gm * v.x * v.y,       gm * v.x * v.z, bg * v.x,
@@ -65,6 +667,32 @@ pub fn translate_along<N: RealField>(v: &na::Unit<na::Vector3<N>>, distance: N)
               bg * v.x,                   bg * v.y,             bg * v.z,        g)
 }
 
+/// `cosh(distance) - 1` and `sinh(distance)`, from which `translate_along` is built
+///
+/// Small distances, such as a single step of movement, use truncated Taylor series. These are
+/// cheaper, and avoid the cancellation in `cosh(distance) - 1` that loses most of its precision
+/// near zero.
+fn boost_factors<N: RealField>(distance: N) -> (N, N) {
+    if distance.abs() >= na::convert(SERIES_THRESHOLD) {
+        return boost_factors_exact(distance);
+    }
+    let one = na::one::<N>();
+    let d2 = distance * distance;
+    let term = |x: f64| d2 / na::convert::<_, N>(x);
+    // d^2/2 + d^4/24 + d^6/720 and d + d^3/6 + d^5/120, in Horner form
+    let gm = term(2.0) * (one + term(12.0) * (one + term(30.0)));
+    let bg = distance * (one + term(6.0) * (one + term(20.0)));
+    (gm, bg)
+}
+
+fn boost_factors_exact<N: RealField>(distance: N) -> (N, N) {
+    (distance.cosh() - na::one(), distance.sinh())
+}
+
+/// Distance below which `boost_factors` uses series, whose first omitted terms are then beneath
+/// double precision
+const SERIES_THRESHOLD: f64 = 1e-2;
+
 /// 4D reflection around a normal vector; length is not significant (so long as it's nonzero)
 pub fn euclidean_reflect<N: RealField>(v: &na::Vector4<N>) -> na::Matrix4<N> {
     na::Matrix4::identity() - v * v.transpose() * (na::convert::<_, N>(2.0) / v.norm_squared())
@@ -78,6 +706,75 @@ pub fn distance<N: RealField>(a: &na::Vector4<N>, b: &na::Vector4<N>) -> N {
     (mip(a, b).powi(2) / (mip(a, a) * mip(b, b))).sqrt().acosh()
 }
 
+/// Like `distance`, but fails rather than producing NaN if either point isn't in hyperbolic space
+pub fn try_distance<N: RealField>(a: &na::Vector4<N>, b: &na::Vector4<N>) -> Result<N, MathError> {
+    check_point(a)?;
+    check_point(b)?;
+    // Rounding can push the cosh of tiny distances just below 1
+    Ok((mip(a, b).powi(2) / (mip(a, a) * mip(b, b)))
+        .sqrt()
+        .max(N::one())
+        .acosh())
+}
+
+/// Like `translate`, but fails rather than producing NaN if either point isn't in hyperbolic space
+pub fn try_translate<N: RealField>(
+    a: &na::Vector4<N>,
+    b: &na::Vector4<N>,
+) -> Result<na::Matrix4<N>, MathError> {
+    check_point(a)?;
+    check_point(b)?;
+    Ok(translate(a, b))
+}
+
+/// Ensure `p` is finite and lies within the upper light cone, i.e. represents a point of hyperbolic
+/// space in homogeneous coordinates
+/// `check_point`, and also require `p` to lie near the hyperboloid
+fn check_on_hyperboloid<N: RealField>(p: &na::Vector4<N>) -> Result<(), MathError> {
+    check_point(p)?;
+    // Rounding error in the inner product grows with the square of the coordinates
+    let tolerance = na::convert::<_, N>(1e-3) * p.w * p.w;
+    if (mip(p, p) + N::one()).abs() > tolerance {
+        return Err(MathError::OffHyperboloid);
+    }
+    Ok(())
+}
+
+fn check_point<N: RealField>(p: &na::Vector4<N>) -> Result<(), MathError> {
+    if !p.iter().all(|x| x.is_finite()) {
+        return Err(MathError::NonFinite);
+    }
+    if p.w <= na::zero() || mip(p, p) >= na::zero() {
+        return Err(MathError::NotTimelike);
+    }
+    Ok(())
+}
+
+/// A violated precondition of a hyperbolic computation, typically on untrusted input
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MathError {
+    /// A coordinate was NaN or infinite
+    NonFinite,
+    /// A point lay outside the upper light cone, so it's ideal, beyond infinity, or on the lower
+    /// sheet of the hyperboloid
+    NotTimelike,
+    /// A transform carried the origin off the hyperboloid, so it's not an isometry
+    OffHyperboloid,
+}
+
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MathError::*;
+        f.write_str(match *self {
+            NonFinite => "coordinates are not finite",
+            NotTimelike => "point is not in hyperbolic space",
+            OffHyperboloid => "transform doesn't preserve the hyperboloid",
+        })
+    }
+}
+
+impl std::error::Error for MathError {}
+
 pub fn origin<N: RealField>() -> na::Vector4<N> {
     na::Vector4::new(na::zero(), na::zero(), na::zero(), na::one())
 }
@@ -143,6 +840,7 @@ fn i31<N: RealField>() -> na::Matrix4<N> {
 mod tests {
     use super::*;
     use approx::*;
+    use rand::SeedableRng;
 
     #[test]
     #[rustfmt::skip]
@@ -239,6 +937,355 @@ mod tests {
         assert_abs_diff_eq!(renormalize_isometry(&mat), mat, epsilon = 1e-5);
     }
 
+    #[test]
+    fn isometry_round_trip() {
+        let cases = [
+            Isometry::identity(),
+            Isometry::from_parts(
+                &na::Vector3::x_axis(),
+                0.0,
+                &na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.2),
+            ),
+            Isometry::from_parts(
+                &na::Vector3::x_axis(),
+                0.0,
+                &na::UnitQuaternion::from_axis_angle(&-na::Vector3::z_axis(), 3.0),
+            ),
+            Isometry::from_parts(&na::Vector3::y_axis(), 2.5, &na::UnitQuaternion::identity()),
+            Isometry::from_parts(
+                &na::Unit::new_normalize(na::Vector3::new(1.0, -2.0, 0.5)),
+                0.7,
+                &na::UnitQuaternion::from_axis_angle(
+                    &na::Unit::new_normalize(na::Vector3::new(-0.3, 1.0, 1.0)),
+                    0.4,
+                ),
+            ),
+            Isometry::from_matrix_unchecked(translate(
+                &na::Vector4::new(-0.5, -0.5, 0.0, 1.0),
+                &na::Vector4::new(0.3, -0.7, 0.0, 1.0),
+            )),
+        ];
+        for x in &cases {
+            let text = x.to_string();
+            let parsed = text.parse::<Isometry<f64>>().unwrap();
+            assert_abs_diff_eq!(parsed.matrix(), x.matrix(), epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn isometry_decompositions() {
+        let cases = [
+            Isometry::identity(),
+            Isometry::from_parts(
+                &na::Vector3::x_axis(),
+                0.0,
+                &na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.2),
+            ),
+            Isometry::from_parts(&na::Vector3::y_axis(), 2.5, &na::UnitQuaternion::identity()),
+            Isometry::from_parts(
+                &na::Unit::new_normalize(na::Vector3::new(1.0, -2.0, 0.5)),
+                0.7,
+                &na::UnitQuaternion::from_axis_angle(
+                    &na::Unit::new_normalize(na::Vector3::new(-0.3, 1.0, 1.0)),
+                    0.4,
+                ),
+            ),
+        ];
+        for x in &cases {
+            let (rotation, after) = x.as_rotate_then_translate();
+            assert_abs_diff_eq!(
+                translate(&origin(), &after) * rotation.to_homogeneous(),
+                x.to_homogeneous(),
+                epsilon = 1e-9
+            );
+            let (before, rotation) = x.as_translate_then_rotate();
+            assert_abs_diff_eq!(
+                rotation.to_homogeneous() * translate(&origin(), &before),
+                x.to_homogeneous(),
+                epsilon = 1e-9
+            );
+
+            // Each form converts to the other by rotating the translation
+            let (rotation, after) = x.as_rotate_then_translate();
+            assert_abs_diff_eq!(rotation.to_homogeneous() * before, after, epsilon = 1e-9);
+            let flipped = Isometry::from_translate_then_rotate(&before, &rotation);
+            assert_abs_diff_eq!(flipped.matrix(), x.matrix(), epsilon = 1e-9);
+            let flipped = Isometry::from_rotate_then_translate(&rotation, &after);
+            assert_abs_diff_eq!(flipped.matrix(), x.matrix(), epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn isometry_parts() {
+        let translation = Isometry::from_parts(
+            &na::Unit::new_normalize(na::Vector3::new(1.0, -2.0, 0.5)),
+            0.7,
+            &na::UnitQuaternion::identity(),
+        );
+        let rotation = Isometry::from_parts(
+            &na::Vector3::x_axis(),
+            0.0,
+            &na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 1.2),
+        );
+        let x = translation * rotation;
+        let (translation_part, rotation_part) = (x.translation_part(), x.rotation_part());
+        assert_abs_diff_eq!(
+            translation_part.matrix(),
+            translation.matrix(),
+            epsilon = 1e-9
+        );
+        assert_abs_diff_eq!(rotation_part.matrix(), rotation.matrix(), epsilon = 1e-9);
+        let recomposed = translation_part * rotation_part;
+        assert_abs_diff_eq!(recomposed.matrix(), x.matrix(), epsilon = 1e-9);
+        // Composing in the other order moves the origin elsewhere
+        let swapped = rotation_part * translation_part;
+        assert!(((swapped * origin()) - (x * origin())).norm() > 0.1);
+
+        // Pure translations and rotations are their own parts
+        for &y in &[translation, rotation, Isometry::identity()] {
+            let recomposed = y.translation_part() * y.rotation_part();
+            assert_abs_diff_eq!(recomposed.matrix(), y.matrix(), epsilon = 1e-9);
+        }
+        let identity = Isometry::identity();
+        let rotation_of_translation = translation.rotation_part();
+        assert_abs_diff_eq!(
+            rotation_of_translation.matrix(),
+            identity.matrix(),
+            epsilon = 1e-9
+        );
+        let translation_of_rotation = rotation.translation_part();
+        assert_abs_diff_eq!(
+            translation_of_rotation.matrix(),
+            identity.matrix(),
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn small_translation_series() {
+        // Agrees with the exact computation where that's precise
+        for &d in &[9.99e-3, 5e-3, -1e-3] {
+            let (gm, bg) = boost_factors::<f64>(d);
+            let (gm_exact, bg_exact) = boost_factors_exact::<f64>(d);
+            assert!(((gm - gm_exact) / gm_exact).abs() < 1e-9);
+            assert!(((bg - bg_exact) / bg_exact).abs() < 1e-15);
+        }
+        // Larger distances are computed exactly
+        assert_eq!(boost_factors(0.5), boost_factors_exact(0.5));
+
+        // Near zero, the series is far more accurate in single precision
+        for &d in &[5e-3f32, 1e-3, 1e-4, 1e-5] {
+            let reference = 2.0 * (f64::from(d) / 2.0).sinh().powi(2);
+            let error = |x: f32| (f64::from(x) - reference).abs() / reference;
+            let (series, _) = boost_factors(d);
+            let (exact, _) = boost_factors_exact(d);
+            assert!(error(series) < 1e-6);
+            assert!(error(series) < error(exact));
+        }
+    }
+
+    #[test]
+    fn isometry_checked() {
+        let valid = translate_along(&na::Vector3::x_axis(), 2.0);
+        assert!(Isometry::try_from_matrix(valid).is_ok());
+        let mut zero = valid;
+        zero[(3, 3)] = 0.0;
+        assert_eq!(Isometry::try_from_matrix(zero), Err(MathError::NotTimelike));
+        // On the lower sheet of the hyperboloid
+        let negative = -valid;
+        assert_eq!(
+            Isometry::try_from_matrix(negative),
+            Err(MathError::NotTimelike)
+        );
+        // Off the hyperboloid entirely
+        let mut off = valid;
+        off[(3, 3)] *= 2.0;
+        assert_eq!(
+            Isometry::try_from_matrix(off),
+            Err(MathError::OffHyperboloid)
+        );
+        let mut nan = valid;
+        nan[(1, 2)] = std::f64::NAN;
+        assert_eq!(Isometry::try_from_matrix(nan), Err(MathError::NonFinite));
+    }
+
+    #[test]
+    fn isometry_from_parts_checked() {
+        let rotation = na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), 0.5);
+        let valid = translate_along(&na::Vector3::x_axis(), 2.0) * origin();
+        let x = Isometry::try_from_parts(&rotation, &valid).unwrap();
+        let expected = Isometry::from_parts(&na::Vector3::x_axis(), 2.0, &rotation);
+        assert_abs_diff_eq!(*x.matrix(), *expected.matrix(), epsilon = 1e-5);
+        let mut zero = valid;
+        zero.w = 0.0;
+        assert_eq!(Isometry::try_from_parts(&rotation, &zero), None);
+        // On the lower sheet of the hyperboloid
+        assert_eq!(Isometry::try_from_parts(&rotation, &-valid), None);
+        // Off the hyperboloid entirely
+        assert_eq!(Isometry::try_from_parts(&rotation, &(valid * 2.0)), None);
+    }
+
+    #[test]
+    fn checked_off_manifold() {
+        let p = na::Vector4::new(0.3, 0.0, 0.0, 1.0);
+        let q = na::Vector4::new(0.0, -0.5, 0.2, 1.0);
+        assert_abs_diff_eq!(try_distance(&p, &q).unwrap(), distance(&p, &q));
+        assert_abs_diff_eq!(try_translate(&p, &q).unwrap(), translate(&p, &q));
+        // Coincident points can't round to NaN
+        assert_eq!(try_distance(&p, &p), Ok(0.0));
+
+        let bad = [
+            // Ideal, on the light cone
+            (na::Vector4::new(1.0, 0.0, 0.0, 1.0), MathError::NotTimelike),
+            // Beyond infinity
+            (na::Vector4::new(2.0, 0.0, 0.0, 1.0), MathError::NotTimelike),
+            // Lower sheet
+            (
+                na::Vector4::new(0.0, 0.0, 0.0, -1.0),
+                MathError::NotTimelike,
+            ),
+            (na::Vector4::zeros(), MathError::NotTimelike),
+            (
+                na::Vector4::new(0.0, std::f64::NAN, 0.0, 1.0),
+                MathError::NonFinite,
+            ),
+            (
+                na::Vector4::new(0.0, 0.0, 0.0, std::f64::INFINITY),
+                MathError::NonFinite,
+            ),
+        ];
+        for &(x, error) in &bad {
+            assert_eq!(try_distance(&p, &x), Err(error));
+            assert_eq!(try_distance(&x, &p), Err(error));
+            assert_eq!(try_translate(&p, &x), Err(error));
+            assert!(!error.to_string().is_empty());
+        }
+    }
+
+    #[test]
+    fn isometry_format() {
+        let x = Isometry::<f64>::from_parts(
+            &na::Vector3::y_axis(),
+            1.5,
+            &na::UnitQuaternion::identity(),
+        );
+        assert_eq!(
+            format!("{:.1}", x),
+            "translate 1.5 along [0.0, 1.0, 0.0], rotate 0.0 about [1.0, 0.0, 0.0]"
+        );
+        assert_eq!(
+            "translate 1 along [0, 1, 0]".parse::<Isometry<f64>>(),
+            Err(ParseIsometryError)
+        );
+    }
+
+    #[test]
+    fn transport_along_geodesic() {
+        // Points along a geodesic that misses the origin
+        let line = translate_along(&na::Vector3::y_axis(), 0.7)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), 0.4).to_homogeneous();
+        let point = |t: f64| line * translate_along(&na::Vector3::x_axis(), t) * origin();
+        let (a, b, c) = (point(-0.5), point(0.3), point(1.2));
+        // The tangent to a geodesic remains tangent to it
+        let tangent = try_direction(&a, &b).unwrap();
+        assert_abs_diff_eq!(
+            parallel_transport(&tangent, &a, &b),
+            -try_direction(&b, &a).unwrap(),
+            epsilon = 1e-9
+        );
+        // Transport along a geodesic doesn't depend on intermediate points
+        let v = na::Vector3::new(0.2, -0.5, 0.9);
+        assert_abs_diff_eq!(
+            parallel_transport(&parallel_transport(&v, &a, &b), &b, &c),
+            parallel_transport(&v, &a, &c),
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn transport_holonomy() {
+        let a = origin();
+        let b = translate_along(&na::Vector3::x_axis(), 0.3) * origin();
+        let c = translate_along(
+            &na::Unit::new_normalize(na::Vector3::new(1.0, 1.5, 0.0)),
+            0.25,
+        ) * origin();
+        let defect = triangle_defect(&a, &b, &c);
+        assert!(defect > 0.0);
+        // A vector in the plane of the triangle is rotated by the defect
+        let v = na::Vector3::new(0.6, 0.8, 0.0);
+        let w = parallel_transport(&v, &a, &b);
+        let w = parallel_transport(&w, &b, &c);
+        let w = parallel_transport(&w, &c, &a);
+        assert_abs_diff_eq!(w.norm(), 1.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(v.angle(&w), defect, epsilon = 1e-9);
+        // A vector normal to the triangle is unaffected
+        let n = na::Vector3::z();
+        let m = parallel_transport(&parallel_transport(&n, &a, &b), &b, &c);
+        assert_abs_diff_eq!(parallel_transport(&m, &c, &a), n, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn angle_examples() {
+        let at = |direction: na::Vector3<f64>, distance| {
+            translate_along(&na::Unit::new_normalize(direction), distance) * origin()
+        };
+        let base = origin();
+        let x = at(na::Vector3::x(), 0.5);
+        let right = angle_between(&base, &x, &at(na::Vector3::y(), 2.0));
+        assert_abs_diff_eq!(right, std::f64::consts::FRAC_PI_2, epsilon = 1e-9);
+        let straight = angle_between(&base, &x, &at(-na::Vector3::x(), 1.0));
+        assert_abs_diff_eq!(straight, std::f64::consts::PI, epsilon = 1e-6);
+        let same = angle_between(&base, &x, &at(na::Vector3::x(), 3.0));
+        assert_abs_diff_eq!(same, 0.0, epsilon = 1e-6);
+
+        // Angles are unaffected by moving the whole configuration
+        let a = at(na::Vector3::new(1.0, 1.0, 0.0), 0.7);
+        let b = at(na::Vector3::new(-0.3, 1.0, 0.5), 1.2);
+        let expected = na::Vector3::new(1.0, 1.0, 0.0).angle(&na::Vector3::new(-0.3, 1.0, 0.5));
+        assert_abs_diff_eq!(angle_between(&base, &a, &b), expected, epsilon = 1e-9);
+        let motion = translate_along(&na::Vector3::z_axis(), 1.5)
+            * translate_along(&na::Vector3::x_axis(), -0.8);
+        assert_abs_diff_eq!(
+            angle_between(&(motion * base), &(motion * a), &(motion * b)),
+            expected,
+            epsilon = 1e-9
+        );
+
+        // Coincident points have no direction
+        assert_eq!(angle_between(&base, &base, &a), 0.0);
+        assert_eq!(angle_between(&a, &b, &a), 0.0);
+    }
+
+    #[test]
+    fn velocity_geodesic() {
+        let start = Isometry::from_parts(
+            &na::Vector3::z_axis(),
+            0.4,
+            &na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), 0.3),
+        );
+        let linear = na::Vector3::new(0.3, -0.2, 0.5);
+        let velocity = Velocity::new(linear, na::zero());
+        let mut pose = start;
+        for i in 1..=10 {
+            pose = velocity.integrate(&pose, 0.1);
+            let expected = start.matrix()
+                * translate_along(&na::Unit::new_normalize(linear), linear.norm() * 0.1 * i as f64);
+            assert_abs_diff_eq!(pose.matrix(), &expected, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn velocity_zero() {
+        let pose = Isometry::from_parts(
+            &na::Unit::new_normalize(na::Vector3::new(1.0, 2.0, 3.0)),
+            1.5,
+            &na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 2.0),
+        );
+        let moved = Velocity::zero().integrate(&pose, 10.0);
+        assert_abs_diff_eq!(moved.matrix(), pose.matrix(), epsilon = 1e-10);
+    }
+
     #[test]
     #[rustfmt::skip]
     fn renormalize_reflection() {
@@ -248,4 +1295,171 @@ mod tests {
                                    0.0, 0.0, 0.0, 1.0);
         assert_abs_diff_eq!(renormalize_isometry(&mat), mat, epsilon = 1e-5);
     }
+
+    #[test]
+    fn isometry_associativity() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for &max_distance in &[1.0, 5.0, 10.0] {
+            for _ in 0..100 {
+                let a = Isometry::<f64>::random(&mut rng, max_distance);
+                let b = Isometry::<f64>::random(&mut rng, max_distance);
+                let c = Isometry::<f64>::random(&mut rng, max_distance);
+                let error = ((a * b) * c).matrix() - (a * (b * c)).matrix();
+                // Each grouping rounds two products whose entries are sums of four terms. The
+                // factor leaves headroom over that, while any systematic error would grow with
+                // distance faster than the entries do and exceed it.
+                let bound = 256.0
+                    * f64::EPSILON
+                    * a.matrix().amax()
+                    * b.matrix().amax()
+                    * c.matrix().amax();
+                assert!(
+                    error.amax() <= bound,
+                    "{} > {} at distance {}",
+                    error.amax(),
+                    bound,
+                    max_distance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mean_of_one() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let pose = Isometry::<f64>::random(&mut rng, 3.0);
+        let (mean, iterations) = Isometry::mean_iterations(&[pose], &[2.0]);
+        assert_abs_diff_eq!(mean.matrix(), pose.matrix(), epsilon = 1e-9);
+        assert_eq!(iterations, 1);
+    }
+
+    #[test]
+    fn mean_of_reflection() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for _ in 0..10 {
+            let pose = Isometry::<f64>::random(&mut rng, 2.0);
+            let center = Isometry::<f64>::random(&mut rng, 2.0) * origin();
+            // Carry `pose` through `center` to the far side
+            let half = Isometry(translate(&(pose * origin()), &center));
+            let reflected = half * half * pose;
+            let mean = Isometry::mean(&[pose, reflected], &[1.0, 1.0]);
+            // Compared in the frame of the expected mean, since matrix entries, and with them
+            // rounding error, grow with distance from the origin. Refinement stops once steps fall
+            // below about 1e-8.
+            let offset = (half * pose).inverse() * mean;
+            assert_abs_diff_eq!(
+                offset.matrix(),
+                &na::Matrix4::identity(),
+                epsilon = 1e-6
+            );
+
+            // Unequal weights pull the mean along the geodesic
+            let mean = Isometry::mean(&[pose, reflected], &[3.0, 1.0]);
+            assert_abs_diff_eq!(
+                distance(&(pose * origin()), &(mean * origin())),
+                distance(&(pose * origin()), &center) / 2.0,
+                epsilon = 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn mean_converges() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for _ in 0..100 {
+            let base = Isometry::<f64>::random(&mut rng, 5.0);
+            let mut poses = Vec::new();
+            let mut weights = Vec::new();
+            for _ in 0..10 {
+                let offset = Isometry::random(&mut rng, 0.5);
+                let (direction, distance, rotation) = offset.to_parts();
+                // Keep orientations near each other, too
+                let rotation = na::UnitQuaternion::identity().slerp(&rotation, 0.1);
+                poses.push(base * Isometry::from_parts(&direction, distance, &rotation));
+                weights.push(rng.gen_range(0.1, 1.0));
+            }
+            let (mean, iterations) = Isometry::mean_iterations(&poses, &weights);
+            assert!(iterations <= 8, "took {} iterations", iterations);
+
+            // The mean position is stationary: the weighted tangents towards each position cancel
+            let center = lorentz_normalize(&(mean * origin()));
+            let residual = poses
+                .iter()
+                .zip(&weights)
+                .fold(na::Vector4::zeros(), |acc, (p, &w)| {
+                    acc + log_point(&center, &lorentz_normalize(&(*p * origin()))) * w
+                });
+            let residual = mip(&residual, &residual).sqrt();
+            assert!(residual < 1e-6, "residual {}", residual);
+            assert!(!parity(mean.matrix()));
+        }
+    }
+
+    #[test]
+    fn random_isometry() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for _ in 0..100 {
+            let x = Isometry::<f64>::random(&mut rng, 3.0);
+            // Preserves the Minkowski inner product, and hence the hyperboloid
+            assert_abs_diff_eq!(
+                x.matrix().transpose() * i31::<f64>() * x.matrix(),
+                i31::<f64>(),
+                epsilon = 1e-9
+            );
+            assert!(x.matrix().determinant() > 0.0);
+            assert!(distance(&origin(), &(x * origin())) <= 3.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn nlerp_error_bounded() {
+        let start =
+            Isometry::from_parts(&na::Vector3::y_axis(), 0.7, &na::UnitQuaternion::identity());
+        let axis = na::Unit::new_normalize(na::Vector3::new(1.0, 2.0, -0.5));
+        for &degrees in &[10.0, 30.0, 60.0, 90.0, 135.0, 179.0] {
+            let angle = f64::to_radians(degrees);
+            let end = start
+                * Isometry::from_parts(
+                    &na::Vector3::x_axis(),
+                    2.0,
+                    &na::UnitQuaternion::from_axis_angle(&axis, angle),
+                );
+            let mut worst = 0.0f64;
+            for i in 0..=100 {
+                let t = f64::from(i) / 100.0;
+                let exact = start.lerp_geodesic(&end, t, RotationLerp::Slerp);
+                let fast = start.lerp_geodesic(&end, t, RotationLerp::Nlerp);
+                // Same path through space
+                assert_abs_diff_eq!(exact * origin(), fast * origin(), epsilon = 1e-9);
+                let (_, _, exact) = exact.to_parts();
+                let (_, _, fast) = fast.to_parts();
+                worst = worst.max(exact.angle_to(&fast));
+            }
+            assert!(
+                worst <= angle.powi(3) / 200.0,
+                "nlerp strayed {} degrees over a {} degree turn",
+                worst.to_degrees(),
+                degrees
+            );
+            if degrees <= 60.0 {
+                assert!(worst.to_degrees() < 1.0 / 3.0);
+            }
+        }
+
+        // Endpoints are exact either way
+        let end = start
+            * Isometry::from_parts(
+                &na::Vector3::z_axis(),
+                1.0,
+                &na::UnitQuaternion::from_axis_angle(&axis, 2.0),
+            );
+        for &method in &[RotationLerp::Slerp, RotationLerp::Nlerp] {
+            let (first, last) = (
+                start.lerp_geodesic(&end, 0.0, method),
+                start.lerp_geodesic(&end, 1.0, method),
+            );
+            assert_abs_diff_eq!(first.matrix(), start.matrix(), epsilon = 1e-9);
+            assert_abs_diff_eq!(last.matrix(), end.matrix(), epsilon = 1e-9);
+        }
+    }
 }
@@ -0,0 +1,143 @@
+//! The hyperbolic plane, in the same hyperboloid model as the 3D helpers in the parent module
+//!
+//! Points are `Vector3`s with `z` as the time-like coordinate, and isometries are `Matrix3`s.
+
+use na::RealField;
+
+/// Point reflection around `p`
+pub fn reflect<N: RealField>(p: &na::Vector3<N>) -> na::Matrix3<N> {
+    na::Matrix3::<N>::identity()
+        - (*p * p.transpose() * i21::<N>()) * na::convert::<_, N>(2.0) / mip(&p, &p)
+}
+
+/// Transform that translates `a` to `b`
+pub fn translate<N: RealField>(a: &na::Vector3<N>, b: &na::Vector3<N>) -> na::Matrix3<N> {
+    reflect(&midpoint(a, b)) * reflect(a)
+}
+
+#[rustfmt::skip]
+pub fn translate_along<N: RealField>(v: &na::Unit<na::Vector2<N>>, distance: N) -> na::Matrix3<N> {
+    // g = Lorentz gamma factor
+    let g = distance.cosh();
+    let one = na::one::<N>();
+    let gm = g - one;
+    let bg = distance.sinh();
+    na::Matrix3::new(
+        one + gm * v.x * v.x,       gm * v.x * v.y, bg * v.x,
+              gm * v.y * v.x, one + gm * v.y * v.y, bg * v.y,
+                    bg * v.x,             bg * v.y,        g)
+}
+
+pub fn midpoint<N: RealField>(a: &na::Vector3<N>, b: &na::Vector3<N>) -> na::Vector3<N> {
+    a * (mip(b, b) * mip(a, b)).sqrt() + b * (mip(a, a) * mip(a, b)).sqrt()
+}
+
+pub fn distance<N: RealField>(a: &na::Vector3<N>, b: &na::Vector3<N>) -> N {
+    (mip(a, b).powi(2) / (mip(a, a) * mip(b, b))).sqrt().acosh()
+}
+
+pub fn origin<N: RealField>() -> na::Vector3<N> {
+    na::Vector3::new(na::zero(), na::zero(), na::one())
+}
+
+pub fn lorentz_normalize<N: RealField>(v: &na::Vector3<N>) -> na::Vector3<N> {
+    let sf2 = mip(v, v);
+    if sf2 == na::zero() {
+        return origin();
+    }
+    let sf = sf2.abs().sqrt();
+    v / sf
+}
+
+/// Minkowski inner product, aka <a, b>_h
+pub fn mip<N: RealField>(a: &na::Vector3<N>, b: &na::Vector3<N>) -> N {
+    a.x * b.x + a.y * b.y - a.z * b.z
+}
+
+#[rustfmt::skip]
+fn i21<N: RealField>() -> na::Matrix3<N> {
+    na::convert::<_, na::Matrix3<N>>(na::Matrix3::<f64>::new(
+        1.0, 0.0,  0.0,
+        0.0, 1.0,  0.0,
+        0.0, 0.0, -1.0
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    /// Interior angle at `p` of the triangle `pqr`
+    fn angle(p: &na::Vector3<f64>, q: &na::Vector3<f64>, r: &na::Vector3<f64>) -> f64 {
+        let to_origin = translate(p, &origin());
+        let u = (to_origin * q).xy().normalize();
+        let v = (to_origin * r).xy().normalize();
+        u.dot(&v).max(-1.0).min(1.0).acos()
+    }
+
+    /// Triangle with a vertex at the origin, where sides of length `a` and `b` meet at angle
+    /// `gamma`
+    fn triangle(a: f64, b: f64, gamma: f64) -> [na::Vector3<f64>; 3] {
+        let u = na::Vector2::x_axis();
+        let v = na::Unit::new_unchecked(na::Vector2::new(gamma.cos(), gamma.sin()));
+        [
+            origin(),
+            translate_along(&u, a) * origin(),
+            translate_along(&v, b) * origin(),
+        ]
+    }
+
+    const CASES: [(f64, f64, f64); 4] = [
+        (0.5, 0.7, 1.0),
+        (1.0, 1.0, 0.3),
+        (2.0, 0.1, 2.5),
+        (1.5, 3.0, 1.5),
+    ];
+
+    #[test]
+    fn translate_distance() {
+        let a = lorentz_normalize(&na::Vector3::new(0.5, -0.3, 1.0));
+        let b = lorentz_normalize(&na::Vector3::new(-0.2, 0.4, 1.0));
+        let m = translate(&a, &b);
+        assert_abs_diff_eq!(m * a, b, epsilon = 1e-10);
+        assert_abs_diff_eq!(mip(&(m * b), &(m * b)), -1.0, epsilon = 1e-10);
+        for &d in &[0.0, 0.5, 2.0] {
+            let p = translate_along(&na::Vector2::y_axis(), d) * origin();
+            assert_abs_diff_eq!(distance(&origin(), &p), d, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn law_of_cosines() {
+        for &(a, b, gamma) in &CASES {
+            let [o, p, q] = triangle(a, b, gamma);
+            assert_abs_diff_eq!(angle(&o, &p, &q), gamma, epsilon = 1e-10);
+            // cosh c = cosh a cosh b - sinh a sinh b cos gamma
+            let c = distance(&p, &q);
+            assert_abs_diff_eq!(
+                c.cosh(),
+                a.cosh() * b.cosh() - a.sinh() * b.sinh() * gamma.cos(),
+                epsilon = 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn dual_law_of_cosines() {
+        for &(a, b, gamma) in &CASES {
+            let [o, p, q] = triangle(a, b, gamma);
+            let c = distance(&p, &q);
+            let alpha = angle(&p, &q, &o);
+            let beta = angle(&q, &o, &p);
+            // cos gamma = -cos alpha cos beta + sin alpha sin beta cosh c
+            assert_abs_diff_eq!(
+                gamma.cos(),
+                -alpha.cos() * beta.cos() + alpha.sin() * beta.sin() * c.cosh(),
+                epsilon = 1e-9
+            );
+            // Angle defect is positive, as for any hyperbolic triangle
+            assert!(alpha + beta + gamma < std::f64::consts::PI);
+        }
+    }
+}
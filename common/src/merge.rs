@@ -0,0 +1,205 @@
+//! Reconciliation of copies of a world edited independently, e.g. offline or on another server
+//!
+//! Edits are found by diffing a copy against the snapshot it started from, then replayed onto
+//! another copy. Graphs populated in different orders assign different `NodeId`s, so chunks are
+//! identified by the path to their node from the root instead.
+
+use crate::dodeca::Vertex;
+use crate::graph::{GraphEvent, NodeId, NodePath};
+use crate::node::{Chunk, DualGraph, VoxelData};
+use crate::world::Material;
+
+/// A voxel that differs between two copies of a world
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub node: NodePath,
+    pub vertex: Vertex,
+    /// Index of the voxel in its chunk's data, including margins
+    pub index: usize,
+    pub before: Material,
+    pub after: Material,
+}
+
+/// A `Change` that couldn't be applied because the voxel no longer held what the change expected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub change: Change,
+    /// What the voxel holds instead, or `None` if its chunk isn't populated
+    pub current: Option<Material>,
+}
+
+/// Every voxel that differs between `base` and `edited`
+///
+/// Only chunks populated in both are compared. A chunk populated in just one has only been
+/// generated there, and generation is deterministic.
+pub fn diff(dimension: u8, base: &DualGraph, edited: &DualGraph) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let nodes = std::iter::once(NodeId::ROOT).chain(
+        edited
+            .tree()
+            .map(|(side, parent)| edited.neighbor(parent, side).unwrap()),
+    );
+    for node in nodes {
+        let path = edited.path(node);
+        let (base_node, edited_node) = match (
+            base.find_path(&path).and_then(|x| base.get(x).as_ref()),
+            edited.get(node).as_ref(),
+        ) {
+            (Some(x), Some(y)) => (x, y),
+            _ => continue,
+        };
+        for vertex in Vertex::iter() {
+            let (before, after) = match (
+                voxels(&base_node.chunks[vertex]),
+                voxels(&edited_node.chunks[vertex]),
+            ) {
+                (Some(x), Some(y)) => (x, y),
+                _ => continue,
+            };
+            if before == after {
+                continue;
+            }
+            for index in 0..(usize::from(dimension) + 2).pow(3) {
+                let (before, after) = (before.get(index), after.get(index));
+                if before != after {
+                    changes.push(Change {
+                        node: path.clone(),
+                        vertex,
+                        index,
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// Apply `changes` to `graph`, returning those that conflict with edits already made there
+///
+/// A change applies only where the voxel still holds what it did before the change, or already
+/// holds what the change leaves; conflicting voxels are left untouched for the caller to resolve.
+/// Changes to chunks not populated in `graph` also conflict, since there's nothing to apply them
+/// to.
+pub fn apply(dimension: u8, graph: &mut DualGraph, changes: &[Change]) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for change in changes {
+        let node = graph.find_path(&change.node);
+        let chunk = node
+            .and_then(|node| graph.get_mut(node).as_mut())
+            .map(|node| &mut node.chunks[change.vertex]);
+        let current = chunk
+            .as_ref()
+            .and_then(|x| voxels(x))
+            .map(|x| x.get(change.index));
+        match (chunk, current) {
+            (Some(chunk), Some(current)) if current == change.before => {
+                chunk.set_voxel(dimension, change.index, change.after);
+                graph.notify(GraphEvent::ChunkEdited(node.unwrap(), change.vertex));
+            }
+            (_, Some(current)) if current == change.after => {}
+            (_, current) => conflicts.push(Conflict {
+                change: change.clone(),
+                current,
+            }),
+        }
+    }
+    conflicts
+}
+
+fn voxels(chunk: &Chunk) -> Option<&VoxelData> {
+    match *chunk {
+        Chunk::Populated { ref voxels, .. } => Some(voxels),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dodeca::Side;
+    use crate::node::{voxel_index, Node};
+    use crate::worldgen::NodeState;
+
+    const DIMENSION: u8 = 4;
+
+    /// A world whose only chunk is the empty `Vertex::A` chunk of the node across `Side::D`
+    fn base() -> DualGraph {
+        let mut graph = DualGraph::new();
+        let node = graph.ensure_neighbor(NodeId::ROOT, Side::D);
+        let mut populated = Node {
+            state: NodeState::root(),
+            chunks: Default::default(),
+        };
+        populated.chunks[Vertex::A] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Void));
+        *graph.get_mut(node) = Some(populated);
+        graph
+    }
+
+    fn set(graph: &mut DualGraph, coords: [u8; 3], material: Material) {
+        let node = graph.find_path(&"D".parse().unwrap()).unwrap();
+        let chunk = &mut graph.get_mut(node).as_mut().unwrap().chunks[Vertex::A];
+        assert!(chunk.set_voxel(DIMENSION, voxel_index(DIMENSION, coords), material));
+    }
+
+    fn get(graph: &DualGraph, coords: [u8; 3]) -> Material {
+        let node = graph.find_path(&"D".parse().unwrap()).unwrap();
+        let chunk = &graph.get(node).as_ref().unwrap().chunks[Vertex::A];
+        voxels(chunk).unwrap().get(voxel_index(DIMENSION, coords))
+    }
+
+    #[test]
+    fn merge_divergent_edits() {
+        let base = base();
+
+        // Rebuilt in a different order, so that node IDs differ
+        let mut theirs = DualGraph::new();
+        for side in Side::iter() {
+            theirs.ensure_neighbor(NodeId::ROOT, side);
+        }
+        let node = theirs.find_path(&"D".parse().unwrap()).unwrap();
+        assert_ne!(Some(node), base.find_path(&"D".parse().unwrap()));
+        *theirs.get_mut(node) = base
+            .get(base.find_path(&"D".parse().unwrap()).unwrap())
+            .clone();
+        set(&mut theirs, [1, 1, 1], Material::Stone);
+        set(&mut theirs, [2, 2, 2], Material::Wood);
+        set(&mut theirs, [3, 3, 3], Material::Dirt);
+
+        let mut ours = base.snapshot();
+        set(&mut ours, [0, 0, 0], Material::Sand);
+        set(&mut ours, [2, 2, 2], Material::Stone);
+        set(&mut ours, [3, 3, 3], Material::Dirt);
+
+        let changes = diff(DIMENSION, &base, &theirs);
+        assert_eq!(changes.len(), 3);
+        assert!(changes.iter().all(|x| x.before == Material::Void));
+        let conflicts = apply(DIMENSION, &mut ours, &changes);
+
+        // Edits to distinct voxels merge cleanly, as do identical edits to the same voxel
+        assert_eq!(get(&ours, [0, 0, 0]), Material::Sand);
+        assert_eq!(get(&ours, [1, 1, 1]), Material::Stone);
+        assert_eq!(get(&ours, [3, 3, 3]), Material::Dirt);
+        // Differing edits to the same voxel don't
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                change: Change {
+                    node: "D".parse().unwrap(),
+                    vertex: Vertex::A,
+                    index: voxel_index(DIMENSION, [2, 2, 2]),
+                    before: Material::Void,
+                    after: Material::Wood,
+                },
+                current: Some(Material::Stone),
+            }]
+        );
+        assert_eq!(get(&ours, [2, 2, 2]), Material::Stone);
+
+        // Nothing to apply to
+        let conflicts = apply(DIMENSION, &mut DualGraph::new(), &changes);
+        assert_eq!(conflicts.len(), 3);
+        assert!(conflicts.iter().all(|x| x.current.is_none()));
+    }
+}
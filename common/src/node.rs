@@ -1,13 +1,256 @@
 /*the name of this module is pretty arbitrary at the moment*/
 
-use crate::graph::Graph;
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+#[cfg(feature = "graphics")]
+use crate::chunk::{Pyramid, VoxelView};
+#[cfg(feature = "graphics")]
+use crate::light::LightField;
+use crate::dodeca::Vertex;
+use crate::graph::{Graph, NodeId, NodePath};
+#[cfg(feature = "graphics")]
 use crate::lru_slab::SlotId;
-use crate::world::Material;
+use crate::math;
+#[cfg(feature = "graphics")]
+use crate::world::VoxelShape;
+use crate::world::{Material, SolidMaterial};
 use crate::worldgen::NodeState;
 use crate::Chunks;
 
 pub type DualGraph = Graph<Node>;
 
+/// Compute the state of every node added to the graph since the last `clear_fresh`
+///
+/// Nodes which have already been populated are left intact.
+pub fn populate_fresh_nodes(graph: &mut DualGraph) {
+    for node in graph.fresh().to_vec() {
+        if graph.get(node).is_none() {
+            populate_node(graph, node);
+        }
+    }
+}
+
+/// Generate the world in `graph` from `seed`
+///
+/// Must be called before the root is populated, as every other node's state derives from it.
+pub fn seed_root(graph: &mut DualGraph, seed: u64) {
+    *graph.get_mut(NodeId::ROOT) = Some(Node {
+        state: NodeState::seeded_root(seed),
+        chunks: Chunks::default(),
+    });
+}
+
+fn populate_node(graph: &mut DualGraph, node: NodeId) {
+    *graph.get_mut(node) = Some(Node {
+        state: graph
+            .parent(node)
+            .and_then(|i| {
+                let parent_state = &graph.get(graph.neighbor(node, i)?).as_ref()?.state;
+                Some(parent_state.child(graph, node, i))
+            })
+            .unwrap_or_else(NodeState::root),
+        chunks: Chunks::default(),
+    });
+}
+
+/// Identifies a chunk by the node it belongs to and the vertex of that node it surrounds
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ChunkId {
+    pub node: NodeId,
+    pub vertex: Vertex,
+}
+
+impl ChunkId {
+    pub fn new(node: NodeId, vertex: Vertex) -> Self {
+        Self { node, vertex }
+    }
+}
+
+/// Locate the voxel containing `point`, given in the coordinates of the node that contains it
+///
+/// Returns the chunk and the index of the voxel in that chunk's data, including margins.
+pub fn locate_voxel(dimension: u8, point: &na::Vector4<f64>) -> Option<(Vertex, usize)> {
+    let (vertex, coords) = chunk_voxel(dimension, point)?;
+    Some((vertex, voxel_index(dimension, coords)))
+}
+
+/// Index of the voxel at `coords` in a chunk's data, including margins
+pub fn voxel_index(dimension: u8, coords: [u8; 3]) -> usize {
+    // Length (of cube sides) with margins
+    let lwm = usize::from(dimension) + 2;
+    let margin = |x: u8| usize::from(x) + 1;
+    let [x, y, z] = coords;
+    margin(x) + margin(y) * lwm + margin(z) * lwm.pow(2)
+}
+
+/// Coordinates of the voxel at `index` in a chunk's data, unless it's in the margins
+pub fn voxel_coords(dimension: u8, index: usize) -> Option<[u8; 3]> {
+    let lwm = usize::from(dimension) + 2;
+    let coord = |x: usize| {
+        let x = x % lwm;
+        if x == 0 || x == lwm - 1 {
+            // Margins duplicate voxels of neighboring chunks
+            return None;
+        }
+        Some(x as u8 - 1)
+    };
+    if index >= lwm.pow(3) {
+        return None;
+    }
+    Some([
+        coord(index)?,
+        coord(index / lwm)?,
+        coord(index / lwm.pow(2))?,
+    ])
+}
+
+/// Distance in chunk coordinates within which a point is treated as lying exactly on a boundary
+///
+/// Keeps rounding error in transforms from deciding which side of a boundary a point falls on.
+const BOUNDARY_EPSILON: f64 = 1e-9;
+
+/// Find the chunk containing `point`, given in the coordinates of the node that contains it
+///
+/// Returns the chunk and the position of `point` within it, with each coordinate in [0, 1]. A
+/// point on the boundary between chunks of the same node belongs to whichever comes first in
+/// `Vertex::iter`.
+pub fn chunk_coords(point: &na::Vector4<f64>) -> Option<(Vertex, na::Vector3<f64>)> {
+    for vertex in Vertex::iter() {
+        let p = vertex.node_to_chunk() * point;
+        let p = p.xyz() / p.w;
+        let outside = |x: f64| x < -BOUNDARY_EPSILON || x > 1.0 + BOUNDARY_EPSILON;
+        if p.iter().any(|&x| outside(x)) {
+            continue;
+        }
+        return Some((vertex, p.map(|x| x.max(0.0).min(1.0))));
+    }
+    None
+}
+
+/// Find the chunk containing `point`, given in the coordinates of `node`, and the position of
+/// `point` within it
+///
+/// Every point resolves to exactly one chunk, however it's expressed. A point on a face shared by
+/// several nodes belongs to the one nearest the root, which is unique; within that node, ties are
+/// broken as in `chunk_coords`. Returns `None` if `point` lies in a node that isn't in `graph`.
+pub fn canonical_chunk<N>(
+    graph: &Graph<N>,
+    node: NodeId,
+    point: &na::Vector4<f64>,
+) -> Option<(ChunkId, na::Vector3<f64>)> {
+    let (mut node, transform) =
+        graph.normalize_transform(node, &math::translate(&math::origin(), point));
+    let mut point = transform * point;
+    loop {
+        // Fails if normalization was cut short by a missing neighbor, leaving `point` outside
+        let (vertex, coords) = chunk_coords(&point)?;
+        // A coordinate of 1 puts `point` on the corresponding face of the node
+        let nearer = vertex
+            .canonical_sides()
+            .iter()
+            .zip(coords.iter())
+            .filter(|&(_, &x)| x >= 1.0 - BOUNDARY_EPSILON)
+            .filter_map(|(&side, _)| Some((side, graph.neighbor(node, side)?)))
+            .find(|&(_, neighbor)| graph.length(neighbor) < graph.length(node));
+        match nearer {
+            None => return Some((ChunkId::new(node, vertex), coords)),
+            Some((side, neighbor)) => {
+                node = neighbor;
+                point = side.reflection() * point;
+            }
+        }
+    }
+}
+
+/// Locate the voxel containing `point`, given in the coordinates of `node`
+///
+/// Boundary points resolve as in `canonical_chunk`, and then to the voxel on their positive side,
+/// except on the outer faces of a chunk where they belong to the outermost voxel. Returns `None`
+/// if `point` lies in a node that isn't in `graph`.
+pub fn world_to_voxel<N>(
+    graph: &Graph<N>,
+    dimension: u8,
+    node: NodeId,
+    point: &na::Vector4<f64>,
+) -> Option<(ChunkId, [u8; 3])> {
+    let (chunk, coords) = canonical_chunk(graph, node, point)?;
+    Some((chunk, voxel_at(dimension, &coords)))
+}
+
+/// Coordinates of the voxel at `coords`, given in chunk coordinates
+///
+/// Each voxel contains its lower boundaries, except that the upper faces of the chunk belong to
+/// the outermost voxels.
+pub fn voxel_at(dimension: u8, coords: &na::Vector3<f64>) -> [u8; 3] {
+    let scale = f64::from(dimension);
+    let v = coords.map(|x| {
+        let x = x * scale;
+        let nearest = x.round();
+        let x = if (x - nearest).abs() < BOUNDARY_EPSILON * scale {
+            nearest
+        } else {
+            x.floor()
+        };
+        x.max(0.0).min(scale - 1.0) as u8
+    });
+    [v.x, v.y, v.z]
+}
+
+/// Where something is, in terms meaningful to a person looking for it
+///
+/// Formatted as e.g. `node DGA, chunk B, voxel (3, 7, 11)`, where the node is identified by its
+/// path from the root.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    pub path: NodePath,
+    pub chunk: Vertex,
+    pub voxel: [u8; 3],
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.0.is_empty() {
+            f.write_str("node root")?;
+        } else {
+            write!(f, "node {}", self.path)?;
+        }
+        let [x, y, z] = self.voxel;
+        write!(f, ", chunk {:?}, voxel ({}, {}, {})", self.chunk, x, y, z)
+    }
+}
+
+/// Locate the voxel something with pose `transform` in the coordinates of `node` is standing in
+///
+/// Returns `None` under the same conditions as `world_to_voxel`.
+pub fn locate<N>(
+    graph: &Graph<N>,
+    dimension: u8,
+    node: NodeId,
+    transform: &math::Isometry<f64>,
+) -> Option<Location> {
+    let (chunk, voxel) = world_to_voxel(graph, dimension, node, &(*transform * math::origin()))?;
+    Some(Location {
+        path: graph.path(chunk.node),
+        chunk: chunk.vertex,
+        voxel,
+    })
+}
+
+/// Center of the voxel at `coords` in `chunk`, in the coordinates of `chunk.node`
+pub fn voxel_to_world_center(dimension: u8, chunk: ChunkId, coords: [u8; 3]) -> na::Vector4<f64> {
+    let scale = f64::from(dimension);
+    let p = na::Vector3::from(coords).map(|x| (f64::from(x) + 0.5) / scale);
+    math::lorentz_normalize(&(chunk.vertex.chunk_to_node() * p.push(1.0)))
+}
+
+/// Find the chunk and voxel coordinates of `point`, given in the coordinates of the node that
+/// contains it
+fn chunk_voxel(dimension: u8, point: &na::Vector4<f64>) -> Option<(Vertex, [u8; 3])> {
+    let (vertex, p) = chunk_coords(point)?;
+    Some((vertex, voxel_at(dimension, &p)))
+}
+
+#[derive(Clone)]
 pub struct Node {
     pub state: NodeState,
     /// We can only populate chunks which lie within a cube of populated nodes, so nodes on the edge
@@ -15,12 +258,20 @@ pub struct Node {
     pub chunks: Chunks<Chunk>,
 }
 
+#[derive(Clone)]
 pub enum Chunk {
     Fresh,
     Generating,
     Populated {
         voxels: VoxelData,
+        #[cfg(feature = "graphics")]
         surface: Option<SlotId>,
+        /// Downsampled copies of `voxels`, which `set_voxel` keeps up to date
+        #[cfg(feature = "graphics")]
+        lod: Pyramid,
+        /// Light level of each voxel, which `set_voxel` keeps up to date
+        #[cfg(feature = "graphics")]
+        light: LightField,
     },
 }
 
@@ -30,16 +281,194 @@ impl Default for Chunk {
     }
 }
 
-#[derive(PartialEq)]
+impl Chunk {
+    /// A chunk made of `voxels`, not yet drawn
+    #[cfg_attr(not(feature = "graphics"), allow(unused_variables))]
+    pub fn populated(dimension: u8, voxels: VoxelData) -> Self {
+        Chunk::Populated {
+            #[cfg(feature = "graphics")]
+            lod: Pyramid::new(dimension, &voxels),
+            #[cfg(feature = "graphics")]
+            light: LightField::compute(dimension, &voxels),
+            voxels,
+            #[cfg(feature = "graphics")]
+            surface: None,
+        }
+    }
+
+    /// Change the voxel at `index`, including margins, keeping any downsampled copies and light in
+    /// sync
+    ///
+    /// Returns `false` if the chunk isn't populated.
+    pub fn set_voxel(&mut self, dimension: u8, index: usize, material: Material) -> bool {
+        match *self {
+            Chunk::Populated {
+                ref mut voxels,
+                #[cfg(feature = "graphics")]
+                ref mut lod,
+                #[cfg(feature = "graphics")]
+                ref mut light,
+                ..
+            } => {
+                voxels.data_mut(dimension)[index] = material;
+                // Margins only duplicate neighboring chunks, so aren't downsampled, and light
+                // doesn't spread across chunk boundaries
+                #[cfg(feature = "graphics")]
+                {
+                    if let Some(coords) = voxel_coords(dimension, index) {
+                        lod.update(voxels, coords);
+                        // Surfaces are extracted again whole whenever a chunk's voxels change, so
+                        // which voxels' light changed doesn't matter here
+                        light.update(voxels, coords);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The chunk's voxels at `level` of detail, halving resolution at each level
+    ///
+    /// Returns `None` if the chunk isn't populated, or `level` exceeds the single-voxel top level.
+    #[cfg(feature = "graphics")]
+    pub fn lod(&self, level: u32) -> Option<VoxelView<'_>> {
+        match *self {
+            Chunk::Populated {
+                ref voxels,
+                ref lod,
+                ..
+            } => lod.level(voxels, level),
+            _ => None,
+        }
+    }
+
+    /// Light level of each of the chunk's voxels, or `None` if it isn't populated
+    #[cfg(feature = "graphics")]
+    pub fn light(&self) -> Option<&LightField> {
+        match *self {
+            Chunk::Populated { ref light, .. } => Some(light),
+            _ => None,
+        }
+    }
+
+    /// Faces of this chunk's voxels that are open to the air, i.e. those that would be drawn
+    ///
+    /// `neighbor` supplies the material of voxels just outside the chunk, given coordinates of
+    /// which exactly one is -1 or `dimension`. Chunks that aren't populated have no faces.
+    #[cfg(feature = "graphics")]
+    pub fn exposed_faces(
+        &self,
+        dimension: u8,
+        neighbor: impl Fn([i16; 3]) -> Material,
+    ) -> impl Iterator<Item = ([u8; 3], VoxelFace)> {
+        let mut faces = Vec::new();
+        let voxels = match *self {
+            Chunk::Populated { ref voxels, .. } => voxels,
+            _ => return faces.into_iter(),
+        };
+        let dim = i16::from(dimension);
+        let material = |coords: [i16; 3]| {
+            if coords.iter().all(|&x| x >= 0 && x < dim) {
+                let [x, y, z] = coords;
+                voxels.get(voxel_index(dimension, [x as u8, y as u8, z as u8]))
+            } else {
+                neighbor(coords)
+            }
+        };
+        for z in 0..dimension {
+            for y in 0..dimension {
+                for x in 0..dimension {
+                    let coords = [x, y, z];
+                    let mat = material([x.into(), y.into(), z.into()]);
+                    if mat.is_void() {
+                        continue;
+                    }
+                    for face in VoxelFace::iter() {
+                        let mut adjacent = [i16::from(x), i16::from(y), i16::from(z)];
+                        adjacent[face.axis()] += if face.is_positive() { 1 } else { -1 };
+                        if face_visible(mat, material(adjacent), face.axis(), face.is_positive()) {
+                            faces.push((coords, face));
+                        }
+                    }
+                }
+            }
+        }
+        faces.into_iter()
+    }
+}
+
+/// One of the six faces of a voxel, identified by the direction it faces in chunk coordinates
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum VoxelFace {
+    NegX,
+    PosX,
+    NegY,
+    PosY,
+    NegZ,
+    PosZ,
+}
+
+impl VoxelFace {
+    pub fn iter() -> impl ExactSizeIterator<Item = Self> {
+        use VoxelFace::*;
+        [NegX, PosX, NegY, PosY, NegZ, PosZ].iter().cloned()
+    }
+
+    /// The axis the face is perpendicular to
+    pub fn axis(self) -> usize {
+        self as usize / 2
+    }
+
+    /// Whether the face looks towards increasing coordinates along its axis
+    pub fn is_positive(self) -> bool {
+        self as usize % 2 == 1
+    }
+}
+
+/// Whether the face of a voxel made of `mat` that adjoins a voxel made of `other` along `axis` can
+/// be seen, where `lower` indicates that the `mat` voxel lies on the negative side of the boundary
+///
+/// Must agree with `face_visible` in `extract.comp`.
+#[cfg(feature = "graphics")]
+fn face_visible(mat: Material, other: Material, axis: usize, lower: bool) -> bool {
+    if mat.is_void() {
+        return false;
+    }
+    if other.is_void() {
+        return true;
+    }
+    let slab = mat.shape() == VoxelShape::Slab;
+    let other_slab = other.shape() == VoxelShape::Slab;
+    if axis == 1 {
+        // The top of a slab lies inside its own voxel, out of reach of its neighbor
+        if slab && lower {
+            return true;
+        }
+        // Otherwise the face spans the boundary, which a full voxel or the bottom of a slab covers
+        return other_slab && !lower;
+    }
+    // The side of a slab only covers the lower half of the boundary
+    other_slab && !slab
+}
+
+/// Cloning shares dense data until either copy is modified
+#[derive(Clone, PartialEq)]
 pub enum VoxelData {
     Solid(Material),
-    Dense(Box<[Material]>),
+    Dense(Arc<[Material]>),
 }
 
 impl VoxelData {
     pub fn data_mut(&mut self, dimension: u8) -> &mut [Material] {
         match *self {
-            VoxelData::Dense(ref mut d) => d,
+            VoxelData::Dense(ref mut d) => {
+                if Arc::get_mut(d).is_none() {
+                    // Shared with a clone; detach before writing
+                    *d = d.to_vec().into();
+                }
+                Arc::get_mut(d).unwrap()
+            }
             VoxelData::Solid(mat) => {
                 *self = VoxelData::Dense(vec![mat; (usize::from(dimension) + 2).pow(3)].into());
                 self.data_mut(dimension)
@@ -47,10 +476,321 @@ impl VoxelData {
         }
     }
 
+    /// Fill in a single voxel
+    ///
+    /// Only solid materials may be placed; carving out space is a distinct operation.
+    ///
+    /// ```
+    /// # use common::{node::{voxel_index, VoxelData}, world::{Material, SolidMaterial}};
+    /// let mut voxels = VoxelData::Solid(Material::Void);
+    /// let index = voxel_index(12, [1, 2, 3]);
+    /// let material = SolidMaterial::new(Material::Stone).unwrap();
+    /// voxels.place(12, index, material);
+    /// assert_eq!(voxels.get(index), Material::Stone);
+    /// ```
+    ///
+    /// A plain `Material`, which might be void, isn't accepted:
+    ///
+    /// ```compile_fail
+    /// # use common::{node::{voxel_index, VoxelData}, world::{Material, SolidMaterial}};
+    /// let mut voxels = VoxelData::Solid(Material::Void);
+    /// let index = voxel_index(12, [1, 2, 3]);
+    /// let material = Material::Void;
+    /// voxels.place(12, index, material);
+    /// assert_eq!(voxels.get(index), Material::Stone);
+    /// ```
+    pub fn place(&mut self, dimension: u8, index: usize, material: SolidMaterial) {
+        self.data_mut(dimension)[index] = material.into();
+    }
+
     pub fn get(&self, index: usize) -> Material {
         match *self {
             VoxelData::Dense(ref d) => d[index],
             VoxelData::Solid(mat) => mat,
         }
     }
+
+    /// Bytes allocated to store the voxels, beyond the size of `VoxelData` itself
+    pub fn heap_size(&self) -> usize {
+        match *self {
+            VoxelData::Dense(ref d) => d.len() * std::mem::size_of::<Material>(),
+            VoxelData::Solid(_) => 0,
+        }
+    }
+}
+
+/// Opaque state attached to individual voxels of a chunk, such as a sign's text, keyed by voxel
+/// coordinates
+pub type VoxelMetadata = BTreeMap<[u8; 3], Vec<u8>>;
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::dodeca::Side;
+
+    const DIMENSION: u8 = 12;
+
+    /// Point at `p` in the coordinates of `vertex`'s chunk, scaled to [0..1]^3
+    fn chunk_point(vertex: Vertex, p: na::Vector3<f64>) -> na::Vector4<f64> {
+        math::lorentz_normalize(&(vertex.chunk_to_node() * p.push(1.0)))
+    }
+
+    #[test]
+    fn voxel_center_round_trip() {
+        let mut graph = Graph::<()>::new();
+        let neighbor = graph.ensure_neighbor(NodeId::ROOT, Side::C);
+        for vertex in Vertex::iter() {
+            for &coords in &[[0, 0, 0], [3, 7, 11], [11, 11, 11], [5, 0, 9]] {
+                let chunk = ChunkId::new(NodeId::ROOT, vertex);
+                let center = voxel_to_world_center(DIMENSION, chunk, coords);
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &center),
+                    Some((chunk, coords))
+                );
+                // Identified from a neighboring node
+                let chunk = ChunkId::new(neighbor, vertex);
+                let center = voxel_to_world_center(DIMENSION, chunk, coords);
+                let center = Side::C.reflection() * center;
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &center),
+                    Some((chunk, coords))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn voxel_coords_round_trip() {
+        let lwm = usize::from(DIMENSION) + 2;
+        let mut interior = 0;
+        for index in 0..lwm.pow(3) {
+            if let Some(coords) = voxel_coords(DIMENSION, index) {
+                assert_eq!(voxel_index(DIMENSION, coords), index);
+                interior += 1;
+            }
+        }
+        assert_eq!(interior, usize::from(DIMENSION).pow(3));
+        assert_eq!(voxel_coords(DIMENSION, lwm.pow(3)), None);
+    }
+
+    #[test]
+    fn voxel_boundaries() {
+        let graph = Graph::<()>::new();
+        let scale = f64::from(DIMENSION);
+        let epsilon = 1e-6;
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            for boundary in 1..DIMENSION {
+                let x = f64::from(boundary) / scale;
+                let below = chunk_point(vertex, na::Vector3::new(x - epsilon, 0.5, 0.5));
+                let above = chunk_point(vertex, na::Vector3::new(0.5, x + epsilon, 0.5));
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &below),
+                    Some((chunk, [boundary - 1, 6, 6]))
+                );
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &above),
+                    Some((chunk, [6, boundary, 6]))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exact_boundaries() {
+        let graph = Graph::<()>::new();
+        let scale = f64::from(DIMENSION);
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            // Each voxel contains its lower boundaries, and the outermost voxels the chunk's faces
+            for boundary in 1..=DIMENSION {
+                let x = f64::from(boundary) / scale;
+                let expected = boundary.min(DIMENSION - 1);
+                let point = chunk_point(vertex, na::Vector3::new(x, 0.5, x));
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &point),
+                    Some((chunk, [expected, 6, expected]))
+                );
+            }
+        }
+        // Every chunk meets at the center of the node, which goes to the first
+        assert_eq!(
+            world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &math::origin()),
+            Some((ChunkId::new(NodeId::ROOT, Vertex::A), [0, 0, 0]))
+        );
+    }
+
+    #[test]
+    fn node_boundaries() {
+        let mut graph = Graph::<()>::new();
+        for vertex in Vertex::iter() {
+            // The corner of the chunk lies on a vertex shared by 8 nodes, of which the root is
+            // nearest the root
+            let corner = chunk_point(vertex, na::Vector3::new(1.0, 1.0, 1.0));
+            let expected = Some((
+                ChunkId::new(NodeId::ROOT, vertex),
+                [DIMENSION - 1, DIMENSION - 1, DIMENSION - 1],
+            ));
+            for (_, path) in vertex.dual_vertices() {
+                let mut node = NodeId::ROOT;
+                let mut point = corner;
+                for side in path {
+                    node = graph.ensure_neighbor(node, side);
+                    point = side.reflection() * point;
+                }
+                assert_eq!(
+                    world_to_voxel(&graph, DIMENSION, node, &point),
+                    expected,
+                    "from {:?}",
+                    graph.path(node)
+                );
+            }
+        }
+
+        // Points on a face resolve to the same voxel from either side, and despite rounding error
+        let neighbor = graph.ensure_neighbor(NodeId::ROOT, Side::C);
+        let face =
+            math::lorentz_normalize(&(math::origin() + Side::C.reflection() * math::origin()));
+        let expected = world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &face);
+        assert_eq!(expected.unwrap().0.node, NodeId::ROOT);
+        for &offset in &[-1e-12, 0.0, 1e-12] {
+            let jitter = na::Vector4::new(offset, offset, offset, 0.0);
+            let point = math::lorentz_normalize(&(face + jitter));
+            assert_eq!(
+                world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &point),
+                expected
+            );
+            assert_eq!(
+                world_to_voxel(&graph, DIMENSION, neighbor, &(Side::C.reflection() * point)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn missing_node() {
+        let graph = Graph::<()>::new();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let point = Side::A.reflection() * voxel_to_world_center(DIMENSION, chunk, [1, 2, 3]);
+        assert_eq!(world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &point), None);
+    }
+
+    #[test]
+    fn describe_location() {
+        let mut graph = Graph::<()>::new();
+        let neighbor = graph.ensure_neighbor(NodeId::ROOT, Side::C);
+        let center =
+            voxel_to_world_center(DIMENSION, ChunkId::new(neighbor, Vertex::B), [3, 7, 11]);
+        let pose = math::Isometry::from_matrix_unchecked(math::translate(
+            &math::origin(),
+            &(Side::C.reflection() * center),
+        ));
+        let location = locate(&graph, DIMENSION, NodeId::ROOT, &pose).unwrap();
+        assert_eq!(
+            location,
+            Location {
+                path: "C".parse().unwrap(),
+                chunk: Vertex::B,
+                voxel: [3, 7, 11],
+            }
+        );
+        assert_eq!(location.to_string(), "node C, chunk B, voxel (3, 7, 11)");
+
+        let center =
+            voxel_to_world_center(DIMENSION, ChunkId::new(NodeId::ROOT, Vertex::A), [0; 3]);
+        let pose = math::Isometry::from_matrix_unchecked(math::translate(&math::origin(), &center));
+        let location = locate(&graph, DIMENSION, NodeId::ROOT, &pose).unwrap();
+        assert_eq!(location.to_string(), "node root, chunk A, voxel (0, 0, 0)");
+    }
+
+    fn voxels(graph: &DualGraph, vertex: Vertex) -> &VoxelData {
+        match graph.get(NodeId::ROOT).as_ref().unwrap().chunks[vertex] {
+            Chunk::Populated { ref voxels, .. } => voxels,
+            _ => panic!("chunk not populated"),
+        }
+    }
+
+    #[test]
+    fn snapshot_copy_on_write() {
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        for &vertex in &[Vertex::A, Vertex::B] {
+            let mut voxels = VoxelData::Solid(Material::Void);
+            voxels.data_mut(DIMENSION);
+            graph.get_mut(NodeId::ROOT).as_mut().unwrap().chunks[vertex] =
+                Chunk::populated(DIMENSION, voxels);
+        }
+
+        let mut snapshot = graph.snapshot();
+        let index = voxel_index(DIMENSION, [1, 2, 3]);
+        if let Chunk::Populated { ref mut voxels, .. } =
+            snapshot.get_mut(NodeId::ROOT).as_mut().unwrap().chunks[Vertex::A]
+        {
+            let stone = SolidMaterial::try_from(Material::Stone).unwrap();
+            voxels.place(DIMENSION, index, stone);
+        }
+        assert_eq!(voxels(&snapshot, Vertex::A).get(index), Material::Stone);
+        assert_eq!(voxels(&graph, Vertex::A).get(index), Material::Void);
+
+        // Untouched chunks are shared rather than copied
+        match (voxels(&graph, Vertex::B), voxels(&snapshot, Vertex::B)) {
+            (VoxelData::Dense(a), VoxelData::Dense(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+        match (voxels(&graph, Vertex::A), voxels(&snapshot, Vertex::A)) {
+            (VoxelData::Dense(a), VoxelData::Dense(b)) => assert!(!Arc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "graphics")]
+    fn exposed_faces() {
+        use VoxelFace::*;
+
+        const DIMENSION: u8 = 4;
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let mut place = |coords, material| {
+            let material = SolidMaterial::try_from(material).unwrap();
+            voxels.place(DIMENSION, voxel_index(DIMENSION, coords), material);
+        };
+        // Two stones against the -X boundary, with a slab on top of one
+        place([0, 0, 0], Material::Stone);
+        place([1, 0, 0], Material::Stone);
+        place([1, 1, 0], Material::GreyBrickSlab);
+        let chunk = Chunk::populated(DIMENSION, voxels);
+        // A wall lies beyond the -X boundary, and air everywhere else
+        let neighbor = |coords: [i16; 3]| {
+            if coords[0] < 0 {
+                Material::Stone
+            } else {
+                Material::Void
+            }
+        };
+
+        let mut faces = chunk.exposed_faces(DIMENSION, neighbor).collect::<Vec<_>>();
+        faces.sort_unstable();
+        let mut expected = vec![
+            ([0, 0, 0], NegY),
+            ([0, 0, 0], PosY),
+            ([0, 0, 0], NegZ),
+            ([0, 0, 0], PosZ),
+            // The slab's bottom covers the top of the stone beneath it
+            ([1, 0, 0], PosX),
+            ([1, 0, 0], NegY),
+            ([1, 0, 0], NegZ),
+            ([1, 0, 0], PosZ),
+            ([1, 1, 0], NegX),
+            ([1, 1, 0], PosX),
+            ([1, 1, 0], PosY),
+            ([1, 1, 0], NegZ),
+            ([1, 1, 0], PosZ),
+        ];
+        expected.sort_unstable();
+        assert_eq!(faces, expected);
+
+        assert_eq!(Chunk::Fresh.exposed_faces(DIMENSION, neighbor).count(), 0);
+    }
 }
@@ -0,0 +1,51 @@
+//! Deterministic data parallelism over a fixed number of threads
+
+use std::{panic, sync::Arc, thread};
+
+/// Apply `f` to each of `items` on up to `threads` threads, preserving order
+///
+/// Items are divided into contiguous runs, one per thread, and the runs' results concatenated, so
+/// the output is identical to `items.into_iter().map(f).collect()` for any `threads`. A `threads`
+/// of 0 or 1 does all the work on the calling thread.
+pub fn map<T, U, F>(items: Vec<T>, threads: usize, f: F) -> Vec<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: Fn(T) -> U + Send + Sync + 'static,
+{
+    if threads <= 1 || items.len() <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+    let run = (items.len() + threads - 1) / threads;
+    let f = Arc::new(f);
+    let mut items = items.into_iter();
+    let mut workers = Vec::with_capacity(threads);
+    loop {
+        let batch = items.by_ref().take(run).collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        let f = f.clone();
+        workers.push(thread::spawn(move || {
+            batch.into_iter().map(|x| f(x)).collect::<Vec<_>>()
+        }));
+    }
+    workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_preserved() {
+        let items = (0..100u32).collect::<Vec<_>>();
+        let expected = items.iter().map(|x| x * x).collect::<Vec<_>>();
+        for &threads in &[0, 1, 3, 8, 200] {
+            assert_eq!(map(items.clone(), threads, |x| x * x), expected);
+        }
+    }
+}
@@ -1,10 +1,17 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
-use crate::{dodeca, graph::NodeId, EntityId, Step};
+use crate::{
+    dodeca, graph::NodeId, inventory::Inventory, math, node::VoxelMetadata, reliable,
+    world::Material, worldgen, EntityId, Step, WorldId,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientHello {
     pub name: String,
+    /// World to join, for the rest of the connection
+    pub world: WorldId,
+    /// Secret proving the player holds the server's account named `name`, if they have one
+    pub token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,14 +24,52 @@ pub struct ServerHello {
     pub movement_speed: f32,
     /// Unit conversion factor
     pub meters_to_absolute: f32,
+    /// Greatest distance in absolute units at which a character can edit voxels
+    pub max_reach: f32,
+    /// Seed the joined world is generated from
+    pub seed: u64,
+    /// Style of terrain the joined world is generated with
+    pub worldgen_preset: worldgen::Preset,
+    /// If the joined world runs in lockstep, the first tick the client must send a `Command` for
+    pub lockstep: Option<TickId>,
+}
+
+/// The parameters from `ServerHello` that can change while a client is connected
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub struct Reconfigure {
+    pub rate: u16,
+    /// Maximum movement speed in absolute units
+    pub movement_speed: f32,
+    /// Greatest distance in absolute units at which a character can edit voxels
+    pub max_reach: f32,
+}
+
+/// Sent as the reason when the server closes a connection because it has no room for more players
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub struct ServerFull {
+    pub max_players: u32,
+}
+
+impl ServerFull {
+    /// Application error code of connections closed for this reason
+    pub const CLOSE_CODE: u32 = 2;
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub struct Position {
     pub node: NodeId,
+    #[serde(deserialize_with = "deserialize_transform")]
     pub local: na::Matrix4<f32>,
 }
 
+/// Reject transforms that can't place anything sensibly, rather than trusting the peer
+fn deserialize_transform<'de, D: Deserializer<'de>>(d: D) -> Result<na::Matrix4<f32>, D::Error> {
+    let m = na::Matrix4::<f32>::deserialize(d)?;
+    math::Isometry::try_from_matrix(m)
+        .map(|x| x.to_homogeneous())
+        .map_err(D::Error::custom)
+}
+
 impl Position {
     pub fn origin() -> Self {
         Self {
@@ -41,6 +86,19 @@ pub struct StateDelta {
     pub latest_input: u16,
     pub positions: Vec<(EntityId, Position)>,
     pub character_orientations: Vec<(EntityId, na::UnitQuaternion<f32>)>,
+    /// Fraction of the way through the day/night cycle, per `daylight::time_of_day`
+    pub time_of_day: f32,
+    /// Reply to the recipient's most recent `Ping`, if not yet answered
+    pub pong: Option<Pong>,
+    /// `state_hash` of the recipient's view of the world as of `step`, with its character having
+    /// moved per inputs up to `latest_input`, sent periodically to detect divergence
+    pub hash: Option<u64>,
+    /// The recipient's inventory, if changed since it was last sent
+    pub inventory: Option<Inventory>,
+    /// Outcomes of the recipient's `AdminCommand`s received since the last `StateDelta`
+    pub admin_replies: Vec<AdminReply>,
+    /// The recipient's `VoxelEdit`s refused since the last `StateDelta`
+    pub edit_rejections: Vec<EditRejected>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,23 +107,227 @@ pub struct Spawns {
     pub spawns: Vec<(EntityId, Vec<Component>)>,
     pub despawns: Vec<EntityId>,
     pub nodes: Vec<FreshNode>,
+    /// Previously existing entities that have come within the recipient's view distance
+    pub enters: Vec<(EntityId, Vec<Component>)>,
+    /// Entities that have left the recipient's view distance without being despawned
+    pub leaves: Vec<EntityId>,
+    /// Projectiles that struck something, and have been despawned
+    pub hits: Vec<Hit>,
+    /// Chunks whose voxels have changed, each to be applied as a whole
+    pub voxels: Vec<ChunkDiff>,
+    /// Chunks whose voxel metadata has changed
+    pub metadata: Vec<ChunkMetadata>,
+    /// New values of the parameters from `ServerHello` that can change at runtime, if changed
+    pub reconfigure: Option<Reconfigure>,
+    /// Whether the recipient should forget all entities before applying this message, in response
+    /// to `Command::resync`
+    pub resync: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Command {
     pub generation: u16,
     pub orientation: na::UnitQuaternion<f32>,
     /// Relative to the character's current position, excluding orientation
     pub velocity: na::Vector3<f32>,
+    /// Request that the server resend every entity the client should know about
+    pub resync: bool,
+    /// Request for timing information, answered in a later `StateDelta`
+    pub ping: Option<Ping>,
+    /// Administrative action to perform, answered in a later `StateDelta`
+    pub admin: Option<AdminCommand>,
+    /// Voxel to change, reported in a later `StateDelta` if refused
+    pub edit: Option<VoxelEdit>,
+    /// Launch a projectile from the character along its line of sight
+    pub fire: bool,
+    /// In a world running in lockstep, the tick this command is the client's input for
+    ///
+    /// A lockstep world doesn't advance past a tick until every client's input for it has
+    /// arrived, so clients must send one command for each tick in turn, starting from
+    /// `ServerHello::lockstep`.
+    pub tick: Option<TickId>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Command {
+    /// Whether the server must act on this command, rather than only on the latest like it
+    ///
+    /// Sent as a critical message per `reliable` if so, and as an update otherwise.
+    pub fn is_critical(&self) -> bool {
+        self.resync
+            || self.admin.is_some()
+            || self.edit.is_some()
+            || self.fire
+            // A world running in lockstep waits for every tick's input
+            || self.tick.is_some()
+    }
+}
+
+/// What a client sends each tick, carrying its `Command`
+pub type ClientPacket = reliable::Packet<Command, Command>;
+
+/// What the server sends each tick, carrying the recipient's `StateDelta`
+///
+/// Messages the server can't afford to lose are sent as `Spawns` over a single ordered stream
+/// instead, so this is only an update, alongside acknowledgements of the client's commands.
+pub type ServerPacket = reliable::Packet<(), StateDelta>;
+
+/// A client's end of the exchange of `ClientPacket`s and `ServerPacket`s
+pub type ClientChannel = reliable::Channel<Command, Command, (), StateDelta>;
+
+/// The server's end of the exchange of `ClientPacket`s and `ServerPacket`s
+pub type ServerChannel = reliable::Channel<(), StateDelta, Command, Command>;
+
+/// Identifies a step of a world running in lockstep, counting from zero when the world is created
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct TickId(pub Step);
+
+impl TickId {
+    pub fn next(self) -> Self {
+        TickId(self.0 + 1)
+    }
+}
+
+/// Authority a connection has over the server, in increasing order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Player,
+    Moderator,
+    Admin,
+}
+
+impl Default for Permission {
+    fn default() -> Self {
+        Permission::Player
+    }
+}
+
+/// An action affecting other players or the world as a whole
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminCommand {
+    /// Disconnect the player named `name`
+    Kick { name: String },
+    /// Disconnect the player named `name` and refuse them if they return, recognizing them by
+    /// their account or, lacking one, their address
+    Ban { name: String },
+    /// Move the player named `name` to the center of the node at `path`, e.g. `DGA`
+    Teleport { name: String, path: String },
+    /// Skip to `time_of_day`, per `daylight::time_of_day`
+    SetTime { time_of_day: f32 },
+    /// Replace the terrain of the node at `path` with a flat floor and clear air, permanently
+    Flatten { path: String },
+    /// Allow only holders of the server accounts named in `members` to edit voxels in the node at
+    /// `path`
+    Protect { path: String, members: Vec<String> },
+    /// Attach `text`, e.g. a sign's inscription, to the solid voxel at `coords` of the chunk
+    /// around `chunk` of the node at `path`
+    Inscribe {
+        path: String,
+        chunk: dodeca::Vertex,
+        coords: [u8; 3],
+        text: String,
+    },
+    /// Report what's attached to the voxel at `coords` of the chunk around `chunk` of the node at
+    /// `path`
+    Inspect {
+        path: String,
+        chunk: dodeca::Vertex,
+        coords: [u8; 3],
+    },
+}
+
+impl AdminCommand {
+    /// Least permission a connection must have to perform this command
+    pub fn permission(&self) -> Permission {
+        match *self {
+            AdminCommand::Kick { .. }
+            | AdminCommand::Teleport { .. }
+            | AdminCommand::Inspect { .. } => Permission::Moderator,
+            AdminCommand::Ban { .. }
+            | AdminCommand::SetTime { .. }
+            | AdminCommand::Flatten { .. }
+            | AdminCommand::Protect { .. }
+            | AdminCommand::Inscribe { .. } => Permission::Admin,
+        }
+    }
+}
+
+/// The outcome of an `AdminCommand`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AdminReply {
+    /// `Command::generation` of the command that carried the request
+    pub generation: u16,
+    /// A description of what was done, or why nothing was
+    pub result: Result<String, String>,
+}
+
+/// A change to a single voxel, made by a player's character
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VoxelEdit {
+    pub node: NodeId,
+    pub chunk: dodeca::Vertex,
+    /// Index of the voxel in the chunk's data, including margins
+    pub index: u32,
+    /// Material to place from the character's inventory, or `Material::Void` to break the voxel
+    /// and collect its material
+    pub material: Material,
+}
+
+/// A `VoxelEdit` the server refused
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EditRejected {
+    /// `Command::generation` of the command that carried the edit
+    pub generation: u16,
+    pub reason: EditRejection,
+}
+
+/// Why a `VoxelEdit` was refused
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EditRejection {
+    /// The voxel lies in a chunk's margins, or in a chunk that can't be generated yet
+    Unavailable,
+    /// The voxel is already solid, or already empty
+    Unchanged,
+    /// The voxel is too far from the character, or hidden from them by something solid
+    OutOfReach,
+    /// The character holds none of the material to place
+    NoMaterial,
+    /// The voxel lies in a protected region the player isn't a member of
+    Protected,
+    /// The player is editing more quickly than the server allows
+    TooFast,
+}
+
+/// A request for the server's clock, used to measure round-trip time and clock offset
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Ping {
+    /// Microseconds on the client's clock when the ping was sent
+    pub client_time: u64,
+}
+
+/// The server's answer to a `Ping`
+///
+/// Times are in microseconds on the respective clocks. The server's time between receipt and reply
+/// is excluded from round-trip time, and the offset between the clocks is estimated by assuming
+/// that the trip each way takes equally long.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Pong {
+    /// `Ping::client_time` of the ping being answered
+    pub client_time: u64,
+    /// Server time when the ping arrived
+    pub received: u64,
+    /// Server time when this reply was sent
+    pub sent: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Component {
     Character(Character),
     Position(Position),
+    Projectile(Projectile),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub struct FreshNode {
     /// The side joining the new node to `parent`
     pub side: dodeca::Side,
@@ -77,3 +339,55 @@ pub struct Character {
     pub name: String,
     pub orientation: na::UnitQuaternion<f32>,
 }
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct Projectile {
+    /// Direction of travel, relative to the projectile's position
+    pub direction: na::Unit<na::Vector3<f32>>,
+    /// Absolute units per second
+    pub speed: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct Hit {
+    pub projectile: EntityId,
+    /// Where the projectile came to rest
+    pub position: Position,
+    pub target: HitTarget,
+}
+
+/// Voxels changed in a single chunk, to be applied atomically
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChunkDiff {
+    pub node: NodeId,
+    pub chunk: dodeca::Vertex,
+    pub changes: VoxelChanges,
+}
+
+/// Every piece of voxel metadata in a single chunk, replacing any sent before
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChunkMetadata {
+    pub node: NodeId,
+    pub chunk: dodeca::Vertex,
+    pub metadata: VoxelMetadata,
+}
+
+/// New contents for some or all of a chunk's voxels, per `voxel_diff`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum VoxelChanges {
+    /// Individual voxels, each identified by its index in the chunk's data, including margins
+    Sparse(Vec<(u32, Material)>),
+    /// The chunk's entire data, including margins, as runs of identical voxels in index order
+    Whole(Vec<(u32, Material)>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum HitTarget {
+    /// A voxel, identified by its index in the data of its chunk, including margins
+    Voxel {
+        node: NodeId,
+        chunk: dodeca::Vertex,
+        index: u32,
+    },
+    Entity(EntityId),
+}
@@ -0,0 +1,220 @@
+//! Reliability over a transport that may lose, duplicate, or reorder packets
+//!
+//! Messages are either critical, such as edits and spawns, which must all arrive exactly once and
+//! in order, or updates, such as positions, where only the latest matters. Everything sent during a
+//! tick is batched into a single `Packet`. Each packet carries every critical message the peer
+//! hasn't yet acknowledged, so losses are repaired by the next packet that gets through, while an
+//! update is sent only once and discarded on arrival if a later one has already been seen.
+//!
+//! Clients send a packet over a fresh QUIC stream each tick. Streams are reliable individually, but
+//! complete in no particular order, so without this a command that arrived behind a later one
+//! would be discarded as stale along with any edit it carried. The server's packets carry its
+//! acknowledgements alongside each `StateDelta`.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    marker::PhantomData,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Everything sent by one endpoint in a single tick
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Packet<C, U> {
+    /// Increases by one with each packet sent, ordering updates
+    pub sequence: u64,
+    /// Number of critical messages received in order from the peer, acknowledging all of them
+    pub ack: u64,
+    /// Identifier of the first message in `critical`, all of which follow it consecutively
+    pub first_critical: u64,
+    pub critical: Vec<C>,
+    pub update: Option<U>,
+}
+
+/// One end of a connection, sending critical messages of type `C` and updates of type `U`, and
+/// receiving the peer's of types `PC` and `PU`, which are the same unless the peer sends different
+/// kinds of messages
+pub struct Channel<C, U, PC = C, PU = U> {
+    /// Sequence number of the next packet to send
+    next_sequence: u64,
+    /// Identifier of the oldest critical message not yet acknowledged by the peer
+    first_unacked: u64,
+    /// Critical messages not yet acknowledged by the peer, oldest first
+    unacked: VecDeque<C>,
+    /// Most recent update queued since the last flush
+    update: Option<U>,
+    /// Identifier of the next critical message to deliver from the peer
+    next_delivery: u64,
+    /// Critical messages from the peer received ahead of an earlier one that hasn't arrived
+    early: BTreeMap<u64, PC>,
+    /// Highest sequence number received from the peer
+    latest_sequence: Option<u64>,
+    /// Updates from the peer are returned on arrival, never stored
+    peer_update: PhantomData<fn() -> PU>,
+}
+
+impl<C: Clone, U, PC, PU> Channel<C, U, PC, PU> {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            first_unacked: 0,
+            unacked: VecDeque::new(),
+            update: None,
+            next_delivery: 0,
+            early: BTreeMap::new(),
+            latest_sequence: None,
+            peer_update: PhantomData,
+        }
+    }
+
+    /// Queue `msg` to be delivered reliably and in order
+    pub fn send(&mut self, msg: C) {
+        self.unacked.push_back(msg);
+    }
+
+    /// Queue `msg` to be delivered at most once, superseding any update queued since the last flush
+    pub fn send_update(&mut self, msg: U) {
+        self.update = Some(msg);
+    }
+
+    /// Number of critical messages sent but not yet acknowledged
+    pub fn unacked(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Batch everything queued this tick into a packet, to be sent once per tick
+    ///
+    /// Critical messages are included in every packet until acknowledged, so this should be
+    /// called every tick while any are outstanding, even if nothing new was queued.
+    pub fn flush(&mut self) -> Packet<C, U> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        Packet {
+            sequence,
+            ack: self.next_delivery,
+            first_critical: self.first_unacked,
+            critical: self.unacked.iter().cloned().collect(),
+            update: self.update.take(),
+        }
+    }
+
+    /// Process a packet from the peer
+    ///
+    /// Returns newly deliverable critical messages in the order they were sent, and the packet's
+    /// update unless a later packet's has already been returned.
+    pub fn receive(&mut self, packet: Packet<PC, PU>) -> (Vec<PC>, Option<PU>) {
+        // Forget everything the peer has acknowledged
+        while self.first_unacked < packet.ack && !self.unacked.is_empty() {
+            self.unacked.pop_front();
+            self.first_unacked += 1;
+        }
+
+        for (id, msg) in (packet.first_critical..).zip(packet.critical) {
+            if id >= self.next_delivery {
+                self.early.entry(id).or_insert(msg);
+            }
+        }
+        let mut delivered = Vec::new();
+        while let Some(msg) = self.early.remove(&self.next_delivery) {
+            delivered.push(msg);
+            self.next_delivery += 1;
+        }
+
+        let sequence = packet.sequence;
+        let update = if self.latest_sequence.map_or(true, |x| sequence > x) {
+            self.latest_sequence = Some(sequence);
+            packet.update
+        } else {
+            None
+        };
+        (delivered, update)
+    }
+}
+
+impl<C: Clone, U, PC, PU> Default for Channel<C, U, PC, PU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministically lose roughly a third of packets
+    fn lost(n: u64) -> bool {
+        n.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 62 == 0 || n % 5 == 3
+    }
+
+    #[test]
+    fn lossy_delivery() {
+        let mut server = Channel::<u32, u64>::new();
+        let mut client = Channel::<u32, u64>::new();
+        let mut delivered = Vec::new();
+        let mut updates = Vec::new();
+        // Packets held back to arrive late, simulating reordering
+        let mut delayed = Vec::new();
+        let mut n = 0;
+        for tick in 0..200u64 {
+            if tick < 100 && tick % 3 == 0 {
+                server.send(tick as u32);
+            }
+            // Several updates in one tick are collapsed into the latest
+            server.send_update(tick * 10);
+            server.send_update(tick * 10 + 1);
+
+            let packet = server.flush();
+            n += 1;
+            if tick % 7 == 0 {
+                delayed.push(packet);
+            } else if !lost(n) {
+                let (critical, update) = client.receive(packet);
+                delivered.extend(critical);
+                updates.extend(update);
+            }
+            if tick % 11 == 0 {
+                for packet in delayed.drain(..) {
+                    let (critical, update) = client.receive(packet);
+                    delivered.extend(critical);
+                    updates.extend(update);
+                }
+            }
+
+            n += 1;
+            let ack = client.flush();
+            if !lost(n) {
+                let (critical, update) = server.receive(ack);
+                assert!(critical.is_empty());
+                assert!(update.is_none());
+            }
+        }
+
+        // Every critical message arrived exactly once, in order
+        assert_eq!(delivered, (0..100).step_by(3).collect::<Vec<_>>());
+        assert_eq!(server.unacked(), 0);
+        // Updates only ever advance, so late ones were dropped, and some were lost outright
+        assert!(updates.windows(2).all(|w| w[0] < w[1]));
+        assert!(updates.iter().all(|x| x % 10 == 1));
+        assert!(updates.len() < 200);
+        assert_eq!(*updates.last().unwrap(), 1991);
+    }
+
+    #[test]
+    fn duplicates_ignored() {
+        let mut a = Channel::<&str, ()>::new();
+        let mut b = Channel::<&str, ()>::new();
+        a.send("place");
+        a.send("break");
+        let packet = a.flush();
+        assert_eq!(b.receive(packet.clone()).0, ["place", "break"]);
+        assert!(b.receive(packet).0.is_empty());
+        // Until acknowledged, messages are resent
+        a.send("spawn");
+        let packet = a.flush();
+        assert_eq!(packet.critical, ["place", "break", "spawn"]);
+        assert_eq!(b.receive(packet).0, ["spawn"]);
+        a.receive(b.flush());
+        assert_eq!(a.unacked(), 0);
+        assert!(a.flush().critical.is_empty());
+    }
+}
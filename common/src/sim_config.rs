@@ -1,13 +1,23 @@
-use std::time::Duration;
+use std::{fmt, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{dodeca, math};
+use crate::{dodeca, math, worldgen};
+
+/// Version of the `SimConfigRaw` schema understood by this build
+pub const SIM_CONFIG_VERSION: u32 = 2;
+
+/// Upgrades from each version of the `SimConfigRaw` schema to the next, starting from version 1
+const MIGRATIONS: &[fn(&mut SimConfigRaw)] = &[v1_to_v2];
 
 /// Manually specified simulation config parameters
+///
+/// Configs written for an older schema must be brought up to date with `migrate` before use.
 #[derive(Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
 pub struct SimConfigRaw {
+    /// Schema the config was written for. Configs that predate versioning are version 1.
+    pub version: Option<u32>,
     pub rate: Option<u16>,
     /// Maximum distance at which anything can be seen in meters
     pub view_distance: Option<f32>,
@@ -24,8 +34,76 @@ pub struct SimConfigRaw {
     pub voxel_size: Option<f32>,
     /// Character movement speed in m/s
     pub movement_speed: Option<f32>,
+    /// Radius of the capsule characters collide with each other as, in meters
+    pub character_radius: Option<f32>,
+    /// Total height of the character capsule in meters
+    pub character_height: Option<f32>,
+    /// Rate at which friction slows a grounded character that isn't walking, in m/s^2
+    pub ground_friction: Option<f32>,
+    /// Steepest slope a character can stand on, in degrees
+    pub max_slope: Option<f32>,
+    /// Acceleration of a character sliding down a vertical surface, in m/s^2
+    ///
+    /// Shallower slopes scale this by the sine of their angle.
+    pub slide_acceleration: Option<f32>,
+    /// Farthest a character can reach to place or break a voxel, in meters from the voxel's center
+    pub max_reach: Option<f32>,
+    /// Most chunks generated around each character per step, nearest first, so that players
+    /// arriving in unexplored regions share generation fairly with everyone else
+    pub worldgen_chunks_per_player: Option<u32>,
+    /// Length of a full day/night cycle in seconds, or 0 for perpetual daylight
+    pub day_length_secs: Option<f32>,
+    /// Style of terrain to generate
+    pub worldgen_preset: Option<worldgen::Preset>,
+    /// Whether players can collide with each other and be struck by projectiles. When disabled,
+    /// players pass through each other and projectiles pass through players, though both still
+    /// collide with terrain. Defaults to enabled.
+    pub pvp_enabled: Option<bool>,
+}
+
+impl SimConfigRaw {
+    /// Upgrade a config written for any supported version of the schema to the current one
+    ///
+    /// Fails if the config was written for a newer version.
+    pub fn migrate(mut self) -> Result<Self, ConfigVersionError> {
+        let version = self.version.unwrap_or(1);
+        if version == 0 || version > SIM_CONFIG_VERSION {
+            return Err(ConfigVersionError::Unsupported(version));
+        }
+        for migration in &MIGRATIONS[version as usize - 1..] {
+            migration(&mut self);
+        }
+        self.version = Some(SIM_CONFIG_VERSION);
+        Ok(self)
+    }
+}
+
+/// Version 1, from before versioning, had only `rate`, `view_distance`, `input_queue_size_ms`,
+/// `chunk_size`, `voxel_size`, and `movement_speed`, which version 2 kept as they were. The fields
+/// version 2 added are absent from version 1 configs, so take their defaults.
+fn v1_to_v2(_: &mut SimConfigRaw) {}
+
+/// Error produced when a config can't be brought up to date with the current schema
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConfigVersionError {
+    /// Written for a version this build doesn't know about
+    Unsupported(u32),
+}
+
+impl fmt::Display for ConfigVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigVersionError::Unsupported(version) => write!(
+                f,
+                "config version {} is not supported; the latest is {}",
+                version, SIM_CONFIG_VERSION
+            ),
+        }
+    }
 }
 
+impl std::error::Error for ConfigVersionError {}
+
 /// Complete simulation config parameters
 #[derive(Clone)]
 pub struct SimConfig {
@@ -34,8 +112,20 @@ pub struct SimConfig {
     pub input_queue_size: Duration,
     pub chunk_size: u8,
     pub movement_speed: f32,
+    pub character_radius: f32,
+    pub character_height: f32,
+    pub ground_friction: f32,
+    /// Radians
+    pub max_slope: f32,
+    pub slide_acceleration: f32,
+    pub max_reach: f32,
+    pub worldgen_chunks_per_player: u32,
+    /// Number of steps in a full day/night cycle
+    pub steps_per_day: u32,
     /// Scaling factor converting meters to absolute units
     pub meters_to_absolute: f32,
+    pub worldgen_preset: worldgen::Preset,
+    pub pvp_enabled: bool,
 }
 
 impl SimConfig {
@@ -43,17 +133,90 @@ impl SimConfig {
         let chunk_size = x.chunk_size.unwrap_or(12);
         let voxel_size = x.voxel_size.unwrap_or(1.0);
         let meters_to_absolute = meters_to_absolute(chunk_size, voxel_size);
+        let rate = x.rate.unwrap_or(10);
         SimConfig {
-            rate: x.rate.unwrap_or(10),
+            rate,
             view_distance: x.view_distance.unwrap_or(90.0) * meters_to_absolute,
             input_queue_size: Duration::from_millis(x.input_queue_size_ms.unwrap_or(50).into()),
             chunk_size,
             movement_speed: x.movement_speed.unwrap_or(12.0) * meters_to_absolute,
+            character_radius: x.character_radius.unwrap_or(0.4) * meters_to_absolute,
+            character_height: x.character_height.unwrap_or(1.8) * meters_to_absolute,
+            ground_friction: x.ground_friction.unwrap_or(60.0) * meters_to_absolute,
+            max_slope: x.max_slope.unwrap_or(50.0).to_radians(),
+            slide_acceleration: x.slide_acceleration.unwrap_or(9.8) * meters_to_absolute,
+            max_reach: x.max_reach.unwrap_or(8.0) * meters_to_absolute,
+            worldgen_chunks_per_player: x.worldgen_chunks_per_player.unwrap_or(16),
+            steps_per_day: (x.day_length_secs.unwrap_or(1200.0) * f32::from(rate)) as u32,
             meters_to_absolute,
+            worldgen_preset: x.worldgen_preset.unwrap_or_default(),
+            pvp_enabled: x.pvp_enabled.unwrap_or(true),
+        }
+    }
+
+    /// Apply the parameters from `new` that may change while the simulation is running
+    ///
+    /// Fails, leaving `self` unchanged, if `new` differs in any parameter that determines the
+    /// structure of the world.
+    pub fn update(&mut self, new: &SimConfig) -> Result<(), ImmutableParameter> {
+        if new.chunk_size != self.chunk_size {
+            return Err(ImmutableParameter("chunk_size"));
+        }
+        if new.meters_to_absolute != self.meters_to_absolute {
+            return Err(ImmutableParameter("voxel_size"));
+        }
+        if new.worldgen_preset != self.worldgen_preset {
+            return Err(ImmutableParameter("worldgen_preset"));
+        }
+        *self = new.clone();
+        Ok(())
+    }
+}
+
+/// Seed a world is generated from, given either as a number or as a name to share it by
+///
+/// Written as a bare integer or a string, e.g. `world_seeds = [42, "fungal caverns"]`. A number
+/// given as a string, such as `"42"`, is a name like any other.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WorldSeed {
+    Number(u64),
+    Name(String),
+}
+
+impl WorldSeed {
+    /// The seed itself
+    ///
+    /// Names are hashed with 64-bit FNV-1a over their UTF-8 encoding, which doesn't depend on the
+    /// platform or on any library's choice of hash, so a name always yields the same world.
+    pub fn value(&self) -> u64 {
+        match *self {
+            WorldSeed::Number(x) => x,
+            WorldSeed::Name(ref name) => name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            }),
         }
     }
 }
 
+impl From<u64> for WorldSeed {
+    fn from(x: u64) -> Self {
+        WorldSeed::Number(x)
+    }
+}
+
+/// Error produced when attempting to change a parameter that's fixed for the life of a simulation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ImmutableParameter(pub &'static str);
+
+impl fmt::Display for ImmutableParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cannot be changed at runtime", self.0)
+    }
+}
+
+impl std::error::Error for ImmutableParameter {}
+
 /// Compute the scaling factor from meters to absolute units, given the number of voxels in a chunk
 /// and the approximate size of a voxel in meters.
 fn meters_to_absolute(chunk_size: u8, voxel_size: f32) -> f32 {
@@ -63,3 +226,111 @@ fn meters_to_absolute(chunk_size: u8, voxel_size: f32) -> f32 {
     let absolute_voxel_size = minimum_chunk_face_separation / f64::from(chunk_size);
     absolute_voxel_size as f32 / voxel_size
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_seeds() {
+        let name = |x: &str| WorldSeed::Name(x.into()).value();
+        // Must never change, or shared names would lead to different worlds
+        assert_eq!(name("hypermine"), 0x1e1c_506b_8b11_9e42);
+        assert_eq!(name(""), 0xcbf2_9ce4_8422_2325);
+        assert_ne!(name("hypermine"), name("Hypermine"));
+        assert_ne!(name("ab"), name("ba"));
+        for &x in &[0, 42, u64::max_value()] {
+            assert_eq!(WorldSeed::from(x).value(), x);
+        }
+        assert_ne!(name("42"), 42);
+    }
+
+    #[test]
+    fn v1_migrated() {
+        // Every field version 1 had
+        let v1 = SimConfigRaw {
+            rate: Some(20),
+            view_distance: Some(60.0),
+            input_queue_size_ms: Some(100),
+            chunk_size: Some(16),
+            voxel_size: Some(0.5),
+            movement_speed: Some(6.0),
+            ..SimConfigRaw::default()
+        };
+        let current = v1.migrate().unwrap();
+        assert_eq!(current.version, Some(SIM_CONFIG_VERSION));
+        let cfg = SimConfig::from_raw(&current);
+        assert_eq!(cfg.rate, 20);
+        assert_eq!(cfg.chunk_size, 16);
+        assert_eq!(cfg.input_queue_size, Duration::from_millis(100));
+        assert_eq!(cfg.meters_to_absolute, meters_to_absolute(16, 0.5));
+        assert_eq!(cfg.view_distance, 60.0 * cfg.meters_to_absolute);
+        assert_eq!(cfg.movement_speed, 6.0 * cfg.meters_to_absolute);
+
+        // Fields added since take their defaults
+        let defaults = SimConfig::from_raw(&SimConfigRaw::default());
+        assert_eq!(cfg.max_slope, defaults.max_slope);
+        assert_eq!(cfg.worldgen_chunks_per_player, 16);
+        assert_eq!(cfg.steps_per_day, 1200 * 20);
+        assert_eq!(cfg.worldgen_preset, defaults.worldgen_preset);
+        assert!(cfg.pvp_enabled);
+    }
+
+    #[test]
+    fn bad_version_rejected() {
+        let future = SimConfigRaw {
+            version: Some(SIM_CONFIG_VERSION + 1),
+            ..SimConfigRaw::default()
+        };
+        assert_eq!(
+            future.migrate().err(),
+            Some(ConfigVersionError::Unsupported(SIM_CONFIG_VERSION + 1))
+        );
+        let zero = SimConfigRaw {
+            version: Some(0),
+            ..SimConfigRaw::default()
+        };
+        assert_eq!(
+            zero.migrate().err(),
+            Some(ConfigVersionError::Unsupported(0))
+        );
+    }
+
+    #[test]
+    fn update_mutable() {
+        let mut cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let new = SimConfig::from_raw(&SimConfigRaw {
+            movement_speed: Some(20.0),
+            view_distance: Some(40.0),
+            rate: Some(30),
+            ..SimConfigRaw::default()
+        });
+        cfg.update(&new).unwrap();
+        assert_eq!(cfg.movement_speed, new.movement_speed);
+        assert_eq!(cfg.view_distance, new.view_distance);
+        assert_eq!(cfg.rate, 30);
+    }
+
+    #[test]
+    fn update_immutable() {
+        let mut cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let speed = cfg.movement_speed;
+        let new = SimConfig::from_raw(&SimConfigRaw {
+            movement_speed: Some(20.0),
+            chunk_size: Some(16),
+            ..SimConfigRaw::default()
+        });
+        assert_eq!(cfg.update(&new), Err(ImmutableParameter("chunk_size")));
+        assert_eq!(cfg.movement_speed, speed);
+        let new = SimConfig::from_raw(&SimConfigRaw {
+            voxel_size: Some(0.5),
+            ..SimConfigRaw::default()
+        });
+        assert_eq!(cfg.update(&new), Err(ImmutableParameter("voxel_size")));
+        let new = SimConfig::from_raw(&SimConfigRaw {
+            worldgen_preset: Some(worldgen::Preset::Flat),
+            ..SimConfigRaw::default()
+        });
+        assert_eq!(cfg.update(&new), Err(ImmutableParameter("worldgen_preset")));
+    }
+}
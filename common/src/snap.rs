@@ -0,0 +1,124 @@
+//! Alignment of building targets to the voxel grid
+//!
+//! Free placement lets blocks drift off the grid. Snapping moves a target to the center of the
+//! voxel containing it, and optionally first to the nearest step along a `Line` extending the
+//! blocks placed before.
+
+use crate::graph::{Graph, NodeId};
+use crate::math;
+use crate::node::{voxel_to_world_center, world_to_voxel, ChunkId};
+
+/// Where to build, as found by `snap`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Snapped {
+    pub chunk: ChunkId,
+    pub voxel: [u8; 3],
+    /// Center of `voxel`, in the coordinates of `chunk.node`
+    pub center: na::Vector4<f64>,
+}
+
+/// Find the voxel to build in for `target`, given in the coordinates of `node`
+///
+/// With a `line`, `target` is first moved to the nearest step along it. Returns `None` if the
+/// voxel lies in a node that isn't in `graph`.
+pub fn snap<N>(
+    graph: &Graph<N>,
+    dimension: u8,
+    node: NodeId,
+    target: &na::Vector4<f64>,
+    line: Option<&Line>,
+) -> Option<Snapped> {
+    let target = line.map_or(*target, |line| line.snap(target));
+    let (chunk, voxel) = world_to_voxel(graph, dimension, node, &target)?;
+    Some(Snapped {
+        chunk,
+        voxel,
+        center: voxel_to_world_center(dimension, chunk, voxel),
+    })
+}
+
+/// Evenly spaced steps along a geodesic, for extending a run of blocks in a straight line
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Line {
+    /// Step zero
+    origin: na::Vector4<f64>,
+    /// Unit tangent at `origin`, pointing towards positive steps
+    direction: na::Vector4<f64>,
+    /// Distance between consecutive steps
+    spacing: f64,
+}
+
+impl Line {
+    /// The line from `from` through `to`, e.g. the centers of the last two blocks placed, with
+    /// steps spaced as far apart as they are
+    ///
+    /// Returns `None` if the points coincide.
+    pub fn through(from: &na::Vector4<f64>, to: &na::Vector4<f64>) -> Option<Self> {
+        let spacing = math::distance(from, to);
+        if spacing < 1e-9 {
+            return None;
+        }
+        let direction = (to - from * spacing.cosh()) / spacing.sinh();
+        Some(Self {
+            origin: *from,
+            direction,
+            spacing,
+        })
+    }
+
+    /// The point `distance` along the line from step zero
+    pub fn at(&self, distance: f64) -> na::Vector4<f64> {
+        math::lorentz_normalize(&(self.origin * distance.cosh() + self.direction * distance.sinh()))
+    }
+
+    /// The step nearest to `target`
+    pub fn snap(&self, target: &na::Vector4<f64>) -> na::Vector4<f64> {
+        // The point of the line nearest `target` lies at `t` where `tanh t = b / a`
+        let a = -math::mip(target, &self.origin);
+        let b = math::mip(target, &self.direction);
+        let t = (b / a).atanh();
+        self.at((t / self.spacing).round() * self.spacing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dodeca::Vertex;
+
+    const DIMENSION: u8 = 12;
+
+    #[test]
+    fn snap_near_center() {
+        let graph = Graph::<()>::new();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::C);
+        let center = voxel_to_world_center(DIMENSION, chunk, [4, 5, 6]);
+        let near = math::lorentz_normalize(&(center + na::Vector4::new(0.002, -0.001, 0.001, 0.0)));
+        let snapped = snap(&graph, DIMENSION, NodeId::ROOT, &near, None).unwrap();
+        assert_eq!(snapped.chunk, chunk);
+        assert_eq!(snapped.voxel, [4, 5, 6]);
+        assert_eq!(snapped.center, center);
+    }
+
+    #[test]
+    fn snap_along_line() {
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let from = voxel_to_world_center(DIMENSION, chunk, [2, 3, 3]);
+        let to = voxel_to_world_center(DIMENSION, chunk, [3, 4, 3]);
+        let line = Line::through(&from, &to).unwrap();
+        let spacing = math::distance(&from, &to);
+        // Wander off the line, a little past its third step
+        let target = math::translate(&math::origin(), &line.at(3.1 * spacing))
+            * math::translate_along(&na::Vector3::z_axis(), 0.2 * spacing)
+            * math::origin();
+        let snapped = line.snap(&target);
+        // Three steps from the first point and two from the second puts it on the line
+        assert!((math::distance(&from, &snapped) - 3.0 * spacing).abs() < 1e-9);
+        assert!((math::distance(&to, &snapped) - 2.0 * spacing).abs() < 1e-9);
+        // Steps go backwards too
+        let behind = line.snap(&line.at(-1.1 * spacing));
+        assert!((math::distance(&behind, &to) - 2.0 * spacing).abs() < 1e-9);
+
+        assert_eq!(Line::through(&from, &from), None);
+    }
+}
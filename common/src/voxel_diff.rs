@@ -0,0 +1,131 @@
+//! Compact encodings of changes to a chunk's voxels, for transmission to clients
+//!
+//! A few scattered edits are cheapest to send voxel by voxel, but a large edit like filling a
+//! region is cheaper to send as the chunk's entire contents, run-length encoded. `diff` picks
+//! whichever encoding is smaller.
+
+use anyhow::{bail, Result};
+
+use crate::node::VoxelData;
+use crate::proto::VoxelChanges;
+use crate::world::Material;
+
+/// The smallest encoding of the changes that turn `before` into `after`, or `None` if they're
+/// identical
+pub fn diff(dimension: u8, before: &VoxelData, after: &VoxelData) -> Option<VoxelChanges> {
+    if before == after {
+        return None;
+    }
+    let len = (usize::from(dimension) + 2).pow(3);
+    let sparse = (0..len)
+        .filter(|&i| before.get(i) != after.get(i))
+        .map(|i| (i as u32, after.get(i)))
+        .collect::<Vec<_>>();
+    if sparse.is_empty() {
+        return None;
+    }
+    let whole = runs(len, after);
+    // Both variants hold the same type, so comparing lengths is comparing encoded sizes
+    Some(if whole.len() < sparse.len() {
+        VoxelChanges::Whole(whole)
+    } else {
+        VoxelChanges::Sparse(sparse)
+    })
+}
+
+/// Changes that replace any chunk's contents with `voxels`
+pub fn whole(dimension: u8, voxels: &VoxelData) -> VoxelChanges {
+    VoxelChanges::Whole(runs((usize::from(dimension) + 2).pow(3), voxels))
+}
+
+/// Apply `changes` to `voxels`, leaving them untouched if `changes` is malformed
+pub fn apply(dimension: u8, voxels: &mut VoxelData, changes: &VoxelChanges) -> Result<()> {
+    let len = (usize::from(dimension) + 2).pow(3);
+    match *changes {
+        VoxelChanges::Sparse(ref changes) => {
+            if let Some(&(index, _)) = changes.iter().find(|&&(i, _)| i as usize >= len) {
+                bail!("voxel index {} out of bounds", index);
+            }
+            let data = voxels.data_mut(dimension);
+            for &(index, material) in changes {
+                data[index as usize] = material;
+            }
+        }
+        VoxelChanges::Whole(ref runs) => {
+            let total = runs.iter().map(|&(n, _)| u64::from(n)).sum::<u64>();
+            if total != len as u64 {
+                bail!("{} voxels in a chunk of {}", total, len);
+            }
+            *voxels = match runs[..] {
+                [(_, material)] => VoxelData::Solid(material),
+                _ => VoxelData::Dense(
+                    runs.iter()
+                        .flat_map(|&(n, material)| std::iter::repeat(material).take(n as usize))
+                        .collect::<Vec<_>>()
+                        .into(),
+                ),
+            };
+        }
+    }
+    Ok(())
+}
+
+/// `voxels` as runs of identical materials in index order
+fn runs(len: usize, voxels: &VoxelData) -> Vec<(u32, Material)> {
+    let mut result = Vec::<(u32, Material)>::new();
+    for i in 0..len {
+        let material = voxels.get(i);
+        match result.last_mut() {
+            Some(&mut (ref mut n, x)) if x == material => *n += 1,
+            _ => result.push((1, material)),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::voxel_index;
+
+    const DIMENSION: u8 = 12;
+
+    fn materials(voxels: &VoxelData) -> Vec<Material> {
+        (0..(usize::from(DIMENSION) + 2).pow(3))
+            .map(|i| voxels.get(i))
+            .collect()
+    }
+
+    #[test]
+    fn few_edits_sparse() {
+        let before = VoxelData::Solid(Material::Void);
+        let mut after = before.clone();
+        after.data_mut(DIMENSION)[voxel_index(DIMENSION, [1, 2, 3])] = Material::Stone;
+        after.data_mut(DIMENSION)[voxel_index(DIMENSION, [7, 7, 0])] = Material::Wood;
+        let changes = diff(DIMENSION, &before, &after).unwrap();
+        match changes {
+            VoxelChanges::Sparse(ref x) => assert_eq!(x.len(), 2),
+            VoxelChanges::Whole(_) => panic!("two edits sent as a whole chunk"),
+        }
+        let mut rebuilt = before.clone();
+        apply(DIMENSION, &mut rebuilt, &changes).unwrap();
+        assert_eq!(materials(&rebuilt), materials(&after));
+        assert_eq!(diff(DIMENSION, &after, &rebuilt), None);
+    }
+
+    #[test]
+    fn malformed_rejected() {
+        let original = VoxelData::Solid(Material::Dirt);
+        let mut voxels = original.clone();
+        let out_of_bounds =
+            VoxelChanges::Sparse(vec![(0, Material::Stone), (u32::MAX, Material::Stone)]);
+        assert!(apply(DIMENSION, &mut voxels, &out_of_bounds).is_err());
+        assert!(apply(
+            DIMENSION,
+            &mut voxels,
+            &VoxelChanges::Whole(vec![(5, Material::Stone)])
+        )
+        .is_err());
+        assert!(voxels == original);
+    }
+}
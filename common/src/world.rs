@@ -1,4 +1,15 @@
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+use std::convert::TryFrom;
+
+use fxhash::FxHashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graph::NodeId,
+    math,
+    node::{voxel_index, world_to_voxel, Chunk, ChunkId, DualGraph, VoxelFace},
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 #[repr(u16)]
 pub enum Material {
     Void = 0,
@@ -26,10 +37,98 @@ pub enum Material {
     Lava = 22,
     GreySand = 23,
     Mud = 24,
+    GreyBrickSlab = 25,
+    WoodPlanksSlab = 26,
 }
 
 impl Material {
-    pub const COUNT: usize = 25;
+    pub const COUNT: usize = 27;
+
+    /// Every material, in order of discriminant
+    pub const ALL: [Material; Material::COUNT] = [
+        Material::Void,
+        Material::Stone,
+        Material::Dirt,
+        Material::Sand,
+        Material::Wood,
+        Material::Leaves,
+        Material::Water,
+        Material::Snow,
+        Material::Grass,
+        Material::Redsand,
+        Material::Redstone,
+        Material::Valite,
+        Material::Greystone,
+        Material::Flowergrass,
+        Material::Gravelstone,
+        Material::Graveldirt,
+        Material::Blackstone,
+        Material::Bigflowergrass,
+        Material::GreyBrick,
+        Material::WoodPlanks,
+        Material::WhiteBrick,
+        Material::Ice,
+        Material::Lava,
+        Material::GreySand,
+        Material::Mud,
+        Material::GreyBrickSlab,
+        Material::WoodPlanksSlab,
+    ];
+
+    /// The material whose discriminant is `x`, if any
+    pub fn from_index(x: u16) -> Option<Self> {
+        Material::ALL.get(usize::from(x)).copied()
+    }
+
+    /// Whether this is empty space, i.e. `Material::Void`
+    #[inline]
+    pub fn is_void(self) -> bool {
+        self == Material::Void
+    }
+
+    /// The part of its voxel that this material occupies
+    #[inline]
+    pub fn shape(self) -> VoxelShape {
+        use Material::*;
+        match self {
+            GreyBrickSlab | WoodPlanksSlab => VoxelShape::Slab,
+            _ => VoxelShape::Full,
+        }
+    }
+
+    /// Whether the material's color varies with climate, per `worldgen::ChunkTints`
+    ///
+    /// Must agree with `material_tintable` in `surface.h`.
+    #[inline]
+    pub fn is_tintable(self) -> bool {
+        use Material::*;
+        match self {
+            Grass | Flowergrass | Bigflowergrass | Leaves => true,
+            _ => false,
+        }
+    }
+}
+
+/// The part of a voxel occupied by a solid material
+///
+/// Shapes are expressed in chunk coordinates, so e.g. a slab is thin along its chunk's y axis,
+/// whichever way that happens to point.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VoxelShape {
+    /// The entire voxel
+    Full,
+    /// The half of the voxel with lower y
+    Slab,
+}
+
+impl VoxelShape {
+    /// Boxes making up the shape, as pairs of minimum and maximum corners within the unit cube
+    pub fn boxes(self) -> &'static [([f32; 3], [f32; 3])] {
+        match self {
+            VoxelShape::Full => &[([0.0; 3], [1.0; 3])],
+            VoxelShape::Slab => &[([0.0; 3], [1.0, 0.5, 1.0])],
+        }
+    }
 }
 
 impl Default for Material {
@@ -37,3 +136,468 @@ impl Default for Material {
         Material::Void
     }
 }
+
+/// A `Material` other than `Material::Void`
+///
+/// Used by APIs that fill in voxels and must never leave a hole, e.g. structure placement.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SolidMaterial(Material);
+
+impl SolidMaterial {
+    /// Returns `None` for `Material::Void`
+    #[inline]
+    pub fn new(material: Material) -> Option<Self> {
+        if material.is_void() {
+            None
+        } else {
+            Some(Self(material))
+        }
+    }
+
+    #[inline]
+    pub fn get(self) -> Material {
+        self.0
+    }
+}
+
+impl TryFrom<Material> for SolidMaterial {
+    type Error = VoidMaterial;
+    fn try_from(material: Material) -> Result<Self, VoidMaterial> {
+        Self::new(material).ok_or(VoidMaterial)
+    }
+}
+
+impl From<SolidMaterial> for Material {
+    fn from(x: SolidMaterial) -> Material {
+        x.0
+    }
+}
+
+/// Material of the voxel containing `point`, given in the coordinates of `node`
+///
+/// Returns `None` if that voxel's chunk isn't populated, e.g. because its node isn't in `graph`.
+pub fn material_at(
+    graph: &DualGraph,
+    dimension: u8,
+    node: NodeId,
+    point: &na::Vector4<f64>,
+) -> Option<Material> {
+    let voxel = world_to_voxel(graph, dimension, node, point)?;
+    voxel_material(graph, dimension, voxel)
+}
+
+/// Material of the voxel at `coords` in `chunk`, if that chunk is populated
+fn voxel_material(
+    graph: &DualGraph,
+    dimension: u8,
+    (chunk, coords): (ChunkId, [u8; 3]),
+) -> Option<Material> {
+    match graph.get(chunk.node).as_ref()?.chunks[chunk.vertex] {
+        Chunk::Populated { ref voxels, .. } => Some(voxels.get(voxel_index(dimension, coords))),
+        _ => None,
+    }
+}
+
+/// Voxels connected to `start` through shared faces, all of whose materials satisfy `predicate`
+///
+/// The fill crosses chunk and node boundaries freely, but voxels in chunks that aren't populated
+/// are treated as failing `predicate`. Returns `None` if `start` itself fails, or if the region
+/// holds more than `limit` voxels, in which case it may well be unbounded.
+pub fn connected_region(
+    graph: &DualGraph,
+    dimension: u8,
+    start: (ChunkId, [u8; 3]),
+    mut predicate: impl FnMut(Material) -> bool,
+    limit: usize,
+) -> Option<FxHashSet<(ChunkId, [u8; 3])>> {
+    let mut matches =
+        |voxel| voxel_material(graph, dimension, voxel).map_or(false, |x| predicate(x));
+    if !matches(start) {
+        return None;
+    }
+    let mut region = FxHashSet::default();
+    region.insert(start);
+    let mut pending = vec![start];
+    while let Some((chunk, coords)) = pending.pop() {
+        for axis in 0..3 {
+            for &offset in &[-1, 1] {
+                let voxel = match adjacent_voxel(graph, dimension, chunk, coords, axis, offset) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if region.contains(&voxel) || !matches(voxel) {
+                    continue;
+                }
+                if region.len() == limit {
+                    return None;
+                }
+                region.insert(voxel);
+                pending.push(voxel);
+            }
+        }
+    }
+    Some(region)
+}
+
+/// A voxel struck by a ray, and where a voxel placed against it would go
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VoxelHit {
+    /// The first non-void voxel along the ray
+    pub voxel: (ChunkId, [u8; 3]),
+    /// The face of `voxel` the ray entered through, in the coordinates of its chunk
+    pub face: VoxelFace,
+    /// The void voxel adjoining `face`, which may belong to another chunk or node
+    pub place: (ChunkId, [u8; 3]),
+}
+
+/// Find the first non-void voxel along the -Z axis of `ray`, a pose in the coordinates of `node`,
+/// within `max_distance`
+///
+/// Returns `None` if no such voxel is found, if `ray` starts inside one, or if the ray passes
+/// through a chunk that isn't populated before striking anything.
+pub fn raycast(
+    graph: &DualGraph,
+    dimension: u8,
+    node: NodeId,
+    ray: &math::Isometry<f64>,
+    max_distance: f64,
+) -> Option<VoxelHit> {
+    let start = *ray * math::origin();
+    let end =
+        *ray * (math::translate_along(&-na::Vector3::z_axis(), max_distance) * math::origin());
+    let scale = f64::from(dimension);
+    let mut current = world_to_voxel(graph, dimension, node, &start)?;
+    if !voxel_material(graph, dimension, current)?.is_void() {
+        return None;
+    }
+    // Geodesics are straight lines in chunk coordinates, so the walk is an ordinary voxel
+    // traversal. Points along the ray are parameterized homogeneously as `start + s * (end -
+    // start)` for `s` in [0, 1], which each chunk's coordinates share.
+    let mut endpoints = None;
+    loop {
+        let (chunk, coords) = current;
+        let (a, b) = match endpoints {
+            Some((c, a, b)) if c == chunk => (a, b),
+            _ => {
+                let to_chunk = chunk.vertex.node_to_chunk()
+                    * graph.relative_transform(chunk.node, node)?.matrix();
+                let (a, b) = (to_chunk * start, to_chunk * end);
+                endpoints = Some((chunk, a, b));
+                (a, b)
+            }
+        };
+        // The face the ray leaves the current voxel through, as the earliest crossing of a face's
+        // plane towards the outside
+        let mut exit: Option<(f64, usize, i8)> = None;
+        for axis in 0..3 {
+            for &offset in &[-1, 1] {
+                let plane = f64::from(coords[axis]) + if offset > 0 { 1.0 } else { 0.0 };
+                // Signed offset from the plane, scaled by w, at either end of the ray
+                let fa = scale * a[axis] - plane * a.w;
+                let fb = scale * b[axis] - plane * b.w;
+                if (fb - fa) * f64::from(offset) <= 0.0 {
+                    continue;
+                }
+                let s = fa / (fa - fb);
+                if exit.map_or(true, |(best, _, _)| s < best) {
+                    exit = Some((s, axis, offset));
+                }
+            }
+        }
+        let (s, axis, offset) = exit?;
+        if s > 1.0 {
+            return None;
+        }
+        let next = adjacent_voxel(graph, dimension, chunk, coords, axis, offset)?;
+        if voxel_material(graph, dimension, next)?.is_void() {
+            current = next;
+            continue;
+        }
+        // Axes may differ between chunks, so find the face from the struck voxel's side
+        let face = VoxelFace::iter().find(|face| {
+            let offset = if face.is_positive() { 1 } else { -1 };
+            adjacent_voxel(graph, dimension, next.0, next.1, face.axis(), offset) == Some(current)
+        })?;
+        return Some(VoxelHit {
+            voxel: next,
+            face,
+            place: current,
+        });
+    }
+}
+
+/// The voxel sharing the face of the voxel at `coords` in `chunk` that lies `offset` voxels along
+/// `axis`, which may belong to another chunk or node
+///
+/// Returns `None` if that voxel lies in a node that isn't in `graph`.
+fn adjacent_voxel(
+    graph: &DualGraph,
+    dimension: u8,
+    chunk: ChunkId,
+    coords: [u8; 3],
+    axis: usize,
+    offset: i8,
+) -> Option<(ChunkId, [u8; 3])> {
+    let mut coords = [
+        i16::from(coords[0]),
+        i16::from(coords[1]),
+        i16::from(coords[2]),
+    ];
+    coords[axis] += i16::from(offset);
+    voxel_near(graph, dimension, chunk, coords)
+}
+
+/// The voxel at `coords` relative to `chunk`, which may lie beyond it, e.g. in its margins, and so
+/// belong to another chunk or node
+///
+/// Returns `None` if that voxel lies in a node that isn't in `graph`.
+pub fn voxel_near(
+    graph: &DualGraph,
+    dimension: u8,
+    chunk: ChunkId,
+    coords: [i16; 3],
+) -> Option<(ChunkId, [u8; 3])> {
+    if coords.iter().all(|&x| x >= 0 && x < i16::from(dimension)) {
+        return Some((chunk, [coords[0] as u8, coords[1] as u8, coords[2] as u8]));
+    }
+    // Locate the center of the voxel, which lies safely away from any boundary
+    let scale = f64::from(dimension);
+    let center = na::Vector3::from(coords).map(|x| (f64::from(x) + 0.5) / scale);
+    let point = math::lorentz_normalize(&(chunk.vertex.chunk_to_node() * center.push(1.0)));
+    world_to_voxel(graph, dimension, chunk.node, &point)
+}
+
+/// Chunks other than `chunk` that its margins duplicate voxels of, i.e. those sharing a face, edge,
+/// or corner with it
+///
+/// Chunks in nodes that aren't in `graph` are omitted.
+pub fn neighboring_chunks(graph: &DualGraph, dimension: u8, chunk: ChunkId) -> Vec<ChunkId> {
+    let dim = i16::from(dimension);
+    let mut result = Vec::new();
+    // A margin voxel in the middle of each face and edge, and at each corner
+    for &x in &[-1, dim / 2, dim] {
+        for &y in &[-1, dim / 2, dim] {
+            for &z in &[-1, dim / 2, dim] {
+                if let Some((other, _)) = voxel_near(graph, dimension, chunk, [x, y, z]) {
+                    if other != chunk && !result.contains(&other) {
+                        result.push(other);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Error produced when converting `Material::Void` into a `SolidMaterial`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VoidMaterial;
+
+impl std::fmt::Display for VoidMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("void is not a solid material")
+    }
+}
+
+impl std::error::Error for VoidMaterial {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_conversions() {
+        assert_eq!(SolidMaterial::new(Material::Void), None);
+        assert_eq!(SolidMaterial::try_from(Material::Void), Err(VoidMaterial));
+        let stone = SolidMaterial::try_from(Material::Stone).unwrap();
+        assert_eq!(stone.get(), Material::Stone);
+        assert_eq!(Material::from(stone), Material::Stone);
+    }
+
+    #[test]
+    fn void_check() {
+        assert!(Material::Void.is_void());
+        assert!(Material::default().is_void());
+        assert!(!Material::Stone.is_void());
+    }
+
+    #[test]
+    fn materials_indexed() {
+        for (i, &material) in Material::ALL.iter().enumerate() {
+            assert_eq!(material as usize, i);
+            assert_eq!(Material::from_index(i as u16), Some(material));
+        }
+        assert_eq!(Material::from_index(Material::COUNT as u16), None);
+    }
+
+    #[test]
+    fn material_lookup() {
+        use crate::{
+            dodeca::{Side, Vertex},
+            node::{populate_fresh_nodes, voxel_to_world_center, ChunkId, VoxelData},
+        };
+
+        const DIMENSION: u8 = 12;
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let stone = SolidMaterial::try_from(Material::Stone).unwrap();
+        voxels.place(DIMENSION, voxel_index(DIMENSION, [1, 2, 3]), stone);
+        graph.get_mut(NodeId::ROOT).as_mut().unwrap().chunks[Vertex::A] =
+            Chunk::populated(DIMENSION, voxels);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let at = |point| material_at(&graph, DIMENSION, NodeId::ROOT, &point);
+
+        let solid = voxel_to_world_center(DIMENSION, chunk, [1, 2, 3]);
+        assert_eq!(at(solid), Some(Material::Stone));
+        let air = voxel_to_world_center(DIMENSION, chunk, [1, 2, 4]);
+        assert_eq!(at(air), Some(Material::Void));
+        // A chunk that hasn't been generated yet
+        let fresh = voxel_to_world_center(DIMENSION, ChunkId::new(NodeId::ROOT, Vertex::B), [0; 3]);
+        assert_eq!(at(fresh), None);
+        // A node that isn't in the graph at all
+        assert_eq!(at(Side::A.reflection() * solid), None);
+    }
+
+    #[test]
+    fn cavity_filled() {
+        use crate::{
+            dodeca::Vertex,
+            node::{populate_fresh_nodes, VoxelData},
+        };
+
+        const DIMENSION: u8 = 12;
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        // The chunk of the same node on the other side of `chunk`'s x = 0 face, and the voxel
+        // there adjoining `chunk`'s voxel (0, 2, 2)
+        let beyond = math::lorentz_normalize(
+            &(chunk.vertex.chunk_to_node() * na::Vector4::new(-0.5, 2.5, 2.5, DIMENSION.into())),
+        );
+        let (other, other_coords) =
+            world_to_voxel(&graph, DIMENSION, NodeId::ROOT, &beyond).unwrap();
+        assert_eq!(other.node, NodeId::ROOT);
+        assert_ne!(other.vertex, chunk.vertex);
+
+        let mut expected = FxHashSet::default();
+        let mut voxels = VoxelData::Solid(Material::Stone);
+        let mut carve = |voxels: &mut VoxelData, id: (ChunkId, [u8; 3]), connected: bool| {
+            voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, id.1)] = Material::Void;
+            if connected {
+                expected.insert(id);
+            }
+        };
+        // A 3x3x3 cavity with a tunnel leading into the neighboring chunk
+        for x in 2..5 {
+            for y in 2..5 {
+                for z in 2..5 {
+                    carve(&mut voxels, (chunk, [x, y, z]), true);
+                }
+            }
+        }
+        for x in 0..2 {
+            carve(&mut voxels, (chunk, [x, 2, 2]), true);
+        }
+        // A separate pocket, sealed off by a single voxel of stone
+        carve(&mut voxels, (chunk, [6, 3, 3]), false);
+        let mut other_voxels = VoxelData::Solid(Material::Stone);
+        carve(&mut other_voxels, (other, other_coords), true);
+        let node = graph.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[chunk.vertex] = Chunk::populated(DIMENSION, voxels);
+        node.chunks[other.vertex] = Chunk::populated(DIMENSION, other_voxels);
+
+        let fill =
+            |start, limit| connected_region(&graph, DIMENSION, start, Material::is_void, limit);
+        assert_eq!(fill((chunk, [3, 3, 3]), 1000), Some(expected.clone()));
+        // Starting from the far end finds the same region
+        assert_eq!(fill((other, other_coords), 1000), Some(expected.clone()));
+        assert_eq!(fill((chunk, [3, 3, 3]), expected.len()), Some(expected));
+        // Too large
+        assert_eq!(fill((chunk, [3, 3, 3]), 10), None);
+        // Starting in stone
+        assert_eq!(fill((chunk, [0, 0, 0]), 1000), None);
+    }
+
+    #[test]
+    fn pick_floor() {
+        use crate::{
+            dodeca::Vertex,
+            node::{populate_fresh_nodes, voxel_to_world_center, VoxelData},
+        };
+
+        const DIMENSION: u8 = 12;
+        let mut graph = DualGraph::new();
+        populate_fresh_nodes(&mut graph);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        // Stone up to and including y = 5
+        let mut voxels = VoxelData::Solid(Material::Void);
+        for x in 0..DIMENSION {
+            for y in 0..6 {
+                for z in 0..DIMENSION {
+                    voxels.data_mut(DIMENSION)[voxel_index(DIMENSION, [x, y, z])] = Material::Stone;
+                }
+            }
+        }
+        graph.get_mut(NodeId::ROOT).as_mut().unwrap().chunks[chunk.vertex] =
+            Chunk::populated(DIMENSION, voxels);
+        let pick = |eye: &na::Vector4<f64>, target: &na::Vector4<f64>, max_distance| {
+            let ray = math::Isometry::look_at(eye, target, &na::Vector3::x());
+            raycast(&graph, DIMENSION, NodeId::ROOT, &ray, max_distance)
+        };
+
+        // Looking down on the floor from above
+        let eye = voxel_to_world_center(DIMENSION, chunk, [5, 9, 5]);
+        let target = voxel_to_world_center(DIMENSION, chunk, [5, 2, 5]);
+        let reach = math::distance(&eye, &target);
+        let hit = pick(&eye, &target, reach).unwrap();
+        assert_eq!(hit.voxel, (chunk, [5, 5, 5]));
+        assert_eq!(hit.face, VoxelFace::PosY);
+        assert_eq!(hit.place, (chunk, [5, 6, 5]), "directly above the hit");
+        // Out of reach
+        assert_eq!(pick(&eye, &target, reach * 0.25), None);
+        // From inside the floor
+        assert_eq!(pick(&target, &eye, reach), None);
+
+        // A floor in the chunk below, across `chunk`'s y = 0 face
+        let mut graph_below = DualGraph::new();
+        populate_fresh_nodes(&mut graph_below);
+        let below = math::lorentz_normalize(
+            &(chunk.vertex.chunk_to_node() * na::Vector4::new(5.5, -0.5, 5.5, DIMENSION.into())),
+        );
+        let (other, other_coords) =
+            world_to_voxel(&graph_below, DIMENSION, NodeId::ROOT, &below).unwrap();
+        assert_ne!(other, chunk);
+        let node = graph_below.get_mut(NodeId::ROOT).as_mut().unwrap();
+        node.chunks[chunk.vertex] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Void));
+        node.chunks[other.vertex] = Chunk::populated(DIMENSION, VoxelData::Solid(Material::Stone));
+        let eye = voxel_to_world_center(DIMENSION, chunk, [5, 4, 5]);
+        let ray = math::Isometry::look_at(&eye, &below, &na::Vector3::x());
+        let hit = raycast(&graph_below, DIMENSION, NodeId::ROOT, &ray, 1.0).unwrap();
+        assert_eq!(hit.voxel, (other, other_coords));
+        assert_eq!(hit.place, (chunk, [5, 0, 5]));
+        let offset = if hit.face.is_positive() { 1 } else { -1 };
+        assert_eq!(
+            adjacent_voxel(
+                &graph_below,
+                DIMENSION,
+                other,
+                other_coords,
+                hit.face.axis(),
+                offset
+            ),
+            Some(hit.place)
+        );
+    }
+
+    #[test]
+    fn slab_shape() {
+        assert_eq!(Material::Stone.shape(), VoxelShape::Full);
+        assert_eq!(Material::GreyBrickSlab.shape(), VoxelShape::Slab);
+        let boxes = VoxelShape::Slab.boxes();
+        assert_eq!(boxes.len(), 1);
+        let (min, max) = boxes[0];
+        assert_eq!(max[1] - min[1], 0.5);
+        assert_eq!([max[0] - min[0], max[2] - min[2]], [1.0, 1.0]);
+    }
+}
@@ -1,13 +1,36 @@
+use std::convert::TryFrom;
+
 use rand::{distributions::Uniform, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 use crate::node::{DualGraph, VoxelData};
 use crate::{
     dodeca::{Side, Vertex},
     graph::NodeId,
-    world::Material,
+    parallel,
+    world::{Material, SolidMaterial},
     Plane,
 };
 
+/// Overall style of generated terrain
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Preset {
+    /// Rolling hills, with roads running through the sky
+    Plains,
+    /// `Plains` hollowed out by a layer of caverns a few meters underground
+    Caves,
+    /// Lenses of land floating in empty sky, wherever `Plains` would be high enough
+    Islands,
+    /// A single featureless floor, for testing
+    Flat,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset::Plains
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum NodeStateKind {
     Sky,
@@ -56,6 +79,7 @@ impl NodeStateRoad {
     }
 }
 
+#[derive(Clone)]
 pub struct NodeState {
     kind: NodeStateKind,
     surface: Plane<f64>,
@@ -81,6 +105,14 @@ impl NodeState {
         }
     }
 
+    /// Root of a world generated from `seed`, with seed 0 giving `root`
+    pub fn seeded_root(seed: u64) -> Self {
+        Self {
+            spice: seed,
+            ..Self::root()
+        }
+    }
+
     pub fn child(&self, graph: &DualGraph, node: NodeId, side: Side) -> Self {
         let spice = graph
             .descenders(node)
@@ -126,6 +158,11 @@ impl NodeState {
             enviro,
         }
     }
+
+    /// Reference plane for the terrain surface, in the node's coordinates
+    pub fn surface(&self) -> &Plane<f64> {
+        &self.surface
+    }
 }
 
 /// Data needed to generate a chunk
@@ -142,13 +179,20 @@ pub struct ChunkParams {
     /// Whether this chunk contains a section of the road's supports
     is_road_support: bool,
     node_spice: u64,
+    preset: Preset,
 }
 
 impl ChunkParams {
     /// Extract data necessary to generate a chunk
     ///
     /// Returns `None` if an unpopulated node is needed.
-    pub fn new(dimension: u8, graph: &DualGraph, node: NodeId, chunk: Vertex) -> Option<Self> {
+    pub fn new(
+        dimension: u8,
+        preset: Preset,
+        graph: &DualGraph,
+        node: NodeId,
+        chunk: Vertex,
+    ) -> Option<Self> {
         let state = &graph.get(node).as_ref()?.state;
         Some(Self {
             dimension,
@@ -160,6 +204,7 @@ impl ChunkParams {
             is_road_support: ((state.kind == Land) || (state.kind == DeepLand))
                 && ((state.road_state == East) || (state.road_state == West)),
             node_spice: state.spice,
+            preset,
         })
     }
 
@@ -167,6 +212,17 @@ impl ChunkParams {
         self.chunk
     }
 
+    /// Where a natural feature in the voxel at `coords` sits, in chunk coordinates
+    ///
+    /// Features placed exactly on voxel centers line up unnaturally, so each is moved off its
+    /// voxel's center by up to `FEATURE_JITTER` along each axis. The offset depends only on the
+    /// voxel and the node it's in, so it's the same every time the chunk is generated, whichever
+    /// nodes were generated first, and never carries a feature out of its voxel.
+    pub fn feature_position(&self, coords: na::Vector3<u8>) -> na::Vector3<f64> {
+        voxel_center(self.dimension, coords)
+            + feature_jitter(self.node_spice, self.chunk, coords) / f64::from(self.dimension)
+    }
+
     fn generate_terrain(&self, center: na::Vector3<f64>) -> Material {
         let cube_coords = center * 0.5;
 
@@ -272,7 +328,18 @@ impl ChunkParams {
         }
 
         let voxel_elevation = self.surface.distance_to_chunk(self.chunk, &center);
-        if voxel_elevation >= max_e / ELEVATION_SCALE {
+        let solid = match self.preset {
+            Preset::Plains | Preset::Flat => voxel_elevation < max_e / ELEVATION_SCALE,
+            Preset::Caves => {
+                // Caverns vary in height, pinching shut in places, with the terrain above them
+                let depth = max_e - voxel_elevation * ELEVATION_SCALE;
+                let height = CAVE_HEIGHT * (1.0 + (slope + rain).sin()) / 2.0;
+                depth > 0.0 && !(depth > CAVE_ROOF && depth < CAVE_ROOF + height)
+            }
+            // Mirroring the terrain below the surface gives each hill an underside
+            Preset::Islands => voxel_elevation.abs() < max_e / ELEVATION_SCALE,
+        };
+        if !solid {
             voxel_mat = Material::Void;
         }
 
@@ -342,6 +409,10 @@ impl ChunkParams {
 
     /// Generate voxels making up the chunk
     pub fn generate_voxels(&self) -> VoxelData {
+        if self.preset == Preset::Flat {
+            return self.generate_flat();
+        }
+
         // Determine whether this chunk might contain a boundary between solid and void
         let mut me_min = self.env.max_elevations[0];
         let mut me_max = self.env.max_elevations[0];
@@ -349,10 +420,6 @@ impl ChunkParams {
             me_min = me_min.min(me);
             me_max = me_max.max(me);
         }
-        // Maximum difference between elevations at the center of a chunk and any other point in the chunk
-        // TODO: Compute what this actually is, current value is a guess! Real one must be > 0.6
-        // empirically.
-        const ELEVATION_MARGIN: f64 = 0.7;
         let center_elevation = self
             .surface
             .distance_to_chunk(self.chunk, &na::Vector3::repeat(0.5));
@@ -363,7 +430,22 @@ impl ChunkParams {
             return VoxelData::Solid(Material::Void);
         }
 
-        if (center_elevation + ELEVATION_MARGIN < me_min / ELEVATION_SCALE) && !self.is_road {
+        if self.preset == Preset::Islands
+            && (center_elevation + ELEVATION_MARGIN < -me_max / ELEVATION_SCALE)
+            && !self.is_road
+        {
+            // The whole chunk is below any island
+            return VoxelData::Solid(Material::Void);
+        }
+
+        let hollow_depth = match self.preset {
+            Preset::Caves => CAVE_ROOF + CAVE_HEIGHT,
+            _ => 0.0,
+        };
+        if self.preset != Preset::Islands
+            && (center_elevation + ELEVATION_MARGIN < (me_min - hollow_depth) / ELEVATION_SCALE)
+            && !self.is_road
+        {
             // The whole chunk is underground
             // TODO: More accurate VoxelData
             return VoxelData::Solid(Material::Stone);
@@ -390,8 +472,8 @@ impl ChunkParams {
                         None
                     };
                     let mat = mat.unwrap_or_else(|| self.generate_terrain(center));
-                    if mat != Material::Void {
-                        voxels.data_mut(self.dimension)[index(self.dimension, coords)] = mat;
+                    if let Ok(mat) = SolidMaterial::try_from(mat) {
+                        voxels.place(self.dimension, index(self.dimension, coords), mat);
                     }
                 }
             }
@@ -413,7 +495,7 @@ impl ChunkParams {
 
                 let num_void_neighbors = neighbor_data
                     .iter()
-                    .filter(|n| n.material == Material::Void)
+                    .filter(|n| n.material.is_void())
                     .count();
 
                 // Only plant a tree if there is exactly one adjacent block of dirt, grass, or flowers.
@@ -423,10 +505,11 @@ impl ChunkParams {
                             || (i.material == Material::Grass)
                             || (i.material == Material::Flowergrass)
                         {
-                            voxels.data_mut(self.dimension)[voxel_of_interest_index] =
-                                Material::Wood;
+                            let wood = SolidMaterial::new(Material::Wood).unwrap();
+                            voxels.place(self.dimension, voxel_of_interest_index, wood);
                             let leaf_location = index(self.dimension, i.coords_opposing);
-                            voxels.data_mut(self.dimension)[leaf_location] = Material::Leaves;
+                            let leaves = SolidMaterial::new(Material::Leaves).unwrap();
+                            voxels.place(self.dimension, leaf_location, leaves);
                         }
                     }
                 }
@@ -435,9 +518,67 @@ impl ChunkParams {
 
         voxels
     }
+
+    /// Generate voxels for `Preset::Flat`
+    fn generate_flat(&self) -> VoxelData {
+        let floor = FLAT_ELEVATION / ELEVATION_SCALE;
+        let center_elevation = self
+            .surface
+            .distance_to_chunk(self.chunk, &na::Vector3::repeat(0.5));
+        if center_elevation - ELEVATION_MARGIN > floor {
+            return VoxelData::Solid(Material::Void);
+        }
+        if center_elevation + ELEVATION_MARGIN < floor {
+            return VoxelData::Solid(Material::Grass);
+        }
+
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let grass = SolidMaterial::new(Material::Grass).unwrap();
+        for z in 0..self.dimension {
+            for y in 0..self.dimension {
+                for x in 0..self.dimension {
+                    let coords = na::Vector3::new(x, y, z);
+                    let center = voxel_center(self.dimension, coords);
+                    if self.surface.distance_to_chunk(self.chunk, &center) < floor {
+                        voxels.place(self.dimension, index(self.dimension, coords), grass);
+                    }
+                }
+            }
+        }
+        voxels
+    }
+}
+
+/// Generate each of `chunks` on up to `threads` threads
+///
+/// Each chunk depends only on its own parameters, so the result is identical for any `threads`,
+/// including 1, which generates everything on the calling thread.
+pub fn generate_all(chunks: Vec<ChunkParams>, threads: usize) -> Vec<VoxelData> {
+    parallel::map(chunks, threads, |params| params.generate_voxels())
 }
 
+/// Revision of the generation algorithm
+///
+/// Must be incremented by any change that alters the contents of chunks generated from the same
+/// seed, so that chunks generated by an earlier revision can be recognized and regenerated rather
+/// than leaving seams against newer ones.
+pub const VERSION: u32 = 1;
+
 const ELEVATION_SCALE: f64 = 10.0;
+/// Maximum difference between elevations at the center of a chunk and any other point in the chunk
+// TODO: Compute what this actually is, current value is a guess! Real one must be > 0.6
+// empirically.
+const ELEVATION_MARGIN: f64 = 0.7;
+/// Depth below the terrain surface at which caverns begin, in elevation units
+const CAVE_ROOF: f64 = 3.0;
+/// Greatest height of a cavern, in elevation units
+const CAVE_HEIGHT: f64 = 4.0;
+/// Elevation of the floor generated by `Preset::Flat`, in elevation units
+const FLAT_ELEVATION: f64 = -2.0;
+/// Farthest a feature is moved along each axis from the center of its voxel, in voxels
+///
+/// Well under half a voxel, so features in neighboring voxels never meet.
+const FEATURE_JITTER: f64 = 0.25;
 
 struct NeighborData {
     coords_opposing: na::Vector3<u8>,
@@ -481,6 +622,81 @@ fn neighbor(
     }
 }
 
+/// Colors multiplied into tintable materials throughout a chunk, varying with climate
+///
+/// A color is chosen for each node incident to the chunk, and blended across it just as the
+/// climate is when generating terrain, so that tints vary smoothly between chunks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChunkTints {
+    /// Colors of the incident nodes, in the order of `Vertex::dual_vertices`, as packed by GLSL's
+    /// `packUnorm4x8`
+    corners: [u32; 8],
+}
+
+impl ChunkTints {
+    /// Returns `None` if an unpopulated node is needed.
+    pub fn new(graph: &DualGraph, node: NodeId, chunk: Vertex) -> Option<Self> {
+        let env = chunk_incident_enviro_factors(graph, node, chunk)?;
+        Some(Self::from_climate(&env.temperatures, &env.rainfalls))
+    }
+
+    fn from_climate(temperatures: &[f64; 8], rainfalls: &[f64; 8]) -> Self {
+        let mut corners = [0; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let color = biome_tint(temperatures[i], rainfalls[i]);
+            *corner = color
+                .iter()
+                .enumerate()
+                .map(|(j, &x)| u32::from((x * 255.0).round() as u8) << (8 * j))
+                .fold(0xFF00_0000, |acc, x| acc | x);
+        }
+        Self { corners }
+    }
+
+    /// Form taken by the voxel vertex shader
+    pub fn packed(&self) -> [u32; 8] {
+        self.corners
+    }
+
+    /// Color of `material` at `coords`, within the unit cube of chunk coordinates
+    ///
+    /// Must agree with `voxels.vert`.
+    pub fn at(&self, material: Material, coords: na::Vector3<f32>) -> na::Vector3<f32> {
+        if !material.is_tintable() {
+            return na::Vector3::repeat(1.0);
+        }
+        let channel = |j: usize| {
+            let mut values = [0.0; 8];
+            for (value, &corner) in values.iter_mut().zip(&self.corners) {
+                *value = ((corner >> (8 * j)) & 0xFF) as f32 / 255.0;
+            }
+            // A chunk spans the half of the cube of incident nodes nearest its own node
+            trilerp(&values, coords * 0.5)
+        };
+        na::Vector3::new(channel(0), channel(1), channel(2))
+    }
+}
+
+impl Default for ChunkTints {
+    /// No tint at all
+    fn default() -> Self {
+        Self {
+            corners: [0xFFFF_FFFF; 8],
+        }
+    }
+}
+
+/// Color of foliage in a climate: yellowed where dry, lush where wet, and bluish where cold
+fn biome_tint(temperature: f64, rainfall: f64) -> na::Vector3<f32> {
+    let warmth = (temperature / 8.0).tanh() as f32;
+    let wetness = (rainfall / 8.0).tanh() as f32;
+    na::Vector3::new(
+        0.85 + 0.075 * warmth - 0.075 * wetness,
+        0.9 + 0.1 * wetness,
+        0.75 - 0.15 * warmth - 0.1 * wetness,
+    )
+}
+
 #[derive(Copy, Clone)]
 struct EnviroFactors {
     max_elevation: i64,
@@ -618,6 +834,18 @@ fn voxel_center(dimension: u8, voxel: na::Vector3<u8>) -> na::Vector3<f64> {
     voxel.map(|x| f64::from(x) + 0.5) / f64::from(dimension)
 }
 
+/// Offset of a feature from the center of the voxel at `coords`, in voxels
+fn feature_jitter(node_spice: u64, chunk: Vertex, coords: na::Vector3<u8>) -> na::Vector3<f64> {
+    let voxel = u64::from(coords.x) | u64::from(coords.y) << 8 | u64::from(coords.z) << 16;
+    let bits = hash(hash(node_spice, chunk as u64), voxel);
+    // 21 bits per axis, mapped onto [-FEATURE_JITTER, FEATURE_JITTER)
+    let axis = |i: u32| {
+        let unit = ((bits >> (21 * i)) & 0x1f_ffff) as f64 / f64::from(1u32 << 21);
+        (unit * 2.0 - 1.0) * FEATURE_JITTER
+    };
+    na::Vector3::new(axis(0), axis(1), axis(2))
+}
+
 fn index(dimension: u8, v: na::Vector3<u8>) -> usize {
     let v = v.map(|x| usize::from(x) + 1);
 
@@ -636,7 +864,7 @@ fn hash(a: u64, b: u64) -> u64 {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::node::{DualGraph, Node};
+    use crate::node::{populate_fresh_nodes, DualGraph, Node};
     use crate::Chunks;
     use approx::*;
 
@@ -697,6 +925,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn biome_tints() {
+        let cold_dry = ChunkTints::from_climate(&[-10.0; 8], &[-10.0; 8]);
+        let warm_wet = ChunkTints::from_climate(&[10.0; 8], &[10.0; 8]);
+        let coords = na::Vector3::repeat(0.5);
+        assert_ne!(
+            cold_dry.at(Material::Grass, coords),
+            warm_wet.at(Material::Grass, coords)
+        );
+        for tints in &[cold_dry, warm_wet, ChunkTints::default()] {
+            assert_eq!(tints.at(Material::Stone, coords), na::Vector3::repeat(1.0));
+        }
+        assert_eq!(
+            ChunkTints::default().at(Material::Grass, coords),
+            na::Vector3::repeat(1.0)
+        );
+
+        // Blended between differing climates
+        let mut temperatures = [-10.0; 8];
+        temperatures[0] = 10.0;
+        let mixed = ChunkTints::from_climate(&temperatures, &[0.0; 8]);
+        let near = mixed.at(Material::Grass, na::Vector3::zeros());
+        let far = mixed.at(Material::Grass, na::Vector3::repeat(1.0));
+        assert_ne!(near, far);
+        let warm = ChunkTints::from_climate(&[10.0; 8], &[0.0; 8]);
+        assert_eq!(near, warm.at(Material::Grass, na::Vector3::zeros()));
+    }
+
     #[test]
     fn check_chunk_incident_max_elevations() {
         let mut g = DualGraph::new();
@@ -746,6 +1002,154 @@ mod test {
         }
     }
 
+    /// A world whose root node has every neighbor needed to generate its chunks
+    fn populated_root() -> DualGraph {
+        let mut g = DualGraph::new();
+        for vertex in Vertex::iter() {
+            for (_, path) in vertex.dual_vertices() {
+                path.fold(NodeId::ROOT, |node, side| g.ensure_neighbor(node, side));
+            }
+        }
+        populate_fresh_nodes(&mut g);
+        g
+    }
+
+    /// Every chunk of the root node, generated with `preset`
+    fn generate_root(g: &DualGraph, preset: Preset) -> Vec<VoxelData> {
+        Vertex::iter()
+            .map(|chunk| {
+                ChunkParams::new(CHUNK_SIZE, preset, g, NodeId::ROOT, chunk)
+                    .unwrap()
+                    .generate_voxels()
+            })
+            .collect()
+    }
+
+    fn materials(voxels: &VoxelData) -> Vec<Material> {
+        (0..usize::from(CHUNK_SIZE + 2).pow(3))
+            .map(|i| voxels.get(i))
+            .collect()
+    }
+
+    #[test]
+    fn presets_differ() {
+        let g = populated_root();
+        let presets = [Preset::Plains, Preset::Caves, Preset::Islands, Preset::Flat];
+        let worlds = presets
+            .iter()
+            .map(|&preset| {
+                generate_root(&g, preset)
+                    .iter()
+                    .map(materials)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for i in 0..presets.len() {
+            // Generation is deterministic
+            assert_eq!(
+                generate_root(&g, presets[i])
+                    .iter()
+                    .map(materials)
+                    .collect::<Vec<_>>(),
+                worlds[i]
+            );
+            for j in 0..i {
+                assert_ne!(
+                    worlds[i], worlds[j],
+                    "{:?} and {:?} are identical",
+                    presets[i], presets[j]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn threads_agree() {
+        let g = populated_root();
+        let generate = |threads| {
+            let chunks = Vertex::iter()
+                .map(|chunk| {
+                    ChunkParams::new(CHUNK_SIZE, Preset::Caves, &g, NodeId::ROOT, chunk).unwrap()
+                })
+                .collect();
+            generate_all(chunks, threads)
+                .iter()
+                .flat_map(materials)
+                .flat_map(|m| (m as u16).to_le_bytes().to_vec())
+                .collect::<Vec<u8>>()
+        };
+        let serial = generate(1);
+        assert_eq!(generate(4), serial);
+        assert_eq!(generate(64), serial);
+    }
+
+    #[test]
+    fn feature_jitter_stable() {
+        let params = |g: &DualGraph, chunk| {
+            ChunkParams::new(CHUNK_SIZE, Preset::Plains, g, NodeId::ROOT, chunk).unwrap()
+        };
+        let (a, b) = (populated_root(), populated_root());
+        let (a, b) = (params(&a, Vertex::C), params(&b, Vertex::C));
+        let mut offsets = Vec::new();
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let coords = na::Vector3::new(x, y, z);
+                    let position = a.feature_position(coords);
+                    // Same in every run
+                    assert_eq!(position, b.feature_position(coords));
+                    // Within the voxel, and well clear of its neighbors
+                    let center = coords.map(|x| f64::from(x) + 0.5);
+                    let offset = position * f64::from(CHUNK_SIZE) - center;
+                    assert!(
+                        offset.amax() < FEATURE_JITTER + 1e-9,
+                        "{:?} left its voxel",
+                        coords
+                    );
+                    offsets.push(offset);
+                }
+            }
+        }
+        // Not all moved alike
+        assert!(offsets
+            .iter()
+            .any(|x| (x - offsets[0]).amax() > FEATURE_JITTER / 2.0));
+        // Nor alike in every chunk
+        let other = params(&populated_root(), Vertex::D);
+        let corner = na::Vector3::repeat(1);
+        assert_ne!(other.feature_position(corner), a.feature_position(corner));
+    }
+
+    #[test]
+    fn flat_floor() {
+        let g = populated_root();
+        let surface = g.get(NodeId::ROOT).as_ref().unwrap().state.surface;
+        let mut straddled = false;
+        for (chunk, voxels) in Vertex::iter().zip(generate_root(&g, Preset::Flat)) {
+            let (mut solid, mut void) = (0, 0);
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        let coords = na::Vector3::new(x, y, z);
+                        let center = voxel_center(CHUNK_SIZE, coords);
+                        let below = surface.distance_to_chunk(chunk, &center)
+                            < FLAT_ELEVATION / ELEVATION_SCALE;
+                        let material = voxels.get(index(CHUNK_SIZE, coords));
+                        if below {
+                            assert_eq!(material, Material::Grass);
+                            solid += 1;
+                        } else {
+                            assert_eq!(material, Material::Void);
+                            void += 1;
+                        }
+                    }
+                }
+            }
+            straddled |= solid > 0 && void > 0;
+        }
+        assert!(straddled, "no chunk contains the floor");
+    }
+
     #[test]
     fn check_trilerp() {
         assert_abs_diff_eq!(
@@ -0,0 +1,47 @@
+//! The simulation core, as used without the `graphics` feature
+//!
+//! Nothing here depends on drawing, so `cargo test -p common --no-default-features` confirms that
+//! generating, editing, and colliding with terrain all build and work feature-free.
+
+use common::{
+    collision::ChunkCollision,
+    dodeca::Vertex,
+    graph::NodeId,
+    node::{populate_fresh_nodes, voxel_index, voxel_to_world_center, Chunk, ChunkId, DualGraph},
+    world::{material_at, Material},
+    worldgen::{ChunkParams, Preset},
+};
+
+const DIMENSION: u8 = 12;
+
+#[test]
+fn generate_edit_collide() {
+    let mut graph = DualGraph::new();
+    for (_, path) in Vertex::A.dual_vertices() {
+        path.fold(NodeId::ROOT, |node, side| graph.ensure_neighbor(node, side));
+    }
+    populate_fresh_nodes(&mut graph);
+
+    let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+    let voxels = ChunkParams::new(DIMENSION, Preset::Flat, &graph, chunk.node, chunk.vertex)
+        .unwrap()
+        .generate_voxels();
+    let populated = &mut graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.vertex];
+    *populated = Chunk::populated(DIMENSION, voxels);
+    let index = voxel_index(DIMENSION, [5, 6, 7]);
+    assert!(populated.set_voxel(DIMENSION, index, Material::Stone));
+
+    let voxels = match *populated {
+        Chunk::Populated { ref voxels, .. } => voxels.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(voxels.get(index), Material::Stone);
+    let center = na::Vector3::new(5.5, 6.5, 7.5);
+    assert!(ChunkCollision::new(DIMENSION, &voxels).contains(&center));
+
+    let point = voxel_to_world_center(DIMENSION, chunk, [5, 6, 7]);
+    assert_eq!(
+        material_at(&graph, DIMENSION, chunk.node, &point),
+        Some(Material::Stone)
+    );
+}
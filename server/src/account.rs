@@ -0,0 +1,107 @@
+//! Verification of who players are
+//!
+//! Players choose their own names, so a name alone can't carry permissions or bans. Instead the
+//! server config reserves names as accounts, each with a secret token that a client must present
+//! to play under that name. Anyone else is a guest, identified by their address.
+
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+use serde::Deserialize;
+
+use common::proto::{ClientHello, Permission};
+
+/// A name reserved for the player who knows its token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    /// Secret the player presents in `ClientHello::token`
+    pub token: String,
+    #[serde(default)]
+    pub permission: Permission,
+}
+
+/// Who a connection belongs to, as far as the server can verify
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Identity {
+    /// A player who presented the token of the account with this name
+    Account(Arc<str>),
+    /// A player without an account, known only by the address they connect from, since the name
+    /// they chose proves nothing
+    Guest(IpAddr),
+}
+
+impl Identity {
+    /// Name of the account the player holds, if any
+    pub fn account(&self) -> Option<&Arc<str>> {
+        match *self {
+            Identity::Account(ref name) => Some(name),
+            Identity::Guest(_) => None,
+        }
+    }
+}
+
+/// Determine who sent `hello` from `address`, and what they may do
+///
+/// Returns `None` if `hello` claims an account without its token.
+pub fn authenticate(
+    accounts: &HashMap<String, Account>,
+    hello: &ClientHello,
+    address: IpAddr,
+) -> Option<(Identity, Permission)> {
+    let account = match accounts.get(&hello.name) {
+        Some(x) => x,
+        None => return Some((Identity::Guest(address), Permission::Player)),
+    };
+    match hello.token {
+        Some(ref token) if secrets_match(token.as_bytes(), account.token.as_bytes()) => Some((
+            Identity::Account(hello.name.as_str().into()),
+            account.permission,
+        )),
+        _ => None,
+    }
+}
+
+/// Compare secrets in time independent of where they differ, so that timing doesn't reveal them
+fn secrets_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::WorldId;
+
+    #[test]
+    fn accounts_need_tokens() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            "alice".to_string(),
+            Account {
+                token: "hunter2".into(),
+                permission: Permission::Admin,
+            },
+        );
+        let address = IpAddr::from([192, 0, 2, 1]);
+        let hello = |name: &str, token: Option<&str>| ClientHello {
+            name: name.into(),
+            world: WorldId::DEFAULT,
+            token: token.map(Into::into),
+        };
+        assert_eq!(
+            authenticate(&accounts, &hello("alice", Some("hunter2")), address),
+            Some((Identity::Account("alice".into()), Permission::Admin))
+        );
+        assert_eq!(
+            authenticate(&accounts, &hello("alice", None), address),
+            None
+        );
+        assert_eq!(
+            authenticate(&accounts, &hello("alice", Some("hunter3")), address),
+            None
+        );
+        assert_eq!(
+            authenticate(&accounts, &hello("bob", None), address),
+            Some((Identity::Guest(address), Permission::Player))
+        );
+    }
+}
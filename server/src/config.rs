@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs,
     net::{Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
@@ -7,7 +8,8 @@ use std::{
 use anyhow::{Context, Result};
 use serde::Deserialize;
 
-use common::SimConfigRaw;
+use common::{SimConfigRaw, WorldSeed};
+use server::{Account, EditRate};
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -16,16 +18,48 @@ pub struct Config {
     pub certificate_chain: Option<PathBuf>,
     pub private_key: Option<PathBuf>,
     pub listen: SocketAddr,
+    /// Maximum number of simultaneously connected players
+    pub max_players: Option<u32>,
+    /// Directory to save edited chunks to. The world isn't saved if unset.
+    pub save_path: Option<PathBuf>,
+    /// Seconds between saves
+    pub autosave_interval: Option<u64>,
+    /// File to record the session to, for later replay
+    pub record_path: Option<PathBuf>,
+    /// Seed of each world to host, which clients select by position. Defaults to a single world.
+    pub world_seeds: Option<Vec<WorldSeed>>,
+    /// Names reserved for players who know a secret token, and the permission each grants, e.g.
+    /// `alice = { token = "correct horse battery staple", permission = "admin" }`. Anyone may
+    /// join under any other name, as a guest without special permission.
+    #[serde(default)]
+    pub accounts: HashMap<String, Account>,
+    /// Run every world in lockstep, advancing only once every player's input for the next step
+    /// has arrived. Suits competitive modes needing reproducible simulation, on fast networks.
+    #[serde(default)]
+    pub lockstep: bool,
+    /// Seconds a player may go without moving or looking around before they're considered idle.
+    /// Idle players stop receiving entity updates until they return. Never if unset.
+    pub idle_timeout: Option<u64>,
+    /// Whether idle players' characters are held in place until they return
+    #[serde(default)]
+    pub freeze_idle: bool,
+    /// How quickly each player may edit voxels, e.g. `{ rate = 10.0, burst = 20 }`. Excess edits
+    /// are refused, and with `disconnect_after` set, a player who keeps it up is disconnected.
+    /// Unlimited if unset.
+    pub edit_rate: Option<EditRate>,
     #[serde(default)]
     pub simulation: SimConfigRaw,
 }
 
 impl Config {
     pub fn load(path: &Path) -> Result<Self> {
-        Ok(
-            toml::from_slice(&fs::read(path).context("reading config file")?)
-                .context("parsing config file")?,
-        )
+        let mut config: Self = toml::from_slice(&fs::read(path).context("reading config file")?)
+            .context("parsing config file")?;
+        config.simulation = config
+            .simulation
+            .migrate()
+            .context("upgrading simulation config")?;
+        Ok(config)
     }
 }
 
@@ -36,7 +70,110 @@ impl Default for Config {
             certificate_chain: None,
             private_key: None,
             listen: SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 1234),
+            max_players: None,
+            save_path: None,
+            autosave_interval: None,
+            record_path: None,
+            world_seeds: None,
+            accounts: HashMap::new(),
+            lockstep: false,
+            idle_timeout: None,
+            freeze_idle: false,
+            edit_rate: None,
             simulation: SimConfigRaw::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::proto::Permission;
+
+    #[test]
+    fn mixed_seeds() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = "[::]:1234"
+            world_seeds = [7, "fungal caverns", "7"]
+            "#,
+        )
+        .unwrap();
+        let seeds = config.world_seeds.unwrap();
+        assert_eq!(
+            seeds,
+            [
+                WorldSeed::Number(7),
+                WorldSeed::Name("fungal caverns".into()),
+                WorldSeed::Name("7".into()),
+            ]
+        );
+        assert_eq!(seeds[0].value(), 7);
+        assert_ne!(seeds[2].value(), 7);
+    }
+
+    #[test]
+    fn edit_rate() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = "[::]:1234"
+            edit_rate = { rate = 10.0, burst = 20 }
+            "#,
+        )
+        .unwrap();
+        let rate = config.edit_rate.unwrap();
+        assert_eq!(rate.rate, 10.0);
+        assert_eq!(rate.burst, 20);
+        assert_eq!(rate.disconnect_after, None);
+    }
+
+    #[test]
+    fn accounts() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = "[::]:1234"
+            [accounts]
+            alice = { token = "secret", permission = "admin" }
+            bob = { token = "hidden" }
+            "#,
+        )
+        .unwrap();
+        let alice = &config.accounts["alice"];
+        assert_eq!(alice.token, "secret");
+        assert_eq!(alice.permission, Permission::Admin);
+        assert_eq!(config.accounts["bob"].permission, Permission::Player);
+    }
+
+    #[test]
+    fn unversioned_simulation() {
+        use common::{SimConfig, SIM_CONFIG_VERSION};
+
+        // A config written before the simulation config was versioned
+        let path = std::env::temp_dir().join(format!("hypermine-config-{}", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+            server_name = "localhost"
+            listen = "[::]:1234"
+            [simulation]
+            rate = 20
+            view_distance = 60.0
+            input_queue_size_ms = 100
+            chunk_size = 16
+            voxel_size = 0.5
+            movement_speed = 6.0
+            "#,
+        )
+        .unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        let simulation = config.unwrap().simulation;
+        assert_eq!(simulation.version, Some(SIM_CONFIG_VERSION));
+        assert_eq!(simulation.rate, Some(20));
+        assert_eq!(simulation.chunk_size, Some(16));
+        assert_eq!(simulation.movement_speed, Some(6.0));
+        // Everything added since is left to its default
+        assert_eq!(simulation.day_length_secs, None);
+        assert_eq!(SimConfig::from_raw(&simulation).steps_per_day, 1200 * 20);
+    }
+}
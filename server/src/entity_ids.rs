@@ -0,0 +1,84 @@
+use hecs::Entity;
+
+use common::EntityId;
+
+/// Maps entity IDs to entities, allocating IDs such that no ID is ever reused
+///
+/// The slot of a destroyed entity is recycled under a new generation, so clients that have yet to
+/// hear of the destruction can't confuse the slot's next occupant with the old one.
+#[derive(Default)]
+pub struct EntityIds {
+    slots: Vec<Slot>,
+    /// Indices of unoccupied slots
+    free: Vec<u32>,
+}
+
+struct Slot {
+    generation: u32,
+    entity: Option<Entity>,
+}
+
+impl EntityIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate an ID and associate it with the entity produced by `f`
+    pub fn insert_with(&mut self, f: impl FnOnce(EntityId) -> Entity) -> (EntityId, Entity) {
+        let index = match self.free.pop() {
+            Some(x) => x,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entity: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        let id = EntityId::new(index, slot.generation);
+        let entity = f(id);
+        slot.entity = Some(entity);
+        (id, entity)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<Entity> {
+        let slot = self.slots.get(id.index() as usize)?;
+        if slot.generation != id.generation() {
+            return None;
+        }
+        slot.entity
+    }
+
+    /// Free `id`'s slot for reuse under a new generation
+    pub fn remove(&mut self, id: EntityId) -> Option<Entity> {
+        self.get(id)?;
+        let slot = &mut self.slots[id.index() as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index());
+        slot.entity.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let mut world = hecs::World::new();
+        let mut ids = EntityIds::new();
+        let (a, a_entity) = ids.insert_with(|id| world.spawn((id,)));
+        assert_eq!(ids.get(a), Some(a_entity));
+        assert_eq!(ids.remove(a), Some(a_entity));
+        assert_eq!(ids.get(a), None);
+        assert_eq!(ids.remove(a), None);
+
+        let (b, b_entity) = ids.insert_with(|id| world.spawn((id,)));
+        assert_eq!(b.index(), a.index());
+        assert_ne!(b, a);
+        assert!(a.is_older_than(b));
+        assert_eq!(ids.get(a), None);
+        assert_eq!(ids.get(b), Some(b_entity));
+    }
+}
@@ -0,0 +1,113 @@
+//! Detection of players who've stopped playing without disconnecting
+//!
+//! Clients send a command every step whether or not anyone's at the controls, so a player counts
+//! as active only while their commands move or turn their character, or ask something of the
+//! server.
+
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use common::proto::Command;
+
+pub struct Idle {
+    /// How long a player may be inactive before they're idle
+    timeout: Duration,
+    last_active: Instant,
+    /// Orientation sent in the previous command, to detect looking around
+    orientation: na::UnitQuaternion<f32>,
+    idle: bool,
+}
+
+impl Idle {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_active: now,
+            orientation: na::one(),
+            idle: false,
+        }
+    }
+
+    /// Note a command from the player, returning whether it woke them from idleness
+    pub fn input(&mut self, command: &Command, now: Instant) -> bool {
+        let active = command.velocity != na::zero()
+            || command.orientation != self.orientation
+            || command.admin.is_some()
+            || command.edit.is_some();
+        self.orientation = command.orientation;
+        if !active {
+            return false;
+        }
+        self.last_active = now;
+        mem::replace(&mut self.idle, false)
+    }
+
+    /// Check for the timeout having passed, returning whether the player became idle just now
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self.idle || now.saturating_duration_since(self.last_active) < self.timeout {
+            return false;
+        }
+        self.idle = true;
+        true
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(velocity: na::Vector3<f32>) -> Command {
+        Command {
+            generation: 0,
+            orientation: na::one(),
+            velocity,
+            resync: false,
+            ping: None,
+            admin: None,
+            edit: None,
+            fire: false,
+            tick: None,
+        }
+    }
+
+    #[test]
+    fn idle_and_wake() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut idle = Idle::new(timeout, start);
+        let still = command(na::zero());
+        let step = Duration::from_secs(10);
+        let mut now = start;
+        while now < start + timeout {
+            assert!(!idle.input(&still, now));
+            assert!(!idle.poll(now));
+            now += step;
+        }
+        // Standing still for the whole timeout
+        assert!(idle.poll(now));
+        assert!(idle.is_idle());
+        assert!(!idle.poll(now + step), "only reported once");
+        assert!(!idle.input(&still, now + step));
+        assert!(idle.is_idle());
+
+        // Moving wakes them
+        now += 2 * step;
+        assert!(idle.input(&command(-na::Vector3::z()), now));
+        assert!(!idle.is_idle());
+        assert!(!idle.poll(now + step));
+
+        // So does looking around
+        now += timeout;
+        assert!(idle.poll(now));
+        let mut turn = command(na::zero());
+        turn.orientation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.1);
+        assert!(idle.input(&turn, now));
+        assert!(!idle.is_idle());
+    }
+}
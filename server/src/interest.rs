@@ -0,0 +1,126 @@
+use std::mem;
+
+use fxhash::FxHashSet;
+use hecs::Entity;
+
+use common::{
+    proto::{Spawns, StateDelta},
+    EntityId,
+};
+
+use crate::sim::Sim;
+
+/// The set of entities a client has been informed of
+///
+/// Clients can only see entities within the view distance of their character, so rather than
+/// broadcasting every update to everyone, we notify each client explicitly as entities enter and
+/// leave that radius and omit state updates for anything it doesn't know about.
+#[derive(Default)]
+pub struct Interest {
+    known: FxHashSet<EntityId>,
+    /// Whether the client has asked to be sent everything from scratch
+    resync: bool,
+}
+
+impl Interest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget everything the client has been told, so the next update informs it afresh
+    pub fn reset(&mut self) {
+        self.known.clear();
+        self.resync = true;
+    }
+
+    /// Restrict a snapshot to the entities visible to `character`
+    pub fn snapshot(&mut self, sim: &Sim, character: Entity, mut snapshot: Spawns) -> Spawns {
+        let visible = sim.visible_entities(character);
+        snapshot.spawns.retain(|(id, _)| visible.contains(id));
+        self.known.extend(snapshot.spawns.iter().map(|&(id, _)| id));
+        snapshot
+    }
+
+    /// Tailor the changes from a simulation step to the entities visible to `character`
+    pub fn update(&mut self, sim: &Sim, character: Entity, spawns: &Spawns) -> Spawns {
+        let visible = sim.visible_entities(character);
+        let hits = spawns
+            .hits
+            .iter()
+            .filter(|hit| self.known.contains(&hit.projectile))
+            .cloned()
+            .collect();
+        let despawns = spawns
+            .despawns
+            .iter()
+            .cloned()
+            .filter(|id| self.known.remove(id))
+            .collect();
+        let leaves = self
+            .known
+            .iter()
+            .cloned()
+            .filter(|id| !visible.contains(id))
+            .collect::<Vec<_>>();
+        for id in &leaves {
+            self.known.remove(id);
+        }
+        let fresh = spawns
+            .spawns
+            .iter()
+            .filter(|(id, _)| visible.contains(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.known.extend(fresh.iter().map(|&(id, _)| id));
+        let enters = visible
+            .into_iter()
+            .filter(|id| !self.known.contains(id))
+            .map(|id| (id, sim.dump(id)))
+            .collect::<Vec<_>>();
+        self.known.extend(enters.iter().map(|&(id, _)| id));
+        Spawns {
+            step: spawns.step,
+            spawns: fresh,
+            despawns,
+            nodes: spawns.nodes.clone(),
+            enters,
+            leaves,
+            hits,
+            // A client resynchronizing may have missed or misapplied earlier changes
+            voxels: if self.resync {
+                sim.edited_chunks()
+            } else {
+                spawns.voxels.clone()
+            },
+            metadata: spawns.metadata.clone(),
+            reconfigure: spawns.reconfigure,
+            resync: mem::replace(&mut self.resync, false),
+        }
+    }
+
+    /// Omit state for entities the client doesn't know about
+    pub fn filter_delta(&self, delta: &StateDelta) -> StateDelta {
+        StateDelta {
+            step: delta.step,
+            latest_input: delta.latest_input,
+            time_of_day: delta.time_of_day,
+            pong: delta.pong,
+            inventory: delta.inventory.clone(),
+            admin_replies: delta.admin_replies.clone(),
+            edit_rejections: delta.edit_rejections.clone(),
+            positions: delta
+                .positions
+                .iter()
+                .filter(|(id, _)| self.known.contains(id))
+                .cloned()
+                .collect(),
+            character_orientations: delta
+                .character_orientations
+                .iter()
+                .filter(|(id, _)| self.known.contains(id))
+                .cloned()
+                .collect(),
+            hash: None,
+        }
+    }
+}
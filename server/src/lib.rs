@@ -1,31 +1,102 @@
+mod account;
+mod entity_ids;
+mod idle;
 mod input_queue;
+mod interest;
+mod lockstep;
+mod protection;
+mod rate_limit;
+mod record;
+mod save;
 mod sim;
 
 use std::{
+    collections::HashMap,
+    fs, mem,
     net::UdpSocket,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Error, Result};
 use futures::{select, StreamExt, TryStreamExt};
+use fxhash::FxHashSet;
 use hecs::Entity;
 use slotmap::DenseSlotMap;
 use tokio::sync::mpsc;
-use tracing::{debug, error, error_span, info, trace};
+use tracing::{debug, error, error_span, info, trace, warn};
 
-use common::{codec, proto, SimConfig};
+use account::Identity;
+use common::{
+    codec,
+    dodeca::Vertex,
+    graph::NodePath,
+    node::ChunkId,
+    proto::{self, AdminCommand, Permission},
+    EntityId, SimConfig, Step, WorldId,
+};
+use idle::Idle;
 use input_queue::InputQueue;
+use interest::Interest;
+use lockstep::Lockstep;
+use record::{Event, Recorder};
+use save::Autosave;
 use sim::Sim;
 
+pub use account::Account;
+pub use protection::{EditDenied, Protections, RegionId, SavedRegion};
+pub use rate_limit::{Admission, EditLimiter, EditRate};
+pub use record::replay;
+pub use save::SaveParams;
+
 pub struct NetParams {
     pub certificate_chain: quinn::CertificateChain,
     pub private_key: quinn::PrivateKey,
     pub socket: UdpSocket,
+    /// Maximum number of simultaneously connected players
+    pub max_players: u32,
+    /// Names reserved for players who present the matching token, and the permission each grants
+    pub accounts: HashMap<String, Account>,
+    /// Whether every world runs in lockstep, advancing only once every player's input for the
+    /// next step has arrived
+    pub lockstep: bool,
+    /// How long a player may go without moving or looking around before they're idle, if ever
+    pub idle_timeout: Option<Duration>,
+    /// Whether idle players' characters are held in place until they return
+    pub freeze_idle: bool,
+    /// How quickly each player may edit voxels, if limited
+    pub edit_rate: Option<EditRate>,
+}
+
+/// A config file to watch for changes to simulation parameters
+pub struct ConfigSource {
+    pub path: PathBuf,
+    /// Extracts the simulation parameters from the file at `path`
+    pub load: fn(&Path) -> Result<SimConfig>,
 }
 
 #[tokio::main]
-pub async fn run(net: NetParams, sim: SimConfig) -> Result<()> {
+pub async fn run(
+    net: NetParams,
+    sim: SimConfig,
+    worlds: Vec<u64>,
+    source: Option<ConfigSource>,
+    save: Option<SaveParams>,
+    record: Option<PathBuf>,
+) -> Result<()> {
+    serve(net, sim, worlds, source, save, record).await
+}
+
+/// Host one world per entry in `worlds`, each generated from the given seed
+async fn serve(
+    net: NetParams,
+    sim: SimConfig,
+    worlds: Vec<u64>,
+    source: Option<ConfigSource>,
+    save: Option<SaveParams>,
+    record: Option<PathBuf>,
+) -> Result<()> {
     let mut server_config = quinn::ServerConfigBuilder::default();
     server_config
         .certificate(net.certificate_chain, net.private_key)
@@ -35,29 +106,128 @@ pub async fn run(net: NetParams, sim: SimConfig) -> Result<()> {
     let (endpoint, incoming) = endpoint.with_socket(net.socket)?;
     info!(address = %endpoint.local_addr().unwrap(), "listening");
 
-    let server = Server::new(sim);
-    server.run(incoming).await;
+    let mut server = Server::new(sim, net.max_players, &worlds, save);
+    server.accounts = net.accounts;
+    server.idle_timeout = net.idle_timeout;
+    server.freeze_idle = net.freeze_idle;
+    server.edit_rate = net.edit_rate;
+    if net.lockstep {
+        server.enable_lockstep();
+    }
+    if let Some(path) = record {
+        let mut recorder =
+            Recorder::create(&path).with_context(|| format!("creating {}", path.display()))?;
+        for (i, &seed) in worlds.iter().enumerate() {
+            recorder.record(Event::World {
+                world: WorldId::from(i as u32),
+                seed,
+            })?;
+        }
+        server.recorder = Some(recorder);
+        // Chunks generated late would make collisions depend on the time each step took
+        for world in &mut server.worlds {
+            world.sim.throttle_worldgen(false);
+        }
+        info!(path = %path.display(), "recording session");
+    }
+    server.run(incoming, source).await;
     Ok(())
 }
 
 struct Server {
     cfg: Arc<SimConfig>,
-    sim: Sim,
+    /// Indexed by `WorldId`
+    worlds: Vec<World>,
     clients: DenseSlotMap<ClientId, Client>,
+    max_players: u32,
+    recorder: Option<Recorder>,
+    /// Reference point for the clock reported to clients in `proto::Pong`
+    epoch: Instant,
+    accounts: HashMap<String, Account>,
+    /// Players refused entry
+    banned: FxHashSet<Identity>,
+    idle_timeout: Option<Duration>,
+    freeze_idle: bool,
+    edit_rate: Option<EditRate>,
+}
+
+/// An independent simulation with its own terrain and players
+///
+/// Players stay in the world they joined until they disconnect. Moving them between worlds, e.g.
+/// through portals, would need clients to discard their graph and everything drawn from it
+/// mid-connection, which they can't yet do.
+struct World {
+    sim: Sim,
+    autosave: Option<Autosave>,
+    /// Inputs collected for the next step, if the world runs in lockstep
+    lockstep: Option<Lockstep<ClientId>>,
 }
 
 impl Server {
-    fn new(params: SimConfig) -> Self {
+    fn new(params: SimConfig, max_players: u32, seeds: &[u64], save: Option<SaveParams>) -> Self {
         let cfg = Arc::new(params);
+        let now = Instant::now();
+        let worlds = seeds
+            .iter()
+            .enumerate()
+            .map(|(i, &seed)| {
+                let mut sim = Sim::seeded(cfg.clone(), seed);
+                let autosave = save.as_ref().map(|save| {
+                    let params = SaveParams {
+                        // The first world keeps the top level, for compatibility with older saves
+                        path: if i == 0 {
+                            save.path.clone()
+                        } else {
+                            save.path.join(format!("world{}", i))
+                        },
+                        interval: save.interval,
+                    };
+                    save::restore(&params.path, &mut sim);
+                    Autosave::new(params, now)
+                });
+                World {
+                    sim,
+                    autosave,
+                    lockstep: None,
+                }
+            })
+            .collect();
         Self {
-            sim: Sim::new(cfg.clone()),
             cfg,
+            worlds,
             clients: DenseSlotMap::default(),
+            max_players,
+            recorder: None,
+            epoch: now,
+            accounts: HashMap::new(),
+            banned: FxHashSet::default(),
+            idle_timeout: None,
+            freeze_idle: false,
+            edit_rate: None,
+        }
+    }
+
+    /// Run every world in lockstep from its first step
+    ///
+    /// Each world then steps only once every player in it has sent their input for that step,
+    /// and generates terrain without regard for time, so that its evolution is determined by
+    /// those inputs alone. A slow player or chunk holds everyone up.
+    fn enable_lockstep(&mut self) {
+        for world in &mut self.worlds {
+            world.lockstep = Some(Lockstep::new());
+            world.sim.throttle_worldgen(false);
         }
     }
 
-    async fn run(mut self, incoming: quinn::Incoming) {
-        let mut ticks = tokio::time::interval(Duration::from_secs(1) / self.cfg.rate as u32).fuse();
+    /// Microseconds since `epoch`, for `proto::Pong`
+    fn clock(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_micros() as u64
+    }
+
+    async fn run(mut self, incoming: quinn::Incoming, source: Option<ConfigSource>) {
+        let mut ticks = tick_interval(self.cfg.rate).fuse();
+        let mut watch = source.map(ConfigWatch::new);
+        let mut polls = tokio::time::interval(CONFIG_POLL_INTERVAL).fuse();
         let mut incoming = incoming
             .inspect(|x| trace!(address = %x.remote_address(), "connection incoming"))
             .buffer_unordered(16);
@@ -66,40 +236,202 @@ impl Server {
         loop {
             select! {
                 _ = ticks.next() => { self.on_step() }
+                _ = polls.next() => {
+                    if let Some(cfg) = watch.as_mut().and_then(|x| x.poll()) {
+                        let rate = self.cfg.rate;
+                        match self.reconfigure(cfg) {
+                            Ok(()) => {
+                                info!("reloaded simulation config");
+                                if self.cfg.rate != rate {
+                                    ticks = tick_interval(self.cfg.rate).fuse();
+                                }
+                            }
+                            Err(e) => error!("refusing config change: {}", e),
+                        }
+                    }
+                }
                 conn = incoming.select_next_some() => { self.on_connect(conn, client_events_send.clone()); }
                 e = client_events.select_next_some() => { self.on_client_event(e.0, e.1); }
             }
         }
     }
 
+    /// Apply new simulation parameters from the next step onwards
+    ///
+    /// Connected clients are sent the parameters they predict with, per `proto::Reconfigure`, in
+    /// that step's `Spawns`.
+    fn reconfigure(&mut self, new: SimConfig) -> Result<(), common::ImmutableParameter> {
+        let mut cfg = (*self.cfg).clone();
+        cfg.update(&new)?;
+        self.cfg = Arc::new(cfg);
+        for world in &mut self.worlds {
+            world.sim.set_config(self.cfg.clone());
+        }
+        Ok(())
+    }
+
     fn on_step(&mut self) {
         let now = Instant::now();
-        // Apply queued inputs
+        // Gather inputs, from the queue or, in lockstep, for the next tick if it's complete
+        let mut inputs = Vec::new();
         for (id, client) in &mut self.clients {
             if let Some(ref handles) = client.handles {
+                if self.worlds[u32::from(handles.world) as usize]
+                    .lockstep
+                    .is_some()
+                {
+                    continue;
+                }
                 if let Some(cmd) = client.inputs.pop(now, self.cfg.input_queue_size) {
-                    client.latest_input_processed = cmd.generation;
-                    if let Err(e) = self.sim.command(handles.character, cmd) {
-                        error!(client = ?id, "couldn't process command: {}", e);
-                    }
+                    inputs.push((id, cmd));
                 }
             }
         }
+        let mut stepping = Vec::with_capacity(self.worlds.len());
+        for world in &mut self.worlds {
+            stepping.push(match world.lockstep {
+                None => true,
+                Some(ref mut lockstep) => match lockstep.advance() {
+                    Some((_, x)) => {
+                        inputs.extend(x);
+                        true
+                    }
+                    None => false,
+                },
+            });
+        }
+
+        // Apply inputs
+        for (id, cmd) in inputs {
+            let client = &mut self.clients[id];
+            let handles = client.handles.as_ref().unwrap();
+            client.latest_input_processed = cmd.generation;
+            if let Some(ref mut recorder) = self.recorder {
+                log_event(
+                    recorder,
+                    Event::Command {
+                        world: handles.world,
+                        character: handles.id,
+                        command: cmd.clone(),
+                    },
+                );
+            }
+            let sim = &mut self.worlds[u32::from(handles.world) as usize].sim;
+            if let Err(e) = sim.command(handles.character, cmd) {
+                error!(client = ?id, "couldn't process command: {}", e);
+            }
+        }
 
-        // Step the simulation
-        let (spawns, delta) = self.sim.step();
-        let spawns = Arc::new(spawns);
+        // Step the simulations
+        let mut steps = Vec::with_capacity(self.worlds.len());
+        for (i, world) in self.worlds.iter_mut().enumerate() {
+            if !stepping[i] {
+                steps.push(None);
+                continue;
+            }
+            let (spawns, delta) = world.sim.step();
+            if let Some(ref mut recorder) = self.recorder {
+                log_event(
+                    recorder,
+                    Event::Step {
+                        world: WorldId::from(i as u32),
+                        delta: delta.clone(),
+                        hash: world.sim.state_hash(),
+                    },
+                );
+            }
+            steps.push(Some((spawns, delta)));
+        }
         let mut overran = Vec::new();
+        let clock = self.clock(Instant::now());
         for (client_id, client) in &mut self.clients {
             if let Some(ref mut handles) = client.handles {
-                let mut delta = delta.clone();
+                let world = u32::from(handles.world) as usize;
+                let (spawns, delta) = match steps[world] {
+                    Some((ref spawns, ref delta)) => (spawns, delta),
+                    // Waiting on lockstep input
+                    None => continue,
+                };
+                if let Some(ref mut idle) = client.idle {
+                    if idle.poll(now) {
+                        info!(client = ?client_id.0, name = %handles.name, "player went idle");
+                        if self.freeze_idle {
+                            self.worlds[world].sim.set_frozen(handles.character, true);
+                        }
+                    }
+                    if idle.is_idle() {
+                        // Entities go unreported until the player returns and is resynchronized,
+                        // but the graph and terrain must stay complete, since neither is resent
+                        if spawns.nodes.is_empty()
+                            && spawns.voxels.is_empty()
+                            && spawns.metadata.is_empty()
+                            && spawns.reconfigure.is_none()
+                        {
+                            continue;
+                        }
+                        let nodes = proto::Spawns {
+                            step: spawns.step,
+                            spawns: Vec::new(),
+                            despawns: Vec::new(),
+                            nodes: spawns.nodes.clone(),
+                            enters: Vec::new(),
+                            leaves: Vec::new(),
+                            hits: Vec::new(),
+                            voxels: spawns.voxels.clone(),
+                            metadata: spawns.metadata.clone(),
+                            reconfigure: spawns.reconfigure,
+                            resync: false,
+                        };
+                        if let Err(mpsc::error::TrySendError::Full(_)) =
+                            handles.ordered.try_send(Arc::new(nodes))
+                        {
+                            overran.push(client_id);
+                        }
+                        continue;
+                    }
+                }
+                let spawns =
+                    client
+                        .interest
+                        .update(&self.worlds[world].sim, handles.character, spawns);
+                let mut delta = client.interest.filter_delta(delta);
                 delta.latest_input = client.latest_input_processed;
-                let r1 = handles.unordered.try_send(delta);
+                delta.pong = client.ping.take().map(|(ping, received)| proto::Pong {
+                    client_time: ping.client_time,
+                    received,
+                    sent: clock,
+                });
+                delta.inventory = self.worlds[world]
+                    .sim
+                    .take_inventory_change(handles.character);
+                delta.admin_replies = mem::replace(&mut client.admin_replies, Vec::new());
+                delta.edit_rejections = mem::replace(&mut client.edit_rejections, Vec::new());
+                delta.edit_rejections.extend(
+                    self.worlds[world]
+                        .sim
+                        .take_edit_rejections(handles.character),
+                );
+                if delta.step % STATE_HASH_INTERVAL == 0 {
+                    delta.hash =
+                        Some(self.worlds[world].sim.client_hash(
+                            handles.character,
+                            delta.positions.iter().map(|&(id, _)| id),
+                        ));
+                }
+                client.channel.send_update(delta);
+                let r1 = handles.unordered.try_send(client.channel.flush());
                 let r2 = if !spawns.spawns.is_empty()
                     || !spawns.despawns.is_empty()
                     || !spawns.nodes.is_empty()
+                    || !spawns.enters.is_empty()
+                    || !spawns.leaves.is_empty()
+                    || !spawns.hits.is_empty()
+                    || !spawns.voxels.is_empty()
+                    || !spawns.metadata.is_empty()
+                    || spawns.reconfigure.is_some()
+                    || spawns.resync
                 {
-                    handles.ordered.try_send(spawns.clone())
+                    handles.ordered.try_send(Arc::new(spawns))
                 } else {
                     Ok(())
                 };
@@ -119,21 +451,105 @@ impl Server {
                 .close(1u32.into(), b"client reading too slowly");
             self.cleanup_client(client_id);
         }
+
+        for world in &mut self.worlds {
+            if let Some(ref mut autosave) = world.autosave {
+                autosave.poll(now, &mut world.sim);
+            }
+        }
+
+        let elapsed = now.elapsed();
+        if elapsed > Duration::from_secs(1) / u32::from(self.cfg.rate) {
+            let (queued, deferred_steps) = self.worlds.iter().fold((0, 0), |(q, d), world| {
+                let stats = world.sim.worldgen_stats();
+                (q + stats.queued, d + stats.deferred_steps)
+            });
+            warn!(
+                ?elapsed,
+                queued_chunks = queued,
+                deferred_steps,
+                "step overran"
+            );
+        }
     }
 
     fn on_client_event(&mut self, client_id: ClientId, event: ClientEvent) {
         let span = error_span!("client", id = ?client_id.0);
         let _guard = span.enter();
-        let client = &mut self.clients[client_id];
+        let received = self.clock(Instant::now());
+        // Admin commands address players by name, so no two may share one
+        let name_taken = match event {
+            ClientEvent::Hello(ref hello) => self.player(&hello.name).is_ok(),
+            _ => false,
+        };
+        let client = match self.clients.get_mut(client_id) {
+            Some(x) => x,
+            // Already cleaned up, e.g. after being dropped for reading too slowly
+            None => return,
+        };
         match event {
-            ClientEvent::Hello(hello) => {
+            ClientEvent::Hello(mut hello) => {
                 assert!(client.handles.is_none());
-                let snapshot = Arc::new(self.sim.snapshot());
-                let (id, entity) = self.sim.spawn_character(hello);
+                let address = client.conn.remote_address().ip();
+                let (identity, permission) =
+                    match account::authenticate(&self.accounts, &hello, address) {
+                        Some(x) => x,
+                        None => {
+                            warn!(name = %hello.name, "refusing unauthenticated player");
+                            // Cleaned up when the receive task reports the connection lost
+                            client.conn.close(0u32.into(), b"unauthenticated");
+                            return;
+                        }
+                    };
+                // Kept out of recordings
+                hello.token = None;
+                if self.banned.contains(&identity) {
+                    info!(name = %hello.name, "refusing banned player");
+                    // Cleaned up when the receive task reports the connection lost
+                    client.conn.close(0u32.into(), b"banned");
+                    return;
+                }
+                if name_taken {
+                    info!(name = %hello.name, "refusing player whose name is in use");
+                    // Cleaned up when the receive task reports the connection lost
+                    client.conn.close(0u32.into(), b"name in use");
+                    return;
+                }
+                client.permission = permission;
+                let world = hello.world;
+                let (sim, lockstep) = match self.worlds.get_mut(u32::from(world) as usize) {
+                    Some(x) => (&mut x.sim, x.lockstep.as_mut().map(|x| x.join(client_id))),
+                    None => {
+                        error!(%world, "requested nonexistent world");
+                        // Cleaned up when the receive task reports the connection lost
+                        client.conn.close(0u32.into(), b"no such world");
+                        return;
+                    }
+                };
+                let snapshot = sim.snapshot();
+                let name = hello.name.clone();
+                let account = identity.account().cloned();
+                let (id, entity) = sim.spawn_character(hello.clone(), account.clone());
+                if let Some(ref mut recorder) = self.recorder {
+                    log_event(
+                        recorder,
+                        Event::Join {
+                            world,
+                            character: id,
+                            hello,
+                            account: account.map(|x| x.to_string()),
+                        },
+                    );
+                }
+                let snapshot = Arc::new(client.interest.snapshot(sim, entity, snapshot));
                 let (mut ordered_send, ordered_recv) = mpsc::channel(32);
                 ordered_send.try_send(snapshot).unwrap();
                 let (unordered_send, unordered_recv) = mpsc::channel(32);
                 client.handles = Some(ClientHandles {
+                    world,
+                    name,
+                    identity,
+                    id,
                     character: entity,
                     ordered: ordered_send,
                     unordered: unordered_send,
@@ -145,7 +561,13 @@ impl Server {
                     chunk_size: self.cfg.chunk_size,
                     meters_to_absolute: self.cfg.meters_to_absolute,
                     movement_speed: self.cfg.movement_speed,
+                    max_reach: self.cfg.max_reach,
+                    seed: sim.seed(),
+                    worldgen_preset: self.cfg.worldgen_preset,
+                    lockstep,
                 };
+                client.idle = self.idle_timeout.map(|x| Idle::new(x, Instant::now()));
+                client.edit_limiter = self.edit_rate.map(|x| EditLimiter::new(x, Instant::now()));
                 tokio::spawn(async move {
                     // Errors will be handled by recv task
                     let _ =
@@ -157,21 +579,278 @@ impl Server {
                 client.conn.close(0u32.into(), b"");
                 self.cleanup_client(client_id);
             }
-            ClientEvent::Command(cmd) => {
-                if cmd.generation.wrapping_sub(client.latest_input_received) < u16::max_value() / 2
-                {
+            ClientEvent::Packet(packet) => {
+                // Critical commands, in the order they were sent, come before the packet's own
+                // command, which is only returned if newer than any seen before
+                let (critical, update) = client.channel.receive(packet);
+                for cmd in critical.into_iter().chain(update) {
+                    self.on_command(client_id, received, cmd);
+                }
+            }
+        }
+    }
+
+    /// Handle `cmd` from `client_id`, which arrived at `received` by the server clock
+    fn on_command(&mut self, client_id: ClientId, received: u64, mut cmd: proto::Command) {
+        let client = &mut self.clients[client_id];
+        if let Some(ping) = cmd.ping {
+            client.ping = Some((ping, received));
+        }
+        if cmd.resync {
+            info!("resynchronizing");
+            client.interest.reset();
+        }
+        if let (Some(idle), Some(handles)) = (&mut client.idle, &client.handles) {
+            if idle.input(&cmd, Instant::now()) {
+                info!(name = %handles.name, "player returned");
+                // Catch up on everything that went unreported while idle
+                client.interest.reset();
+                if self.freeze_idle {
+                    self.worlds[u32::from(handles.world) as usize]
+                        .sim
+                        .set_frozen(handles.character, false);
+                }
+            }
+        }
+        let admin = cmd.admin.take();
+        let generation = cmd.generation;
+        let lockstep = match client.handles {
+            Some(ref handles) => self.worlds[u32::from(handles.world) as usize]
+                .lockstep
+                .as_mut(),
+            None => None,
+        };
+        let accepted = if let Some(ref lockstep) = lockstep {
+            let accepted = cmd
+                .tick
+                .map_or(false, |tick| lockstep.accepts(client_id, tick));
+            if !accepted {
+                debug!("dropping unexpected lockstep input");
+            }
+            accepted
+        } else if cmd.generation.wrapping_sub(client.latest_input_received) < u16::max_value() / 2 {
+            true
+        } else {
+            debug!("dropping obsolete command");
+            false
+        };
+        if accepted {
+            // Charged only now, so that a stale or duplicated command costs nothing
+            if let (Some(_), Some(limiter)) = (&cmd.edit, &mut client.edit_limiter) {
+                match limiter.admit(Instant::now()) {
+                    Admission::Accept => {}
+                    Admission::Drop => {
+                        debug!("dropping excess edit");
+                        cmd.edit = None;
+                        client.edit_rejections.push(proto::EditRejected {
+                            generation: cmd.generation,
+                            reason: proto::EditRejection::TooFast,
+                        });
+                    }
+                    Admission::Disconnect => {
+                        warn!("disconnecting for flooding edits");
+                        // Cleaned up when the receive task reports the connection lost
+                        client.conn.close(0u32.into(), b"editing too quickly");
+                        return;
+                    }
+                }
+            }
+            match lockstep {
+                Some(lockstep) => {
+                    lockstep.submit(client_id, cmd.tick.unwrap(), cmd);
+                }
+                None => {
                     client.latest_input_received = cmd.generation;
                     client.inputs.push(cmd, Instant::now());
-                } else {
-                    debug!("dropping obsolete command");
                 }
             }
         }
+
+        // Performed immediately rather than with the rest of the command, but only if the
+        // command was accepted as in order, so that a stale packet can't act
+        if let Some(command) = admin {
+            if !accepted {
+                return;
+            }
+            let permission = client.permission;
+            let world = client.handles.as_ref().map(|x| x.world);
+            let result = match world {
+                Some(world) => self.admin(permission, world, command),
+                None => Err("not yet joined".into()),
+            };
+            let reply = proto::AdminReply { generation, result };
+            self.clients[client_id].admin_replies.push(reply);
+        }
+    }
+
+    /// Perform `command` in `world` on behalf of a client with `permission`
+    ///
+    /// Returns a description of the outcome for the client.
+    fn admin(
+        &mut self,
+        permission: Permission,
+        world: WorldId,
+        command: AdminCommand,
+    ) -> Result<String, String> {
+        if permission < command.permission() {
+            warn!(?command, "refusing unpermitted admin command");
+            return Err(format!("requires {:?} permission", command.permission()));
+        }
+        info!(?command, "performing admin command");
+        match command {
+            AdminCommand::Kick { name } => {
+                let target = self.player(&name)?;
+                // Cleaned up when the receive task reports the connection lost
+                self.clients[target].conn.close(0u32.into(), b"kicked");
+                Ok(format!("kicked {}", name))
+            }
+            AdminCommand::Ban { name } => {
+                let identity = match self.player(&name) {
+                    Ok(target) => {
+                        let client = &self.clients[target];
+                        // Cleaned up when the receive task reports the connection lost
+                        client.conn.close(0u32.into(), b"banned");
+                        client.handles.as_ref().unwrap().identity.clone()
+                    }
+                    // Absent players can only be banned by account
+                    Err(_) if self.accounts.contains_key(&name) => {
+                        Identity::Account(name.as_str().into())
+                    }
+                    Err(e) => return Err(e),
+                };
+                self.banned.insert(identity);
+                Ok(format!("banned {}", name))
+            }
+            AdminCommand::Teleport { name, path } => {
+                let target = self.player(&name)?;
+                let handles = self.clients[target].handles.as_ref().unwrap();
+                self.worlds[u32::from(handles.world) as usize]
+                    .sim
+                    .teleport(handles.character, &path)
+                    .map_err(|e| format!("{:#}", e))?;
+                if let Some(ref mut recorder) = self.recorder {
+                    log_event(
+                        recorder,
+                        Event::Teleport {
+                            world: handles.world,
+                            character: handles.id,
+                            path: path.clone(),
+                        },
+                    );
+                }
+                Ok(format!("teleported {} to {}", name, path))
+            }
+            AdminCommand::SetTime { time_of_day } => {
+                let sim = &mut self.worlds[u32::from(world) as usize].sim;
+                sim.set_time_of_day(time_of_day);
+                let reply = format!("time of day is now {}", sim.time_of_day());
+                self.record_admin(world, AdminCommand::SetTime { time_of_day });
+                Ok(reply)
+            }
+            AdminCommand::Flatten { path } => {
+                let parsed = path.parse::<NodePath>().map_err(|e| format!("{:#}", e))?;
+                self.worlds[u32::from(world) as usize].sim.flatten(&parsed);
+                self.record_admin(world, AdminCommand::Flatten { path });
+                Ok(format!("flattened {}", parsed))
+            }
+            AdminCommand::Protect { path, members } => {
+                let parsed = path.parse::<NodePath>().map_err(|e| format!("{:#}", e))?;
+                // Guests can't be members, since anyone can take their names
+                if let Some(x) = members.iter().find(|x| !self.accounts.contains_key(*x)) {
+                    return Err(format!("no account named {}", x));
+                }
+                self.worlds[u32::from(world) as usize]
+                    .sim
+                    .protect(&[parsed.clone()], members.iter().map(|x| Arc::from(&x[..])));
+                self.record_admin(world, AdminCommand::Protect { path, members });
+                Ok(format!("protected {}", parsed))
+            }
+            AdminCommand::Inscribe {
+                path,
+                chunk,
+                coords,
+                text,
+            } => {
+                let id = self.voxel_chunk(world, &path, chunk, coords)?;
+                let sim = &mut self.worlds[u32::from(world) as usize].sim;
+                if !sim.set_metadata(id, coords, text.clone().into_bytes()) {
+                    return Err("no solid voxel there".into());
+                }
+                self.record_admin(
+                    world,
+                    AdminCommand::Inscribe {
+                        path,
+                        chunk,
+                        coords,
+                        text,
+                    },
+                );
+                Ok(format!("inscribed {:?} of {:?}", coords, id))
+            }
+            AdminCommand::Inspect {
+                path,
+                chunk,
+                coords,
+            } => {
+                let id = self.voxel_chunk(world, &path, chunk, coords)?;
+                let sim = &self.worlds[u32::from(world) as usize].sim;
+                Ok(match sim.metadata(id, coords) {
+                    Some(data) => String::from_utf8_lossy(data).into_owned(),
+                    None => format!("nothing attached to {:?} of {:?}", coords, id),
+                })
+            }
+        }
+    }
+
+    /// The chunk of `world` around `chunk` of the node at `path`, ready for its voxel at `coords`
+    /// to be addressed
+    fn voxel_chunk(
+        &mut self,
+        world: WorldId,
+        path: &str,
+        chunk: Vertex,
+        coords: [u8; 3],
+    ) -> Result<ChunkId, String> {
+        let parsed = path.parse::<NodePath>().map_err(|e| format!("{:#}", e))?;
+        if coords.iter().any(|&x| x >= self.cfg.chunk_size) {
+            return Err(format!("{:?} lies outside the chunk", coords));
+        }
+        let sim = &mut self.worlds[u32::from(world) as usize].sim;
+        Ok(ChunkId::new(sim.locate(&parsed), chunk))
+    }
+
+    /// Log an admin command that changed `world`, so that replays reproduce it
+    fn record_admin(&mut self, world: WorldId, command: AdminCommand) {
+        if let Some(ref mut recorder) = self.recorder {
+            log_event(recorder, Event::Admin { world, command });
+        }
+    }
+
+    /// The client playing as `name`
+    fn player(&self, name: &str) -> Result<ClientId, String> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.handles.as_ref().map_or(false, |x| x.name == name))
+            .map(|(id, _)| id)
+            .ok_or_else(|| format!("no player named {}", name))
     }
 
     fn cleanup_client(&mut self, client: ClientId) {
         if let Some(ref x) = self.clients[client].handles {
-            self.sim.destroy(x.character);
+            let world = &mut self.worlds[u32::from(x.world) as usize];
+            world.sim.destroy(x.character);
+            if let Some(ref mut lockstep) = world.lockstep {
+                lockstep.leave(client);
+            }
+            if let Some(ref mut recorder) = self.recorder {
+                log_event(
+                    recorder,
+                    Event::Leave {
+                        world: x.world,
+                        character: x.id,
+                    },
+                );
+            }
         }
         self.clients.remove(client);
     }
@@ -192,6 +871,14 @@ impl Server {
                 return;
             }
         };
+        if self.clients.len() >= self.max_players as usize {
+            info!(address = %connection.remote_address(), "rejecting connection: server full");
+            let reason = proto::ServerFull {
+                max_players: self.max_players,
+            };
+            connection.close(proto::ServerFull::CLOSE_CODE.into(), &codec::encode(&reason));
+            return;
+        }
         let id = self.clients.insert(Client::new(connection.clone()));
         info!(id = ?id.0, address = %connection.remote_address(), "connection established");
         tokio::spawn(async move {
@@ -202,6 +889,56 @@ impl Server {
     }
 }
 
+/// Log `event`, tolerating failure so that a full disk doesn't take the server down with it
+fn log_event(recorder: &mut Recorder, event: Event) {
+    if let Err(e) = recorder.record(event) {
+        error!("failed to record event: {:#}", e);
+    }
+}
+
+fn tick_interval(rate: u16) -> tokio::time::Interval {
+    tokio::time::interval(Duration::from_secs(1) / u32::from(rate))
+}
+
+/// Number of steps between hashes of the state known to each client
+const STATE_HASH_INTERVAL: Step = 10;
+
+/// How often to check the config file for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks modifications to a config file
+struct ConfigWatch {
+    source: ConfigSource,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatch {
+    fn new(source: ConfigSource) -> Self {
+        let modified = modified(&source.path);
+        Self { source, modified }
+    }
+
+    /// Load the config if it's been modified since the last call
+    fn poll(&mut self) -> Option<SimConfig> {
+        let modified = modified(&self.source.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match (self.source.load)(&self.source.path) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                warn!("failed to reload config: {:#}", e);
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
 const MAX_CLIENT_MSG_SIZE: usize = 1 << 16;
 
 async fn drive_recv(
@@ -217,13 +954,15 @@ async fn drive_recv(
     };
     let _ = send.send((id, ClientEvent::Hello(hello))).await;
 
-    let mut cmds = streams
+    let mut packets = streams
         .map(|stream| async {
-            Ok::<_, Error>(codec::recv_whole::<proto::Command>(MAX_CLIENT_MSG_SIZE, stream?).await?)
+            Ok::<_, Error>(
+                codec::recv_whole::<proto::ClientPacket>(MAX_CLIENT_MSG_SIZE, stream?).await?,
+            )
         })
         .buffer_unordered(16); // Allow a modest amount of out-of-order completion
-    while let Some(msg) = cmds.try_next().await? {
-        let _ = send.send((id, ClientEvent::Command(msg))).await;
+    while let Some(msg) = packets.try_next().await? {
+        let _ = send.send((id, ClientEvent::Packet(msg))).await;
     }
     Ok(())
 }
@@ -271,6 +1010,21 @@ struct Client {
     latest_input_received: u16,
     latest_input_processed: u16,
     inputs: InputQueue,
+    interest: Interest,
+    /// Most recent unanswered ping, and the server clock when it arrived
+    ping: Option<(proto::Ping, u64)>,
+    /// Filled in after receiving ClientHello
+    permission: Permission,
+    /// Outcomes of admin commands not yet sent
+    admin_replies: Vec<proto::AdminReply>,
+    /// Edits refused before reaching the simulation since the last `StateDelta`
+    edit_rejections: Vec<proto::EditRejected>,
+    /// Filled in after receiving ClientHello, if idle players are detected
+    idle: Option<Idle>,
+    /// Filled in after receiving ClientHello, if edits are rate limited
+    edit_limiter: Option<EditLimiter>,
+    /// Delivers the client's commands, acknowledging them with each `StateDelta`
+    channel: proto::ServerChannel,
 }
 
 impl Client {
@@ -281,11 +1035,24 @@ impl Client {
             latest_input_received: 0,
             latest_input_processed: 0,
             inputs: InputQueue::new(),
+            interest: Interest::new(),
+            ping: None,
+            permission: Permission::Player,
+            admin_replies: Vec::new(),
+            edit_rejections: Vec::new(),
+            idle: None,
+            edit_limiter: None,
+            channel: proto::ServerChannel::new(),
         }
     }
 }
 
 struct ClientHandles {
+    world: WorldId,
+    /// Name the player joined with
+    name: String,
+    identity: Identity,
+    id: EntityId,
     character: Entity,
     ordered: mpsc::Sender<Ordered>,
     unordered: mpsc::Sender<Unordered>,
@@ -293,10 +1060,701 @@ struct ClientHandles {
 
 enum ClientEvent {
     Hello(proto::ClientHello),
-    Command(proto::Command),
+    Packet(proto::ClientPacket),
     Lost(Error),
 }
 
-type Unordered = proto::StateDelta;
+type Unordered = proto::ServerPacket;
 
 type Ordered = Arc<proto::Spawns>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        dodeca::Vertex, graph::NodeId, world::Material, ImmutableParameter, SimConfigRaw,
+    };
+    use futures::{pin_mut, FutureExt};
+    use std::net::SocketAddr;
+
+    fn client_endpoint(cert: quinn::Certificate) -> quinn::Endpoint {
+        let mut client_cfg = quinn::ClientConfigBuilder::default();
+        client_cfg.add_certificate_authority(cert).unwrap();
+        let mut endpoint = quinn::Endpoint::builder();
+        endpoint.default_client_config(client_cfg.build());
+        endpoint.bind(&"127.0.0.1:0".parse().unwrap()).unwrap().0
+    }
+
+    /// Parameters for a server on a fresh local socket, with the certificate clients should trust
+    fn local_net(max_players: u32) -> (NetParams, quinn::Certificate, SocketAddr) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = quinn::PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
+        let cert = quinn::Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let net = NetParams {
+            certificate_chain: quinn::CertificateChain::from_certs(Some(cert.clone())),
+            private_key: key,
+            socket,
+            max_players,
+            accounts: HashMap::new(),
+            lockstep: false,
+            idle_timeout: None,
+            freeze_idle: false,
+            edit_rate: None,
+        };
+        (net, cert, addr)
+    }
+
+    /// Connect and complete the handshake as a player, returning the connection and ordered stream
+    async fn join(
+        endpoint: &quinn::Endpoint,
+        server: &SocketAddr,
+        world: WorldId,
+        name: &str,
+    ) -> (quinn::NewConnection, quinn::RecvStream) {
+        try_join(endpoint, server, world, name).await.unwrap()
+    }
+
+    /// Like `join`, but reporting why the server closed the connection instead of panicking
+    async fn try_join(
+        endpoint: &quinn::Endpoint,
+        server: &SocketAddr,
+        world: WorldId,
+        name: &str,
+    ) -> Result<(quinn::NewConnection, quinn::RecvStream), quinn::ConnectionError> {
+        let hello = proto::ClientHello {
+            name: name.into(),
+            world,
+            token: None,
+        };
+        try_join_with(endpoint, server, &hello).await
+    }
+
+    /// Like `try_join`, but sending `hello` as given
+    async fn try_join_with(
+        endpoint: &quinn::Endpoint,
+        server: &SocketAddr,
+        hello: &proto::ClientHello,
+    ) -> Result<(quinn::NewConnection, quinn::RecvStream), quinn::ConnectionError> {
+        let mut conn = endpoint.connect(server, "localhost").unwrap().await?;
+        let stream = conn.connection.open_uni().await?;
+        // If the server closed the connection, sending fails and the reason is found below
+        let _ = codec::send_whole(stream, hello).await;
+        let mut ordered = conn.uni_streams.next().await.unwrap()?;
+        codec::recv::<proto::ServerHello>(&mut ordered)
+            .await
+            .unwrap()
+            .unwrap();
+        Ok((conn, ordered))
+    }
+
+    #[tokio::test]
+    async fn server_full() {
+        const MAX_PLAYERS: u32 = 2;
+        let (net, cert, addr) = local_net(MAX_PLAYERS);
+        let server = serve(
+            net,
+            SimConfig::from_raw(&SimConfigRaw::default()),
+            vec![0],
+            None,
+            None,
+            None,
+        )
+        .fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            let mut players = Vec::new();
+            for i in 0..MAX_PLAYERS {
+                let name = format!("player{}", i);
+                players.push(join(&endpoint, &addr, WorldId::DEFAULT, &name).await);
+            }
+
+            // One too many
+            let error = match try_join(&endpoint, &addr, WorldId::DEFAULT, "extra").await {
+                Ok(_) => panic!("excess connection wasn't closed"),
+                Err(e) => e,
+            };
+            match error {
+                quinn::ConnectionError::ApplicationClosed(close) => {
+                    assert_eq!(close.error_code, proto::ServerFull::CLOSE_CODE.into());
+                    assert_eq!(
+                        codec::decode::<proto::ServerFull>(&close.reason).unwrap(),
+                        proto::ServerFull {
+                            max_players: MAX_PLAYERS
+                        }
+                    );
+                }
+                e => panic!("unexpected error: {}", e),
+            }
+
+            // Existing players continue to receive updates
+            for (player, _) in &mut players {
+                let stream = player.uni_streams.next().await.unwrap().unwrap();
+                codec::recv_whole::<proto::ServerPacket>(MAX_CLIENT_MSG_SIZE, stream)
+                    .await
+                    .unwrap();
+            }
+
+            // Disconnecting frees a slot, as soon as the server notices
+            let (leaving, _) = players.pop().unwrap();
+            leaving.connection.close(0u32.into(), b"");
+            drop(leaving);
+            loop {
+                match try_join(&endpoint, &addr, WorldId::DEFAULT, "extra").await {
+                    Ok(_) => break,
+                    Err(quinn::ConnectionError::ApplicationClosed(close))
+                        if close.error_code == proto::ServerFull::CLOSE_CODE.into() => {}
+                    Err(e) => panic!("unexpected error: {}", e),
+                }
+            }
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+    }
+
+    /// A `ClientHello` for the account `name`, whose token is its name backwards
+    fn account_hello(name: &str) -> proto::ClientHello {
+        proto::ClientHello {
+            name: name.into(),
+            world: WorldId::DEFAULT,
+            token: Some(name.chars().rev().collect()),
+        }
+    }
+
+    /// Register `name` in `net` per `account_hello`
+    fn add_account(net: &mut NetParams, name: &str, permission: Permission) {
+        net.accounts.insert(
+            name.into(),
+            Account {
+                token: account_hello(name).token.unwrap(),
+                permission,
+            },
+        );
+    }
+
+    /// A command carrying nothing but `admin`
+    fn admin_command(generation: u16, admin: Option<AdminCommand>) -> proto::Command {
+        proto::Command {
+            generation,
+            orientation: na::UnitQuaternion::identity(),
+            velocity: na::Vector3::zeros(),
+            resync: false,
+            ping: None,
+            admin,
+            edit: None,
+            fire: false,
+            tick: None,
+        }
+    }
+
+    /// Send `command` over `conn` by way of `channel`, as the client does
+    async fn send_command(
+        conn: &quinn::NewConnection,
+        channel: &mut proto::ClientChannel,
+        command: proto::Command,
+    ) {
+        if command.is_critical() {
+            channel.send(command);
+        } else {
+            channel.send_update(command);
+        }
+        send_packet(conn, &channel.flush()).await;
+    }
+
+    async fn send_packet(conn: &quinn::NewConnection, packet: &proto::ClientPacket) {
+        let stream = conn.connection.open_uni().await.unwrap();
+        codec::send_whole(stream, packet).await.unwrap();
+    }
+
+    /// Receive the next `StateDelta` over `conn`, passing the acknowledgements it came with on to
+    /// `channel`
+    async fn next_delta(
+        conn: &mut quinn::NewConnection,
+        channel: &mut proto::ClientChannel,
+    ) -> proto::StateDelta {
+        let stream = conn.uni_streams.next().await.unwrap().unwrap();
+        let mut packet = codec::recv_whole::<proto::ServerPacket>(MAX_CLIENT_MSG_SIZE, stream)
+            .await
+            .unwrap();
+        let delta = packet.update.take().expect("every packet carries a delta");
+        channel.receive(packet);
+        delta
+    }
+
+    /// Wait for the server to answer an admin command sent over `conn` by way of `channel`
+    async fn admin_reply(
+        conn: &mut quinn::NewConnection,
+        channel: &mut proto::ClientChannel,
+    ) -> proto::AdminReply {
+        loop {
+            let mut delta = next_delta(conn, channel).await;
+            if !delta.admin_replies.is_empty() {
+                assert_eq!(delta.admin_replies.len(), 1, "unexpected reply");
+                return delta.admin_replies.remove(0);
+            }
+        }
+    }
+
+    /// Expect `result` to be a connection the server closed, giving `reason`
+    fn assert_refused<T>(result: Result<T, quinn::ConnectionError>, reason: &[u8]) {
+        match result {
+            Ok(_) => panic!("connection wasn't closed"),
+            Err(quinn::ConnectionError::ApplicationClosed(close)) => {
+                assert_eq!(&close.reason[..], reason);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn impersonation() {
+        let (mut net, cert, addr) = local_net(8);
+        add_account(&mut net, "admin", Permission::Admin);
+        let server = serve(
+            net,
+            SimConfig::from_raw(&SimConfigRaw::default()),
+            vec![0],
+            None,
+            None,
+            None,
+        )
+        .fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            // Claiming an account's name without its token gets nowhere
+            assert_refused(
+                try_join(&endpoint, &addr, WorldId::DEFAULT, "admin").await,
+                b"unauthenticated",
+            );
+            let forged = proto::ClientHello {
+                token: Some("admin".into()),
+                ..account_hello("admin")
+            };
+            assert_refused(
+                try_join_with(&endpoint, &addr, &forged).await,
+                b"unauthenticated",
+            );
+
+            // The genuine admin gets their permission, and a guest doesn't
+            let (mut admin, _) = try_join_with(&endpoint, &addr, &account_hello("admin"))
+                .await
+                .unwrap();
+            let (mut guest, _) = join(&endpoint, &addr, WorldId::DEFAULT, "guest").await;
+            let mut admin_channel = proto::ClientChannel::new();
+            let mut guest_channel = proto::ClientChannel::new();
+            let set_time = AdminCommand::SetTime { time_of_day: 0.5 };
+            send_command(
+                &guest,
+                &mut guest_channel,
+                admin_command(0, Some(set_time.clone())),
+            )
+            .await;
+            assert_eq!(
+                admin_reply(&mut guest, &mut guest_channel).await.result,
+                Err("requires Admin permission".into())
+            );
+
+            // A banned guest can't return under a new name
+            let ban = AdminCommand::Ban {
+                name: "guest".into(),
+            };
+            send_command(&admin, &mut admin_channel, admin_command(0, Some(ban))).await;
+            assert_eq!(
+                admin_reply(&mut admin, &mut admin_channel).await.result,
+                Ok("banned guest".into())
+            );
+            assert_refused(
+                try_join(&endpoint, &addr, WorldId::DEFAULT, "renamed").await,
+                b"banned",
+            );
+
+            // A stale copy of a packet that has already arrived doesn't act again
+            admin_channel.send(admin_command(1, Some(set_time)));
+            let packet = admin_channel.flush();
+            send_packet(&admin, &packet).await;
+            assert_eq!(
+                admin_reply(&mut admin, &mut admin_channel).await.generation,
+                1
+            );
+            send_packet(&admin, &packet).await;
+            let later = AdminCommand::SetTime { time_of_day: 0.25 };
+            send_command(&admin, &mut admin_channel, admin_command(2, Some(later))).await;
+            assert_eq!(
+                admin_reply(&mut admin, &mut admin_channel).await.generation,
+                2
+            );
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn kick() {
+        let (mut net, cert, addr) = local_net(2);
+        add_account(&mut net, "moderator", Permission::Moderator);
+        let server = serve(
+            net,
+            SimConfig::from_raw(&SimConfigRaw::default()),
+            vec![0],
+            None,
+            None,
+            None,
+        )
+        .fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            let (mut moderator, _) = try_join_with(&endpoint, &addr, &account_hello("moderator"))
+                .await
+                .unwrap();
+            let (mut target, _) = join(&endpoint, &addr, WorldId::DEFAULT, "target").await;
+
+            let mut channel = proto::ClientChannel::new();
+            let kick = AdminCommand::Kick {
+                name: "target".into(),
+            };
+            send_command(&moderator, &mut channel, admin_command(0, Some(kick))).await;
+
+            // The target is disconnected
+            loop {
+                match target.uni_streams.next().await {
+                    Some(Ok(_)) => continue,
+                    Some(Err(quinn::ConnectionError::ApplicationClosed(close))) => {
+                        assert_eq!(&close.reason[..], b"kicked");
+                        break;
+                    }
+                    e => panic!("unexpected stream: {:?}", e.map(|x| x.map(|_| ()))),
+                }
+            }
+
+            // The server carries on once the connection is lost, and its moderator hears back
+            send_command(&moderator, &mut channel, admin_command(1, None)).await;
+            assert_eq!(
+                admin_reply(&mut moderator, &mut channel).await.result,
+                Ok("kicked target".into())
+            );
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_name() {
+        let (net, cert, addr) = local_net(8);
+        let server = serve(
+            net,
+            SimConfig::from_raw(&SimConfigRaw::default()),
+            vec![0],
+            None,
+            None,
+            None,
+        )
+        .fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            let (first, _) = join(&endpoint, &addr, WorldId::DEFAULT, "twin").await;
+            assert_refused(
+                try_join(&endpoint, &addr, WorldId::DEFAULT, "twin").await,
+                b"name in use",
+            );
+            // Other names are unaffected
+            join(&endpoint, &addr, WorldId::DEFAULT, "other").await;
+
+            // The name is free again once the server notices its player has left
+            first.connection.close(0u32.into(), b"");
+            drop(first);
+            loop {
+                match try_join(&endpoint, &addr, WorldId::DEFAULT, "twin").await {
+                    Ok(_) => break,
+                    Err(quinn::ConnectionError::ApplicationClosed(close))
+                        if &close.reason[..] == b"name in use" => {}
+                    Err(e) => panic!("unexpected error: {}", e),
+                }
+            }
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_rate_limited() {
+        let (mut net, cert, addr) = local_net(1);
+        net.edit_rate = Some(EditRate {
+            rate: 0.0,
+            burst: 2,
+            disconnect_after: None,
+        });
+        let server = serve(
+            net,
+            SimConfig::from_raw(&SimConfigRaw::default()),
+            vec![0],
+            None,
+            None,
+            None,
+        )
+        .fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            let (mut player, _) = join(&endpoint, &addr, WorldId::DEFAULT, "a").await;
+            let mut channel = proto::ClientChannel::new();
+            // A voxel in the margins, which the simulation refuses if the edit reaches it
+            let edit = |generation| proto::Command {
+                generation,
+                orientation: na::UnitQuaternion::identity(),
+                velocity: na::Vector3::zeros(),
+                resync: false,
+                ping: None,
+                admin: None,
+                edit: Some(proto::VoxelEdit {
+                    node: NodeId::ROOT,
+                    chunk: Vertex::A,
+                    index: 0,
+                    material: Material::Void,
+                }),
+                fire: false,
+                tick: None,
+            };
+            let mut reasons = Vec::new();
+            for generation in 1..6 {
+                if generation == 2 {
+                    // A stale repeat of an earlier command, which is dropped without a word and
+                    // without using up the burst
+                    send_command(&player, &mut channel, edit(0)).await;
+                }
+                send_command(&player, &mut channel, edit(generation)).await;
+                let rejection = loop {
+                    let delta = next_delta(&mut player, &mut channel).await;
+                    if let Some(&x) = delta.edit_rejections.first() {
+                        break x;
+                    }
+                };
+                assert_eq!(rejection.generation, generation);
+                reasons.push(rejection.reason);
+            }
+            // The burst is admitted, and nothing after it
+            use proto::EditRejection::*;
+            assert_eq!(
+                reasons,
+                [Unavailable, Unavailable, TooFast, TooFast, TooFast]
+            );
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_survive_restart() {
+        use common::{
+            node::{voxel_index, ChunkId, VoxelData},
+            voxel_diff,
+        };
+
+        let dir = std::env::temp_dir().join(format!("hypermine-restart-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let dimension = cfg.chunk_size;
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = [1, 2, 3];
+        let index = voxel_index(dimension, coords);
+        let interval = Duration::from_secs(60);
+
+        // An earlier session edits a voxel and attaches metadata to it
+        let mut sim = Sim::seeded(Arc::new(cfg.clone()), 0);
+        assert!(sim.set_voxel(chunk, index, Material::Wood));
+        assert!(sim.set_metadata(chunk, coords, b"chest".to_vec()));
+        let start = Instant::now();
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        assert!(autosave.poll(start + interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+
+        let (net, cert, addr) = local_net(1);
+        let save = SaveParams {
+            path: dir.clone(),
+            interval,
+        };
+        let server = serve(net, cfg, vec![0], None, Some(save), None).fuse();
+
+        let test = async {
+            let endpoint = client_endpoint(cert);
+            let (_player, mut ordered) = join(&endpoint, &addr, WorldId::DEFAULT, "a").await;
+            let snapshot = codec::recv::<proto::Spawns>(&mut ordered)
+                .await
+                .unwrap()
+                .unwrap();
+            let diff = snapshot
+                .voxels
+                .iter()
+                .find(|x| x.node == chunk.node && x.chunk == chunk.vertex)
+                .expect("edited chunk sent");
+            let mut voxels = VoxelData::Solid(Material::Void);
+            voxel_diff::apply(dimension, &mut voxels, &diff.changes).unwrap();
+            assert_eq!(voxels.get(index), Material::Wood);
+            let metadata = snapshot
+                .metadata
+                .iter()
+                .find(|x| x.node == chunk.node && x.chunk == chunk.vertex)
+                .expect("metadata sent");
+            assert_eq!(metadata.metadata[&coords], b"chest");
+        }
+        .fuse();
+
+        pin_mut!(server, test);
+        select! {
+            result = server => panic!("server exited: {:?}", result.err()),
+            () = test => {}
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reconfigure() {
+        let mut server = Server::new(SimConfig::from_raw(&SimConfigRaw::default()), 1, &[0], None);
+        server
+            .reconfigure(SimConfig::from_raw(&SimConfigRaw {
+                movement_speed: Some(24.0),
+                ..SimConfigRaw::default()
+            }))
+            .unwrap();
+        let speed = server.cfg.movement_speed;
+        assert_eq!(
+            server.reconfigure(SimConfig::from_raw(&SimConfigRaw {
+                chunk_size: Some(8),
+                ..SimConfigRaw::default()
+            })),
+            Err(ImmutableParameter("chunk_size"))
+        );
+        assert_eq!(server.cfg.movement_speed, speed);
+        assert_eq!(server.cfg.chunk_size, 12);
+    }
+
+    #[test]
+    fn admin_permissions() {
+        let mut server = Server::new(
+            SimConfig::from_raw(&SimConfigRaw {
+                rate: Some(10),
+                day_length_secs: Some(1.0),
+                ..SimConfigRaw::default()
+            }),
+            1,
+            &[0],
+            None,
+        );
+        let set_time = || AdminCommand::SetTime { time_of_day: 0.5 };
+        let before = server.worlds[0].sim.time_of_day();
+        for &permission in &[Permission::Player, Permission::Moderator] {
+            let result = server.admin(permission, WorldId::DEFAULT, set_time());
+            assert_eq!(result, Err("requires Admin permission".into()));
+        }
+        assert_eq!(server.worlds[0].sim.time_of_day(), before);
+        assert!(server
+            .admin(Permission::Admin, WorldId::DEFAULT, set_time())
+            .is_ok());
+        assert_eq!(server.worlds[0].sim.time_of_day(), 0.5);
+        // Permitted commands can still fail
+        let kick = AdminCommand::Kick {
+            name: "nobody".into(),
+        };
+        assert_eq!(
+            server.admin(Permission::Moderator, WorldId::DEFAULT, kick),
+            Err("no player named nobody".into())
+        );
+        let protect = AdminCommand::Protect {
+            path: String::new(),
+            members: vec!["guest".into()],
+        };
+        assert_eq!(
+            server.admin(Permission::Admin, WorldId::DEFAULT, protect),
+            Err("no account named guest".into())
+        );
+    }
+
+    #[test]
+    fn worlds_are_independent() {
+        use common::node::{voxel_index, ChunkId};
+
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut server = Server::new(cfg.clone(), 2, &[0, 1], None);
+        // World 1 as it would be with no other world beside it
+        let mut control = Sim::seeded(Arc::new(cfg.clone()), 1);
+        let hello = |world| proto::ClientHello {
+            name: "a".into(),
+            world,
+            token: None,
+        };
+        let (zero, one) = server.worlds.split_at_mut(1);
+        let (zero, one) = (&mut zero[0].sim, &mut one[0].sim);
+        for sim in &mut [&mut *zero, &mut *one, &mut control] {
+            // Generate the same chunks each step regardless of timing
+            sim.throttle_worldgen(false);
+        }
+        let (_, a) = zero.spawn_character(hello(WorldId::from(0)), None);
+        for sim in &mut [&mut *one, &mut control] {
+            sim.spawn_character(hello(WorldId::from(1)), None);
+            sim.spawn_character(hello(WorldId::from(1)), None);
+        }
+        zero.destroy(a);
+        assert_eq!(zero.step().1.positions.len(), 0);
+        assert_eq!(one.step().1.positions.len(), 2);
+        control.step();
+        assert_ne!(zero.seed(), one.seed());
+
+        // Edits stay in the world they're made in
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let index = voxel_index(cfg.chunk_size, [1, 2, 3]);
+        let before = one.voxel_material(chunk, index);
+        assert!(zero.set_voxel(chunk, index, Material::Wood));
+        assert!(zero
+            .step()
+            .0
+            .voxels
+            .iter()
+            .any(|x| x.node == chunk.node && x.chunk == chunk.vertex));
+        assert_eq!(one.voxel_material(chunk, index), before);
+        for _ in 0..3 {
+            let (spawns, delta) = one.step();
+            let (expected_spawns, expected_delta) = control.step();
+            assert!(spawns.voxels.is_empty());
+            assert_eq!(
+                bincode::serialize(&(spawns, delta)).unwrap(),
+                bincode::serialize(&(expected_spawns, expected_delta)).unwrap()
+            );
+        }
+        assert_eq!(one.state_hash(), control.state_hash());
+    }
+}
@@ -0,0 +1,180 @@
+//! Input collection for worlds that run in lockstep
+//!
+//! Ordinarily a world steps on a timer, applying whatever input each client has sent by then. A
+//! world in lockstep instead waits at each tick until every participant's input for that tick has
+//! arrived, so that its evolution depends only on those inputs and never on network timing.
+
+use std::collections::BTreeMap;
+
+use common::proto::{Command, TickId};
+
+/// How far beyond the tick being collected a participant may send input, bounding what's buffered
+/// for a client that runs ahead
+const MAX_AHEAD: i32 = 64;
+
+pub struct Lockstep<K> {
+    /// Tick whose inputs are being collected
+    next: TickId,
+    /// Inputs received from each participant, by tick
+    inputs: BTreeMap<K, BTreeMap<TickId, Command>>,
+}
+
+impl<K: Ord + Copy> Lockstep<K> {
+    pub fn new() -> Self {
+        Self {
+            next: TickId(0),
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// Add a participant, returning the first tick it must send input for
+    pub fn join(&mut self, who: K) -> TickId {
+        self.inputs.insert(who, BTreeMap::new());
+        self.next
+    }
+
+    /// Remove a participant, so that ticks no longer wait on it
+    pub fn leave(&mut self, who: K) {
+        self.inputs.remove(&who);
+    }
+
+    /// Whether `submit` would accept `who`'s input for `tick`, i.e. `who` is participating, and
+    /// `tick` hasn't passed and doesn't lie too far ahead
+    pub fn accepts(&self, who: K, tick: TickId) -> bool {
+        tick >= self.next && tick.0 - self.next.0 <= MAX_AHEAD && self.inputs.contains_key(&who)
+    }
+
+    /// Record `who`'s input for `tick`
+    ///
+    /// Returns `false`, discarding the input, unless `accepts(who, tick)`.
+    pub fn submit(&mut self, who: K, tick: TickId, command: Command) -> bool {
+        if !self.accepts(who, tick) {
+            return false;
+        }
+        self.inputs.get_mut(&who).unwrap().insert(tick, command);
+        true
+    }
+
+    /// If every participant's input for the current tick has arrived, move on to the next tick,
+    /// returning the completed tick and its inputs in order of participant
+    pub fn advance(&mut self) -> Option<(TickId, Vec<(K, Command)>)> {
+        let tick = self.next;
+        if self.inputs.values().any(|x| !x.contains_key(&tick)) {
+            return None;
+        }
+        let inputs = self
+            .inputs
+            .iter_mut()
+            .map(|(&who, x)| (who, x.remove(&tick).unwrap()))
+            .collect();
+        self.next = tick.next();
+        Some((tick, inputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Sim;
+    use common::{proto::ClientHello, SimConfig, SimConfigRaw, WorldId};
+    use std::sync::Arc;
+
+    fn command(tick: TickId, velocity: na::Vector3<f32>) -> Command {
+        Command {
+            generation: tick.0 as u16,
+            orientation: na::one(),
+            velocity,
+            resync: false,
+            ping: None,
+            admin: None,
+            edit: None,
+            fire: false,
+            tick: Some(tick),
+        }
+    }
+
+    #[test]
+    fn waits_for_everyone() {
+        let mut lockstep = Lockstep::new();
+        assert_eq!(lockstep.join(0), TickId(0));
+        assert_eq!(lockstep.join(1), TickId(0));
+        let input = |tick| command(tick, na::zero());
+        assert!(lockstep.submit(0, TickId(0), input(TickId(0))));
+        assert!(lockstep.submit(0, TickId(1), input(TickId(1))));
+        assert!(lockstep.advance().is_none());
+        assert!(lockstep.submit(1, TickId(0), input(TickId(0))));
+        let (tick, inputs) = lockstep.advance().unwrap();
+        assert_eq!(tick, TickId(0));
+        assert_eq!(inputs.iter().map(|x| x.0).collect::<Vec<_>>(), [0, 1]);
+        // Past, distant, and unknown inputs are refused
+        assert!(!lockstep.submit(1, TickId(0), input(TickId(0))));
+        assert!(!lockstep.submit(1, TickId(1 + MAX_AHEAD + 1), input(TickId(0))));
+        assert!(!lockstep.submit(2, TickId(1), input(TickId(1))));
+        // A departed participant is no longer waited on
+        lockstep.leave(1);
+        assert_eq!(lockstep.advance().unwrap().0, TickId(1));
+        assert_eq!(lockstep.join(1), TickId(2));
+    }
+
+    /// Two clients each simulate the world from the inputs collected for every tick, receiving
+    /// those inputs at different times, and must agree exactly on its state after each
+    #[test]
+    fn replicas_agree() {
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(20.0),
+            ..SimConfigRaw::default()
+        }));
+        let mut replicas = [Sim::seeded(cfg.clone(), 0), Sim::seeded(cfg, 0)];
+        let mut characters = Vec::new();
+        for replica in &mut replicas {
+            replica.throttle_worldgen(false);
+            let ids = ["a", "b"]
+                .iter()
+                .map(|&name| {
+                    replica.spawn_character(
+                        ClientHello {
+                            name: name.into(),
+                            world: WorldId::DEFAULT,
+                            token: None,
+                        },
+                        None,
+                    )
+                })
+                .collect::<Vec<_>>();
+            characters.push(ids);
+        }
+
+        let mut lockstep = Lockstep::new();
+        lockstep.join(0);
+        lockstep.join(1);
+        let velocity = |who: usize, tick: i32| {
+            let angle = tick as f32 * 0.3 + who as f32;
+            na::Vector3::new(angle.cos(), 0.0, angle.sin())
+        };
+        for i in 0..20 {
+            let tick = TickId(i);
+            // Client 1 lags a tick behind client 0
+            assert!(lockstep.submit(0, tick, command(tick, velocity(0, i))));
+            if i > 0 {
+                let late = TickId(i - 1);
+                assert!(lockstep.submit(1, late, command(late, velocity(1, i - 1))));
+            } else {
+                assert!(lockstep.advance().is_none());
+                continue;
+            }
+            let (_, inputs) = lockstep.advance().unwrap();
+            let hashes = replicas
+                .iter_mut()
+                .zip(&characters)
+                .map(|(replica, ids)| {
+                    for &(who, ref cmd) in &inputs {
+                        replica.command(ids[who].1, cmd.clone()).unwrap();
+                    }
+                    replica.step();
+                    replica.state_hash()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(hashes[0], hashes[1], "diverged at tick {}", i - 1);
+        }
+    }
+}
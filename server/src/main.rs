@@ -1,12 +1,17 @@
 mod config;
 
-use std::{fs, net::UdpSocket, path::Path};
+use std::{
+    fs,
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context, Result};
 use quinn::{Certificate, CertificateChain, PrivateKey};
-use tracing::warn;
+use tracing::{info, warn};
 
-use common::SimConfig;
+use common::{SimConfig, WorldSeed};
 use config::Config;
 
 fn main() {
@@ -20,11 +25,28 @@ fn main() {
 }
 
 pub fn run() -> Result<()> {
-    let cfg = match std::env::args_os().nth(1) {
-        Some(path) => Config::load(Path::new(&path))?,
+    let mut args = std::env::args_os().skip(1).peekable();
+    // Reproduce a recorded session instead of serving, with `--replay <log> [config]`
+    let replay = if args.peek().map_or(false, |x| x == "--replay") {
+        args.next();
+        Some(PathBuf::from(
+            args.next().ok_or_else(|| anyhow!("missing path to log"))?,
+        ))
+    } else {
+        None
+    };
+    let path = args.next().map(PathBuf::from);
+    let cfg = match path {
+        Some(ref path) => Config::load(path)?,
         None => Config::default(),
     };
 
+    if let Some(log) = replay {
+        let hash = server::replay(SimConfig::from_raw(&cfg.simulation), &log)?;
+        info!(hash = %format_args!("{:016x}", hash), "replay complete");
+        return Ok(());
+    }
+
     let (certificate_chain, private_key) = match (&cfg.certificate_chain, &cfg.private_key) {
         (&Some(ref certificate_chain), &Some(ref private_key)) => (
             CertificateChain::from_pem(
@@ -57,12 +79,35 @@ pub fn run() -> Result<()> {
         }
     };
 
+    let autosave_interval = Duration::from_secs(cfg.autosave_interval.unwrap_or(60));
     server::run(
         server::NetParams {
             certificate_chain,
             private_key,
             socket: UdpSocket::bind(&cfg.listen).context("binding socket")?,
+            max_players: cfg.max_players.unwrap_or(64),
+            accounts: cfg.accounts,
+            lockstep: cfg.lockstep,
+            idle_timeout: cfg.idle_timeout.map(Duration::from_secs),
+            freeze_idle: cfg.freeze_idle,
+            edit_rate: cfg.edit_rate,
         },
         SimConfig::from_raw(&cfg.simulation),
+        cfg.world_seeds
+            .map(|seeds| seeds.iter().map(WorldSeed::value).collect())
+            .unwrap_or_else(|| vec![0]),
+        path.map(|path| server::ConfigSource {
+            path,
+            load: load_sim_config,
+        }),
+        cfg.save_path.map(|path| server::SaveParams {
+            path,
+            interval: autosave_interval,
+        }),
+        cfg.record_path,
     )
 }
+
+fn load_sim_config(path: &Path) -> Result<SimConfig> {
+    Ok(SimConfig::from_raw(&Config::load(path)?.simulation))
+}
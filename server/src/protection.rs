@@ -0,0 +1,146 @@
+use std::{fmt, sync::Arc};
+
+use fxhash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use slotmap::{new_key_type, DenseSlotMap};
+
+use common::graph::NodeId;
+
+new_key_type! {
+    /// Identifies a protected region
+    pub struct RegionId;
+}
+
+/// Regions of the world in which only certain players may edit voxels
+///
+/// Each region is a set of nodes. A node belongs to at most one region; protecting a node that is
+/// already protected moves it into the new region. Members are identified by account, per
+/// `account::Identity`, so that permissions survive reconnection and can't be claimed by anyone
+/// else; guests are never members.
+#[derive(Default)]
+pub struct Protections {
+    regions: DenseSlotMap<RegionId, Region>,
+    /// The region each protected node belongs to
+    nodes: FxHashMap<NodeId, RegionId>,
+}
+
+struct Region {
+    nodes: FxHashSet<NodeId>,
+    /// Accounts permitted to edit within the region
+    members: FxHashSet<Arc<str>>,
+}
+
+impl Protections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restrict edits within `nodes` to `members`
+    pub fn protect(
+        &mut self,
+        nodes: impl IntoIterator<Item = NodeId>,
+        members: impl IntoIterator<Item = Arc<str>>,
+    ) -> RegionId {
+        let id = self.regions.insert(Region {
+            nodes: FxHashSet::default(),
+            members: members.into_iter().collect(),
+        });
+        for node in nodes {
+            if let Some(old) = self.nodes.insert(node, id) {
+                self.regions[old].nodes.remove(&node);
+            }
+            self.regions[id].nodes.insert(node);
+        }
+        id
+    }
+
+    /// Lift the protection on a region, returning whether it existed
+    pub fn unprotect(&mut self, region: RegionId) -> bool {
+        let region = match self.regions.remove(region) {
+            Some(x) => x,
+            None => return false,
+        };
+        for node in &region.nodes {
+            self.nodes.remove(node);
+        }
+        true
+    }
+
+    /// Permit the holder of `account` to edit within `region`
+    pub fn grant(&mut self, region: RegionId, account: Arc<str>) {
+        if let Some(region) = self.regions.get_mut(region) {
+            region.members.insert(account);
+        }
+    }
+
+    /// The nodes and members of every region
+    pub fn regions(&self) -> impl Iterator<Item = (&FxHashSet<NodeId>, &FxHashSet<Arc<str>>)> {
+        self.regions.values().map(|x| (&x.nodes, &x.members))
+    }
+
+    /// Determine whether a player holding `account`, if any, may edit voxels in `node`
+    pub fn check(&self, account: Option<&str>, node: NodeId) -> Result<(), EditDenied> {
+        let region = match self.nodes.get(&node) {
+            Some(&x) => x,
+            None => return Ok(()),
+        };
+        match account {
+            Some(account) if self.regions[region].members.contains(account) => Ok(()),
+            _ => Err(EditDenied { region }),
+        }
+    }
+}
+
+/// A protected region in a form that outlasts the session, identifying nodes by path
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SavedRegion {
+    /// Each node's `NodePath`, as written by its `Display` implementation
+    pub nodes: Vec<String>,
+    /// Names of the member accounts
+    pub members: Vec<String>,
+}
+
+/// An edit was attempted in a region the player is not a member of
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EditDenied {
+    pub region: RegionId,
+}
+
+impl fmt::Display for EditDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("this area is protected")
+    }
+}
+
+impl std::error::Error for EditDenied {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{dodeca::Side, graph::Graph};
+
+    #[test]
+    fn unauthorized_edit() {
+        let mut graph = Graph::<()>::new();
+        let inside = graph.ensure_neighbor(NodeId::ROOT, Side::A);
+        let outside = graph.ensure_neighbor(NodeId::ROOT, Side::B);
+
+        let mut protections = Protections::new();
+        let region = protections.protect(vec![NodeId::ROOT, inside], vec!["alice".into()]);
+
+        assert_eq!(protections.check(Some("alice"), inside), Ok(()));
+        assert_eq!(
+            protections.check(Some("bob"), inside),
+            Err(EditDenied { region })
+        );
+        assert_eq!(protections.check(None, inside), Err(EditDenied { region }));
+        assert_eq!(protections.check(Some("bob"), outside), Ok(()));
+        assert_eq!(protections.check(None, outside), Ok(()));
+
+        protections.grant(region, "bob".into());
+        assert_eq!(protections.check(Some("bob"), inside), Ok(()));
+
+        assert!(protections.unprotect(region));
+        assert_eq!(protections.check(None, NodeId::ROOT), Ok(()));
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Instant;
+
+use serde::Deserialize;
+
+/// How quickly a player may edit voxels
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EditRate {
+    /// Sustained edits per second
+    pub rate: f32,
+    /// Edits that may be made in quick succession after a pause
+    pub burst: u32,
+    /// Number of consecutive excess edits after which a player is considered abusive
+    pub disconnect_after: Option<u32>,
+}
+
+/// What to do with an edit submitted by a player
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Admission {
+    Accept,
+    /// The player is editing too quickly; ignore the edit
+    Drop,
+    /// The player has persistently exceeded the limit
+    Disconnect,
+}
+
+/// Token bucket limiting a single player's edits
+///
+/// Each edit consumes a token. Tokens are replenished continuously at `EditRate::rate` per second,
+/// up to `EditRate::burst`.
+pub struct EditLimiter {
+    params: EditRate,
+    tokens: f32,
+    updated: Instant,
+    /// Edits dropped since the last accepted edit
+    excess: u32,
+}
+
+impl EditLimiter {
+    pub fn new(params: EditRate, now: Instant) -> Self {
+        Self {
+            tokens: params.burst as f32,
+            params,
+            updated: now,
+            excess: 0,
+        }
+    }
+
+    /// Decide whether to apply an edit submitted at `now`
+    pub fn admit(&mut self, now: Instant) -> Admission {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f32();
+        self.updated = now;
+        self.tokens = (self.tokens + elapsed * self.params.rate).min(self.params.burst as f32);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.excess = 0;
+            return Admission::Accept;
+        }
+        self.excess += 1;
+        match self.params.disconnect_after {
+            Some(limit) if self.excess > limit => Admission::Disconnect,
+            _ => Admission::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn excess_throttled() {
+        let start = Instant::now();
+        let mut limiter = EditLimiter::new(
+            EditRate {
+                rate: 10.0,
+                burst: 5,
+                disconnect_after: Some(10),
+            },
+            start,
+        );
+
+        // A burst is accepted, but no more
+        for _ in 0..5 {
+            assert_eq!(limiter.admit(start), Admission::Accept);
+        }
+        for _ in 0..10 {
+            assert_eq!(limiter.admit(start), Admission::Drop);
+        }
+
+        // Edits at the sustained rate pass, and forgive earlier excess
+        let interval = Duration::from_millis(100);
+        for i in 1..=20 {
+            assert_eq!(limiter.admit(start + interval * i), Admission::Accept);
+        }
+
+        // Flooding is eventually treated as abuse
+        let now = start + interval * 20;
+        for _ in 0..10 {
+            assert_eq!(limiter.admit(now), Admission::Drop);
+        }
+        assert_eq!(limiter.admit(now), Admission::Disconnect);
+    }
+}
@@ -0,0 +1,556 @@
+//! Session recording and replay
+//!
+//! Every input that reaches the simulation is logged in the order it takes effect, interleaved with
+//! the state broadcast after each step. The simulation is deterministic given its inputs, so
+//! feeding a log back through a fresh `Sim` reproduces the recorded session, and comparing each
+//! step's state against the log pinpoints where a reproduction diverges. Each world is simulated
+//! independently, so events are tagged with the world they apply to.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::sim::Sim;
+use common::{
+    graph::NodePath,
+    node::ChunkId,
+    proto::{self, AdminCommand},
+    EntityId, SimConfig, WorldId,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Event {
+    /// `world` was created from `seed`, before any events concerning it
+    World { world: WorldId, seed: u64 },
+    /// A client finished connecting, spawning `character`
+    Join {
+        world: WorldId,
+        character: EntityId,
+        hello: proto::ClientHello,
+        /// Server account the player proved they hold, if any
+        account: Option<String>,
+    },
+    /// A command was applied to `character`
+    Command {
+        world: WorldId,
+        character: EntityId,
+        command: proto::Command,
+    },
+    /// A client disconnected, destroying `character`
+    Leave { world: WorldId, character: EntityId },
+    /// An administrator moved `character` to the node at `path`
+    Teleport {
+        world: WorldId,
+        character: EntityId,
+        path: String,
+    },
+    /// An administrator changed the world other than by teleporting, e.g. by flattening terrain
+    Admin {
+        world: WorldId,
+        command: proto::AdminCommand,
+    },
+    /// The world's simulation stepped, producing this state for broadcast
+    Step {
+        world: WorldId,
+        delta: proto::StateDelta,
+        /// `Sim::state_hash` afterwards
+        hash: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// Time since recording began
+    time: Duration,
+    event: Event,
+}
+
+/// Appends events to a log file
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: Event) -> Result<()> {
+        let record = Record {
+            time: self.start.elapsed(),
+            event,
+        };
+        bincode::serialize_into(&mut self.file, &record)?;
+        if let Event::Step { .. } = record.event {
+            // Keep the log usable if the server dies mid-session
+            self.file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reproduce the session recorded at `path`, returning the combined hash of each world's final
+/// state
+///
+/// Fails at the first step whose state differs from the recording.
+pub fn replay(cfg: SimConfig, path: &Path) -> Result<u64> {
+    let mut file = BufReader::new(File::open(path).context("opening log")?);
+    let cfg = Arc::new(cfg);
+    let mut worlds = FxHashMap::<WorldId, (Sim, u64)>::default();
+    // Entity IDs are allocated by each world independently, so are only unique within one
+    let mut characters = FxHashMap::<(WorldId, EntityId), _>::default();
+    loop {
+        let record = match bincode::deserialize_from::<_, Record>(&mut file) {
+            Ok(x) => x,
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(worlds
+                        .values()
+                        .fold(0, |acc, &(_, hash)| acc.wrapping_add(hash)))
+                }
+                _ => return Err(e).context("reading log"),
+            },
+        };
+        let world = match record.event {
+            Event::World { world, seed } => {
+                let mut sim = Sim::seeded(cfg.clone(), seed);
+                sim.throttle_worldgen(false);
+                worlds.insert(world, (sim, 0));
+                continue;
+            }
+            Event::Join { world, .. }
+            | Event::Command { world, .. }
+            | Event::Leave { world, .. }
+            | Event::Teleport { world, .. }
+            | Event::Admin { world, .. }
+            | Event::Step { world, .. } => world,
+        };
+        let (sim, hash) = worlds
+            .get_mut(&world)
+            .ok_or_else(|| anyhow!("event for unknown world {}", world))?;
+        match record.event {
+            Event::World { .. } => unreachable!(),
+            Event::Join {
+                character,
+                hello,
+                account,
+                ..
+            } => {
+                let (id, entity) = sim.spawn_character(hello, account.map(Arc::from));
+                if id != character {
+                    bail!(
+                        "diverged at {:?}: spawned {} as {}",
+                        record.time,
+                        character,
+                        id
+                    );
+                }
+                characters.insert((world, character), entity);
+            }
+            Event::Command {
+                character, command, ..
+            } => {
+                let entity = *characters
+                    .get(&(world, character))
+                    .ok_or_else(|| anyhow!("command for unknown character {}", character))?;
+                sim.command(entity, command)?;
+            }
+            Event::Leave { character, .. } => {
+                let entity = characters
+                    .remove(&(world, character))
+                    .ok_or_else(|| anyhow!("unknown character {} left", character))?;
+                sim.destroy(entity);
+            }
+            Event::Teleport {
+                character, path, ..
+            } => {
+                let entity = *characters
+                    .get(&(world, character))
+                    .ok_or_else(|| anyhow!("teleported unknown character {}", character))?;
+                sim.teleport(entity, &path)?;
+            }
+            Event::Admin { command, .. } => match command {
+                AdminCommand::SetTime { time_of_day } => sim.set_time_of_day(time_of_day),
+                AdminCommand::Flatten { path } => {
+                    sim.flatten(&path.parse::<NodePath>()?);
+                }
+                AdminCommand::Protect { path, members } => {
+                    sim.protect(
+                        &[path.parse::<NodePath>()?],
+                        members.into_iter().map(Arc::from),
+                    );
+                }
+                AdminCommand::Inscribe {
+                    path,
+                    chunk,
+                    coords,
+                    text,
+                } => {
+                    let chunk = ChunkId::new(sim.locate(&path.parse::<NodePath>()?), chunk);
+                    sim.set_metadata(chunk, coords, text.into_bytes());
+                }
+                AdminCommand::Kick { .. }
+                | AdminCommand::Ban { .. }
+                | AdminCommand::Teleport { .. }
+                | AdminCommand::Inspect { .. } => {
+                    bail!("unexpected admin command {:?}", command)
+                }
+            },
+            Event::Step {
+                delta: expected,
+                hash: expected_hash,
+                ..
+            } => {
+                let (_, delta) = sim.step();
+                *hash = sim.state_hash();
+                if delta.step != expected.step || *hash != expected_hash {
+                    bail!(
+                        "diverged at step {} of world {} ({:?})",
+                        expected.step,
+                        world,
+                        record.time
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use common::{dodeca::Vertex, graph::NodeId, node::voxel_index, world::Material, SimConfigRaw};
+
+    fn cfg() -> SimConfig {
+        SimConfig::from_raw(&SimConfigRaw::default())
+    }
+
+    /// Replace the terrain around where characters spawn with a flat floor, as an administrator
+    fn flatten_spawn(sim: &mut Sim, recorder: &mut Recorder, world: WorldId) {
+        sim.flatten(&NodePath::default());
+        recorder
+            .record(Event::Admin {
+                world,
+                command: AdminCommand::Flatten {
+                    path: String::new(),
+                },
+            })
+            .unwrap();
+    }
+
+    /// An edit breaking a voxel of the floor laid by `flatten_spawn`, as seen by the first
+    /// character to spawn in a world generated from `seed`
+    fn breakable(seed: u64) -> proto::VoxelEdit {
+        let mut sim = Sim::seeded(Arc::new(cfg()), seed);
+        let (_, character) = sim.spawn_character(
+            proto::ClientHello {
+                name: "scout".into(),
+                world: WorldId::DEFAULT,
+                token: None,
+            },
+            None,
+        );
+        sim.flatten(&NodePath::default());
+        let voxels = usize::from(cfg().chunk_size) + 2;
+        Vertex::iter()
+            .flat_map(|vertex| {
+                (0..voxels.pow(3)).map(move |index| proto::VoxelEdit {
+                    node: NodeId::ROOT,
+                    chunk: vertex,
+                    index: index as u32,
+                    material: Material::Void,
+                })
+            })
+            .find(|edit| sim.edit(character, edit).is_ok())
+            .expect("no breakable voxel")
+    }
+
+    #[test]
+    fn replay_reproduces_session() {
+        let path = env::temp_dir().join(format!("hypermine-replay-{}", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let world = WorldId::DEFAULT;
+        recorder.record(Event::World { world, seed: 7 }).unwrap();
+        let mut sim = Sim::seeded(Arc::new(cfg()), 7);
+        sim.throttle_worldgen(false);
+        let join = |sim: &mut Sim, recorder: &mut Recorder, name: &str| {
+            let hello = || proto::ClientHello {
+                name: name.into(),
+                world,
+                token: None,
+            };
+            let (character, entity) = sim.spawn_character(hello(), None);
+            recorder
+                .record(Event::Join {
+                    world,
+                    character,
+                    hello: hello(),
+                    account: None,
+                })
+                .unwrap();
+            (character, entity)
+        };
+        let (a, a_entity) = join(&mut sim, &mut recorder, "a");
+        let (b, b_entity) = join(&mut sim, &mut recorder, "b");
+        flatten_spawn(&mut sim, &mut recorder, world);
+        let edit = breakable(7);
+        let mut hash = 0;
+        for step in 0..30u16 {
+            let command = || proto::Command {
+                generation: step,
+                orientation: na::UnitQuaternion::from_axis_angle(
+                    &na::Vector3::y_axis(),
+                    f32::from(step) * 0.1,
+                ),
+                velocity: na::Vector3::new(0.0, 0.0, -1.0),
+                resync: false,
+                ping: None,
+                admin: None,
+                // a digs in before moving off
+                edit: if step == 0 { Some(edit) } else { None },
+                fire: false,
+                tick: None,
+            };
+            // b leaves partway through
+            let (character, entity) = if step % 2 == 0 || step > 20 {
+                (a, a_entity)
+            } else {
+                (b, b_entity)
+            };
+            sim.command(entity, command()).unwrap();
+            recorder
+                .record(Event::Command {
+                    world,
+                    character,
+                    command: command(),
+                })
+                .unwrap();
+            if step == 20 {
+                sim.destroy(b_entity);
+                recorder
+                    .record(Event::Leave {
+                        world,
+                        character: b,
+                    })
+                    .unwrap();
+            }
+            let (_, delta) = sim.step();
+            hash = sim.state_hash();
+            recorder.record(Event::Step { world, delta, hash }).unwrap();
+        }
+        drop(recorder);
+        assert!(sim.take_edit_rejections(a_entity).is_empty());
+
+        assert_eq!(replay(cfg(), &path).unwrap(), hash);
+
+        // A reproduction under different parameters is caught
+        let fast = SimConfigRaw {
+            movement_speed: Some(100.0),
+            ..SimConfigRaw::default()
+        };
+        assert!(replay(SimConfig::from_raw(&fast), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_reproduces_inscription() {
+        let path = env::temp_dir().join(format!(
+            "hypermine-replay-inscription-{}",
+            std::process::id()
+        ));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let world = WorldId::DEFAULT;
+        recorder.record(Event::World { world, seed: 7 }).unwrap();
+        let mut sim = Sim::seeded(Arc::new(cfg()), 7);
+        sim.throttle_worldgen(false);
+        flatten_spawn(&mut sim, &mut recorder, world);
+
+        // Any voxel of the floor
+        let dimension = cfg().chunk_size;
+        let node = sim.locate(&NodePath::default());
+        let (chunk, coords) = Vertex::iter()
+            .flat_map(|vertex| {
+                (0..dimension).flat_map(move |z| {
+                    (0..dimension)
+                        .flat_map(move |y| (0..dimension).map(move |x| (vertex, [x, y, z])))
+                })
+            })
+            .find(|&(vertex, coords)| {
+                let index = voxel_index(dimension, coords);
+                sim.voxel_material(ChunkId::new(node, vertex), index)
+                    .map_or(false, |x| !x.is_void())
+            })
+            .expect("no floor");
+        let id = ChunkId::new(node, chunk);
+        assert!(sim.set_metadata(id, coords, b"hello".to_vec()));
+        recorder
+            .record(Event::Admin {
+                world,
+                command: AdminCommand::Inscribe {
+                    path: String::new(),
+                    chunk,
+                    coords,
+                    text: "hello".into(),
+                },
+            })
+            .unwrap();
+        let (_, delta) = sim.step();
+        let hash = sim.state_hash();
+        recorder.record(Event::Step { world, delta, hash }).unwrap();
+        drop(recorder);
+
+        assert_eq!(sim.metadata(id, coords), Some(&b"hello"[..]));
+        assert_eq!(replay(cfg(), &path).unwrap(), hash);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_worlds_sharing_ids() {
+        let path = env::temp_dir().join(format!("hypermine-replay-worlds-{}", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let worlds = [WorldId::from(0), WorldId::from(1)];
+        let mut sims = Vec::new();
+        let mut players = Vec::new();
+        for (&world, name) in worlds.iter().zip(&["a", "b"]) {
+            recorder.record(Event::World { world, seed: 7 }).unwrap();
+            let mut sim = Sim::seeded(Arc::new(cfg()), 7);
+            sim.throttle_worldgen(false);
+            let hello = || proto::ClientHello {
+                name: (*name).into(),
+                world,
+                token: None,
+            };
+            let (character, entity) = sim.spawn_character(hello(), None);
+            recorder
+                .record(Event::Join {
+                    world,
+                    character,
+                    hello: hello(),
+                    account: None,
+                })
+                .unwrap();
+            sims.push(sim);
+            players.push((character, entity));
+        }
+        // Each world's first player gets the same ID
+        assert_eq!(players[0].0, players[1].0);
+
+        let mut hash = 0u64;
+        for step in 0..10u16 {
+            for (i, &world) in worlds.iter().enumerate() {
+                let command = || proto::Command {
+                    generation: step,
+                    orientation: na::UnitQuaternion::identity(),
+                    // The players head in different directions
+                    velocity: na::Vector3::new(i as f32 * 2.0 - 1.0, 0.0, 0.0),
+                    resync: false,
+                    ping: None,
+                    admin: None,
+                    edit: None,
+                    fire: false,
+                    tick: None,
+                };
+                let (character, entity) = players[i];
+                // a leaves partway through
+                let present = i != 0 || step <= 5;
+                if present {
+                    sims[i].command(entity, command()).unwrap();
+                    recorder
+                        .record(Event::Command {
+                            world,
+                            character,
+                            command: command(),
+                        })
+                        .unwrap();
+                }
+                if present && i == 0 && step == 5 {
+                    sims[i].destroy(entity);
+                    recorder.record(Event::Leave { world, character }).unwrap();
+                }
+                let (_, delta) = sims[i].step();
+                let state = sims[i].state_hash();
+                if step == 9 {
+                    hash = hash.wrapping_add(state);
+                }
+                recorder
+                    .record(Event::Step {
+                        world,
+                        delta,
+                        hash: state,
+                    })
+                    .unwrap();
+            }
+        }
+        drop(recorder);
+
+        assert_eq!(replay(cfg(), &path).unwrap(), hash);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_compares_voxels() {
+        let path = env::temp_dir().join(format!("hypermine-replay-voxels-{}", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let world = WorldId::DEFAULT;
+        recorder.record(Event::World { world, seed: 7 }).unwrap();
+        let mut sim = Sim::seeded(Arc::new(cfg()), 7);
+        sim.throttle_worldgen(false);
+        let hello = || proto::ClientHello {
+            name: "scout".into(),
+            world,
+            token: None,
+        };
+        let (character, entity) = sim.spawn_character(hello(), None);
+        recorder
+            .record(Event::Join {
+                world,
+                character,
+                hello: hello(),
+                account: None,
+            })
+            .unwrap();
+        flatten_spawn(&mut sim, &mut recorder, world);
+        let command = |edit| proto::Command {
+            generation: 0,
+            orientation: na::one(),
+            velocity: na::zero(),
+            resync: false,
+            ping: None,
+            admin: None,
+            edit,
+            fire: false,
+            tick: None,
+        };
+        // The edit goes unrecorded, leaving everyone in place but the terrain different
+        sim.command(entity, command(Some(breakable(7)))).unwrap();
+        assert!(sim.take_edit_rejections(entity).is_empty());
+        recorder
+            .record(Event::Command {
+                world,
+                character,
+                command: command(None),
+            })
+            .unwrap();
+        let (_, delta) = sim.step();
+        let hash = sim.state_hash();
+        recorder.record(Event::Step { world, delta, hash }).unwrap();
+        drop(recorder);
+
+        assert!(replay(cfg(), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
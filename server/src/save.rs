@@ -0,0 +1,463 @@
+//! Periodic saving of edited chunks
+//!
+//! Each save writes only the chunks edited since the previous one, each to its own file named
+//! after its compactly encoded `ChunkId`, alongside a file holding any voxel metadata and, for
+//! chunks never edited since they were generated, one holding the worldgen revision. Paths of
+//! flattened nodes are kept in a file of their own, one per line, and protected regions in another.
+//! Writing happens on a background thread so that it never delays a step.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use tracing::{error, info};
+
+use crate::{
+    protection::SavedRegion,
+    sim::{DirtyChunk, Sim},
+};
+use common::{
+    graph::NodePath,
+    node::{VoxelData, VoxelMetadata},
+    world::Material,
+};
+
+/// Where and how often to save
+pub struct SaveParams {
+    /// Directory to write chunks into
+    pub path: PathBuf,
+    pub interval: Duration,
+}
+
+pub struct Autosave {
+    params: SaveParams,
+    /// When the next save is due
+    next: Instant,
+    /// Outcome of the save in progress, if any
+    pending: Option<mpsc::Receiver<io::Result<SaveStats>>>,
+}
+
+impl Autosave {
+    pub fn new(params: SaveParams, now: Instant) -> Self {
+        Self {
+            next: now + params.interval,
+            params,
+            pending: None,
+        }
+    }
+
+    /// Report on the previous save if it's finished, then start a new one if it's due
+    ///
+    /// At most one save is in progress at a time; edits made while one is running are left for
+    /// the next. Returns whether a save was started.
+    pub fn poll(&mut self, now: Instant, sim: &mut Sim) -> bool {
+        if let Some(ref pending) = self.pending {
+            match pending.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => return false,
+                Ok(result) => report(result),
+                Err(mpsc::TryRecvError::Disconnected) => error!("save thread panicked"),
+            }
+            self.pending = None;
+        }
+        if now < self.next {
+            return false;
+        }
+        self.next = now + self.params.interval;
+        let chunks = sim.take_dirty();
+        let flattened = sim.take_flattened_change();
+        let protections = sim.take_protections_change();
+        if chunks.is_empty() && flattened.is_none() && protections.is_none() {
+            return false;
+        }
+        let path = self.params.path.clone();
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = send.send(write(
+                &path,
+                &chunks,
+                flattened.as_ref().map(|x| &x[..]),
+                protections.as_ref().map(|x| &x[..]),
+            ));
+        });
+        self.pending = Some(recv);
+        true
+    }
+
+    /// Block until the save in progress, if any, has finished, returning its outcome
+    pub fn flush(&mut self) -> Option<io::Result<SaveStats>> {
+        self.pending.take()?.recv().ok()
+    }
+}
+
+impl Drop for Autosave {
+    /// Finish the save in progress, rather than abandoning the edits it holds
+    fn drop(&mut self) {
+        if let Some(result) = self.flush() {
+            report(result);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SaveStats {
+    pub chunks: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+fn report(result: io::Result<SaveStats>) {
+    match result {
+        Ok(stats) => info!(
+            chunks = stats.chunks,
+            bytes = stats.bytes,
+            elapsed = ?stats.elapsed,
+            "saved"
+        ),
+        Err(e) => error!("save failed: {}", e),
+    }
+}
+
+/// Restore the save in `dir` to carry on where it left off, if there is one
+pub fn restore(dir: &Path, sim: &mut Sim) {
+    restore_flattened(dir, sim);
+    restore_protections(dir, sim);
+    restore_chunks(dir, sim);
+    sim.regenerate_outdated();
+}
+
+fn restore_chunks(dir: &Path, sim: &mut Sim) {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("reading saved chunks: {}", e);
+            return;
+        }
+    };
+    let mut count = 0;
+    for entry in entries {
+        let path = match entry {
+            Ok(x) => x.path(),
+            Err(e) => {
+                error!("reading saved chunks: {}", e);
+                continue;
+            }
+        };
+        if path.extension().map_or(true, |x| x != "chunk") {
+            continue;
+        }
+        match restore_chunk(&path, sim) {
+            Ok(()) => count += 1,
+            Err(e) => error!(path = %path.display(), "skipping unreadable chunk: {:#}", e),
+        }
+    }
+    info!(count, "restored edited chunks");
+}
+
+fn restore_chunk(path: &Path, sim: &mut Sim) -> Result<()> {
+    let key = path
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(parse_key)
+        .ok_or_else(|| anyhow!("malformed file name"))?;
+    let voxels = decode(&fs::read(path)?).ok_or_else(|| anyhow!("malformed voxel data"))?;
+    let metadata = match fs::read(path.with_extension("meta")) {
+        Ok(x) => bincode::deserialize::<VoxelMetadata>(&x)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => VoxelMetadata::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let generated = match fs::read(path.with_extension("gen")) {
+        Ok(x) if x.len() == 4 => Some(u32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+        Ok(_) => return Err(anyhow!("malformed worldgen revision")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    sim.restore_chunk(&key, voxels, metadata, generated)?;
+    Ok(())
+}
+
+fn restore_flattened(dir: &Path, sim: &mut Sim) {
+    let text = match fs::read_to_string(dir.join(FLATTENED)) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("reading flattened nodes: {}", e);
+            return;
+        }
+    };
+    let paths = text
+        .lines()
+        .filter_map(|line| match line.parse::<NodePath>() {
+            Ok(path) => Some(path),
+            Err(e) => {
+                error!(%line, "skipping malformed flattened node: {:#}", e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    info!(count = paths.len(), "restoring flattened nodes");
+    sim.restore_flattened(&paths);
+}
+
+fn restore_protections(dir: &Path, sim: &mut Sim) {
+    let data = match fs::read(dir.join(PROTECTIONS)) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("reading protected regions: {}", e);
+            return;
+        }
+    };
+    let regions = match bincode::deserialize::<Vec<SavedRegion>>(&data) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("malformed protected regions: {}", e);
+            return;
+        }
+    };
+    info!(count = regions.len(), "restoring protected regions");
+    if let Err(e) = sim.restore_protections(&regions) {
+        error!("restoring protected regions: {:#}", e);
+    }
+}
+
+/// Write each of `chunks` to its own file in `dir`, along with the paths of flattened nodes and
+/// the protected regions if they've changed
+fn write(
+    dir: &Path,
+    chunks: &[DirtyChunk],
+    flattened: Option<&[NodePath]>,
+    protections: Option<&[SavedRegion]>,
+) -> io::Result<SaveStats> {
+    let started = Instant::now();
+    fs::create_dir_all(dir)?;
+    let mut bytes = 0;
+    if let Some(paths) = flattened {
+        let text = paths.iter().map(|x| format!("{}\n", x)).collect::<String>();
+        bytes += replace(&dir.join(FLATTENED), text.as_bytes())?;
+    }
+    if let Some(regions) = protections {
+        let data =
+            bincode::serialize(regions).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        bytes += replace(&dir.join(PROTECTIONS), &data)?;
+    }
+    for chunk in chunks {
+        let path = dir.join(file_name(&chunk.key));
+        bytes += replace(&path, &encode(&chunk.voxels))?;
+        let meta = path.with_extension("meta");
+        if chunk.metadata.is_empty() {
+            // Discard metadata of voxels that have since been removed
+            remove(&meta)?;
+        } else {
+            let data = bincode::serialize(&chunk.metadata)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            bytes += replace(&meta, &data)?;
+        }
+        let gen = path.with_extension("gen");
+        match chunk.generated {
+            // Edited since it was generated, so never to be regenerated
+            None => remove(&gen)?,
+            Some(version) => bytes += replace(&gen, &version.to_le_bytes())?,
+        }
+    }
+    Ok(SaveStats {
+        chunks: chunks.len(),
+        bytes,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Write `data` to `path`, returning its length
+fn replace(path: &Path, data: &[u8]) -> io::Result<u64> {
+    // Write to a temporary file first so a crash mid-write can't corrupt an earlier save
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, data)?;
+    fs::rename(&temp, path)?;
+    Ok(data.len() as u64)
+}
+
+/// Delete the file at `path`, if it exists
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Name of the file listing the paths of flattened nodes
+const FLATTENED: &str = "flattened.txt";
+
+/// Name of the file holding the protected regions
+const PROTECTIONS: &str = "protections.bin";
+
+/// Name of the file holding the chunk whose encoded `ChunkId` is `key`
+fn file_name(key: &[u8]) -> String {
+    let mut name = key.iter().map(|x| format!("{:02x}", x)).collect::<String>();
+    name.push_str(".chunk");
+    name
+}
+
+/// Inverse of `file_name`, given the name without its extension
+fn parse_key(name: &str) -> Option<Vec<u8>> {
+    if name.len() % 2 != 0 {
+        return None;
+    }
+    (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Little-endian material of each voxel, or of the whole chunk if it's solid
+fn encode(voxels: &VoxelData) -> Vec<u8> {
+    match *voxels {
+        VoxelData::Solid(mat) => (mat as u16).to_le_bytes().to_vec(),
+        VoxelData::Dense(ref data) => data
+            .iter()
+            .flat_map(|&mat| (mat as u16).to_le_bytes().to_vec())
+            .collect(),
+    }
+}
+
+/// Inverse of `encode`
+fn decode(data: &[u8]) -> Option<VoxelData> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    let materials = data
+        .chunks_exact(2)
+        .map(|x| Material::from_index(u16::from_le_bytes([x[0], x[1]])))
+        .collect::<Option<Vec<_>>>()?;
+    match materials.len() {
+        0 => None,
+        1 => Some(VoxelData::Solid(materials[0])),
+        _ => Some(VoxelData::Dense(materials.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use super::*;
+    use common::{
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{self, ChunkId, VoxelMetadata},
+        world::Material,
+        SimConfig, SimConfigRaw,
+    };
+
+    /// Paths of the files in `dir` with extension `ext`
+    fn files(dir: &Path, ext: &str) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |x| x == ext))
+            .collect()
+    }
+
+    /// Material of voxel `index` in each chunk file in `dir`
+    fn saved(dir: &Path, index: usize) -> Vec<u16> {
+        files(dir, "chunk")
+            .into_iter()
+            .map(|path| {
+                let data = fs::read(path).unwrap();
+                u16::from_le_bytes([data[2 * index], data[2 * index + 1]])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_saved_incrementally() {
+        let dir = env::temp_dir().join(format!("hypermine-autosave-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let dimension = cfg.chunk_size;
+        let mut sim = Sim::seeded(Arc::new(cfg), 0);
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let (a, b) = (
+            node::voxel_index(dimension, [1, 2, 3]),
+            node::voxel_index(dimension, [4, 5, 6]),
+        );
+
+        assert!(sim.set_voxel(chunk, a, Material::Stone));
+        assert!(sim.set_voxel(chunk, b, Material::Stone));
+        assert!(!autosave.poll(start, &mut sim), "save isn't due yet");
+        assert!(autosave.poll(start + interval, &mut sim));
+        // Edited while the save is in progress
+        assert!(sim.set_voxel(chunk, b, Material::Dirt));
+        let stats = autosave.flush().unwrap().unwrap();
+        assert_eq!(stats.chunks, 1);
+        assert_eq!(saved(&dir, a), [Material::Stone as u16]);
+        assert_eq!(saved(&dir, b), [Material::Stone as u16]);
+
+        // The next save isn't due until the interval elapses again
+        assert!(!autosave.poll(start + interval, &mut sim));
+        assert!(autosave.poll(start + 2 * interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+        assert_eq!(saved(&dir, b), [Material::Dirt as u16]);
+
+        // Nothing left to save
+        assert!(!autosave.poll(start + 3 * interval, &mut sim));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn metadata_saved_until_voxel_removed() {
+        let dir = env::temp_dir().join(format!("hypermine-metadata-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let dimension = cfg.chunk_size;
+        let mut sim = Sim::seeded(Arc::new(cfg), 0);
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = [1, 2, 3];
+        let index = node::voxel_index(dimension, coords);
+
+        assert!(sim.set_voxel(chunk, index, Material::Void));
+        assert!(
+            !sim.set_metadata(chunk, coords, b"empty".to_vec()),
+            "empty voxels can't hold metadata"
+        );
+        assert!(sim.set_voxel(chunk, index, Material::Wood));
+        assert!(sim.set_metadata(chunk, coords, b"chest".to_vec()));
+        assert!(autosave.poll(start + interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+        let meta = files(&dir, "meta");
+        assert_eq!(meta.len(), 1);
+        let loaded = bincode::deserialize::<VoxelMetadata>(&fs::read(&meta[0]).unwrap()).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[&coords], b"chest");
+
+        // Breaking the voxel drops its metadata, in the world and in the save
+        assert!(sim.set_voxel(chunk, index, Material::Void));
+        assert_eq!(sim.metadata(chunk, coords), None);
+        assert!(autosave.poll(start + 2 * interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+        assert!(files(&dir, "meta").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
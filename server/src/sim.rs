@@ -1,83 +1,299 @@
-use std::{mem, sync::Arc};
+use std::{
+    collections::VecDeque,
+    hash::Hasher,
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use fxhash::FxHashMap;
+use anyhow::{ensure, Result};
+use fxhash::{FxHashMap, FxHashSet, FxHasher64};
 use hecs::Entity;
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use tracing::{error_span, info, trace};
 
+use crate::{
+    entity_ids::EntityIds,
+    protection::{Protections, RegionId, SavedRegion},
+};
 use common::{
-    graph::{Graph, NodeId},
+    chunk_hash,
+    collision::ChunkCollision,
+    compact, daylight,
+    dodeca::{self, Vertex},
+    graph::{Graph, GraphEvent, NodeId, NodePath},
+    ground,
+    inventory::Inventory,
     math,
-    proto::{self, ClientHello, Command, Component, FreshNode, Position, Spawns, StateDelta},
-    sanitize_motion_input, EntityId, SimConfig, Step,
+    node::{
+        self, populate_fresh_nodes, seed_root, Chunk, ChunkId, DualGraph, VoxelData, VoxelMetadata,
+    },
+    proto::{
+        self, ChunkDiff, ChunkMetadata, ClientHello, Command, Component, EditRejection, FreshNode,
+        Hit, HitTarget, Position, Spawns, StateDelta, VoxelEdit,
+    },
+    sanitize_motion_input, state_hash, voxel_diff,
+    world::{Material, SolidMaterial},
+    worldgen::{self, ChunkParams},
+    EntityId, SimConfig, Step,
 };
 
 pub struct Sim {
     cfg: Arc<SimConfig>,
-    rng: SmallRng,
+    /// Whether `cfg` has changed since the last step, for broadcast
+    cfg_changed: bool,
+    /// Seed the world is generated from
+    seed: u64,
     step: Step,
-    entity_ids: FxHashMap<EntityId, Entity>,
+    /// Added to `step` to find the time of day, so that time can be skipped without disturbing
+    /// anything else
+    day_offset: Step,
+    entity_ids: EntityIds,
     world: hecs::World,
-    graph: Graph<Empty>,
+    graph: DualGraph,
+    /// Simplified geometry of each populated chunk, for collision queries
+    collision: FxHashMap<ChunkId, ChunkCollision>,
+    /// Chunks awaiting generation, in the order they were requested
+    worldgen_queue: VecDeque<ChunkId>,
+    /// Computes the contents of a chunk
+    worldgen: fn(&ChunkParams) -> VoxelData,
+    /// Revision of `worldgen`, per `worldgen::VERSION`
+    worldgen_version: u32,
+    /// Revision of `worldgen` that produced each chunk, for those never edited since
+    generated: FxHashMap<ChunkId, u32>,
+    /// Time after which chunk generation is deferred to a later step
+    worldgen_deadline: Instant,
+    /// Reads the current time, for comparison with `worldgen_deadline`
+    clock: fn() -> Instant,
+    /// Whether chunk generation is limited by `worldgen_deadline` at all
+    worldgen_throttled: bool,
+    worldgen_stats: WorldgenStats,
+    /// Nodes whose terrain is generated as a flat floor, regardless of `worldgen`
+    flattened: FxHashSet<NodeId>,
+    /// Whether `flattened` has changed since it was last saved
+    flattened_changed: bool,
+    /// State attached to individual voxels, for chunks that have any
+    metadata: FxHashMap<ChunkId, VoxelMetadata>,
+    /// `chunk_hash` of each chunk ever changed for clients, as of the changes last sent
+    chunk_hashes: FxHashMap<ChunkId, u64>,
+    /// Chunks whose metadata has changed since the last step, for broadcast
+    metadata_changed: FxHashSet<ChunkId>,
+    /// Regions in which only certain players may edit voxels
+    protections: Protections,
+    /// Whether `protections` has changed since it was last saved
+    protections_changed: bool,
+    /// Chunks edited since they were last saved
+    dirty: FxHashSet<ChunkId>,
+    /// Contents before the current step of each chunk edited during it, to broadcast the changes
+    voxel_edits: FxHashMap<ChunkId, VoxelData>,
+    /// Chunks generated since the last step other than as clients generate them, i.e. flattened
+    /// or previously sent to clients, to broadcast whole
+    replaced: FxHashSet<ChunkId>,
+    /// Characters whose inventory has changed since it was last sent to their owner
+    inventory_changes: FxHashSet<Entity>,
+    /// Edits refused since they were last sent to the owner of the character that made them
+    edit_rejections: FxHashMap<Entity, Vec<proto::EditRejected>>,
     spawns: Vec<Entity>,
     despawns: Vec<EntityId>,
+    hits: Vec<Hit>,
 }
 
 impl Sim {
-    pub fn new(cfg: Arc<SimConfig>) -> Self {
+    /// Simulate the world generated from `seed`
+    pub fn seeded(cfg: Arc<SimConfig>, seed: u64) -> Self {
         let mut result = Self {
             cfg,
-            rng: SmallRng::from_entropy(),
+            cfg_changed: false,
+            seed,
             step: 0,
-            entity_ids: FxHashMap::default(),
+            day_offset: 0,
+            entity_ids: EntityIds::new(),
             world: hecs::World::new(),
             graph: Graph::new(),
+            collision: FxHashMap::default(),
+            worldgen_queue: VecDeque::new(),
+            worldgen: ChunkParams::generate_voxels,
+            worldgen_version: worldgen::VERSION,
+            generated: FxHashMap::default(),
+            worldgen_deadline: Instant::now(),
+            clock: Instant::now,
+            worldgen_throttled: true,
+            worldgen_stats: WorldgenStats::default(),
+            flattened: FxHashSet::default(),
+            flattened_changed: false,
+            metadata: FxHashMap::default(),
+            chunk_hashes: FxHashMap::default(),
+            metadata_changed: FxHashSet::default(),
+            protections: Protections::new(),
+            protections_changed: false,
+            dirty: FxHashSet::default(),
+            voxel_edits: FxHashMap::default(),
+            replaced: FxHashSet::default(),
+            inventory_changes: FxHashSet::default(),
+            edit_rejections: FxHashMap::default(),
             spawns: Vec::new(),
             despawns: Vec::new(),
+            hits: Vec::new(),
         };
+        seed_root(&mut result.graph, seed);
         result
             .graph
             .ensure_nearby(&Position::origin(), f64::from(result.cfg.view_distance));
+        populate_fresh_nodes(&mut result.graph);
         result
     }
 
-    pub fn spawn_character(&mut self, hello: ClientHello) -> (EntityId, Entity) {
-        let id = self.new_id();
-        info!(%id, name = %hello.name, "spawning character");
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Spawn a character for the player who sent `hello`, holding the server account named
+    /// `account` if they proved as much
+    pub fn spawn_character(
+        &mut self,
+        hello: ClientHello,
+        account: Option<Arc<str>>,
+    ) -> (EntityId, Entity) {
         let position = Position {
             node: NodeId::ROOT,
             local: math::translate_along(&na::Vector3::y_axis(), 0.9),
         };
         let character = Character {
             name: hello.name,
+            account,
             speed: 0.0,
             direction: -na::Vector3::z_axis(),
+            velocity: na::zero(),
+            frozen: false,
             orientation: na::one(),
         };
-        let entity = self.world.spawn((id, position, character));
-        self.entity_ids.insert(id, entity);
+        let world = &mut self.world;
+        let (id, entity) = self.entity_ids.insert_with(|id| {
+            info!(%id, name = %character.name, "spawning character");
+            world.spawn((id, position, character, Inventory::new()))
+        });
         self.spawns.push(entity);
+        self.inventory_changes.insert(entity);
         (id, entity)
     }
 
+    /// Launch a projectile from `position` along `direction`, relative to `position`
+    ///
+    /// The projectile passes through `owner`.
+    pub fn spawn_projectile(
+        &mut self,
+        owner: Entity,
+        position: Position,
+        direction: na::Unit<na::Vector3<f32>>,
+        speed: f32,
+    ) -> (EntityId, Entity) {
+        let lifetime = (PROJECTILE_LIFETIME.as_secs_f32() * self.cfg.rate as f32) as Step;
+        let projectile = Projectile {
+            direction,
+            speed,
+            owner,
+            expires: self.step + lifetime,
+        };
+        let world = &mut self.world;
+        let (id, entity) = self
+            .entity_ids
+            .insert_with(|id| world.spawn((id, position, projectile)));
+        self.spawns.push(entity);
+        (id, entity)
+    }
+
+    /// Replace the simulation parameters, taking effect from the next step
+    pub fn set_config(&mut self, cfg: Arc<SimConfig>) {
+        self.cfg = cfg;
+        self.cfg_changed = true;
+    }
+
     pub fn command(
         &mut self,
         entity: Entity,
         command: Command,
     ) -> Result<(), hecs::ComponentError> {
-        let mut ch = self.world.get_mut::<Character>(entity)?;
-        let (direction, speed) = sanitize_motion_input(command.velocity);
-        ch.direction = direction;
-        ch.speed = speed * self.cfg.movement_speed;
-        ch.orientation = command.orientation;
+        {
+            let mut ch = self.world.get_mut::<Character>(entity)?;
+            let (direction, speed) = sanitize_motion_input(command.velocity);
+            ch.direction = direction;
+            ch.speed = speed;
+            ch.orientation = command.orientation;
+        }
+        if let Some(ref edit) = command.edit {
+            if let Err(reason) = self.edit(entity, edit) {
+                self.edit_rejections
+                    .entry(entity)
+                    .or_default()
+                    .push(proto::EditRejected {
+                        generation: command.generation,
+                        reason,
+                    });
+            }
+        }
+        if command.fire {
+            let position = *self.world.get::<Position>(entity)?;
+            let direction = command.orientation * -na::Vector3::z_axis();
+            let speed = PROJECTILE_SPEED * self.cfg.meters_to_absolute;
+            self.spawn_projectile(entity, position, direction, speed);
+        }
         Ok(())
     }
 
+    /// Move `entity` to the center of the node reached by `path`, e.g. `DGA`, for debugging and
+    /// administration
+    ///
+    /// The destination and its surroundings are populated first, so the entity never stands in a
+    /// node that doesn't exist yet.
+    pub fn teleport(&mut self, entity: Entity, path: &str) -> Result<NodeId> {
+        let path = path.parse::<NodePath>()?;
+        let node = self.graph.lookup_path(&path);
+        let position = Position {
+            node,
+            local: na::Matrix4::identity(),
+        };
+        self.graph
+            .ensure_nearby(&position, f64::from(self.cfg.view_distance));
+        populate_fresh_nodes(&mut self.graph);
+        *self.world.get_mut::<Position>(entity)? = position;
+        info!(%path, ?node, "teleported");
+        Ok(node)
+    }
+
+    /// Fraction of the way through the day/night cycle, per `daylight::time_of_day`
+    pub fn time_of_day(&self) -> f32 {
+        daylight::time_of_day(
+            self.step.wrapping_add(self.day_offset),
+            self.cfg.steps_per_day,
+        )
+    }
+
+    /// Skip forwards to `time_of_day`, a fraction of the day/night cycle
+    pub fn set_time_of_day(&mut self, time_of_day: f32) {
+        let steps_per_day = i64::from(self.cfg.steps_per_day);
+        let target = (f64::from(time_of_day).rem_euclid(1.0) * steps_per_day as f64) as i64;
+        let now = i64::from(self.step.wrapping_add(self.day_offset));
+        let skip = (target - now).rem_euclid(steps_per_day.max(1));
+        self.day_offset = self.day_offset.wrapping_add(skip as Step);
+    }
+
+    /// Hold a character in place, ignoring its input and halting any coasting or sliding, or
+    /// release it
+    ///
+    /// Frozen characters can still be pushed aside by others.
+    pub fn set_frozen(&mut self, character: Entity, frozen: bool) {
+        if let Ok(mut ch) = self.world.get_mut::<Character>(character) {
+            ch.frozen = frozen;
+            ch.velocity = na::zero();
+        }
+    }
+
     pub fn destroy(&mut self, entity: Entity) {
         let id = *self.world.get::<EntityId>(entity).unwrap();
-        self.entity_ids.remove(&id);
+        self.entity_ids.remove(id);
         self.world.despawn(entity).unwrap();
+        self.inventory_changes.remove(&entity);
+        self.edit_rejections.remove(&entity);
         self.despawns.push(id);
     }
 
@@ -92,6 +308,21 @@ impl Sim {
                 .tree()
                 .map(|(side, parent)| FreshNode { side, parent })
                 .collect(),
+            enters: Vec::new(),
+            leaves: Vec::new(),
+            hits: Vec::new(),
+            voxels: self.edited_chunks(),
+            metadata: self
+                .metadata
+                .iter()
+                .map(|(&chunk, metadata)| ChunkMetadata {
+                    node: chunk.node,
+                    chunk: chunk.vertex,
+                    metadata: metadata.clone(),
+                })
+                .collect(),
+            reconfigure: None,
+            resync: false,
         };
         for (entity, &id) in &mut self.world.query::<&EntityId>() {
             spawns.spawns.push((id, dump_entity(&self.world, entity)));
@@ -102,20 +333,40 @@ impl Sim {
     pub fn step(&mut self) -> (Spawns, StateDelta) {
         let span = error_span!("step", step = self.step);
         let _guard = span.enter();
+        let tick = Duration::from_secs(1) / u32::from(self.cfg.rate);
+        self.worldgen_deadline = (self.clock)() + tick.mul_f32(WORLDGEN_BUDGET);
 
         // Simulate
-        for (_, (ch, pos)) in self.world.query::<(&Character, &mut Position)>().iter() {
-            let next_xf =
-                pos.local * math::translate_along(&ch.direction, ch.speed / self.cfg.rate as f32);
-            pos.local = math::renormalize_isometry(&next_xf);
-            let (next_node, transition_xf) = self.graph.normalize_transform(pos.node, &pos.local);
-            if next_node != pos.node {
-                pos.node = next_node;
-                pos.local = transition_xf * pos.local;
+        let dt = 1.0 / self.cfg.rate as f32;
+        let characters = self
+            .world
+            .query::<(&Character, &Position)>()
+            .iter()
+            .filter(|(_, (ch, _))| !ch.frozen)
+            .map(|(entity, (ch, &pos))| {
+                let input = ch.direction.into_inner() * ch.speed * self.cfg.movement_speed;
+                (entity, ch.direction, input, ch.velocity, pos)
+            })
+            .collect::<Vec<_>>();
+        for (entity, direction, input, velocity, start) in characters {
+            let normal = self.ground(&start);
+            let mut velocity = ground::velocity(&self.cfg, &velocity, &input, normal.as_ref(), dt);
+            let (direction, speed) =
+                na::Unit::try_new_and_get(velocity, 1e-9).unwrap_or((direction, 0.0));
+            let (pos, stopped) = self.sweep(&start, &direction, speed * dt);
+            if stopped {
+                // Whatever's in the way absorbs the character's momentum
+                velocity = na::zero();
             }
+            self.world.get_mut::<Character>(entity).unwrap().velocity = velocity;
+            *self.world.get_mut::<Position>(entity).unwrap() = pos;
             self.graph
-                .ensure_nearby(pos, f64::from(self.cfg.view_distance));
+                .ensure_nearby(&pos, f64::from(self.cfg.view_distance));
         }
+        populate_fresh_nodes(&mut self.graph);
+        self.resolve_collisions();
+        self.step_projectiles();
+        self.pregenerate();
 
         // Capture state changes for broadcast to clients
         let mut spawns = Vec::with_capacity(self.spawns.len());
@@ -142,6 +393,21 @@ impl Sim {
                     })
                 })
                 .collect(),
+            enters: Vec::new(),
+            leaves: Vec::new(),
+            hits: mem::replace(&mut self.hits, Vec::new()),
+            voxels: self.take_voxel_diffs(),
+            metadata: self.take_metadata_changes(),
+            reconfigure: if mem::replace(&mut self.cfg_changed, false) {
+                Some(proto::Reconfigure {
+                    rate: self.cfg.rate,
+                    movement_speed: self.cfg.movement_speed,
+                    max_reach: self.cfg.max_reach,
+                })
+            } else {
+                None
+            },
+            resync: false,
         };
         self.graph.clear_fresh();
 
@@ -161,23 +427,1038 @@ impl Sim {
                 .iter()
                 .map(|(_, (&id, ch))| (id, ch.orientation))
                 .collect(),
+            time_of_day: self.time_of_day(),
+            pong: None, // To be filled in by the caller
+            hash: None,
+            inventory: None,             // To be filled in by the caller
+            admin_replies: Vec::new(),   // To be filled in by the caller
+            edit_rejections: Vec::new(), // To be filled in by the caller
         };
 
         self.step += 1;
         (spawns, delta)
     }
 
-    fn new_id(&mut self) -> EntityId {
-        loop {
-            let id = self.rng.gen();
-            if !self.entity_ids.contains_key(&id) {
-                return id;
+    /// Push apart characters whose capsules overlap
+    ///
+    /// Every push is computed from positions prior to any resolution, so the outcome doesn't
+    /// depend on iteration order, and each character in an overlapping pair moves away from the
+    /// other by half of the overlap. Characters pass through each other freely when PVP is
+    /// disabled.
+    fn resolve_collisions(&mut self) {
+        if !self.cfg.pvp_enabled {
+            return;
+        }
+        let radius = self.cfg.character_radius;
+        let half_axis = (self.cfg.character_height / 2.0 - radius).max(0.0);
+        // Characters lie within their nodes, so a node containing a character that might collide
+        // with another must be centered within this distance of the other
+        let range = 2.0 * f64::from(radius + half_axis) + dodeca::BOUNDING_SPHERE_RADIUS;
+        let mut characters = self
+            .world
+            .query::<(&EntityId, &Position, &Character)>()
+            .iter()
+            .map(|(entity, (&id, &pos, _))| (id, entity, pos))
+            .collect::<Vec<_>>();
+        characters.sort_unstable_by_key(|&(id, _, _)| id);
+
+        let mut pushes = Vec::new();
+        for &(id, entity, ref pos) in &characters {
+            let nodes = self
+                .graph
+                .nearby_nodes(pos, range)
+                .into_iter()
+                .collect::<FxHashMap<_, _>>();
+            let local_inverse = pos.local.try_inverse().unwrap();
+            let mut push = None::<na::Matrix4<f32>>;
+            for &(other_id, _, ref other) in &characters {
+                if other_id == id {
+                    continue;
+                }
+                let node_transform = match nodes.get(&other.node) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let other = local_inverse * node_transform * other.local;
+                if let Some(x) = capsule_push(&other, radius, half_axis, id < other_id) {
+                    push = Some(push.map_or(x, |push| push * x));
+                }
+            }
+            if let Some(push) = push {
+                pushes.push((entity, push));
+            }
+        }
+
+        for (entity, push) in pushes {
+            let mut pos = self.world.get_mut::<Position>(entity).unwrap();
+            pos.local = math::renormalize_isometry(&(pos.local * push));
+            let (next_node, transition_xf) = self.graph.normalize_transform(pos.node, &pos.local);
+            if next_node != pos.node {
+                pos.node = next_node;
+                pos.local = transition_xf * pos.local;
+            }
+        }
+    }
+
+    /// Advance projectiles, despawning any that hit something or expire
+    fn step_projectiles(&mut self) {
+        let projectiles = self
+            .world
+            .query::<(&EntityId, &Position, &Projectile)>()
+            .iter()
+            .map(|(entity, (&id, &pos, projectile))| (entity, id, pos, projectile.clone()))
+            .collect::<Vec<_>>();
+        for (entity, id, pos, projectile) in projectiles {
+            let distance = projectile.speed / self.cfg.rate as f32;
+            let (end, target) = self.cast(&pos, &projectile.direction, distance, projectile.owner);
+            match target {
+                Some(target) => {
+                    trace!(%id, ?target, "projectile hit");
+                    self.hits.push(Hit {
+                        projectile: id,
+                        position: end,
+                        target,
+                    });
+                    self.destroy(entity);
+                }
+                None if self.step >= projectile.expires => self.destroy(entity),
+                None => *self.world.get_mut::<Position>(entity).unwrap() = end,
+            }
+        }
+    }
+
+    /// Trace a path `distance` along `direction` from `start`, stopping short of the first solid
+    /// voxel or character capsule, other than `owner`'s, that it meets
+    ///
+    /// Characters are ignored entirely when PVP is disabled.
+    fn cast(
+        &mut self,
+        start: &Position,
+        direction: &na::Unit<na::Vector3<f32>>,
+        distance: f32,
+        owner: Entity,
+    ) -> (Position, Option<HitTarget>) {
+        let radius = self.cfg.character_radius;
+        let half_axis = (self.cfg.character_height / 2.0 - radius).max(0.0);
+        let range = f64::from(distance + radius + half_axis) + dodeca::BOUNDING_SPHERE_RADIUS;
+        let nodes = self
+            .graph
+            .nearby_nodes(start, range)
+            .into_iter()
+            .collect::<FxHashMap<_, _>>();
+        let pvp_enabled = self.cfg.pvp_enabled;
+        // Characters in the frame of `start.node`
+        let characters = self
+            .world
+            .query::<(&EntityId, &Position, &Character)>()
+            .iter()
+            .filter(|&(entity, _)| pvp_enabled && entity != owner)
+            .filter_map(|(_, (&id, pos, _))| {
+                let xf = nodes.get(&pos.node)? * pos.local;
+                Some((id, xf.try_inverse().unwrap()))
+            })
+            .collect::<Vec<_>>();
+
+        let mut last = *start;
+        for (point, current) in self.path(start, direction, distance) {
+            for &(id, ref inverse) in &characters {
+                let p = inverse * point;
+                if math::distance(&capsule_axis(capsule_project(&p, half_axis)), &p) < radius {
+                    return (last, Some(HitTarget::Entity(id)));
+                }
+            }
+            if let Some((chunk, index, solid)) = self.voxel(&current) {
+                if solid {
+                    let target = HitTarget::Voxel {
+                        node: chunk.node,
+                        chunk: chunk.vertex,
+                        index: index as u32,
+                    };
+                    return (last, Some(target));
+                }
+            }
+            last = current;
+        }
+        (last, None)
+    }
+
+    /// Trace a path `distance` along `direction` from `start`, stopping short of the first solid
+    /// voxel that it enters
+    ///
+    /// Testing the whole path rather than just its end keeps fast movers from tunneling through
+    /// thin walls. Unlike `cast`, this never generates chunks, treating those not yet generated
+    /// as empty. A path that begins inside solid voxels may leave them freely, so that nothing can
+    /// become trapped. Returns where the path ended and whether it was stopped short.
+    fn sweep(
+        &self,
+        start: &Position,
+        direction: &na::Unit<na::Vector3<f32>>,
+        distance: f32,
+    ) -> (Position, bool) {
+        let solid = |pos: &Position| self.loaded_voxel(pos).map_or(false, |(_, _, x)| x);
+        let mut embedded = solid(start);
+        let mut last = *start;
+        for (_, current) in self.path(start, direction, distance) {
+            if !solid(&current) {
+                embedded = false;
+            } else if !embedded {
+                return (last, true);
+            }
+            last = current;
+        }
+        (last, false)
+    }
+
+    /// Unit normal of the solid surface underfoot of a character at `pos`, in its frame
+    ///
+    /// Probes just below the bottom of the character's capsule. Voxel faces are the only surfaces,
+    /// so the normal is that of whichever face of the voxel found there is most nearly upright.
+    /// Returns `None` if the character isn't standing on anything, or stands on a chunk that isn't
+    /// yet generated.
+    fn ground(&self, pos: &Position) -> Option<na::Unit<na::Vector3<f32>>> {
+        let dimension = self.cfg.chunk_size;
+        let depth = self.cfg.character_height / 2.0 + GROUND_PROBE * self.cfg.meters_to_absolute;
+        let down = math::translate_along(&-na::Vector3::y_axis(), depth);
+        let foot = na::convert::<_, na::Vector4<f64>>(pos.local * down * math::origin());
+        let (chunk, coords) = node::canonical_chunk(&self.graph, pos.node, &foot)?;
+        let voxel = node::voxel_at(dimension, &coords);
+        let center = na::Vector3::from(voxel).map(|x| f32::from(x) + 0.5);
+        if !self.collision.get(&chunk)?.contains(&center) {
+            return None;
+        }
+
+        // The chunk's axes at the foot, carried back up to the character
+        let to_character =
+            math::translate_along(&na::Vector3::y_axis(), depth) * pos.local.try_inverse().unwrap();
+        let to_node = na::convert::<_, na::Matrix4<f32>>(
+            self.graph
+                .relative_transform(pos.node, chunk.node)?
+                .to_homogeneous()
+                * chunk.vertex.chunk_to_node(),
+        );
+        let coords = na::convert::<_, na::Vector3<f32>>(coords);
+        let at = |coords: na::Vector3<f32>| {
+            math::lorentz_normalize(&(to_character * to_node * coords.push(1.0)))
+        };
+        let axis = |i: usize| {
+            let mut step = na::Vector3::zeros();
+            step[i] = 1e-3;
+            (at(coords + step) - at(coords)).xyz()
+        };
+
+        // Each face's normal, on the side facing most nearly up. Far from the origin, precision
+        // can run out before the differences above do, leaving degenerate normals to skip.
+        (0..3)
+            .map(|i| {
+                let normal = axis((i + 1) % 3).cross(&axis((i + 2) % 3));
+                na::Unit::new_normalize(normal * normal.y.signum())
+            })
+            .filter(|normal| normal.iter().all(|x| x.is_finite()))
+            .max_by(|a, b| a.y.total_cmp(&b.y))
+    }
+
+    /// Points along the geodesic `distance` along `direction` from `start`, at intervals of at
+    /// most `COLLISION_SAMPLE_SPACING` and ending at its far end
+    ///
+    /// Each point is given both in the frame of `start.node` and as a normalized position.
+    fn path(
+        &self,
+        start: &Position,
+        direction: &na::Unit<na::Vector3<f32>>,
+        distance: f32,
+    ) -> Vec<(na::Vector4<f32>, Position)> {
+        let spacing = COLLISION_SAMPLE_SPACING * self.cfg.meters_to_absolute;
+        let samples = (distance / spacing).ceil().max(1.0) as u32;
+        (1..=samples)
+            .map(|i| {
+                let t = distance * i as f32 / samples as f32;
+                let local = start.local * math::translate_along(direction, t);
+                let (node, transition) = self.graph.normalize_transform(start.node, &local);
+                let position = Position {
+                    node,
+                    local: math::renormalize_isometry(&(transition * local)),
+                };
+                (local * math::origin(), position)
+            })
+            .collect()
+    }
+
+    /// Find the voxel at `pos` and whether it's solid, generating its chunk if necessary
+    ///
+    /// Returns `None` if the voxel's chunk can't yet be generated, or if generating it now would
+    /// exceed this step's worldgen budget, in which case it's queued for a later step.
+    fn voxel(&mut self, pos: &Position) -> Option<(ChunkId, usize, bool)> {
+        let point = na::convert::<_, na::Vector4<f64>>(pos.local * math::origin());
+        let (chunk, _) = node::canonical_chunk(&self.graph, pos.node, &point)?;
+        if let Chunk::Fresh = self.graph.get(chunk.node).as_ref()?.chunks[chunk.vertex] {
+            if !self.worldgen_expired() {
+                self.generate(chunk);
+            } else {
+                self.request_chunk(chunk);
+            }
+        }
+        self.loaded_voxel(pos)
+    }
+
+    /// Like `voxel`, but returns `None` rather than generating a chunk that hasn't been already
+    ///
+    /// Points on boundaries resolve to the same voxel as they do in `node::world_to_voxel`.
+    fn loaded_voxel(&self, pos: &Position) -> Option<(ChunkId, usize, bool)> {
+        let dimension = self.cfg.chunk_size;
+        let point = na::convert::<_, na::Vector4<f64>>(pos.local * math::origin());
+        let (chunk, coords) = node::canonical_chunk(&self.graph, pos.node, &point)?;
+        let collision = self.collision.get(&chunk)?;
+        let coords = node::voxel_at(dimension, &coords);
+        let index = node::voxel_index(dimension, coords);
+        // Test the voxel's center, which lies unambiguously within or without any box
+        let center = na::Vector3::from(coords).map(|x| f32::from(x) + 0.5);
+        let solid = collision.contains(&center);
+        Some((chunk, index, solid))
+    }
+
+    /// Generate fresh chunks near characters, then queued chunks, as far as this step's budget
+    /// allows
+    ///
+    /// Generation is optional work: whatever doesn't fit is deferred to later steps, so that a
+    /// burst of demand doesn't delay movement and networking. Characters take turns, each having
+    /// at most `worldgen_chunks_per_player` of their nearest fresh chunks generated per step, so
+    /// that one arriving somewhere unexplored doesn't hold up terrain for everyone else.
+    fn pregenerate(&mut self) {
+        // Each character's fresh chunks, nearest last
+        let mut demands = Vec::new();
+        for (_, (pos, _)) in self.world.query::<(&Position, &Character)>().iter() {
+            let start = na::convert::<_, na::Vector4<f64>>(pos.local * math::origin());
+            let mut chunks = Vec::new();
+            for (node, transform) in self.graph.nearby_nodes(pos, dodeca::BOUNDING_SPHERE_RADIUS) {
+                let transform = na::convert::<_, na::Matrix4<f64>>(transform);
+                for vertex in Vertex::iter() {
+                    let chunk = ChunkId::new(node, vertex);
+                    if !self.is_fresh(chunk) {
+                        continue;
+                    }
+                    let center =
+                        transform * vertex.chunk_to_node() * na::Vector4::new(0.5, 0.5, 0.5, 1.0);
+                    chunks.push((math::distance(&start, &center), chunk));
+                }
+            }
+            chunks.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+            demands.push(chunks);
+        }
+
+        let mut expired = false;
+        'rounds: for _ in 0..self.cfg.worldgen_chunks_per_player {
+            let mut progress = false;
+            for chunks in &mut demands {
+                // Skip chunks generated on behalf of someone else in the meantime
+                while let Some(&(_, chunk)) = chunks.last() {
+                    if !self.is_fresh(chunk) {
+                        chunks.pop();
+                        continue;
+                    }
+                    if self.worldgen_expired() {
+                        expired = true;
+                        break 'rounds;
+                    }
+                    chunks.pop();
+                    self.generate(chunk);
+                    progress = true;
+                    break;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        while let Some(&chunk) = self.worldgen_queue.front() {
+            if expired || self.worldgen_expired() {
+                expired = true;
+                break;
+            }
+            self.worldgen_queue.pop_front();
+            self.generate(chunk);
+        }
+
+        let waiting = demands
+            .iter()
+            .flatten()
+            .map(|&(_, chunk)| chunk)
+            .filter(|&chunk| self.is_fresh(chunk))
+            .collect::<FxHashSet<_>>();
+        self.worldgen_stats.queued = self.worldgen_queue.len() + waiting.len();
+        if expired {
+            self.worldgen_stats.deferred_steps += 1;
+            trace!(queued = self.worldgen_stats.queued, "deferring worldgen");
+        }
+    }
+
+    /// Whether `chunk` is known but has been neither generated nor queued for generation
+    fn is_fresh(&self, chunk: ChunkId) -> bool {
+        match self.graph.get(chunk.node) {
+            Some(node) => match node.chunks[chunk.vertex] {
+                Chunk::Fresh => true,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Whether this step's time for generating chunks has run out
+    fn worldgen_expired(&self) -> bool {
+        self.worldgen_throttled && (self.clock)() >= self.worldgen_deadline
+    }
+
+    /// Whether to defer chunk generation that doesn't fit in a step's budget
+    ///
+    /// Deferral keeps steps prompt, but makes their outcome depend on how long generation takes.
+    /// Without it, every chunk a step needs is generated within that step, so the simulation
+    /// depends only on its inputs, as lockstep requires.
+    pub fn throttle_worldgen(&mut self, enabled: bool) {
+        self.worldgen_throttled = enabled;
+    }
+
+    /// Queue `chunk` for generation if it hasn't been already
+    fn request_chunk(&mut self, chunk: ChunkId) {
+        let node = match self.graph.get_mut(chunk.node).as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+        if let Chunk::Fresh = node.chunks[chunk.vertex] {
+            node.chunks[chunk.vertex] = Chunk::Generating;
+            self.worldgen_queue.push_back(chunk);
+        }
+    }
+
+    /// Generate a fresh or queued chunk immediately, if its surroundings allow
+    fn generate(&mut self, chunk: ChunkId) {
+        let flattened = self.flattened.contains(&chunk.node);
+        let preset = if flattened {
+            worldgen::Preset::Flat
+        } else {
+            self.cfg.worldgen_preset
+        };
+        match ChunkParams::new(
+            self.cfg.chunk_size,
+            preset,
+            &self.graph,
+            chunk.node,
+            chunk.vertex,
+        ) {
+            Some(params) => {
+                let voxels = if flattened {
+                    params.generate_voxels()
+                } else {
+                    (self.worldgen)(&params)
+                };
+                self.populate_chunk(chunk, voxels);
+                self.generated.insert(chunk, self.worldgen_version);
+                self.worldgen_stats.generated += 1;
+                if flattened || self.chunk_hashes.contains_key(&chunk) {
+                    self.replaced.insert(chunk);
+                }
+            }
+            None => {
+                // Try again once the neighborhood is populated
+                self.graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.vertex] =
+                    Chunk::Fresh;
+            }
+        }
+    }
+
+    pub fn worldgen_stats(&self) -> WorldgenStats {
+        self.worldgen_stats
+    }
+
+    /// Replace the terrain of the node at `path` with a flat floor and clear air above it
+    ///
+    /// Edits and metadata in the node are discarded. The node's chunks are generated flat from
+    /// then on, as by `worldgen::Preset::Flat`, so the override survives regeneration and, via
+    /// `flattened`, restarts.
+    pub fn flatten(&mut self, path: &NodePath) -> NodeId {
+        let node = self.locate(path);
+        self.flattened_changed |= self.flattened.insert(node);
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            self.reset_chunk(chunk);
+            self.generate(chunk);
+            self.dirty.insert(chunk);
+        }
+        info!(%path, ?node, "flattened");
+        node
+    }
+
+    /// Paths of every node flattened by `flatten`, if any have been flattened since this was last
+    /// called
+    pub fn take_flattened_change(&mut self) -> Option<Vec<NodePath>> {
+        if !mem::replace(&mut self.flattened_changed, false) {
+            return None;
+        }
+        Some(self.flattened.iter().map(|&x| self.graph.path(x)).collect())
+    }
+
+    /// Reinstate nodes flattened in an earlier session, per `take_flattened_change`
+    pub fn restore_flattened(&mut self, paths: &[NodePath]) {
+        for path in paths {
+            let node = self.locate(path);
+            self.flattened.insert(node);
+            for vertex in Vertex::iter() {
+                self.reset_chunk(ChunkId::new(node, vertex));
+            }
+        }
+    }
+
+    /// Allow only holders of the accounts named in `members` to edit voxels in the nodes at `paths`
+    pub fn protect(
+        &mut self,
+        paths: &[NodePath],
+        members: impl IntoIterator<Item = Arc<str>>,
+    ) -> RegionId {
+        let nodes = paths
+            .iter()
+            .map(|x| self.graph.lookup_path(x))
+            .collect::<Vec<_>>();
+        populate_fresh_nodes(&mut self.graph);
+        self.protections_changed = true;
+        self.protections.protect(nodes, members)
+    }
+
+    /// Every protected region, if any have changed since this was last called
+    pub fn take_protections_change(&mut self) -> Option<Vec<SavedRegion>> {
+        if !mem::replace(&mut self.protections_changed, false) {
+            return None;
+        }
+        let graph = &self.graph;
+        Some(
+            self.protections
+                .regions()
+                .map(|(nodes, members)| SavedRegion {
+                    nodes: nodes.iter().map(|&x| graph.path(x).to_string()).collect(),
+                    members: members.iter().map(|x| x.to_string()).collect(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Reinstate regions protected in an earlier session, per `take_protections_change`
+    pub fn restore_protections(&mut self, regions: &[SavedRegion]) -> Result<()> {
+        for region in regions {
+            let paths = region
+                .nodes
+                .iter()
+                .map(|x| x.parse::<NodePath>())
+                .collect::<Result<Vec<_>>>()?;
+            let members = region.members.iter().map(|x| Arc::from(&x[..]));
+            self.protect(&paths, members);
+        }
+        // Already saved as is
+        self.protections_changed = false;
+        Ok(())
+    }
+
+    /// Find the node at `path`, along with every node its chunks need to be generated
+    pub fn locate(&mut self, path: &NodePath) -> NodeId {
+        let node = self.graph.lookup_path(path);
+        let center = Position {
+            node,
+            local: na::Matrix4::identity(),
+        };
+        self.graph
+            .ensure_nearby(&center, 2.0 * dodeca::BOUNDING_SPHERE_RADIUS);
+        populate_fresh_nodes(&mut self.graph);
+        node
+    }
+
+    /// Forget the contents of `chunk`, so that it's generated afresh when next needed
+    fn reset_chunk(&mut self, chunk: ChunkId) {
+        self.collision.remove(&chunk);
+        self.voxel_edits.remove(&chunk);
+        self.replaced.remove(&chunk);
+        if self.metadata.remove(&chunk).is_some() {
+            self.metadata_changed.insert(chunk);
+        }
+        self.generated.remove(&chunk);
+        if let Some(node) = self.graph.get_mut(chunk.node).as_mut() {
+            // A chunk already queued will be generated anew in due course
+            if let Chunk::Populated { .. } = node.chunks[chunk.vertex] {
+                node.chunks[chunk.vertex] = Chunk::Fresh;
+                self.graph
+                    .notify(GraphEvent::ChunkEvicted(chunk.node, chunk.vertex));
+            }
+        }
+    }
+
+    /// Reinstate a chunk saved in an earlier session, per `take_dirty`
+    ///
+    /// `key` is the compact encoding of the chunk's `ChunkId`. A chunk stamped with the revision
+    /// of worldgen that `generated` it is regenerated by `regenerate_outdated` if that's out of
+    /// date.
+    pub fn restore_chunk(
+        &mut self,
+        key: &[u8],
+        voxels: VoxelData,
+        metadata: VoxelMetadata,
+        generated: Option<u32>,
+    ) -> Result<ChunkId> {
+        if let VoxelData::Dense(ref data) = voxels {
+            let expected = (usize::from(self.cfg.chunk_size) + 2).pow(3);
+            ensure!(
+                data.len() == expected,
+                "expected {} voxels, got {}",
+                expected,
+                data.len()
+            );
+        }
+        let chunk = compact::decode_chunk(&mut self.graph, &mut &key[..])?;
+        populate_fresh_nodes(&mut self.graph);
+        self.chunk_hashes
+            .insert(chunk, chunk_hash(self.cfg.chunk_size, &voxels));
+        self.populate_chunk(chunk, voxels);
+        if !metadata.is_empty() {
+            self.metadata.insert(chunk, metadata);
+        }
+        if let Some(version) = generated {
+            self.generated.insert(chunk, version);
+        }
+        Ok(chunk)
+    }
+
+    /// Queue every chunk generated by an earlier revision of worldgen to be generated afresh
+    ///
+    /// Edited chunks are left intact, whatever generated them. Until a queued chunk is regenerated,
+    /// its old contents remain solid to collision. Returns the number of chunks queued.
+    pub fn regenerate_outdated(&mut self) -> usize {
+        let version = self.worldgen_version;
+        let outdated = self
+            .generated
+            .iter()
+            .filter(|&(_, &x)| x < version)
+            .map(|(&chunk, _)| chunk)
+            .collect::<Vec<_>>();
+        for &chunk in &outdated {
+            self.generated.remove(&chunk);
+            self.graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.vertex] =
+                Chunk::Generating;
+            self.worldgen_queue.push_back(chunk);
+        }
+        let count = outdated.len();
+        if count > 0 {
+            info!(count, version, "regenerating outdated chunks");
+        }
+        count
+    }
+
+    /// Store voxel data for `chunk`, keeping its collision geometry in sync
+    fn populate_chunk(&mut self, chunk: ChunkId, voxels: VoxelData) {
+        self.collision
+            .insert(chunk, ChunkCollision::new(self.cfg.chunk_size, &voxels));
+        self.graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.vertex] =
+            Chunk::populated(self.cfg.chunk_size, voxels);
+        self.graph
+            .notify(GraphEvent::ChunkGenerated(chunk.node, chunk.vertex));
+    }
+
+    /// Generate `chunk` if necessary, returning its voxel data
+    ///
+    /// Returns `None` if the chunk can't be generated yet.
+    fn populate(&mut self, chunk: ChunkId) -> Option<&mut Chunk> {
+        if let Chunk::Fresh = self.graph.get(chunk.node).as_ref()?.chunks[chunk.vertex] {
+            self.generate(chunk);
+        }
+        match self.graph.get_mut(chunk.node).as_mut().unwrap().chunks[chunk.vertex] {
+            ref mut x @ Chunk::Populated { .. } => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Change a single voxel, generating its chunk first if necessary
+    ///
+    /// Any metadata attached to the voxel is discarded along with its previous contents. Returns
+    /// `false` if the chunk can't be generated yet.
+    pub fn set_voxel(&mut self, chunk: ChunkId, index: usize, material: Material) -> bool {
+        let dimension = self.cfg.chunk_size;
+        let before = match self.populate(chunk) {
+            Some(&mut Chunk::Populated { ref voxels, .. }) => voxels.clone(),
+            _ => return false,
+        };
+        self.voxel_edits.entry(chunk).or_insert(before);
+        let populated = self.populate(chunk).unwrap();
+        populated.set_voxel(dimension, index, material);
+        let collision = match *populated {
+            Chunk::Populated { ref voxels, .. } => ChunkCollision::new(dimension, voxels),
+            _ => unreachable!(),
+        };
+        self.collision.insert(chunk, collision);
+        self.graph
+            .notify(GraphEvent::ChunkEdited(chunk.node, chunk.vertex));
+        if let Some(coords) = node::voxel_coords(dimension, index) {
+            if let Some(metadata) = self.metadata.get_mut(&chunk) {
+                if metadata.remove(&coords).is_some() {
+                    self.metadata_changed.insert(chunk);
+                }
+                if metadata.is_empty() {
+                    self.metadata.remove(&chunk);
+                }
             }
         }
+        self.generated.remove(&chunk);
+        self.dirty.insert(chunk);
+        true
+    }
+
+    /// Attach `data` to the solid voxel at `coords`, replacing any existing metadata
+    ///
+    /// Returns `false` if the chunk can't be generated yet or the voxel is empty.
+    pub fn set_metadata(&mut self, chunk: ChunkId, coords: [u8; 3], data: Vec<u8>) -> bool {
+        let index = node::voxel_index(self.cfg.chunk_size, coords);
+        match self.populate(chunk) {
+            Some(&mut Chunk::Populated { ref voxels, .. }) if !voxels.get(index).is_void() => {}
+            _ => return false,
+        }
+        self.metadata.entry(chunk).or_default().insert(coords, data);
+        self.metadata_changed.insert(chunk);
+        self.generated.remove(&chunk);
+        self.dirty.insert(chunk);
+        true
+    }
+
+    /// Make the change a player requested of a voxel through `character`
+    ///
+    /// A request to place `Material::Void` breaks the voxel instead.
+    pub fn edit(&mut self, character: Entity, edit: &VoxelEdit) -> Result<(), EditRejection> {
+        let chunk = ChunkId::new(edit.node, edit.chunk);
+        let index = edit.index as usize;
+        match SolidMaterial::new(edit.material) {
+            None => self.break_voxel(character, chunk, index).map(|_| ()),
+            Some(material) => self.place_voxel(character, chunk, index, material),
+        }
+    }
+
+    /// Place `material` at voxel `index` of `chunk` on behalf of `character`, spending one from
+    /// their inventory
+    ///
+    /// Nothing changes if the edit is refused.
+    pub fn place_voxel(
+        &mut self,
+        character: Entity,
+        chunk: ChunkId,
+        index: usize,
+        material: SolidMaterial,
+    ) -> Result<(), EditRejection> {
+        if !self.editable(chunk, index)?.is_void() {
+            return Err(EditRejection::Unchanged);
+        }
+        self.check_protection(character, chunk)?;
+        if !self.within_reach(character, chunk, index) {
+            return Err(EditRejection::OutOfReach);
+        }
+        let spent = self
+            .world
+            .get_mut::<Inventory>(character)
+            .map_or(false, |mut inventory| inventory.take(material.get()));
+        if !spent {
+            return Err(EditRejection::NoMaterial);
+        }
+        self.set_voxel(chunk, index, material.get());
+        self.inventory_changes.insert(character);
+        Ok(())
+    }
+
+    /// Empty voxel `index` of `chunk` on behalf of `character`, adding its material to their
+    /// inventory
+    ///
+    /// Returns the material removed. Nothing changes if the edit is refused.
+    pub fn break_voxel(
+        &mut self,
+        character: Entity,
+        chunk: ChunkId,
+        index: usize,
+    ) -> Result<Material, EditRejection> {
+        let material = self.editable(chunk, index)?;
+        if material.is_void() {
+            return Err(EditRejection::Unchanged);
+        }
+        self.check_protection(character, chunk)?;
+        if !self.within_reach(character, chunk, index) {
+            return Err(EditRejection::OutOfReach);
+        }
+        self.world
+            .get_mut::<Inventory>(character)
+            .map_err(|_| EditRejection::NoMaterial)?
+            .add(material, 1);
+        self.set_voxel(chunk, index, Material::Void);
+        self.inventory_changes.insert(character);
+        Ok(material)
+    }
+
+    /// Whether the player controlling `character` may edit voxels in `chunk`
+    fn check_protection(&self, character: Entity, chunk: ChunkId) -> Result<(), EditRejection> {
+        let ch = self
+            .world
+            .get::<Character>(character)
+            .map_err(|_| EditRejection::Protected)?;
+        self.protections
+            .check(ch.account.as_ref().map(|x| &x[..]), chunk.node)
+            .map_err(|_| EditRejection::Protected)
+    }
+
+    /// Material of voxel `index` of `chunk`, if a player may edit it at all
+    ///
+    /// Guards against requests naming nodes or voxels that don't exist, since they come from
+    /// clients.
+    fn editable(&mut self, chunk: ChunkId, index: usize) -> Result<Material, EditRejection> {
+        if !self.graph.contains(chunk.node)
+            || node::voxel_coords(self.cfg.chunk_size, index).is_none()
+        {
+            return Err(EditRejection::Unavailable);
+        }
+        self.voxel_material(chunk, index)
+            .ok_or(EditRejection::Unavailable)
+    }
+
+    /// Whether `character` can reach voxel `index` of `chunk` to edit it
+    ///
+    /// The voxel's center must lie within `max_reach` of the character, and in plain sight: a solid
+    /// voxel in between blocks the edit, so that nobody can edit through walls. Chunks not yet
+    /// generated are treated as empty, as in `sweep`. Voxels in a chunk's margins belong to a
+    /// neighboring chunk, so can't be reached through this one.
+    fn within_reach(&self, character: Entity, chunk: ChunkId, index: usize) -> bool {
+        let dimension = self.cfg.chunk_size;
+        let coords = match node::voxel_coords(dimension, index) {
+            Some(x) => x,
+            None => return false,
+        };
+        let pos = match self.world.get::<Position>(character) {
+            Ok(x) => *x,
+            Err(_) => return false,
+        };
+        let transform = match self.graph.relative_transform(pos.node, chunk.node) {
+            Some(x) => x,
+            None => return false,
+        };
+        let center = transform * node::voxel_to_world_center(dimension, chunk, coords);
+        let target = pos.local.try_inverse().unwrap() * na::convert::<_, na::Vector4<f32>>(center);
+        let distance = math::distance(&math::origin(), &target);
+        if distance > self.cfg.max_reach {
+            return false;
+        }
+        let direction = match na::Unit::try_new(target.xyz(), 1e-9) {
+            Some(x) => x,
+            // Standing at the very center
+            None => return true,
+        };
+        for (_, current) in self.path(&pos, &direction, distance) {
+            match self.loaded_voxel(&current) {
+                Some((c, i, _)) if c == chunk && i == index => return true,
+                Some((_, _, true)) => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Edits by `character` refused since this was last called for them
+    pub fn take_edit_rejections(&mut self, character: Entity) -> Vec<proto::EditRejected> {
+        self.edit_rejections.remove(&character).unwrap_or_default()
+    }
+
+    /// `character`'s inventory, if it's changed since this was last called for them
+    pub fn take_inventory_change(&mut self, character: Entity) -> Option<Inventory> {
+        if !self.inventory_changes.remove(&character) {
+            return None;
+        }
+        let inventory = self.world.get::<Inventory>(character).ok()?;
+        Some((*inventory).clone())
+    }
+
+    /// Material of voxel `index` of `chunk`, generating the chunk if necessary
+    pub fn voxel_material(&mut self, chunk: ChunkId, index: usize) -> Option<Material> {
+        match *self.populate(chunk)? {
+            Chunk::Populated { ref voxels, .. } => Some(voxels.get(index)),
+            _ => None,
+        }
+    }
+
+    /// Metadata attached to the voxel at `coords`, if any
+    pub fn metadata(&self, chunk: ChunkId, coords: [u8; 3]) -> Option<&[u8]> {
+        self.metadata.get(&chunk)?.get(&coords).map(|x| &x[..])
+    }
+
+    /// Encode the changes to every chunk edited since the last call
+    fn take_voxel_diffs(&mut self) -> Vec<ChunkDiff> {
+        let mut result = Vec::with_capacity(self.voxel_edits.len() + self.replaced.len());
+        for chunk in mem::replace(&mut self.replaced, FxHashSet::default()) {
+            // Any edits since are included
+            self.voxel_edits.remove(&chunk);
+            if let Chunk::Populated { ref voxels, .. } =
+                self.graph.get(chunk.node).as_ref().unwrap().chunks[chunk.vertex]
+            {
+                self.chunk_hashes
+                    .insert(chunk, chunk_hash(self.cfg.chunk_size, voxels));
+                result.push(ChunkDiff {
+                    node: chunk.node,
+                    chunk: chunk.vertex,
+                    changes: voxel_diff::whole(self.cfg.chunk_size, voxels),
+                });
+            }
+        }
+        for (chunk, before) in mem::replace(&mut self.voxel_edits, FxHashMap::default()) {
+            if let Chunk::Populated { ref voxels, .. } =
+                self.graph.get(chunk.node).as_ref().unwrap().chunks[chunk.vertex]
+            {
+                if let Some(changes) = voxel_diff::diff(self.cfg.chunk_size, &before, voxels) {
+                    self.chunk_hashes
+                        .insert(chunk, chunk_hash(self.cfg.chunk_size, voxels));
+                    result.push(ChunkDiff {
+                        node: chunk.node,
+                        chunk: chunk.vertex,
+                        changes,
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    /// The entire contents of every chunk ever changed for clients
+    ///
+    /// Clients generate terrain themselves, so need only learn of these.
+    pub fn edited_chunks(&self) -> Vec<ChunkDiff> {
+        let mut result = Vec::with_capacity(self.chunk_hashes.len());
+        for &chunk in self.chunk_hashes.keys() {
+            if let Chunk::Populated { ref voxels, .. } =
+                self.graph.get(chunk.node).as_ref().unwrap().chunks[chunk.vertex]
+            {
+                result.push(ChunkDiff {
+                    node: chunk.node,
+                    chunk: chunk.vertex,
+                    changes: voxel_diff::whole(self.cfg.chunk_size, voxels),
+                });
+            }
+        }
+        result
+    }
+
+    /// The `chunk_hash` of every chunk ever changed for clients
+    fn chunk_hashes(&self) -> impl Iterator<Item = (ChunkId, u64)> + '_ {
+        self.chunk_hashes
+            .iter()
+            .map(|(&chunk, &contents)| (chunk, contents))
+    }
+
+    /// `common::state_hash` of what the client controlling `character` should know, given that it
+    /// knows of `entities`
+    pub fn client_hash(
+        &self,
+        character: Entity,
+        entities: impl IntoIterator<Item = EntityId>,
+    ) -> u64 {
+        let id = *self.world.get::<EntityId>(character).unwrap();
+        let position = *self.world.get::<Position>(character).unwrap();
+        state_hash(entities, (id, &position), self.chunk_hashes())
+    }
+
+    /// Order-independent hash of everything inputs can affect, i.e. entities, edited voxels and
+    /// metadata, for checking that a replay reproduced a recorded session exactly
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = hash_serialized(&(self.step, self.day_offset));
+        for (entity, &id) in &mut self.world.query::<&EntityId>() {
+            let velocity = self.world.get::<Character>(entity).ok().map(|x| x.velocity);
+            let inventory = self.world.get::<Inventory>(entity).ok();
+            hash = hash.wrapping_add(hash_serialized(&(
+                id,
+                dump_entity(&self.world, entity),
+                velocity,
+                inventory.as_deref(),
+            )));
+        }
+        for chunk in self.edited_chunks() {
+            hash = hash.wrapping_add(hash_serialized(&chunk));
+        }
+        for (&chunk, metadata) in &self.metadata {
+            hash = hash.wrapping_add(hash_serialized(&ChunkMetadata {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                metadata: metadata.clone(),
+            }));
+        }
+        hash
+    }
+
+    /// Collect the metadata of every chunk whose metadata has changed since the last call
+    fn take_metadata_changes(&mut self) -> Vec<ChunkMetadata> {
+        let metadata = &self.metadata;
+        self.metadata_changed
+            .drain()
+            .map(|chunk| ChunkMetadata {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                metadata: metadata.get(&chunk).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Take the contents of every chunk edited since the last call
+    ///
+    /// Voxel data is shared with the world until it's next edited, so this is cheap, and later
+    /// edits don't disturb the result.
+    pub fn take_dirty(&mut self) -> Vec<DirtyChunk> {
+        let mut result = Vec::with_capacity(self.dirty.len());
+        for chunk in self.dirty.drain() {
+            if let Chunk::Populated { ref voxels, .. } =
+                self.graph.get(chunk.node).as_ref().unwrap().chunks[chunk.vertex]
+            {
+                let mut key = Vec::new();
+                compact::encode_chunk(&self.graph, chunk, &mut key);
+                result.push(DirtyChunk {
+                    key,
+                    voxels: voxels.clone(),
+                    metadata: self.metadata.get(&chunk).cloned().unwrap_or_default(),
+                    generated: self.generated.get(&chunk).copied(),
+                });
+            }
+        }
+        result
+    }
+
+    /// Identify all entities within view distance of `entity`, including `entity` itself
+    pub fn visible_entities(&self, entity: Entity) -> FxHashSet<EntityId> {
+        let position = *self.world.get::<Position>(entity).unwrap();
+        let nodes = self
+            .graph
+            .nearby_nodes(&position, f64::from(self.cfg.view_distance))
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<FxHashSet<_>>();
+        self.world
+            .query::<(&EntityId, &Position)>()
+            .iter()
+            .filter(|(_, (_, pos))| nodes.contains(&pos.node))
+            .map(|(_, (&id, _))| id)
+            .collect()
+    }
+
+    /// Collect the components of an entity for transmission to a client
+    pub fn dump(&self, id: EntityId) -> Vec<Component> {
+        dump_entity(&self.world, self.entity_ids.get(id).unwrap())
     }
 }
 
-enum Empty {}
+/// An edited chunk's contents, as of `Sim::take_dirty`
+pub struct DirtyChunk {
+    /// Compact encoding of the chunk's `ChunkId`
+    pub key: Vec<u8>,
+    pub voxels: VoxelData,
+    pub metadata: VoxelMetadata,
+    /// Revision of worldgen that produced the chunk, if it hasn't been edited since
+    pub generated: Option<u32>,
+}
+
+/// Hash of the serialized form of `x`, for types that don't implement `Hash`
+fn hash_serialized(x: &impl Serialize) -> u64 {
+    let mut hasher = FxHasher64::default();
+    hasher.write(&bincode::serialize(x).unwrap());
+    hasher.finish()
+}
 
 fn dump_entity(world: &hecs::World, entity: Entity) -> Vec<Component> {
     let mut components = Vec::new();
@@ -190,12 +1471,1373 @@ fn dump_entity(world: &hecs::World, entity: Entity) -> Vec<Component> {
             orientation: x.orientation,
         }));
     }
+    if let Ok(x) = world.get::<Projectile>(entity) {
+        components.push(Component::Projectile(proto::Projectile {
+            direction: x.direction,
+            speed: x.speed,
+        }));
+    }
     components
 }
 
+/// Compute the motion, in the frame of a character capsule, that moves it out of overlap with
+/// another capsule whose frame is given by `other`, by half of the overlap
+///
+/// Capsule axes lie along the local Y axis, extending `half_axis` either way from the origin.
+/// `tiebreak` selects a direction when the axes intersect. Returns `None` if the capsules are
+/// disjoint.
+fn capsule_push(
+    other: &na::Matrix4<f32>,
+    radius: f32,
+    half_axis: f32,
+    tiebreak: bool,
+) -> Option<na::Matrix4<f32>> {
+    let other_inverse = other.try_inverse().unwrap();
+
+    // Alternate between the closest points on each axis, converging on the closest pair
+    let mut ours = capsule_axis(0.0);
+    let mut theirs = other * capsule_axis(capsule_project(&(other_inverse * ours), half_axis));
+    for _ in 0..COLLISION_ITERATIONS {
+        ours = capsule_axis(capsule_project(&theirs, half_axis));
+        theirs = other * capsule_axis(capsule_project(&(other_inverse * ours), half_axis));
+    }
+
+    // Coincident points may produce NaN, which `max` discards
+    let distance = math::distance(&ours, &theirs).max(0.0);
+    let overlap = 2.0 * radius - distance;
+    if overlap <= 0.0 {
+        return None;
+    }
+    let to_ours = math::translate(&math::origin(), &ours);
+    let from_ours = to_ours.try_inverse().unwrap();
+    let direction = na::Unit::try_new(-(from_ours * theirs).xyz(), 1e-5).unwrap_or_else(|| {
+        if tiebreak {
+            -na::Vector3::x_axis()
+        } else {
+            na::Vector3::x_axis()
+        }
+    });
+    Some(to_ours * math::translate_along(&direction, overlap / 2.0) * from_ours)
+}
+
+/// Point `t` along the axis of a character capsule, in the character's frame
+fn capsule_axis(t: f32) -> na::Vector4<f32> {
+    na::Vector4::new(0.0, t.sinh(), 0.0, t.cosh())
+}
+
+/// Position along the axis of a character capsule of the closest point to `p`, in the character's
+/// frame
+fn capsule_project(p: &na::Vector4<f32>, half_axis: f32) -> f32 {
+    // Geodesics perpendicular to a geodesic through the origin are perpendicular chords in the
+    // Klein model
+    let limit = half_axis.tanh();
+    (p.y / p.w).max(-limit).min(limit).atanh()
+}
+
+/// Fraction of each step's time that may be spent generating chunks
+const WORLDGEN_BUDGET: f32 = 0.5;
+
+/// Number of refinements of the closest points between capsule axes
+const COLLISION_ITERATIONS: usize = 4;
+
+/// How long a projectile can travel without hitting anything before it's despawned
+const PROJECTILE_LIFETIME: Duration = Duration::from_secs(5);
+
+/// Speed of projectiles fired by characters, in meters per second
+const PROJECTILE_SPEED: f32 = 30.0;
+
+/// Distance between points along a swept path that are tested for collision, in meters
+const COLLISION_SAMPLE_SPACING: f32 = 0.1;
+
+/// How far below a character's capsule to look for ground to stand on, in meters
+const GROUND_PROBE: f32 = 0.1;
+
+/// Progress of chunk generation, which is deferred when a step runs out of time
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WorldgenStats {
+    /// Chunks generated so far
+    pub generated: u64,
+    /// Steps that ended with chunks left waiting for want of time
+    pub deferred_steps: u64,
+    /// Chunks currently waiting to be generated
+    pub queued: usize,
+}
+
 struct Character {
     name: String,
+    /// Server account the player proved they hold, which decides where they may edit
+    account: Option<Arc<str>>,
     orientation: na::UnitQuaternion<f32>,
     direction: na::Unit<na::Vector3<f32>>,
+    /// Fraction of the configured movement speed
     speed: f32,
+    /// Velocity in absolute units per second, relative to the character's own frame
+    velocity: na::Vector3<f32>,
+    /// Whether the character is held in place regardless of input, per `Sim::set_frozen`
+    frozen: bool,
+}
+
+#[derive(Clone)]
+struct Projectile {
+    direction: na::Unit<na::Vector3<f32>>,
+    /// Absolute units per second
+    speed: f32,
+    /// Character that launched the projectile, which it passes through
+    owner: Entity,
+    /// Step at which the projectile despawns, if it hasn't hit anything
+    expires: Step,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::interest::Interest;
+    use crate::save::{Autosave, SaveParams};
+    use common::{dodeca::Side, proto::VoxelChanges, SimConfigRaw, WorldId};
+
+    fn sim() -> Sim {
+        Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                view_distance: Some(60.0),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        )
+    }
+
+    fn spawn(sim: &mut Sim, name: &str) -> (EntityId, Entity) {
+        sim.spawn_character(
+            ClientHello {
+                name: name.into(),
+                world: WorldId::DEFAULT,
+                token: None,
+            },
+            None,
+        )
+    }
+
+    /// Spawn a character for the holder of the account `name`
+    fn spawn_account(sim: &mut Sim, name: &str) -> (EntityId, Entity) {
+        sim.spawn_character(
+            ClientHello {
+                name: name.into(),
+                world: WorldId::DEFAULT,
+                token: None,
+            },
+            Some(name.into()),
+        )
+    }
+
+    /// Find a node at least `length` steps from the root
+    fn distant_node(graph: &mut DualGraph, length: u32) -> NodeId {
+        let mut node = NodeId::ROOT;
+        while graph.length(node) < length {
+            for side in Side::iter() {
+                let neighbor = graph.ensure_neighbor(node, side);
+                if graph.length(neighbor) > graph.length(node) {
+                    node = neighbor;
+                    break;
+                }
+            }
+        }
+        node
+    }
+
+    fn has(positions: &[(EntityId, Position)], id: EntityId) -> bool {
+        positions.iter().any(|&(x, _)| x == id)
+    }
+
+    fn local(sim: &Sim, entity: Entity) -> na::Matrix4<f32> {
+        let pos = sim.world.get::<Position>(entity).unwrap();
+        assert_eq!(pos.node, NodeId::ROOT);
+        pos.local
+    }
+
+    #[test]
+    fn overlapping_players_separate() {
+        let mut sim = sim();
+        let (_, a) = spawn(&mut sim, "a");
+        let (_, b) = spawn(&mut sim, "b");
+        let radius = sim.cfg.character_radius;
+        {
+            let mut pos = sim.world.get_mut::<Position>(b).unwrap();
+            pos.local *= math::translate_along(&na::Vector3::x_axis(), radius / 2.0);
+        }
+
+        sim.step();
+        let separated = (local(&sim, a), local(&sim, b));
+        let distance = math::distance(
+            &(separated.0 * math::origin()),
+            &(separated.1 * math::origin()),
+        );
+        assert!((distance - 2.0 * radius).abs() < 1e-4);
+
+        // Resolution must be stable once separated
+        for _ in 0..10 {
+            sim.step();
+        }
+        assert!((local(&sim, a) - separated.0).abs().max() < 1e-5);
+        assert!((local(&sim, b) - separated.1).abs().max() < 1e-5);
+    }
+
+    /// A simulation with PVP `enabled` and open air throughout the root node
+    fn pvp_sim(enabled: bool) -> Sim {
+        let mut sim = Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                view_distance: Some(20.0),
+                pvp_enabled: Some(enabled),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        );
+        for vertex in Vertex::iter() {
+            sim.populate_chunk(
+                ChunkId::new(NodeId::ROOT, vertex),
+                VoxelData::Solid(Material::Void),
+            );
+        }
+        sim
+    }
+
+    #[test]
+    fn pvp_toggle() {
+        for &enabled in &[false, true] {
+            let mut sim = pvp_sim(enabled);
+            let (_, a) = spawn(&mut sim, "a");
+            let (b_id, b) = spawn(&mut sim, "b");
+            let radius = sim.cfg.character_radius;
+            // Place `b` `distance` along x from `a` at the origin
+            let offset = |sim: &mut Sim, distance| {
+                sim.world.get_mut::<Position>(a).unwrap().local = na::Matrix4::identity();
+                let mut pos = sim.world.get_mut::<Position>(b).unwrap();
+                pos.local = math::translate_along(&na::Vector3::x_axis(), distance);
+            };
+
+            // Overlapping characters
+            offset(&mut sim, radius / 2.0);
+            sim.step();
+            let distance = math::distance(
+                &(local(&sim, a) * math::origin()),
+                &(local(&sim, b) * math::origin()),
+            );
+            if enabled {
+                assert!((distance - 2.0 * radius).abs() < 1e-4);
+            } else {
+                assert!((distance - radius / 2.0).abs() < 1e-4, "pushed apart");
+            }
+
+            // A projectile fired by one character straight at the other
+            let separation = 2.0 * sim.cfg.meters_to_absolute;
+            offset(&mut sim, separation);
+            let position = *sim.world.get::<Position>(a).unwrap();
+            let speed = 10.0 * sim.cfg.meters_to_absolute;
+            let (id, _) = sim.spawn_projectile(a, position, na::Vector3::x_axis(), speed);
+            let mut hits = Vec::new();
+            for _ in 0..5 {
+                hits.extend(sim.step().0.hits);
+            }
+            if enabled {
+                assert_eq!(hits.len(), 1);
+                assert_eq!(hits[0].projectile, id);
+                assert_eq!(hits[0].target, HitTarget::Entity(b_id));
+            } else {
+                assert!(hits.is_empty(), "struck {:?}", hits);
+                assert!(sim.entity_ids.get(id).is_some(), "passes through");
+            }
+        }
+    }
+
+    #[test]
+    fn teleport_to_path() {
+        let mut sim = sim();
+        let (_, entity) = spawn(&mut sim, "a");
+        let node = sim.teleport(entity, "DGA").unwrap();
+        assert!(sim.graph.get(node).is_some());
+
+        // Center of the destination, in the root's coordinates
+        let center = [Side::D, Side::G, Side::A]
+            .iter()
+            .fold(math::Isometry::identity(), |xf, &side| {
+                xf * math::Isometry::from_matrix_unchecked(*side.reflection())
+            });
+        let mut transform = math::Isometry::identity();
+        let mut current = node;
+        while let Some(side) = sim.graph.parent(current) {
+            transform = math::Isometry::from_matrix_unchecked(*side.reflection()) * transform;
+            current = sim.graph.neighbor(current, side).unwrap();
+        }
+        let pos = *sim.world.get::<Position>(entity).unwrap();
+        assert_eq!(pos.node, node);
+        let actual = transform.matrix() * na::convert::<_, na::Matrix4<f64>>(pos.local);
+        let distance = math::distance(
+            &(actual * math::origin()),
+            &(center.matrix() * math::origin()),
+        );
+        assert!(distance < 1e-6, "{}", distance);
+
+        assert!(sim.teleport(entity, "DGZ").is_err());
+        assert_eq!(sim.world.get::<Position>(entity).unwrap().node, node);
+    }
+
+    #[test]
+    fn projectile_hits_wall() {
+        let mut sim = sim();
+        let (_, owner) = spawn(&mut sim, "a");
+        let dim = usize::from(sim.cfg.chunk_size);
+        let lwm = dim + 2;
+        let index = |x: usize, y: usize, z: usize| (x + 1) + (y + 1) * lwm + (z + 1) * lwm.pow(2);
+
+        // An empty chunk, except for a wall at x = 6
+        let chunk = Vertex::A;
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        for z in 0..dim {
+            for y in 0..dim {
+                voxels[index(6, y, z)] = Material::Stone;
+            }
+        }
+        sim.populate_chunk(
+            ChunkId::new(NodeId::ROOT, chunk),
+            VoxelData::Dense(voxels.into()),
+        );
+
+        // Fire from the center of voxel (1, 3, 3) towards the center of (9, 3, 3)
+        let voxel_center = |x: f64| {
+            let d = dim as f64;
+            let p = chunk.chunk_to_node() * na::Vector4::new(x / d, 3.5 / d, 3.5 / d, 1.0);
+            na::convert::<_, na::Vector4<f32>>(math::lorentz_normalize(&p))
+        };
+        let local = math::translate(&math::origin(), &voxel_center(1.5));
+        let target = local.try_inverse().unwrap() * voxel_center(9.5);
+        let direction = na::Unit::new_normalize(target.xyz());
+        let speed = 10.0 * sim.cfg.meters_to_absolute;
+        let position = Position {
+            node: NodeId::ROOT,
+            local,
+        };
+        let (id, _) = sim.spawn_projectile(owner, position, direction, speed);
+
+        let mut hits = Vec::new();
+        for _ in 0..50 {
+            let (spawns, _) = sim.step();
+            hits.extend(spawns.hits);
+            if spawns.despawns.contains(&id) {
+                break;
+            }
+        }
+        assert_eq!(hits.len(), 1);
+        let hit = hits[0];
+        assert_eq!(hit.projectile, id);
+        assert_eq!(
+            hit.target,
+            HitTarget::Voxel {
+                node: NodeId::ROOT,
+                chunk,
+                index: index(6, 3, 3) as u32,
+            }
+        );
+        // Stopped in the last voxel before the wall
+        assert_eq!(hit.position.node, NodeId::ROOT);
+        let point = na::convert::<_, na::Vector4<f64>>(hit.position.local * math::origin());
+        assert_eq!(
+            node::locate_voxel(sim.cfg.chunk_size, &point),
+            Some((chunk, index(5, 3, 3)))
+        );
+        assert_eq!(sim.entity_ids.get(id), None);
+    }
+
+    #[test]
+    fn fire_launches_projectile() {
+        let mut sim = sim();
+        let (_, owner) = spawn(&mut sim, "a");
+        let orientation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.5);
+        sim.command(
+            owner,
+            Command {
+                generation: 0,
+                orientation,
+                velocity: na::zero(),
+                resync: false,
+                ping: None,
+                admin: None,
+                edit: None,
+                fire: true,
+                tick: None,
+            },
+        )
+        .unwrap();
+        let projectiles = sim
+            .world
+            .query::<(&Position, &Projectile)>()
+            .iter()
+            .map(|(_, (&pos, projectile))| (pos, projectile.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(projectiles.len(), 1);
+        let (pos, ref projectile) = projectiles[0];
+        // From the character, along its line of sight
+        assert_eq!(pos.local, local(&sim, owner));
+        assert_eq!(projectile.owner, owner);
+        assert!(
+            (projectile.direction.into_inner() - orientation * -na::Vector3::z()).norm() < 1e-6
+        );
+    }
+
+    #[test]
+    fn character_stopped_by_wall() {
+        let mut sim = sim();
+        let (_, entity) = spawn(&mut sim, "a");
+        let dim = usize::from(sim.cfg.chunk_size);
+        let lwm = dim + 2;
+        let index = |x: usize, y: usize, z: usize| (x + 1) + (y + 1) * lwm + (z + 1) * lwm.pow(2);
+
+        // An empty chunk, except for a wall at x = 6
+        let chunk = Vertex::A;
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        for z in 0..dim {
+            for y in 0..dim {
+                voxels[index(6, y, z)] = Material::Stone;
+            }
+        }
+        sim.populate_chunk(
+            ChunkId::new(NodeId::ROOT, chunk),
+            VoxelData::Dense(voxels.into()),
+        );
+
+        // Move from the center of voxel (1, 3, 3) fast enough to reach (9, 3, 3) in a single step
+        let voxel_center = |x: f64| {
+            let d = dim as f64;
+            let p = chunk.chunk_to_node() * na::Vector4::new(x / d, 3.5 / d, 3.5 / d, 1.0);
+            na::convert::<_, na::Vector4<f32>>(math::lorentz_normalize(&p))
+        };
+        let start = math::translate(&math::origin(), &voxel_center(1.5));
+        let target = start.try_inverse().unwrap() * voxel_center(9.5);
+        let mut cfg = (*sim.cfg).clone();
+        cfg.movement_speed =
+            math::distance(&voxel_center(1.5), &voxel_center(9.5)) * f32::from(cfg.rate);
+        sim.set_config(Arc::new(cfg));
+        *sim.world.get_mut::<Position>(entity).unwrap() = Position {
+            node: NodeId::ROOT,
+            local: start,
+        };
+        {
+            let mut ch = sim.world.get_mut::<Character>(entity).unwrap();
+            ch.direction = na::Unit::new_normalize(target.xyz());
+            ch.speed = 1.0;
+        }
+
+        sim.step();
+        // Stopped in the last voxel before the wall
+        let point = na::convert::<_, na::Vector4<f64>>(local(&sim, entity) * math::origin());
+        assert_eq!(
+            node::locate_voxel(sim.cfg.chunk_size, &point),
+            Some((chunk, index(5, 3, 3)))
+        );
+    }
+
+    #[test]
+    fn reconfigure_speed() {
+        let mut sim = sim();
+        let (_, a) = spawn(&mut sim, "a");
+        sim.command(
+            a,
+            Command {
+                generation: 0,
+                orientation: na::one(),
+                velocity: -na::Vector3::z(),
+                resync: false,
+                ping: None,
+                admin: None,
+                edit: None,
+                fire: false,
+                tick: None,
+            },
+        )
+        .unwrap();
+        let travel = |sim: &mut Sim| {
+            let start = local(sim, a) * math::origin();
+            let (spawns, _) = sim.step();
+            (
+                math::distance(&start, &(local(sim, a) * math::origin())),
+                spawns.reconfigure,
+            )
+        };
+        let (slow, reconfigure) = travel(&mut sim);
+        assert_eq!(reconfigure, None);
+
+        let mut cfg = (*sim.cfg).clone();
+        cfg.movement_speed *= 2.0;
+        sim.set_config(Arc::new(cfg));
+        let (fast, reconfigure) = travel(&mut sim);
+        assert!((fast - 2.0 * slow).abs() < 1e-4);
+        // Clients are told of the change once
+        assert_eq!(
+            reconfigure.map(|x| x.movement_speed),
+            Some(sim.cfg.movement_speed)
+        );
+        assert_eq!(travel(&mut sim).1, None);
+    }
+
+    /// Fill the bottom three layers of a chunk of the root with stone
+    fn floor(sim: &mut Sim) {
+        let dim = usize::from(sim.cfg.chunk_size);
+        let lwm = dim + 2;
+        let index = |x: usize, y: usize, z: usize| (x + 1) + (y + 1) * lwm + (z + 1) * lwm.pow(2);
+        let mut voxels = vec![Material::Void; lwm.pow(3)];
+        for z in 0..dim {
+            for y in 0..3 {
+                for x in 0..dim {
+                    voxels[index(x, y, z)] = Material::Stone;
+                }
+            }
+        }
+        sim.populate_chunk(
+            ChunkId::new(NodeId::ROOT, Vertex::A),
+            VoxelData::Dense(voxels.into()),
+        );
+    }
+
+    /// Stand a character on the `floor`, leaning `tilt` radians from upright about its x axis
+    fn stand(sim: &mut Sim, entity: Entity, tilt: f32) {
+        // Low enough that the floor lies underfoot even when leaning
+        let d = f64::from(sim.cfg.chunk_size);
+        let point = |x: f64, y: f64, z: f64| {
+            let p = Vertex::A.chunk_to_node() * na::Vector4::new(x / d, y / d, z / d, 1.0);
+            na::convert::<_, na::Vector4<f32>>(math::lorentz_normalize(&p))
+        };
+        let center = point(6.5, 3.2, 6.5);
+        let tangent = |p: na::Vector4<f32>| (math::translate(&center, &math::origin()) * p).xyz();
+        let normal = tangent(point(6.5, 3.2, 6.51)).cross(&tangent(point(6.51, 3.2, 6.5)));
+        let normal = normal * normal.dot(&tangent(point(6.5, 3.21, 6.5))).signum();
+        let upright = na::UnitQuaternion::rotation_between(&na::Vector3::y(), &normal).unwrap();
+        let lean = na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), tilt);
+        *sim.world.get_mut::<Position>(entity).unwrap() = Position {
+            node: NodeId::ROOT,
+            local: math::translate(&math::origin(), &center) * (upright * lean).to_homogeneous(),
+        };
+        sim.world.get_mut::<Character>(entity).unwrap().velocity = na::zero();
+    }
+
+    fn velocity(sim: &Sim, entity: Entity) -> na::Vector3<f32> {
+        sim.world.get::<Character>(entity).unwrap().velocity
+    }
+
+    #[test]
+    fn character_coasts_to_rest() {
+        let mut sim = sim();
+        let (_, entity) = spawn(&mut sim, "a");
+        floor(&mut sim);
+        stand(&mut sim, entity, 0.0);
+        let speed = sim.cfg.movement_speed;
+        sim.world.get_mut::<Character>(entity).unwrap().velocity = na::Vector3::x() * speed;
+
+        let start = local(&sim, entity) * math::origin();
+        sim.step();
+        let after = local(&sim, entity) * math::origin();
+        // Slowed by friction, but still moving
+        let travel = math::distance(&start, &after);
+        assert!(travel > 0.0 && travel < speed / f32::from(sim.cfg.rate));
+        assert!(velocity(&sim, entity).norm() < speed);
+        for _ in 0..3 {
+            sim.step();
+        }
+        // Come to rest
+        assert_eq!(velocity(&sim, entity), na::zero());
+        let before = local(&sim, entity) * math::origin();
+        sim.step();
+        assert!((local(&sim, entity) * math::origin() - before).norm() < 1e-5);
+    }
+
+    #[test]
+    fn character_slides_down_steep_ground() {
+        let mut sim = Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                view_distance: Some(60.0),
+                max_slope: Some(20.0),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        );
+        let (_, entity) = spawn(&mut sim, "a");
+        floor(&mut sim);
+        let max_slope = sim.cfg.max_slope;
+
+        // A slope gentle enough to stand on holds the character still
+        stand(&mut sim, entity, max_slope - 0.2);
+        let start = local(&sim, entity) * math::origin();
+        sim.step();
+        assert_eq!(velocity(&sim, entity), na::zero());
+        assert!((local(&sim, entity) * math::origin() - start).norm() < 1e-5);
+
+        // A steeper one doesn't. The ground is whichever face of the voxel underfoot is most nearly
+        // level, so leaning halfway between two faces is as steep as leaning about one axis gets.
+        stand(&mut sim, entity, std::f32::consts::FRAC_PI_4);
+        let mut speed = 0.0;
+        for _ in 0..3 {
+            sim.step();
+            let v = velocity(&sim, entity);
+            assert!(v.y < 0.0, "{:?} isn't downhill", v);
+            assert!(v.norm() > speed);
+            speed = v.norm();
+        }
+        let end = local(&sim, entity) * math::origin();
+        assert!(math::distance(&start, &end) > 0.0);
+    }
+
+    #[test]
+    fn time_of_day_advances() {
+        let mut sim = Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                rate: Some(10),
+                day_length_secs: Some(1.0),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        );
+        let times = (0..11)
+            .map(|_| sim.step().1.time_of_day)
+            .collect::<Vec<_>>();
+        assert_eq!(times[0], 0.0);
+        assert_eq!(times[1], 0.1);
+        assert!(times[1..10].windows(2).all(|w| w[0] < w[1]));
+        // A full day later, the cycle repeats
+        assert_eq!(times[10], times[0]);
+    }
+
+    #[test]
+    fn nearby_players_see_each_other() {
+        let mut sim = sim();
+        let (a_id, a) = spawn(&mut sim, "a");
+        let (b_id, b) = spawn(&mut sim, "b");
+        let mut a_interest = Interest::new();
+        let mut b_interest = Interest::new();
+
+        let (spawns, delta) = sim.step();
+        let a_spawns = a_interest.update(&sim, a, &spawns);
+        let b_spawns = b_interest.update(&sim, b, &spawns);
+        assert_eq!(a_spawns.spawns.len(), 2);
+        assert_eq!(b_spawns.spawns.len(), 2);
+        let a_delta = a_interest.filter_delta(&delta);
+        let b_delta = b_interest.filter_delta(&delta);
+        assert!(has(&a_delta.positions, a_id) && has(&a_delta.positions, b_id));
+        assert!(has(&b_delta.positions, a_id) && has(&b_delta.positions, b_id));
+    }
+
+    #[test]
+    fn distant_players_isolated() {
+        let mut sim = sim();
+        let (a_id, a) = spawn(&mut sim, "a");
+        let (b_id, b) = spawn(&mut sim, "b");
+        let mut a_interest = Interest::new();
+        let mut b_interest = Interest::new();
+        let far = distant_node(&mut sim.graph, 10);
+        sim.world.get_mut::<Position>(b).unwrap().node = far;
+
+        let (spawns, delta) = sim.step();
+        let a_spawns = a_interest.update(&sim, a, &spawns);
+        let b_spawns = b_interest.update(&sim, b, &spawns);
+        assert!(a_spawns.spawns.iter().all(|&(id, _)| id != b_id));
+        assert!(b_spawns.spawns.iter().all(|&(id, _)| id != a_id));
+        let a_delta = a_interest.filter_delta(&delta);
+        let b_delta = b_interest.filter_delta(&delta);
+        assert!(has(&a_delta.positions, a_id) && !has(&a_delta.positions, b_id));
+        assert!(has(&b_delta.positions, b_id) && !has(&b_delta.positions, a_id));
+
+        // Bring them together
+        sim.world.get_mut::<Position>(b).unwrap().node = NodeId::ROOT;
+        let (spawns, _) = sim.step();
+        let a_spawns = a_interest.update(&sim, a, &spawns);
+        assert_eq!(a_spawns.enters.len(), 1);
+        assert_eq!(a_spawns.enters[0].0, b_id);
+
+        // And apart again
+        sim.world.get_mut::<Position>(b).unwrap().node = far;
+        let (spawns, _) = sim.step();
+        let a_spawns = a_interest.update(&sim, a, &spawns);
+        assert_eq!(a_spawns.leaves, vec![b_id]);
+    }
+
+    thread_local! {
+        static EPOCH: Instant = Instant::now();
+        /// Time passed according to `fake_clock`
+        static ELAPSED: Cell<Duration> = Cell::new(Duration::from_secs(0));
+    }
+
+    /// A clock that advances only when told to, for timing-dependent behavior to be reproducible
+    fn fake_clock() -> Instant {
+        EPOCH.with(|&epoch| epoch + ELAPSED.with(Cell::get))
+    }
+
+    /// Takes 20ms by `fake_clock` to generate each chunk
+    fn slow_worldgen(_: &ChunkParams) -> VoxelData {
+        ELAPSED.with(|x| x.set(x.get() + Duration::from_millis(20)));
+        VoxelData::Solid(Material::Void)
+    }
+
+    #[test]
+    fn worldgen_throttled() {
+        let mut sim = Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                view_distance: Some(40.0),
+                rate: Some(10),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        );
+        sim.worldgen = slow_worldgen;
+        sim.clock = fake_clock;
+        let (_, entity) = spawn(&mut sim, "a");
+        sim.world.get_mut::<Character>(entity).unwrap().speed = 1.0;
+        let expected = sim.cfg.movement_speed / f32::from(sim.cfg.rate);
+
+        for i in 1..=5 {
+            let before = local(&sim, entity) * math::origin();
+            sim.step();
+            // Movement proceeds at the usual rate
+            let after = local(&sim, entity) * math::origin();
+            assert!((math::distance(&before, &after) - expected).abs() < 1e-4);
+            // Half of each 100ms step is spent generating chunks, so the third to start is the
+            // last, leaving the rest for later
+            let stats = sim.worldgen_stats();
+            assert_eq!(stats.generated, 3 * i);
+            assert!(stats.queued > 0);
+            assert_eq!(stats.deferred_steps, i);
+        }
+    }
+
+    #[test]
+    fn worldgen_shared_nearest_first() {
+        let mut sim = Sim::seeded(
+            Arc::new(SimConfig::from_raw(&SimConfigRaw {
+                view_distance: Some(20.0),
+                worldgen_chunks_per_player: Some(1),
+                ..SimConfigRaw::default()
+            })),
+            0,
+        );
+        sim.throttle_worldgen(false);
+        sim.worldgen = stone_worldgen;
+        // Within chunk A, and nearer its center than any other chunk's
+        let center = math::lorentz_normalize(
+            &(Vertex::A.chunk_to_node() * na::Vector4::new(0.5, 0.5, 0.5, 1.0)),
+        );
+        let local = na::convert::<_, na::Matrix4<f32>>(math::translate(&math::origin(), &center));
+        let (_, near) = spawn(&mut sim, "near");
+        let (_, far) = spawn(&mut sim, "far");
+        let far_node = distant_node(&mut sim.graph, 6);
+        for &(entity, node) in &[(near, NodeId::ROOT), (far, far_node)] {
+            *sim.world.get_mut::<Position>(entity).unwrap() = Position { node, local };
+        }
+
+        for _ in 0..3 {
+            let before = sim.worldgen_stats().generated;
+            sim.step();
+            // One chunk per player per step, however much either is waiting on
+            assert_eq!(sim.worldgen_stats().generated - before, 2);
+            assert!(sim.worldgen_stats().queued > 0);
+            // Starting with the one each is standing in
+            for &entity in &[near, far] {
+                let pos = *sim.world.get::<Position>(entity).unwrap();
+                assert!(sim.loaded_voxel(&pos).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn flatten_persists() {
+        let dir = std::env::temp_dir().join(format!("hypermine-flatten-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut sim = sim();
+        sim.worldgen = stone_worldgen;
+        sim.throttle_worldgen(false);
+        let index = node::voxel_index(sim.cfg.chunk_size, [1, 1, 1]);
+        let path = "DG".parse::<NodePath>().unwrap();
+        let node = sim.locate(&path);
+        assert!(sim.set_voxel(ChunkId::new(node, Vertex::A), index, Material::Wood));
+
+        /// Whether every chunk of `node` holds exactly what `Preset::Flat` generates
+        fn is_flat(sim: &mut Sim, node: NodeId) -> bool {
+            Vertex::iter().all(|vertex| {
+                let chunk = ChunkId::new(node, vertex);
+                let expected = ChunkParams::new(
+                    sim.cfg.chunk_size,
+                    worldgen::Preset::Flat,
+                    &sim.graph,
+                    node,
+                    vertex,
+                )
+                .unwrap()
+                .generate_voxels();
+                match *sim.populate(chunk).unwrap() {
+                    Chunk::Populated { ref voxels, .. } => *voxels == expected,
+                    _ => unreachable!(),
+                }
+            })
+        }
+        assert!(!is_flat(&mut sim, node));
+        assert_eq!(sim.flatten(&path), node);
+        assert!(is_flat(&mut sim, node));
+        // Regenerating leaves the override in place
+        sim.worldgen_version += 1;
+        assert!(sim.regenerate_outdated() > 0);
+        while !sim.worldgen_queue.is_empty() {
+            sim.pregenerate();
+        }
+        assert!(is_flat(&mut sim, node));
+
+        // As does restarting from a save
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        assert!(autosave.poll(start + interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+        let mut reloaded = self::sim();
+        reloaded.worldgen = stone_worldgen;
+        crate::save::restore(&dir, &mut reloaded);
+        let node = reloaded.graph.find_path(&path).unwrap();
+        assert!(is_flat(&mut reloaded, node));
+        // Elsewhere is untouched
+        let elsewhere = ChunkId::new(NodeId::ROOT, Vertex::A);
+        assert_eq!(
+            reloaded.voxel_material(elsewhere, index),
+            Some(Material::Stone)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flatten_sent_whole() {
+        let mut sim = sim();
+        sim.worldgen = stone_worldgen;
+        sim.throttle_worldgen(false);
+        let dimension = sim.cfg.chunk_size;
+        let path = "DG".parse::<NodePath>().unwrap();
+        let node = sim.locate(&path);
+        let edited = ChunkId::new(node, Vertex::A);
+        assert!(sim.set_voxel(
+            edited,
+            node::voxel_index(dimension, [1, 1, 1]),
+            Material::Wood
+        ));
+        assert_eq!(sim.step().0.voxels.len(), 1);
+
+        /// Check that `voxels` replaces every chunk of `node`, as generated by clients, with what
+        /// `sim` holds
+        fn check(sim: &Sim, node: NodeId, voxels: &[ChunkDiff]) {
+            assert_eq!(voxels.len(), Vertex::iter().len());
+            for diff in voxels {
+                assert_eq!(diff.node, node);
+                let mut client = VoxelData::Solid(Material::Stone);
+                match diff.changes {
+                    VoxelChanges::Whole(_) => {}
+                    VoxelChanges::Sparse(_) => panic!("flattened chunk sent voxel by voxel"),
+                }
+                voxel_diff::apply(sim.cfg.chunk_size, &mut client, &diff.changes).unwrap();
+                match sim.graph.get(node).as_ref().unwrap().chunks[diff.chunk] {
+                    Chunk::Populated { ref voxels, .. } => assert!(*voxels == client),
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        sim.flatten(&path);
+        let voxels = sim.step().0.voxels;
+        check(&sim, node, &voxels);
+        // Every chunk is hashed as flattened, including the one edited earlier
+        let hashes = sim.chunk_hashes().collect::<FxHashMap<_, _>>();
+        assert_eq!(hashes.len(), Vertex::iter().len());
+        for vertex in Vertex::iter() {
+            match sim.graph.get(node).as_ref().unwrap().chunks[vertex] {
+                Chunk::Populated { ref voxels, .. } => assert_eq!(
+                    hashes[&ChunkId::new(node, vertex)],
+                    chunk_hash(dimension, voxels)
+                ),
+                _ => unreachable!(),
+            }
+        }
+        assert!(sim.step().0.voxels.is_empty());
+        assert_eq!(sim.snapshot().voxels.len(), Vertex::iter().len());
+
+        // Chunks generated flat after a restart are sent too
+        let mut reloaded = self::sim();
+        reloaded.worldgen = stone_worldgen;
+        reloaded.restore_flattened(&[path.clone()]);
+        let node = reloaded.graph.find_path(&path).unwrap();
+        for vertex in Vertex::iter() {
+            reloaded.populate(ChunkId::new(node, vertex)).unwrap();
+        }
+        let voxels = reloaded.step().0.voxels;
+        check(&reloaded, node, &voxels);
+    }
+
+    fn stone_worldgen(_: &ChunkParams) -> VoxelData {
+        VoxelData::Solid(Material::Stone)
+    }
+
+    fn dirt_worldgen(_: &ChunkParams) -> VoxelData {
+        VoxelData::Solid(Material::Dirt)
+    }
+
+    #[test]
+    fn chunk_events() {
+        let mut sim = sim();
+        sim.worldgen = dirt_worldgen;
+        let dimension = sim.cfg.chunk_size;
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let subscription = sim.graph.subscribe();
+        let (a, b) = (
+            node::voxel_index(dimension, [1, 2, 3]),
+            node::voxel_index(dimension, [4, 5, 6]),
+        );
+        assert!(sim.set_voxel(chunk, a, Material::Stone));
+        assert!(sim.set_voxel(chunk, b, Material::Void));
+        sim.reset_chunk(chunk);
+        // Already fresh
+        sim.reset_chunk(chunk);
+        assert_eq!(
+            sim.graph.events(&subscription),
+            &[
+                GraphEvent::ChunkGenerated(chunk.node, chunk.vertex),
+                GraphEvent::ChunkEdited(chunk.node, chunk.vertex),
+                GraphEvent::ChunkEdited(chunk.node, chunk.vertex),
+                GraphEvent::ChunkEvicted(chunk.node, chunk.vertex),
+            ]
+        );
+        assert!(sim.graph.events(&subscription).is_empty());
+    }
+
+    #[test]
+    fn fill_sent_whole() {
+        let mut sim = sim();
+        sim.worldgen = dirt_worldgen;
+        let dimension = sim.cfg.chunk_size;
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        // The chunk as a client generates it for itself
+        let mut client = VoxelData::Solid(Material::Dirt);
+        let mut edits = 0;
+        for x in 0..dimension {
+            for y in 0..dimension {
+                for z in 0..dimension {
+                    assert!(sim.set_voxel(
+                        chunk,
+                        node::voxel_index(dimension, [x, y, z]),
+                        Material::Stone
+                    ));
+                    edits += 1;
+                }
+            }
+        }
+        let (spawns, _) = sim.step();
+        assert_eq!(spawns.voxels.len(), 1);
+        let diff = &spawns.voxels[0];
+        assert_eq!((diff.node, diff.chunk), (chunk.node, chunk.vertex));
+        match diff.changes {
+            VoxelChanges::Whole(ref runs) => assert!(runs.len() < edits / 4),
+            VoxelChanges::Sparse(_) => panic!("fill sent voxel by voxel"),
+        }
+
+        voxel_diff::apply(dimension, &mut client, &diff.changes).unwrap();
+        match sim.graph.get(chunk.node).as_ref().unwrap().chunks[chunk.vertex] {
+            Chunk::Populated { ref voxels, .. } => assert!(*voxels == client),
+            _ => unreachable!(),
+        }
+        // Sent only once
+        assert!(sim.step().0.voxels.is_empty());
+        // Also sent to clients that connect later
+        assert_eq!(sim.snapshot().voxels.len(), 1);
+        assert_eq!(
+            sim.chunk_hashes().collect::<Vec<_>>(),
+            [(chunk, chunk_hash(dimension, &client))]
+        );
+
+        // Each later change is hashed in turn
+        assert!(sim.set_voxel(
+            chunk,
+            node::voxel_index(dimension, [0, 0, 0]),
+            Material::Dirt
+        ));
+        let diff = sim.step().0.voxels.pop().unwrap();
+        voxel_diff::apply(dimension, &mut client, &diff.changes).unwrap();
+        assert_eq!(
+            sim.chunk_hashes().collect::<Vec<_>>(),
+            [(chunk, chunk_hash(dimension, &client))]
+        );
+    }
+
+    #[test]
+    fn metadata_sent() {
+        let mut sim = sim();
+        let dimension = sim.cfg.chunk_size;
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = [1, 2, 3];
+        let index = node::voxel_index(dimension, coords);
+        assert!(sim.set_voxel(chunk, index, Material::Wood));
+        assert!(sim.set_metadata(chunk, coords, b"chest".to_vec()));
+
+        let (spawns, _) = sim.step();
+        assert_eq!(spawns.metadata.len(), 1);
+        let sent = &spawns.metadata[0];
+        assert_eq!((sent.node, sent.chunk), (chunk.node, chunk.vertex));
+        assert_eq!(sent.metadata[&coords], b"chest");
+        // Sent only once, and to clients that connect later
+        assert!(sim.step().0.metadata.is_empty());
+        assert_eq!(sim.snapshot().metadata, [sent.clone()]);
+
+        // Breaking the voxel clears the chunk's metadata for clients too
+        assert!(sim.set_voxel(chunk, index, Material::Void));
+        let (spawns, _) = sim.step();
+        assert_eq!(spawns.metadata.len(), 1);
+        assert!(spawns.metadata[0].metadata.is_empty());
+        assert!(sim.snapshot().metadata.is_empty());
+    }
+
+    #[test]
+    fn outdated_chunks_regenerated_after_restart() {
+        let dir = std::env::temp_dir().join(format!("hypermine-outdated-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut sim = sim();
+        sim.worldgen = stone_worldgen;
+        sim.throttle_worldgen(false);
+        let index = node::voxel_index(sim.cfg.chunk_size, [1, 2, 3]);
+        // Flattening saves freshly generated chunks
+        let node = sim.flatten(&"DG".parse::<NodePath>().unwrap());
+        let (generated, edited) = (ChunkId::new(node, Vertex::A), ChunkId::new(node, Vertex::B));
+        assert!(sim.set_voxel(edited, index, Material::Wood));
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        assert!(autosave.poll(start + interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+
+        // Restarting with newer worldgen
+        let mut reloaded = self::sim();
+        reloaded.worldgen = stone_worldgen;
+        reloaded.worldgen_version = sim.worldgen_version + 1;
+        crate::save::restore(&dir, &mut reloaded);
+        assert!(reloaded.worldgen_queue.contains(&generated));
+        assert!(!reloaded.worldgen_queue.contains(&edited));
+        assert_eq!(reloaded.voxel_material(edited, index), Some(Material::Wood));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outdated_chunks_regenerated() {
+        let mut sim = sim();
+        sim.worldgen = stone_worldgen;
+        let dimension = sim.cfg.chunk_size;
+        let (untouched, edited) = (
+            ChunkId::new(NodeId::ROOT, Vertex::A),
+            ChunkId::new(NodeId::ROOT, Vertex::B),
+        );
+        let (a, b) = (
+            node::voxel_index(dimension, [1, 2, 3]),
+            node::voxel_index(dimension, [4, 5, 6]),
+        );
+        let material = |sim: &Sim, chunk: ChunkId, index: usize| match sim
+            .graph
+            .get(chunk.node)
+            .as_ref()
+            .unwrap()
+            .chunks[chunk.vertex]
+        {
+            Chunk::Populated { ref voxels, .. } => Some(voxels.get(index)),
+            _ => None,
+        };
+        assert!(sim.populate(untouched).is_some());
+        assert!(sim.set_voxel(edited, a, Material::Wood));
+        assert_eq!(sim.regenerate_outdated(), 0, "everything is up to date");
+
+        sim.worldgen = dirt_worldgen;
+        sim.worldgen_version += 1;
+        assert_eq!(sim.regenerate_outdated(), 1);
+        assert_eq!(material(&sim, untouched, a), None);
+        sim.step();
+        assert_eq!(material(&sim, untouched, a), Some(Material::Dirt));
+        // The edited chunk keeps both its edit and the rest of its original contents
+        assert_eq!(material(&sim, edited, a), Some(Material::Wood));
+        assert_eq!(material(&sim, edited, b), Some(Material::Stone));
+        assert_eq!(sim.regenerate_outdated(), 0);
+    }
+
+    /// Empty `chunk` entirely
+    fn hollow(sim: &mut Sim, chunk: ChunkId) {
+        let lwm = usize::from(sim.cfg.chunk_size) + 2;
+        sim.populate_chunk(
+            chunk,
+            VoxelData::Dense(vec![Material::Void; lwm.pow(3)].into()),
+        );
+    }
+
+    fn stone() -> SolidMaterial {
+        SolidMaterial::new(Material::Stone).unwrap()
+    }
+
+    /// Move `entity` to the center of voxel `coords` of `chunk`
+    fn stand_at(sim: &mut Sim, entity: Entity, chunk: ChunkId, coords: [u8; 3]) {
+        let center = node::voxel_to_world_center(sim.cfg.chunk_size, chunk, coords);
+        *sim.world.get_mut::<Position>(entity).unwrap() = Position {
+            node: chunk.node,
+            local: math::translate(&math::origin(), &na::convert(center)),
+        };
+    }
+
+    #[test]
+    fn inventory_spent_and_refunded() {
+        let mut sim = sim();
+        let (_, character) = spawn(&mut sim, "a");
+        let count = |sim: &Sim| {
+            sim.world
+                .get::<Inventory>(character)
+                .unwrap()
+                .count(Material::Stone)
+        };
+        // Sent once on spawning
+        assert_eq!(sim.take_inventory_change(character), Some(Inventory::new()));
+        assert_eq!(sim.take_inventory_change(character), None);
+
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let dimension = sim.cfg.chunk_size;
+        let (a, b) = (
+            node::voxel_index(dimension, [1, 2, 3]),
+            node::voxel_index(dimension, [4, 5, 6]),
+        );
+        hollow(&mut sim, chunk);
+        stand_at(&mut sim, character, chunk, [3, 4, 5]);
+        assert!(sim.set_voxel(chunk, a, Material::Stone));
+        assert!(sim.set_voxel(chunk, b, Material::Void));
+
+        assert_eq!(sim.break_voxel(character, chunk, a), Ok(Material::Stone));
+        assert_eq!(count(&sim), 1);
+        let synced = sim.take_inventory_change(character).unwrap();
+        assert_eq!(synced.count(Material::Stone), 1);
+        assert_eq!(
+            sim.break_voxel(character, chunk, a),
+            Err(EditRejection::Unchanged),
+            "already empty"
+        );
+
+        assert_eq!(sim.place_voxel(character, chunk, b, stone()), Ok(()));
+        assert_eq!(count(&sim), 0);
+        assert_eq!(sim.voxel_material(chunk, b), Some(Material::Stone));
+        assert_eq!(sim.take_inventory_change(character), Some(Inventory::new()));
+
+        // Nothing left to place
+        assert_eq!(
+            sim.place_voxel(character, chunk, a, stone()),
+            Err(EditRejection::NoMaterial)
+        );
+        assert_eq!(sim.voxel_material(chunk, a), Some(Material::Void));
+        assert_eq!(sim.take_inventory_change(character), None);
+
+        // Breaking restores what was spent
+        assert_eq!(sim.break_voxel(character, chunk, b), Ok(Material::Stone));
+        assert_eq!(count(&sim), 1);
+    }
+
+    #[test]
+    fn edit_reach() {
+        let mut sim = sim();
+        let (_, character) = spawn(&mut sim, "a");
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let dimension = sim.cfg.chunk_size;
+        let index = |coords| node::voxel_index(dimension, coords);
+        hollow(&mut sim, chunk);
+        stand_at(&mut sim, character, chunk, [1, 6, 6]);
+        // Reach four voxels along a row
+        let center = |coords| {
+            let center = node::voxel_to_world_center(dimension, chunk, coords);
+            na::convert::<_, na::Vector4<f32>>(center)
+        };
+        let mut cfg = (*sim.cfg).clone();
+        cfg.max_reach = math::distance(&center([1, 6, 6]), &center([5, 6, 6]));
+        sim.set_config(Arc::new(cfg));
+
+        let near = index([4, 6, 6]);
+        let (wall, hidden) = (index([1, 8, 6]), index([1, 9, 6]));
+        let (far, open) = (index([1, 11, 11]), index([1, 6, 8]));
+        for &voxel in &[near, wall, hidden] {
+            assert!(sim.set_voxel(chunk, voxel, Material::Stone));
+        }
+
+        // Within reach and in plain sight
+        let broken = sim.break_voxel(character, chunk, near);
+        assert_eq!(broken, Ok(Material::Stone));
+        // Within reach, but behind a wall
+        assert_eq!(
+            sim.break_voxel(character, chunk, hidden),
+            Err(EditRejection::OutOfReach)
+        );
+        assert_eq!(sim.voxel_material(chunk, hidden), Some(Material::Stone));
+        // In plain sight, but too far
+        assert_eq!(
+            sim.place_voxel(character, chunk, far, stone()),
+            Err(EditRejection::OutOfReach)
+        );
+        assert_eq!(sim.voxel_material(chunk, far), Some(Material::Void));
+        assert_eq!(sim.place_voxel(character, chunk, open, stone()), Ok(()));
+    }
+
+    #[test]
+    fn edit_commands() {
+        let mut sim = sim();
+        let (_, character) = spawn(&mut sim, "a");
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let dimension = sim.cfg.chunk_size;
+        let index = node::voxel_index(dimension, [4, 5, 6]);
+        hollow(&mut sim, chunk);
+        stand_at(&mut sim, character, chunk, [3, 4, 5]);
+        assert!(sim.set_voxel(chunk, index, Material::Stone));
+        let command = |generation, index, material| Command {
+            generation,
+            orientation: na::one(),
+            velocity: na::zero(),
+            resync: false,
+            ping: None,
+            admin: None,
+            edit: Some(VoxelEdit {
+                node: chunk.node,
+                chunk: chunk.vertex,
+                index: index as u32,
+                material,
+            }),
+            fire: false,
+            tick: None,
+        };
+
+        // Breaking, then placing what was collected
+        sim.command(character, command(0, index, Material::Void))
+            .unwrap();
+        assert_eq!(sim.voxel_material(chunk, index), Some(Material::Void));
+        sim.command(character, command(1, index, Material::Stone))
+            .unwrap();
+        assert_eq!(sim.voxel_material(chunk, index), Some(Material::Stone));
+        assert!(sim.take_edit_rejections(character).is_empty());
+
+        // Refusals are reported to the editor, including those of nonsensical requests
+        sim.command(character, command(2, index, Material::Stone))
+            .unwrap();
+        let margin = node::voxel_index(dimension, [0, 0, 0]) - 1;
+        sim.command(character, command(3, margin, Material::Void))
+            .unwrap();
+        sim.command(
+            character,
+            command(4, u32::max_value() as usize, Material::Void),
+        )
+        .unwrap();
+        assert_eq!(
+            sim.take_edit_rejections(character),
+            [
+                proto::EditRejected {
+                    generation: 2,
+                    reason: EditRejection::Unchanged,
+                },
+                proto::EditRejected {
+                    generation: 3,
+                    reason: EditRejection::Unavailable,
+                },
+                proto::EditRejected {
+                    generation: 4,
+                    reason: EditRejection::Unavailable,
+                },
+            ]
+        );
+        assert!(sim.take_edit_rejections(character).is_empty());
+    }
+
+    #[test]
+    fn protected_edits() {
+        let mut sim = sim();
+        let (_, owner) = spawn_account(&mut sim, "owner");
+        // A guest going by the owner's name, which earns them nothing
+        let (_, stranger) = spawn(&mut sim, "owner");
+        let inside = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let side = Side::iter().next().unwrap();
+        let outside = ChunkId::new(sim.graph.neighbor(NodeId::ROOT, side).unwrap(), Vertex::A);
+        let dimension = sim.cfg.chunk_size;
+        let index = node::voxel_index(dimension, [4, 5, 6]);
+        for &chunk in &[inside, outside] {
+            hollow(&mut sim, chunk);
+            assert!(sim.set_voxel(chunk, index, Material::Stone));
+        }
+        sim.protect(&[NodePath::default()], vec!["owner".into()]);
+        let edit = |sim: &mut Sim, character, generation, chunk: ChunkId| {
+            let command = Command {
+                generation,
+                orientation: na::one(),
+                velocity: na::zero(),
+                resync: false,
+                ping: None,
+                admin: None,
+                edit: Some(VoxelEdit {
+                    node: chunk.node,
+                    chunk: chunk.vertex,
+                    index: index as u32,
+                    material: Material::Void,
+                }),
+                fire: false,
+                tick: None,
+            };
+            sim.command(character, command).unwrap();
+            sim.take_edit_rejections(character)
+        };
+
+        // Refused to anyone but members, in the region only
+        stand_at(&mut sim, stranger, inside, [4, 5, 5]);
+        assert_eq!(
+            edit(&mut sim, stranger, 0, inside),
+            [proto::EditRejected {
+                generation: 0,
+                reason: EditRejection::Protected,
+            }]
+        );
+        assert_eq!(sim.voxel_material(inside, index), Some(Material::Stone));
+        stand_at(&mut sim, stranger, outside, [4, 5, 5]);
+        assert!(edit(&mut sim, stranger, 1, outside).is_empty());
+        assert_eq!(sim.voxel_material(outside, index), Some(Material::Void));
+        stand_at(&mut sim, owner, inside, [4, 5, 5]);
+        assert!(edit(&mut sim, owner, 0, inside).is_empty());
+        assert_eq!(sim.voxel_material(inside, index), Some(Material::Void));
+
+        // Protection outlasts a restart
+        let dir = std::env::temp_dir().join(format!("hypermine-protect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        let mut autosave = Autosave::new(
+            SaveParams {
+                path: dir.clone(),
+                interval,
+            },
+            start,
+        );
+        assert!(autosave.poll(start + interval, &mut sim));
+        autosave.flush().unwrap().unwrap();
+        let mut reloaded = self::sim();
+        crate::save::restore(&dir, &mut reloaded);
+        assert!(
+            reloaded.take_protections_change().is_none(),
+            "already saved"
+        );
+        let (_, owner) = spawn_account(&mut reloaded, "owner");
+        let (_, stranger) = spawn_account(&mut reloaded, "stranger");
+        assert_eq!(
+            reloaded.check_protection(stranger, inside),
+            Err(EditRejection::Protected)
+        );
+        assert_eq!(reloaded.check_protection(owner, inside), Ok(()));
+        assert_eq!(reloaded.check_protection(stranger, outside), Ok(()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }